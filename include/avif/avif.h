@@ -212,6 +212,7 @@ enum avifDecoderSource {
     AVIF_DECODER_SOURCE_AUTO = 0,
     AVIF_DECODER_SOURCE_PRIMARY_ITEM = 1,
     AVIF_DECODER_SOURCE_TRACKS = 2,
+    AVIF_DECODER_SOURCE_THUMBNAIL = 3,
 };
 
 enum avifTransferCharacteristics : uint16_t {
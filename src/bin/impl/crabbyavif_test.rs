@@ -24,3 +24,34 @@ fn crabbyavif_test() -> AvifResult<()> {
     );
     Ok(())
 }
+
+#[test]
+fn extract_test() -> AvifResult<()> {
+    let output_file = std::env::temp_dir().join("crabbyavif_extract_test.ivf");
+    let output_file = output_file.to_str().unwrap();
+    main_impl(
+        [
+            "crabbyavif",
+            "tests/data/colors-animated-8bpc.avif",
+            output_file,
+        ]
+        .iter(),
+    )?;
+    let data = std::fs::read(output_file).unwrap();
+    assert_eq!(&data[..4], b"DKIF");
+    // Frame count.
+    assert_eq!(u32::from_le_bytes(data[24..28].try_into().unwrap()), 5);
+    assert_eq!(
+        main_impl(
+            [
+                "crabbyavif",
+                "tests/data/colors-animated-8bpc.avif",
+                output_file,
+                "--extract-alpha",
+            ]
+            .iter()
+        ),
+        Err(AvifError::NoContent)
+    );
+    Ok(())
+}
@@ -21,6 +21,7 @@ use clap::Parser;
 use crabby_avif::decoder::*;
 #[cfg(feature = "encoder")]
 use crabby_avif::encoder::*;
+use crabby_avif::utils::av1::*;
 use crabby_avif::utils::clap::CleanAperture;
 use crabby_avif::utils::clap::CropRect;
 use crabby_avif::utils::IFraction;
@@ -43,6 +44,8 @@ use std::fs::File;
 #[cfg(feature = "encoder")]
 use std::io;
 #[cfg(feature = "encoder")]
+use std::io::BufWriter;
+#[cfg(feature = "encoder")]
 use std::io::Read;
 #[cfg(feature = "encoder")]
 use std::io::Write;
@@ -165,6 +168,14 @@ fn codec_choice_parser(s: &str) -> Result<CodecChoice, String> {
     }
 }
 
+fn tonemap_primaries_parser(s: &str) -> Result<ColorPrimaries, String> {
+    match s {
+        "srgb" | "bt709" => Ok(ColorPrimaries::Srgb),
+        "p3" => Ok(ColorPrimaries::Smpte432),
+        _ => Err(format!("Invalid tone mapping primaries: {s}")),
+    }
+}
+
 fn header_format_parser(s: &str) -> Result<HeaderFormat, String> {
     match s {
         "meta" | "default" => Ok(HeaderFormat::Default),
@@ -219,6 +230,12 @@ struct CommandLineArgs {
     #[arg(long, value_parser = value_parser!(f32))]
     quality_gainmap: Option<f32>,
 
+    /// AVIF Encode only: Maximum output file size in bytes. The highest quality whose output fits
+    /// is searched by encoding the image several times. Overrides --quality. (Single still image
+    /// only)
+    #[arg(long, value_parser = value_parser!(u64).range(1..))]
+    target_size: Option<u64>,
+
     /// PNG output compression level in 0..9 (default: 5).
     #[arg(long, value_parser = value_parser!(i32).range(0..=9))]
     png_compress: Option<i32>,
@@ -338,6 +355,20 @@ struct CommandLineArgs {
     #[arg(long, default_value = "false")]
     extract_gainmap: bool,
 
+    /// AVIF Decode only: When the output file is .ivf or .obu, extract the alpha payloads instead
+    /// of the color payloads.
+    #[arg(long, default_value = "false")]
+    extract_alpha: bool,
+
+    /// AVIF Decode only: Tone map PQ and HLG images to SDR with the given peak luminance in cd/m²
+    /// (PNG/JPEG only, default: 203). Ignored for SDR images.
+    #[arg(long, num_args = 0..=1, default_missing_value = "203", value_parser = value_parser!(f32))]
+    tonemap: Option<f32>,
+
+    /// AVIF Decode only: Color primaries of the tone mapped output, either srgb or p3.
+    #[arg(long, value_parser = tonemap_primaries_parser, default_value = "srgb")]
+    tonemap_primaries: ColorPrimaries,
+
     /// AVIF Encode only: CLLI information of the alternate image. Only used when encoding an image
     /// with gainmap. Ignored otherwise.
     #[arg(long, value_parser = clli_parser)]
@@ -673,6 +704,11 @@ fn decode(args: &CommandLineArgs, input_file: &String) -> AvifResult<()> {
         decoder.image().unwrap()
     };
     let extension = get_extension(output_filename);
+    #[cfg(any(feature = "png", feature = "jpeg"))]
+    let tone_mapping = args.tonemap.map(|target_nits| reformat::rgb::ToneMapping {
+        target_nits,
+        color_primaries: args.tonemap_primaries,
+    });
     let mut writer: Box<dyn Writer> = match extension.as_str() {
         "y4m" | "yuv" => {
             if !image.icc.is_empty() || !image.exif.is_empty() || !image.xmp.is_empty() {
@@ -684,10 +720,12 @@ fn decode(args: &CommandLineArgs, input_file: &String) -> AvifResult<()> {
         "png" => Box::new(PngWriter {
             depth: args.depth,
             compression_level: args.png_compress,
+            tone_mapping,
         }),
         #[cfg(feature = "jpeg")]
         "jpg" | "jpeg" => Box::new(JpegWriter {
             quality: args.quality.map(|quality| quality as u8),
+            tone_mapping,
         }),
         _ => {
             return Err(AvifError::UnknownError(format!(
@@ -698,11 +736,14 @@ fn decode(args: &CommandLineArgs, input_file: &String) -> AvifResult<()> {
     let mut output_file = File::create(output_filename).or(Err(AvifError::UnknownError(
         "Could not open output file".into(),
     )))?;
-    let cropped_image = if image.clap.is_some() {
-        if let Ok(cropped_image) = image.cropped_image() {
-            Some(cropped_image)
+    let transformed_image = if image.clap.is_some()
+        || image.irot_angle.is_some()
+        || image.imir_axis.is_some()
+    {
+        if let Ok(transformed_image) = image.transformed_image() {
+            Some(transformed_image)
         } else {
-            println!("Warning: clap was invalid. So writing whole image.");
+            println!("Warning: clap, irot or imir was invalid. So writing untransformed image.");
             None
         }
     } else {
@@ -710,8 +751,8 @@ fn decode(args: &CommandLineArgs, input_file: &String) -> AvifResult<()> {
     };
     writer.write_frame(
         &mut output_file,
-        match &cropped_image {
-            Some(cropped_image) => cropped_image,
+        match &transformed_image {
+            Some(transformed_image) => transformed_image,
             None => image,
         },
     )?;
@@ -723,6 +764,64 @@ fn decode(args: &CommandLineArgs, input_file: &String) -> AvifResult<()> {
     Ok(())
 }
 
+// Writes the AV1 payloads of all the frames of the input file without decoding them. Each cell of a
+// grid image is written to a separate file.
+fn extract(args: &CommandLineArgs, input_file: &String) -> AvifResult<()> {
+    let mut decoder = create_decoder_and_parse(args, input_file)?;
+    if decoder.compression_format() != CompressionFormat::Avif {
+        return Err(AvifError::UnknownError(
+            "Only AV1 payloads can be extracted".into(),
+        ));
+    }
+    let category = if args.extract_gainmap {
+        Category::Gainmap
+    } else if args.extract_alpha {
+        Category::Alpha
+    } else {
+        Category::Color
+    };
+    let output_filename = args.output_file.as_ref().unwrap();
+    let extension = get_extension(output_filename);
+    let format = if extension == "ivf" {
+        Av1StreamFormat::Ivf
+    } else {
+        Av1StreamFormat::LowOverhead
+    };
+    let mut cells: Vec<Vec<Vec<u8>>> = Vec::new();
+    let mut timings = Vec::new();
+    for index in 0..decoder.image_count() {
+        let payloads = decoder.encoded_payloads(category, index)?;
+        cells.resize(payloads.len(), Vec::new());
+        for (cell, payload) in cells.iter_mut().zip(payloads) {
+            cell.push(payload);
+        }
+        if decoder.image().unwrap().image_sequence_track_present {
+            timings.push(decoder.nth_image_timing(index)?);
+        }
+    }
+    for (cell_index, temporal_units) in cells.iter().enumerate() {
+        let filename = if cells.len() == 1 {
+            output_filename.clone()
+        } else {
+            let path = Path::new(output_filename);
+            let file_stem = path.file_stem().unwrap_or_default().to_string_lossy();
+            path.with_file_name(format!("{file_stem}_cell{cell_index}.{extension}"))
+                .to_string_lossy()
+                .into_owned()
+        };
+        let data = join_temporal_units(temporal_units, &timings, format)?;
+        std::fs::write(&filename, data).or(Err(AvifError::UnknownError(
+            "Could not write output file".into(),
+        )))?;
+        println!(
+            "Wrote {} {category:?} payload{} to output {filename}",
+            temporal_units.len(),
+            if temporal_units.len() == 1 { "" } else { "s" }
+        );
+    }
+    Ok(())
+}
+
 #[cfg(feature = "encoder")]
 fn read_file(filepath: &String) -> io::Result<Vec<u8>> {
     let mut file = File::open(filepath)?;
@@ -835,19 +934,42 @@ fn encode(args: &CommandLineArgs, input_file: &str, output_file: &str) -> AvifRe
             vertical: scaling_mode,
         };
     }
+    if let Some(target_size) = args.target_size {
+        if reader.has_more_frames() || args.progressive {
+            println!("--target-size can only be used with a single input image.");
+            return Err(AvifError::InvalidArgument);
+        }
+        let target_size = TargetSize::Bytes(usize::try_from(target_size).unwrap_or(usize::MAX));
+        let (encoded_data, quality) =
+            Encoder::encode_with_target_size(&settings, target_size, |encoder| match &gainmap {
+                Some(gainmap) => encoder.add_image_gainmap(&image, gainmap),
+                None => encoder.add_image(&image),
+            })?;
+        println!("Chosen quality: {quality}");
+        return write_encoded_file(args, &encoded_data);
+    }
     let mut encoder = Encoder::create_with_settings(&settings)?;
     if reader.has_more_frames() {
         if args.progressive {
             println!("Automatic progressive encoding can only have one input image.");
             return Err(AvifError::InvalidArgument);
         }
+        // Write the frames to the output file as they are encoded, so that they do not have to be
+        // kept in memory.
+        let output_file = args.output_file.as_ref().unwrap();
+        let mut file = BufWriter::new(File::create(output_file).expect("file creation failed"));
         loop {
             encoder.add_image_for_sequence(&image, duration_ms)?;
+            encoder.flush_to_writer(&mut file)?;
             if !reader.has_more_frames() {
                 break;
             }
             (image, duration_ms, _) = reader.read_frame(&reader_config)?;
         }
+        encoder.finish_to_writer(&mut file)?;
+        file.flush().expect("file writing failed");
+        println!("Write output AVIF: {output_file}");
+        return Ok(());
     } else if args.progressive {
         // Encode the base layer with very low quality.
         settings.mutable.quality = 2.0;
@@ -864,9 +986,14 @@ fn encode(args: &CommandLineArgs, input_file: &str, output_file: &str) -> AvifRe
     }
 
     let encoded_data = encoder.finish()?;
+    write_encoded_file(args, &encoded_data)
+}
+
+#[cfg(feature = "encoder")]
+fn write_encoded_file(args: &CommandLineArgs, encoded_data: &[u8]) -> AvifResult<()> {
     let output_file = args.output_file.as_ref().unwrap();
     let mut file = File::create(output_file).expect("file creation failed");
-    file.write_all(&encoded_data).expect("file writing failed");
+    file.write_all(encoded_data).expect("file writing failed");
     println!("Write output AVIF: {output_file}");
     Ok(())
 }
@@ -928,16 +1055,38 @@ fn validate_args(args: &CommandLineArgs) -> AvifResult<()> {
                         ));
                     }
                 }
+                if args.extract_alpha && extension != "ivf" && extension != "obu" {
+                    return Err(AvifError::UnknownError(
+                        "extract-alpha is only supported for ivf and obu output".into(),
+                    ));
+                }
                 if args.depth.is_some() && extension != "png" {
                     return Err(AvifError::UnknownError(
                         "depth is only supported for png output".into(),
                     ));
                 }
+                if args.target_size.is_some() {
+                    return Err(AvifError::UnknownError(
+                        "target-size is only supported for avif output".into(),
+                    ));
+                }
                 if args.png_compress.is_some() && extension != "png" {
                     return Err(AvifError::UnknownError(
                         "png-compress-level is only supported for png output".into(),
                     ));
                 }
+                if let Some(tonemap) = args.tonemap {
+                    if !matches!(extension.as_str(), "png" | "jpg" | "jpeg") {
+                        return Err(AvifError::UnknownError(
+                            "tonemap is only supported for png and jpeg output".into(),
+                        ));
+                    }
+                    if tonemap <= 0.0 {
+                        return Err(AvifError::UnknownError(
+                            "tonemap must be a positive luminance".into(),
+                        ));
+                    }
+                }
             }
         } else {
             // TODO: b/403090413 - validate encoding args.
@@ -989,6 +1138,11 @@ where
         if can_decode(input_file) {
             if args.info {
                 info(&args, input_file)?;
+            } else if matches!(
+                get_extension(args.output_file.as_ref().unwrap()).as_str(),
+                "ivf" | "obu"
+            ) {
+                extract(&args, input_file)?;
             } else {
                 decode(&args, input_file)?;
             }
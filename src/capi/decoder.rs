@@ -229,6 +229,9 @@ impl From<&avifDecoder> for Settings {
             max_threads: u32::try_from(decoder.maxThreads).unwrap_or(0),
             android_mediacodec_output_color_format: decoder.androidMediaCodecOutputColorFormat,
             allow_sample_transform: decoder.allowSampleTransform == AVIF_TRUE,
+            stereo_view: Default::default(),
+            color_track_id: None,
+            alpha_track_id: None,
         }
     }
 }
@@ -141,6 +141,7 @@ impl From<&avifEncoder> for Settings {
             keyframe_interval: encoder.keyframeInterval,
             timescale: if encoder.timescale == 0 { 1 } else { encoder.timescale },
             repetition_count: RepetitionCount::create_from(encoder.repetitionCount),
+            fragmented: false,
             extra_layer_count: encoder.extraLayerCount,
             recipe: Recipe::None,
             force_write_extended_pixi: false,
@@ -154,6 +155,7 @@ impl From<&avifEncoder> for Settings {
             } else {
                 Some(encoder.modificationTime)
             },
+            target_quality: None,
             mutable: encoder.into(),
         }
     }
@@ -263,6 +265,8 @@ pub unsafe extern "C" fn crabby_avifEncoderAddImage(
                 Some(gainmap) => rust_encoder(encoder).add_image_gainmap(&image, gainmap),
                 None => rust_encoder(encoder).add_image(&image),
             }
+        } else if (addImageFlags & AVIF_ADD_IMAGE_FLAG_FORCE_KEYFRAME) != 0 {
+            rust_encoder(encoder).add_keyframe_for_sequence(&image, durationInTimescales)
         } else {
             rust_encoder(encoder).add_image_for_sequence(&image, durationInTimescales)
         };
@@ -71,6 +71,7 @@ impl From<&avifRGBImage> for rgb::Image {
             max_threads: rgb.max_threads,
             pixels: Pixels::from_raw_pointer(rgb.pixels, rgb.depth, rgb.height, rgb.row_bytes).ok(),
             row_bytes: rgb.row_bytes,
+            tone_mapping: None,
         };
         let format = match (rgb.format, rgb.ignore_alpha) {
             (rgb::Format::Rgb, _) => rgb::Format::Rgb,
@@ -385,6 +385,11 @@ pub struct MediaCodec {
     codec_initializers: Vec<CodecInitializer>,
 }
 
+// SAFETY: AMediaCodec and AMediaFormat instances can be used from any thread as long as the calls
+// are not concurrent (see also MediaCodecThreadWrapper). They are only accessed through
+// `&mut self`.
+unsafe impl Send for MediaCodec {}
+
 struct MediaCodecThreadWrapper(*mut AMediaCodec);
 unsafe impl Send for MediaCodecThreadWrapper {}
 
@@ -479,8 +479,10 @@ impl Encoder for Aom {
         aom_image.cp = image.color_primaries as u32;
         aom_image.tc = image.transfer_characteristics as u32;
         aom_image.mc = image.matrix_coefficients as u32;
-        // TODO: b/392112497 - force keyframes when necessary.
         let mut encode_flags = 0i64;
+        if config.force_keyframe {
+            encode_flags |= AOM_EFLAG_FORCE_KF as i64;
+        }
         if self.current_layer > 0 {
             encode_flags |= AOM_EFLAG_NO_REF_GF as i64
                 | AOM_EFLAG_NO_REF_ARF as i64
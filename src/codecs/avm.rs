@@ -68,6 +68,11 @@ pub struct Avm {
     image: *mut avm_image_t,
 }
 
+// SAFETY: The libavm codec context and the images it returns are not bound to the thread that
+// created them. They are only accessed through `&mut self`, so never from two threads at the
+// same time.
+unsafe impl Send for Avm {}
+
 // Functions mapping from CrabbyAvif structures to AV2 or libavm constants.
 
 fn avm_format(image: &Image, category: Category) -> AvifResult<avm_img_fmt_t> {
@@ -440,8 +445,10 @@ impl Encoder for Avm {
         avm_image.cp = image.color_primaries as u32;
         avm_image.tc = image.transfer_characteristics as u32;
         avm_image.mc = image.matrix_coefficients as u32;
-        // TODO: b/392112497 - force keyframes when necessary.
         let mut encode_flags = 0;
+        if config.force_keyframe {
+            encode_flags |= AVM_EFLAG_FORCE_KF as i64;
+        }
         if self.current_layer > 0 {
             encode_flags |= AVM_EFLAG_NO_REF_GF as i64
                 | AVM_EFLAG_NO_REF_ARF as i64
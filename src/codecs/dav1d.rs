@@ -39,6 +39,10 @@ pub struct Dav1d {
     config: Option<DecoderConfig>,
 }
 
+// SAFETY: A dav1d context and the pictures it returns are not bound to the thread that created
+// them. They are only accessed through `&mut self`, so never from two threads at the same time.
+unsafe impl Send for Dav1d {}
+
 // See https://code.videolan.org/videolan/dav1d/-/blob/9849ede1304da1443cfb4a86f197765081034205/include/dav1d/common.h#L55-59
 const DAV1D_EAGAIN: i32 = if libc::EPERM > 0 { -libc::EAGAIN } else { libc::EAGAIN };
 
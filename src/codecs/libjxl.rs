@@ -49,6 +49,10 @@ pub struct Libjxl {
     reconstructed_jxl: Option<Vec<u8>>,
 }
 
+// SAFETY: libjxl encoder and decoder instances are not bound to the thread that created them.
+// They are only accessed through `&mut self`, so never from two threads at the same time.
+unsafe impl Send for Libjxl {}
+
 // Convenient error mapping.
 trait JxlEncoderStatusTrait {
     fn map_enc_err(self, encoder: *mut JxlEncoder) -> Result<(), AvifError>;
@@ -151,7 +155,7 @@ impl Encoder for Libjxl {
         match category {
             Category::Color => {}
             Category::Alpha => unreachable!(), // Should be a channel, not an auxiliary item.
-            Category::Gainmap => return AvifError::not_implemented(),
+            Category::Gainmap | Category::Depth => return AvifError::not_implemented(),
         }
         let num_channels = rgb.pixel_size();
         let num_alpha_channels = if rgb.has_alpha() { 1 } else { 0 };
@@ -383,7 +387,7 @@ impl Decoder for Libjxl {
         match category {
             Category::Color => {}
             Category::Alpha => unreachable!(), // Should be a channel, not an auxiliary item.
-            Category::Gainmap => return AvifError::not_implemented(),
+            Category::Gainmap | Category::Depth => return AvifError::not_implemented(),
         }
 
         if self.decoder.is_null() {
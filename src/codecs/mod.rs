@@ -27,6 +27,9 @@ pub mod avm;
 #[cfg(feature = "jpegxl")]
 pub mod libjxl;
 
+#[cfg(feature = "encoder")]
+pub mod remux;
+
 use crate::decoder::item::Item;
 use crate::decoder::GridImageHelper;
 use crate::image::Image;
@@ -99,6 +102,7 @@ pub(crate) struct EncoderConfig {
     pub quality: f32, // From 0 (maximum distortion) to 100 (lossless) inclusive.
     pub disable_lagged_output: bool,
     pub is_single_image: bool,
+    pub force_keyframe: bool,
     pub speed: Option<u32>,
     pub extra_layer_count: u32,
     pub threads: u32,
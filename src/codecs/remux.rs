@@ -0,0 +1,55 @@
+// Copyright 2025 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::codecs::Encoder;
+use crate::codecs::EncoderConfig;
+use crate::encoder::Sample;
+use crate::image::Image;
+use crate::parser::mp4box::CodecConfiguration;
+use crate::parser::obu::Av1SequenceHeader;
+use crate::*;
+
+// Placeholder codec of the items whose samples are AV1 temporal units that were encoded outside of
+// this library. See Encoder::add_encoded_image().
+#[derive(Default)]
+pub struct Remux {}
+
+impl Encoder for Remux {
+    fn encode_image(
+        &mut self,
+        _image: &Image,
+        _category: Category,
+        _config: &EncoderConfig,
+        _output_samples: &mut Vec<Sample>,
+    ) -> AvifResult<()> {
+        // Pre-encoded and regular images cannot be mixed.
+        AvifError::invalid_argument()
+    }
+
+    fn finish(&mut self, _output_samples: &mut Vec<Sample>) -> AvifResult<()> {
+        Ok(())
+    }
+
+    fn get_codec_config(
+        &self,
+        _image: &Image,
+        _is_single_image: bool,
+        _is_lossless: bool,
+        output_samples: &[Sample],
+    ) -> AvifResult<CodecConfiguration> {
+        Ok(CodecConfiguration::Av1(
+            Av1SequenceHeader::parse_from_obus(&output_samples[0].data)?.config,
+        ))
+    }
+}
@@ -0,0 +1,222 @@
+// Copyright 2025 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::decoder::track::*;
+use crate::decoder::Extent;
+use crate::decoder::GenericIO;
+use crate::internal_utils::*;
+use crate::parser::mp4box::*;
+use crate::*;
+
+/// Property associated with an item in the 'ipma' box.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PropertyDescription {
+    /// 1-based index of the property in the 'ipco' box.
+    pub index: u16,
+    pub essential: bool,
+    /// Four-character code of the property box, such as "ispe" or "colr". Empty for files using
+    /// the MinimizedImageBox ('mini'), which has no 'ipco' box.
+    pub box_type: String,
+    /// Raw payload of the property box, after its header. Empty for files using the
+    /// MinimizedImageBox ('mini').
+    pub payload: Vec<u8>,
+}
+
+/// References of a given type from an item in the 'iref' box.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ItemReferenceDescription {
+    /// Four-character code of the reference, such as "thmb", "auxl", "cdsc" or "dimg".
+    pub reference_type: String,
+    /// Referenced item ids, in the order they appear in the 'iref' box.
+    pub to_item_ids: Vec<u32>,
+}
+
+/// Item of the top-level 'meta' box, as parsed from the 'iinf', 'iloc', 'iprp' and 'iref' boxes.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ItemDescription {
+    pub id: u32,
+    /// Four-character code of the item, such as "av01", "grid", "Exif" or "mime".
+    pub item_type: String,
+    pub name: String,
+    /// Only set for "mime" items.
+    pub content_type: String,
+    /// True if (flags & 1) is set in the 'infe' box, meaning that the item is not intended to be
+    /// part of the presentation.
+    pub hidden: bool,
+    pub references: Vec<ItemReferenceDescription>,
+    pub properties: Vec<PropertyDescription>,
+    /// 0 if the extent offsets are file offsets, 1 if they are offsets in the 'idat' box.
+    pub construction_method: u8,
+    pub extents: Vec<Extent>,
+}
+
+/// Entity group of the 'grpl' box.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EntityGroupDescription {
+    /// Four-character code of the group, such as "altr" or "ster".
+    pub grouping_type: String,
+    pub group_id: u32,
+    /// Item or track ids, in the order they appear in the group.
+    pub entity_ids: Vec<u32>,
+}
+
+/// Track of the 'moov' box.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TrackDescription {
+    pub id: u32,
+    /// Four-character code of the 'hdlr' box, such as "pict", "vide", "auxv" or "soun".
+    pub handler_type: String,
+    /// Four-character code of the first sample entry of the 'stsd' box, such as "av01". Empty if
+    /// there is none.
+    pub format: String,
+    /// True if the samples of this track can be decoded, which is required for
+    /// Settings::color_track_id and Settings::alpha_track_id.
+    pub decodable: bool,
+    /// Auxiliary type of the 'auxi' box, such as "urn:mpeg:mpegB:cicp:systems:auxiliary:alpha".
+    /// Empty if there is none.
+    pub aux_type: String,
+    /// Id of the track referenced by an 'auxl' track reference.
+    pub aux_for_id: Option<u32>,
+    /// Id of the track referenced by a 'prem' track reference.
+    pub prem_by_id: Option<u32>,
+    pub width: u32,
+    pub height: u32,
+    /// Timescale of the 'mdhd' box.
+    pub timescale: u32,
+    /// Sum of the sample durations, in timescale units.
+    pub duration_in_timescales: u64,
+    pub sample_count: u32,
+    /// Entries of the 'elst' box. Empty if there is none.
+    pub edit_list: Vec<EditListEntry>,
+    /// True if (flags & 1) is set in the 'elst' box.
+    pub edit_list_repeats: bool,
+}
+
+pub(crate) fn describe_items(
+    meta: &MetaBox,
+    io: &mut GenericIO,
+) -> AvifResult<Vec<ItemDescription>> {
+    let mut items: Vec<ItemDescription> = create_vec_exact(meta.iinf.len())?;
+    for iinf in &meta.iinf {
+        let mut item = ItemDescription {
+            id: iinf.item_id,
+            item_type: iinf.item_type.clone(),
+            name: iinf.item_name.clone(),
+            content_type: iinf.content_type.clone(),
+            hidden: iinf.hidden,
+            ..Default::default()
+        };
+        for reference in meta.iref.iter().filter(|x| x.from_item_id == item.id) {
+            match item.references.last_mut() {
+                Some(last) if last.reference_type == reference.reference_type => {
+                    last.to_item_ids.push(reference.to_item_id)
+                }
+                _ => item.references.push(ItemReferenceDescription {
+                    reference_type: reference.reference_type.clone(),
+                    to_item_ids: vec![reference.to_item_id],
+                }),
+            }
+        }
+        for association in meta
+            .iprp
+            .associations
+            .iter()
+            .filter(|x| x.item_id == item.id)
+        {
+            for (index, essential) in &association.associations {
+                if *index == 0 {
+                    continue;
+                }
+                let (box_type, payload) =
+                    match meta.iprp.property_ranges.get(usize::from(*index) - 1) {
+                        Some(property) => (
+                            property.box_type.clone(),
+                            io.read_exact(
+                                checked_add!(
+                                    meta.iprp.ipco_offset,
+                                    u64_from_usize(property.payload.start)?
+                                )?,
+                                property.payload.len(),
+                            )?
+                            .to_vec(),
+                        ),
+                        None => Default::default(),
+                    };
+                item.properties.push(PropertyDescription {
+                    index: *index,
+                    essential: *essential,
+                    box_type,
+                    payload,
+                });
+            }
+        }
+        if let Some(iloc) = meta.iloc.items.iter().find(|x| x.item_id == item.id) {
+            item.construction_method = iloc.construction_method;
+            for extent in &iloc.extents {
+                item.extents.push(Extent {
+                    offset: checked_add!(iloc.base_offset, extent.offset)?,
+                    size: extent.size,
+                });
+            }
+        }
+        items.push(item);
+    }
+    Ok(items)
+}
+
+pub(crate) fn describe_entity_groups(meta: &MetaBox) -> Vec<EntityGroupDescription> {
+    meta.grpl
+        .iter()
+        .map(|group| EntityGroupDescription {
+            grouping_type: group.grouping_type.clone(),
+            group_id: group.group_id,
+            entity_ids: group.entity_ids.clone(),
+        })
+        .collect()
+}
+
+pub(crate) fn describe_tracks(tracks: &[Track]) -> AvifResult<Vec<TrackDescription>> {
+    let mut descriptions: Vec<TrackDescription> = create_vec_exact(tracks.len())?;
+    for track in tracks {
+        let mut description = TrackDescription {
+            id: track.id,
+            handler_type: track.handler_type.clone(),
+            decodable: track.is_decodable(),
+            aux_for_id: track.aux_for_id,
+            prem_by_id: track.prem_by_id,
+            width: track.width,
+            height: track.height,
+            timescale: track.media_timescale,
+            duration_in_timescales: track.media_duration,
+            edit_list: track.edit_list.clone(),
+            edit_list_repeats: track.is_repeating,
+            ..Default::default()
+        };
+        if let Some(sample_table) = &track.sample_table {
+            if let Some(sample_description) = sample_table.sample_descriptions.first() {
+                description.format = sample_description.format.clone();
+            }
+            for time_to_sample in &sample_table.time_to_sample {
+                checked_incr!(description.sample_count, time_to_sample.sample_count);
+            }
+        }
+        if let Some(properties) = track.get_properties() {
+            if let Some(aux_type) = find_property!(properties, AuxiliaryType) {
+                description.aux_type = aux_type.aux_type.clone();
+            }
+        }
+        descriptions.push(description);
+    }
+    Ok(descriptions)
+}
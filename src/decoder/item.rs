@@ -13,6 +13,7 @@
 // limitations under the License.
 
 use crate::decoder::*;
+use crate::depth::DepthRepresentationInfo;
 use crate::internal_utils::stream::*;
 use crate::parser::mp4box::*;
 use crate::utils::pixels::ChannelIdc;
@@ -370,7 +371,13 @@ impl Item {
 
     pub(crate) fn is_auxiliary_alpha(&self) -> bool {
         matches!(find_property!(&self.properties, AuxiliaryType),
-                 Some(aux_type) if is_auxiliary_type_alpha(aux_type))
+                 Some(aux_type) if is_auxiliary_type_alpha(&aux_type.aux_type))
+            && !self.is_sample_transform_item()
+    }
+
+    pub(crate) fn is_auxiliary_depth(&self) -> bool {
+        matches!(find_property!(&self.properties, AuxiliaryType),
+                 Some(aux_type) if is_auxiliary_type_depth(&aux_type.aux_type))
             && !self.is_sample_transform_item()
     }
 
@@ -543,6 +550,19 @@ pub(crate) fn depth_from_properties(
     AvifError::bmff_parse_failed(format!("{item_name} item must have a specified depth"))
 }
 
+// Returns the depth representation information from the aux_subtype field of the auxiliary type
+// property, if any.
+pub(crate) fn depth_representation_info_from_properties(
+    properties: &[ItemProperty],
+) -> AvifResult<Option<DepthRepresentationInfo>> {
+    match find_property!(properties, AuxiliaryType) {
+        Some(aux_type) if !aux_type.aux_subtype.is_empty() => Ok(Some(
+            parse_depth_representation_info(&mut IStream::create(&aux_type.aux_subtype))?,
+        )),
+        _ => Ok(None),
+    }
+}
+
 // Returns the subsampling information from either the codec configuration property or the extended
 // PixelInformationProperty (px_flags&1==1), or an error if the information cannot be found.
 // Item::validate_properties() checks for cross-property consistency.
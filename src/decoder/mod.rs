@@ -12,10 +12,12 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+pub mod inspect;
 pub mod item;
 pub mod tile;
 pub mod track;
 
+use crate::decoder::inspect::*;
 use crate::decoder::item::*;
 use crate::decoder::tile::*;
 use crate::decoder::track::*;
@@ -33,6 +35,7 @@ use crate::codecs::avm::Avm;
 use crate::codecs::libjxl::Libjxl;
 
 use crate::codecs::DecoderConfig;
+use crate::depth::*;
 use crate::gainmap::*;
 use crate::image::*;
 use crate::internal_utils::io::*;
@@ -41,6 +44,7 @@ use crate::parser::exif;
 use crate::parser::mp4box;
 use crate::parser::mp4box::*;
 use crate::parser::obu::Av1SequenceHeader;
+use crate::utils::clap::CropRect;
 use crate::utils::pixels::ChannelIdc;
 use crate::utils::pixels::Pixels;
 use crate::*;
@@ -68,7 +72,47 @@ impl dyn IO {
 }
 
 pub type GenericIO = Box<dyn IO>;
-pub(crate) type Codec = Box<dyn crate::codecs::Decoder>;
+// Codecs are Send so that the tiles of a frame can be decoded on several threads.
+pub(crate) type Codec = Box<dyn crate::codecs::Decoder + Send>;
+
+struct TileJob<'a> {
+    tile: &'a mut Tile,
+    category: Category,
+    spatial_id: u8,
+    data: &'a [u8],
+    item: Option<&'a Item>,
+}
+
+// The tiles to be decoded with a given codec instance.
+struct CodecJobs<'a> {
+    codec: &'a mut Codec,
+    tiles: Vec<TileJob<'a>>,
+}
+
+impl CodecJobs<'_> {
+    // Decodes the tiles of all the given jobs. Run by a single decoding thread.
+    fn decode_all(all_jobs: Vec<Self>) -> AvifResult<()> {
+        for jobs in all_jobs {
+            let tile_count = jobs.tiles.len();
+            for (index, job) in jobs.tiles.into_iter().enumerate() {
+                jobs.codec.get_next_image(
+                    job.data,
+                    job.spatial_id,
+                    &mut job.tile.image,
+                    job.category,
+                    job.item,
+                    false,
+                )?;
+                if index + 1 < tile_count {
+                    // The decoded pixels may point to memory owned by the codec, which is reused
+                    // by the next decode call.
+                    job.tile.image = job.tile.image.try_deep_clone()?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
 
 impl CodecChoice {
     pub(crate) fn get_decoder_codec(&self, compression_format: CompressionFormat) -> Option<Codec> {
@@ -113,7 +157,30 @@ pub enum Source {
     Auto = 0,
     PrimaryItem = 1,
     Tracks = 2,
-    // TODO: Thumbnail,
+    // Thumbnail item ('thmb' reference) of the primary item. Parsing fails with
+    // AvifError::NoContent if there is none.
+    Thumbnail = 3,
+}
+
+/// View of a stereo pair ('ster' entity group containing the primary item) to decode. Views other
+/// than Primary require Settings::source to be Source::Auto or Source::PrimaryItem, otherwise
+/// parsing fails with AvifError::InvalidArgument.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum StereoView {
+    /// Decode the primary item, whichever view it is.
+    #[default]
+    Primary,
+    /// Decode the left view. Parsing fails with AvifError::NoContent if there is no stereo pair.
+    Left,
+    /// Decode the right view. Parsing fails with AvifError::NoContent if there is no stereo pair.
+    Right,
+}
+
+/// Item ids of the 'ster' entity group containing the primary item.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct StereoPair {
+    pub left_item_id: u32,
+    pub right_item_id: u32,
 }
 
 pub const DEFAULT_IMAGE_SIZE_LIMIT: u32 = 16384 * 16384;
@@ -125,6 +192,9 @@ pub enum ImageContentType {
     None,
     ColorAndAlpha,
     GainMap,
+    /// Depth maps are only decoded if requested explicitly, with DepthMap.
+    DepthMap,
+    /// Color, alpha and gain map. Does not include the depth map.
     All,
 }
 
@@ -134,6 +204,7 @@ impl ImageContentType {
             Self::None => vec![],
             Self::ColorAndAlpha => vec![Category::Color, Category::Alpha],
             Self::GainMap => vec![Category::Gainmap],
+            Self::DepthMap => vec![Category::Depth],
             Self::All => Category::ALL.to_vec(),
         };
         DecodingItem::all_for_categories(&categories)
@@ -142,6 +213,10 @@ impl ImageContentType {
     pub(crate) fn gainmap(&self) -> bool {
         matches!(self, Self::GainMap | Self::All)
     }
+
+    pub(crate) fn depth_map(&self) -> bool {
+        matches!(self, Self::DepthMap)
+    }
 }
 
 #[derive(Debug)]
@@ -160,6 +235,13 @@ pub struct Settings {
     pub max_threads: u32,
     pub android_mediacodec_output_color_format: AndroidMediaCodecOutputColorFormat,
     pub allow_sample_transform: bool,
+    pub stereo_view: StereoView,
+    /// Id of the track to decode when the source is Source::Tracks (which Source::Auto selects if
+    /// this is set). If None, the first color track is decoded. See Decoder::tracks().
+    pub color_track_id: Option<u32>,
+    /// Id of the alpha track to decode along with the color track. If None, the first auxiliary
+    /// alpha track of the color track is decoded, if any.
+    pub alpha_track_id: Option<u32>,
 }
 
 impl Default for Settings {
@@ -179,11 +261,14 @@ impl Default for Settings {
             max_threads: 1,
             android_mediacodec_output_color_format: AndroidMediaCodecOutputColorFormat::default(),
             allow_sample_transform: false,
+            stereo_view: Default::default(),
+            color_track_id: None,
+            alpha_track_id: None,
         }
     }
 }
 
-#[derive(Clone, Copy, Debug, Default)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
 #[repr(C)]
 pub struct Extent {
     pub offset: u64,
@@ -253,7 +338,7 @@ impl Strictness {
 }
 
 #[repr(C)]
-#[derive(Clone, Copy, Debug, Default)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
 pub enum ProgressiveState {
     #[default]
     Unavailable = 0,
@@ -285,7 +370,7 @@ pub struct DecodingItem {
 }
 
 impl DecodingItem {
-    const COUNT: usize = 3 + Self::MAX_EXTRA_INPUTS * 2;
+    const COUNT: usize = 4 + Self::MAX_EXTRA_INPUTS * 2;
     // Max supported number of inputs for derived image items.
     const MAX_EXTRA_INPUTS: usize = 3;
     const ALL: [DecodingItem; Self::COUNT] = [
@@ -298,8 +383,9 @@ impl DecodingItem {
         Self::alpha(2),
         Self::alpha(3),
         Self::GAINMAP,
+        Self::DEPTH,
     ];
-    const ALL_USIZE: [usize; Self::COUNT] = [0, 1, 2, 3, 4, 5, 6, 7, 8];
+    const ALL_USIZE: [usize; Self::COUNT] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9];
 
     const COLOR: DecodingItem = Self::color(0);
     const ALPHA: DecodingItem = Self::alpha(0);
@@ -307,6 +393,10 @@ impl DecodingItem {
         category: Category::Gainmap,
         item_idx: 0,
     };
+    const DEPTH: DecodingItem = DecodingItem {
+        category: Category::Depth,
+        item_idx: 0,
+    };
 
     const fn color(item_idx: usize) -> DecodingItem {
         DecodingItem {
@@ -335,6 +425,7 @@ impl DecodingItem {
             Category::Color => self.item_idx,
             Category::Alpha => 1 + Self::MAX_EXTRA_INPUTS + self.item_idx,
             Category::Gainmap => (1 + Self::MAX_EXTRA_INPUTS) * 2,
+            Category::Depth => (1 + Self::MAX_EXTRA_INPUTS) * 2 + 1,
         }
     }
 }
@@ -351,18 +442,32 @@ pub struct Decoder {
     repetition_count: RepetitionCount,
     gainmap: GainMap,
     gainmap_present: bool,
+    depth_map: DepthMap,
+    depth_map_present: bool,
     image: Image,
     extra_inputs: [Image; DecodingItem::MAX_EXTRA_INPUTS],
     source: Source,
     tile_info: [TileInfo; DecodingItem::COUNT],
     tiles: [Vec<Tile>; DecodingItem::COUNT],
     items: Items,
+    primary_item_id: u32,
+    item_descriptions: Vec<ItemDescription>,
+    entity_group_descriptions: Vec<EntityGroupDescription>,
+    stereo_pair: Option<StereoPair>,
+    alternative_item_ids: Vec<u32>,
+    selected_item_id: u32,
+    // Set by decode_item() to parse and decode that item instead of the primary item.
+    requested_item_id: Option<u32>,
     tracks: Vec<Track>,
+    track_descriptions: Vec<TrackDescription>,
+    // Offset of the movie fragments that were not available yet when parsing, if any.
+    fragment_offset: Option<u64>,
     // To replicate the C-API, we need to keep this optional. Otherwise this
     // could be part of the initialization.
     io: Option<GenericIO>,
     codecs: Vec<Codec>,
     color_track_id: Option<u32>,
+    alpha_track_id: Option<u32>,
     parse_state: ParseState,
     io_stats: IOStats,
     compression_format: CompressionFormat,
@@ -474,6 +579,53 @@ impl Decoder {
     pub fn gainmap_present(&self) -> bool {
         self.gainmap_present
     }
+    pub fn depth_map(&self) -> &DepthMap {
+        &self.depth_map
+    }
+    pub fn depth_map_present(&self) -> bool {
+        self.depth_map_present
+    }
+    /// Returns the id of the primary item ('pitm' box), or 0 if there is none.
+    pub fn primary_item_id(&self) -> u32 {
+        self.primary_item_id
+    }
+    /// Returns all the items of the top-level 'meta' box, in 'iinf' order. Empty until parse() is
+    /// successful.
+    pub fn items(&self) -> &[ItemDescription] {
+        &self.item_descriptions
+    }
+    /// Returns all the entity groups of the top-level 'meta' box, in 'grpl' order.
+    pub fn entity_groups(&self) -> &[EntityGroupDescription] {
+        &self.entity_group_descriptions
+    }
+    /// Returns the left and right views of the 'ster' entity group containing the primary item, if
+    /// any. See Settings::stereo_view.
+    pub fn stereo_pair(&self) -> Option<StereoPair> {
+        self.stereo_pair
+    }
+    /// Returns the ids of the 'altr' entity group containing the requested item (the primary item
+    /// or the view selected by Settings::stereo_view), in order of preference. Empty if there is
+    /// none.
+    pub fn alternative_item_ids(&self) -> &[u32] {
+        &self.alternative_item_ids
+    }
+    /// Returns the id of the item that was chosen as the color item, which is the first supported
+    /// item of alternative_item_ids() if not empty. 0 if the source is not an item.
+    pub fn selected_item_id(&self) -> u32 {
+        self.selected_item_id
+    }
+    /// Returns all the tracks of the 'moov' box, in file order. Empty until parse() is successful.
+    pub fn tracks(&self) -> &[TrackDescription] {
+        &self.track_descriptions
+    }
+    /// Returns the ids of the color and alpha tracks that were chosen, if the source is
+    /// Source::Tracks.
+    pub fn color_track_id(&self) -> Option<u32> {
+        self.color_track_id
+    }
+    pub fn alpha_track_id(&self) -> Option<u32> {
+        self.alpha_track_id
+    }
     pub fn io_stats(&self) -> IOStats {
         self.io_stats
     }
@@ -571,6 +723,15 @@ impl Decoder {
         Ok(Some(alpha_item_id))
     }
 
+    fn find_depth_item(&self, color_item_id: u32) -> Option<u32> {
+        self.items
+            .values()
+            .find(|item| {
+                !item.should_skip() && item.aux_for_id == color_item_id && item.is_auxiliary_depth()
+            })
+            .map(|item| item.id)
+    }
+
     fn harvest_and_validate_gainmap_properties(
         &mut self,
         gainmap_id: u32,
@@ -932,11 +1093,7 @@ impl Decoder {
         ftyp: &FileTypeBox,
         meta: &MetaBox,
     ) -> AvifResult<u32> {
-        let altr_group = meta
-            .grpl
-            .iter()
-            .find(|g| g.grouping_type == "altr" && g.entity_ids.contains(&item_id));
-        let item_ids = match altr_group {
+        let item_ids = match meta.find_entity_group("altr", item_id) {
             Some(altr_group) => &altr_group.entity_ids,
             None => &vec![item_id],
         };
@@ -959,6 +1116,27 @@ impl Decoder {
         AvifError::no_content()
     }
 
+    fn find_stereo_view_item(&self, primary_item_id: u32) -> AvifResult<u32> {
+        match self.settings.stereo_view {
+            StereoView::Primary => Ok(primary_item_id),
+            StereoView::Left => Ok(self.stereo_pair.ok_or(AvifError::NoContent)?.left_item_id),
+            StereoView::Right => Ok(self.stereo_pair.ok_or(AvifError::NoContent)?.right_item_id),
+        }
+    }
+
+    fn find_thumbnail_item(&self, primary_item_id: u32) -> AvifResult<u32> {
+        // If there are several thumbnails, use the first one in item id order.
+        self.items
+            .values()
+            .find(|item| {
+                item.thumbnail_for_id == primary_item_id
+                    && !item.should_skip()
+                    && item.is_image_item()
+            })
+            .map(|item| item.id)
+            .ok_or(AvifError::NoContent)
+    }
+
     fn reset(&mut self) {
         let decoder = Decoder::default();
         // Reset all fields to default except the following: settings, io, source.
@@ -972,16 +1150,28 @@ impl Decoder {
         self.repetition_count = decoder.repetition_count;
         self.gainmap = decoder.gainmap;
         self.gainmap_present = decoder.gainmap_present;
+        self.depth_map = decoder.depth_map;
+        self.depth_map_present = decoder.depth_map_present;
         self.image = decoder.image;
         self.extra_inputs = decoder.extra_inputs;
         /* Do not reset 'source' */
         self.tile_info = decoder.tile_info;
         self.tiles = decoder.tiles;
         self.items = decoder.items;
+        self.primary_item_id = decoder.primary_item_id;
+        self.item_descriptions = decoder.item_descriptions;
+        self.entity_group_descriptions = decoder.entity_group_descriptions;
+        self.stereo_pair = decoder.stereo_pair;
+        self.alternative_item_ids = decoder.alternative_item_ids;
+        self.selected_item_id = decoder.selected_item_id;
+        /* Do not reset 'requested_item_id' */
         self.tracks = decoder.tracks;
+        self.track_descriptions = decoder.track_descriptions;
+        self.fragment_offset = decoder.fragment_offset;
         /* Do not reset 'io' */
         self.codecs = decoder.codecs;
         self.color_track_id = decoder.color_track_id;
+        self.alpha_track_id = decoder.alpha_track_id;
         self.parse_state = decoder.parse_state;
         self.io_stats = decoder.io_stats;
         self.compression_format = decoder.compression_format;
@@ -1003,6 +1193,8 @@ impl Decoder {
             self.reset();
             let avif_boxes = mp4box::parse(self.io.unwrap_mut())?;
             self.tracks = avif_boxes.tracks;
+            self.fragment_offset = avif_boxes.fragment_offset;
+            self.track_descriptions = describe_tracks(&self.tracks)?;
             if !self.tracks.is_empty() {
                 self.image.image_sequence_track_present = true;
                 for track in &self.tracks {
@@ -1017,6 +1209,25 @@ impl Decoder {
                 }
             }
             self.items = construct_items(&avif_boxes.meta)?;
+            self.primary_item_id = avif_boxes.meta.primary_item_id;
+            self.item_descriptions = describe_items(&avif_boxes.meta, self.io.unwrap_mut())?;
+            self.entity_group_descriptions = describe_entity_groups(&avif_boxes.meta);
+            if let Some(ster_group) = avif_boxes
+                .meta
+                .find_entity_group("ster", avif_boxes.meta.primary_item_id)
+            {
+                // HEIF (ISO/IEC 23008-12:2022) Section 6.8.5.2: the first entity is the left view
+                // and the second entity is the right view.
+                if ster_group.entity_ids.len() == 2 {
+                    self.stereo_pair = Some(StereoPair {
+                        left_item_id: ster_group.entity_ids[0],
+                        right_item_id: ster_group.entity_ids[1],
+                    });
+                } else if self.settings.stereo_view != StereoView::Primary {
+                    return AvifError::bmff_parse_failed("ster group must have two entities");
+                }
+                // Otherwise the malformed group is ignored since no view was requested.
+            }
             if avif_boxes.ftyp.has_tmap() && !self.items.values().any(|x| x.item_type == "tmap") {
                 return AvifError::bmff_parse_failed("tmap was required but not found");
             }
@@ -1029,6 +1240,15 @@ impl Decoder {
             }
 
             self.source = match self.settings.source {
+                _ if self.requested_item_id.is_some() => Source::PrimaryItem,
+                // The views of a stereo pair are items.
+                Source::Tracks | Source::Thumbnail
+                    if self.settings.stereo_view != StereoView::Primary =>
+                {
+                    return AvifError::invalid_argument();
+                }
+                _ if self.settings.stereo_view != StereoView::Primary => Source::PrimaryItem,
+                Source::Auto if self.settings.color_track_id.is_some() => Source::Tracks,
                 // Decide the source based on the major brand.
                 Source::Auto => match avif_boxes.ftyp.major_brand.as_str() {
                     "avis" => Source::Tracks,
@@ -1043,6 +1263,7 @@ impl Decoder {
                 },
                 Source::Tracks => Source::Tracks,
                 Source::PrimaryItem => Source::PrimaryItem,
+                Source::Thumbnail => Source::Thumbnail,
             };
 
             let color_properties: &Vec<ItemProperty>;
@@ -1050,11 +1271,24 @@ impl Decoder {
             let gainmap_properties: Option<&Vec<ItemProperty>>;
             let mut is_sample_transform = false;
             if self.source == Source::Tracks {
-                let color_track = self
-                    .tracks
-                    .iter()
-                    .find(|x| x.is_color())
-                    .ok_or(AvifError::NoContent)?;
+                let color_track = match self.settings.color_track_id {
+                    Some(track_id) => {
+                        let track = self
+                            .tracks
+                            .iter()
+                            .find(|x| x.id == track_id)
+                            .ok_or(AvifError::NoContent)?;
+                        if !track.is_decodable() {
+                            return AvifError::invalid_argument();
+                        }
+                        track
+                    }
+                    None => self
+                        .tracks
+                        .iter()
+                        .find(|x| x.is_color())
+                        .ok_or(AvifError::NoContent)?,
+                };
                 if let Some(meta) = &color_track.meta {
                     let mut color_track_items = construct_items(meta)?;
                     Self::search_exif_or_xmp_metadata(
@@ -1079,11 +1313,28 @@ impl Decoder {
                 )?);
                 self.tile_info[DecodingItem::COLOR.usize()].tile_count = 1;
 
-                if let Some(alpha_track) = self
-                    .tracks
-                    .iter()
-                    .find(|x| x.is_aux(color_track.id) && x.is_auxiliary_alpha())
-                {
+                let alpha_track = match self.settings.alpha_track_id {
+                    Some(track_id) => {
+                        let track = self
+                            .tracks
+                            .iter()
+                            .find(|x| x.id == track_id)
+                            .ok_or(AvifError::NoContent)?;
+                        if track.id == color_track.id
+                            || !track.is_decodable()
+                            || !track.is_auxiliary_alpha()
+                        {
+                            return AvifError::invalid_argument();
+                        }
+                        Some(track)
+                    }
+                    None => self
+                        .tracks
+                        .iter()
+                        .find(|x| x.is_aux(color_track.id) && x.is_auxiliary_alpha()),
+                };
+                if let Some(alpha_track) = alpha_track {
+                    self.alpha_track_id = Some(alpha_track.id);
                     self.tiles[DecodingItem::ALPHA.usize()].push(Tile::create_from_track(
                         alpha_track,
                         self.settings.image_count_limit,
@@ -1102,6 +1353,34 @@ impl Decoder {
                     alpha_properties = None;
                 }
 
+                if let Some(depth_track) = self
+                    .tracks
+                    .iter()
+                    .find(|x| x.is_aux(color_track.id) && x.is_auxiliary_depth())
+                {
+                    let depth_properties = depth_track
+                        .get_properties()
+                        .ok_or(AvifError::BmffParseFailed("".into()))?;
+                    self.depth_map.representation_info =
+                        depth_representation_info_from_properties(depth_properties)?;
+                    self.depth_map_present = true;
+                    if self.settings.image_content_to_decode.depth_map() {
+                        self.tiles[DecodingItem::DEPTH.usize()].push(Tile::create_from_track(
+                            depth_track,
+                            self.settings.image_count_limit,
+                            self.io.unwrap_ref().size_hint(),
+                            DecodingItem::DEPTH,
+                        )?);
+                        self.tile_info[DecodingItem::DEPTH.usize()].tile_count = 1;
+                        self.depth_map.image.width = depth_track.width;
+                        self.depth_map.image.height = depth_track.height;
+                        self.depth_map.image.depth =
+                            depth_from_properties(depth_properties, "depth map")?;
+                        self.depth_map.image.yuv_format =
+                            pixel_format_from_properties(depth_properties, "depth map")?;
+                    }
+                }
+
                 self.image_index = -1;
                 self.image_count = self.tiles[DecodingItem::COLOR.usize()][0]
                     .input
@@ -1121,7 +1400,7 @@ impl Decoder {
                 }
 
                 self.timescale = color_track.media_timescale as u64;
-                self.duration_in_timescales = color_track.media_duration;
+                self.duration_in_timescales = color_track.presentation_duration()?;
                 if self.timescale != 0 {
                     self.duration = (self.duration_in_timescales as f64) / (self.timescale as f64);
                 } else {
@@ -1133,16 +1412,48 @@ impl Decoder {
                 self.image.width = color_track.width;
                 self.image.height = color_track.height;
             } else {
-                assert_eq!(self.source, Source::PrimaryItem);
+                assert!(matches!(
+                    self.source,
+                    Source::PrimaryItem | Source::Thumbnail
+                ));
                 let mut item_ids: [u32; DecodingItem::COUNT] = [0; DecodingItem::COUNT];
 
-                // Mandatory color item (primary item).
-                let mut primary_item_id = self.find_and_parse_item(
-                    avif_boxes.meta.primary_item_id,
-                    DecodingItem::COLOR,
-                    &avif_boxes.ftyp,
-                    &avif_boxes.meta,
-                )?;
+                // Mandatory color item (primary item or its thumbnail).
+                let requested_item_id = if self.source == Source::Thumbnail {
+                    self.find_thumbnail_item(avif_boxes.meta.primary_item_id)?
+                } else {
+                    self.find_stereo_view_item(avif_boxes.meta.primary_item_id)?
+                };
+                let mut primary_item_id = match self.requested_item_id {
+                    Some(item_id) => {
+                        let item = self
+                            .items
+                            .get(&item_id)
+                            .ok_or(AvifError::MissingImageItem)?;
+                        if !item.is_image_item() {
+                            return AvifError::invalid_argument();
+                        }
+                        if item.should_skip() {
+                            return AvifError::not_implemented();
+                        }
+                        self.read_and_parse_item(item_id, DecodingItem::COLOR)?;
+                        item_id
+                    }
+                    None => {
+                        if let Some(altr_group) =
+                            avif_boxes.meta.find_entity_group("altr", requested_item_id)
+                        {
+                            self.alternative_item_ids = altr_group.entity_ids.clone();
+                        }
+                        self.find_and_parse_item(
+                            requested_item_id,
+                            DecodingItem::COLOR,
+                            &avif_boxes.ftyp,
+                            &avif_boxes.meta,
+                        )?
+                    }
+                };
+                self.selected_item_id = primary_item_id;
                 loop {
                     let primary_item = self.items.get(&primary_item_id).unwrap();
                     if !primary_item.is_identity_item() {
@@ -1232,10 +1543,16 @@ impl Decoder {
                     is_sample_transform = true;
                 }
 
-                // Find exif/xmp from meta if any.
+                // Find exif/xmp from meta if any. Thumbnails share the metadata of the primary
+                // item.
+                let metadata_item_id = if self.source == Source::Thumbnail {
+                    avif_boxes.meta.primary_item_id
+                } else {
+                    item_ids[DecodingItem::COLOR.usize()]
+                };
                 Self::search_exif_or_xmp_metadata(
                     &mut self.items,
-                    Some(item_ids[DecodingItem::COLOR.usize()]),
+                    Some(metadata_item_id),
                     &self.settings,
                     self.io.unwrap_mut(),
                     &mut self.image,
@@ -1258,6 +1575,20 @@ impl Decoder {
                         == alpha_item_id
                 }
 
+                // Optional depth auxiliary item
+                if let Some(depth_item_id) =
+                    self.find_depth_item(item_ids[DecodingItem::COLOR.usize()])
+                {
+                    self.depth_map.representation_info = depth_representation_info_from_properties(
+                        &self.items.get(&depth_item_id).unwrap().properties,
+                    )?;
+                    self.depth_map_present = true;
+                    if self.settings.image_content_to_decode.depth_map() {
+                        self.read_and_parse_item(depth_item_id, DecodingItem::DEPTH)?;
+                        item_ids[DecodingItem::DEPTH.usize()] = depth_item_id;
+                    }
+                }
+
                 self.image_index = -1;
                 self.image_count = 1;
                 self.timescale = 1;
@@ -1343,6 +1674,19 @@ impl Decoder {
                         codec_config.chroma_sample_position();
                 }
 
+                if item_ids[DecodingItem::DEPTH.usize()] != 0 {
+                    let depth_item = self
+                        .items
+                        .get(&item_ids[DecodingItem::DEPTH.usize()])
+                        .unwrap();
+                    self.depth_map.image.width = depth_item.width;
+                    self.depth_map.image.height = depth_item.height;
+                    self.depth_map.image.depth =
+                        depth_from_properties(&depth_item.properties, "depth map")?;
+                    self.depth_map.image.yuv_format =
+                        pixel_format_from_properties(&depth_item.properties, "depth map")?;
+                }
+
                 // This borrow has to be in the end of this branch.
                 color_properties = &self
                     .items
@@ -1567,7 +1911,32 @@ impl Decoder {
         Ok(true)
     }
 
-    fn create_codec(&mut self, decoding_item: DecodingItem, tile_index: usize) -> AvifResult<()> {
+    // Grid cells and items are decoded concurrently (each with its own codec instance) only when
+    // they are all decoded in a single call.
+    fn can_decode_in_parallel(&self) -> bool {
+        self.settings.max_threads > 1
+            && !self.settings.allow_progressive
+            && !self.settings.allow_incremental
+            && !cfg!(feature = "android_mediacodec")
+    }
+
+    // Returns the number of threads each codec instance may use so that the max_threads budget is
+    // shared across the codec instances running concurrently.
+    fn codec_max_threads(&self, codec_count: usize) -> u32 {
+        let max_threads = self.settings.max_threads;
+        if !self.can_decode_in_parallel() || codec_count <= 1 {
+            return max_threads;
+        }
+        let concurrent_codecs = min(max_threads, u32::try_from(codec_count).unwrap_or(u32::MAX));
+        max(1, max_threads / concurrent_codecs)
+    }
+
+    fn create_codec(
+        &mut self,
+        decoding_item: DecodingItem,
+        tile_index: usize,
+        max_threads: u32,
+    ) -> AvifResult<()> {
         let tile = &self.tiles[decoding_item.usize()][tile_index];
         let mut codec: Codec = match self
             .settings
@@ -1582,12 +1951,12 @@ impl Decoder {
             all_layers: tile.input.all_layers,
             width: tile.width,
             height: tile.height,
-            depth: if decoding_item.category == Category::Gainmap {
-                self.gainmap.image.depth
-            } else {
-                self.image.depth
+            depth: match decoding_item.category {
+                Category::Gainmap => self.gainmap.image.depth,
+                Category::Depth => self.depth_map.image.depth,
+                _ => self.image.depth,
             },
-            max_threads: self.settings.max_threads,
+            max_threads,
             image_size_limit: self.settings.image_size_limit,
             max_input_size: tile.max_sample_size(),
             codec_config: tile.codec_config.clone(),
@@ -1612,28 +1981,44 @@ impl Decoder {
             //  2) If android_mediacodec is true, then we will use at most three codec instances
             //     (one for each category).
             self.codecs = create_vec_exact(3)?;
-            for decoding_item in self.settings.image_content_to_decode.decoding_items() {
-                if self.tiles[decoding_item.usize()].is_empty() {
-                    continue;
-                }
-                self.create_codec(decoding_item, 0)?;
+            let decoding_items: Vec<_> = self
+                .settings
+                .image_content_to_decode
+                .decoding_items()
+                .into_iter()
+                .filter(|decoding_item| !self.tiles[decoding_item.usize()].is_empty())
+                .collect();
+            let max_threads = self.codec_max_threads(decoding_items.len());
+            for decoding_item in decoding_items {
+                self.create_codec(decoding_item, 0, max_threads)?;
                 for tile in &mut self.tiles[decoding_item.usize()] {
                     tile.codec_index = self.codecs.len() - 1;
                 }
             }
         } else if self.can_use_single_codec()? {
-            self.codecs = create_vec_exact(1)?;
-            self.create_codec(DecodingItem::COLOR, 0)?;
-            for tiles in &mut self.tiles {
-                for tile in tiles {
-                    tile.codec_index = 0;
-                }
+            let tile_count: usize = self.tiles.iter().map(|tiles| tiles.len()).sum();
+            // When decoding in parallel, use a pool of interchangeable codec instances instead
+            // of a single one and distribute the tiles among them.
+            let codec_count = if self.can_decode_in_parallel() {
+                min(usize_from_u32(self.settings.max_threads)?, tile_count)
+            } else {
+                1
+            };
+            let max_threads = self.codec_max_threads(codec_count);
+            self.codecs = create_vec_exact(codec_count)?;
+            for _ in 0..codec_count {
+                self.create_codec(DecodingItem::COLOR, 0, max_threads)?;
+            }
+            for (index, tile) in self.tiles.iter_mut().flatten().enumerate() {
+                tile.codec_index = index % codec_count;
             }
         } else {
-            self.codecs = create_vec_exact(self.tiles.iter().map(|tiles| tiles.len()).sum())?;
+            let tile_count: usize = self.tiles.iter().map(|tiles| tiles.len()).sum();
+            let max_threads = self.codec_max_threads(tile_count);
+            self.codecs = create_vec_exact(tile_count)?;
             for decoding_item in self.settings.image_content_to_decode.decoding_items() {
                 for tile_index in 0..self.tiles[decoding_item.usize()].len() {
-                    self.create_codec(decoding_item, tile_index)?;
+                    self.create_codec(decoding_item, tile_index, max_threads)?;
                     self.tiles[decoding_item.usize()][tile_index].codec_index =
                         self.codecs.len() - 1;
                 }
@@ -1738,11 +2123,7 @@ impl Decoder {
                 // category.
                 tile_index == self.tiles[decoding_item.usize()].len() - 1
             };
-        // Split the tiles array into two mutable arrays so that we can validate the
-        // properties of tiles with index > 0 with that of the first tile.
-        let (tiles_slice1, tiles_slice2) =
-            self.tiles[decoding_item.usize()].split_at_mut(tile_index);
-        let tile = &mut tiles_slice2[0];
+        let tile = &mut self.tiles[decoding_item.usize()][tile_index];
         let sample = &tile.input.samples[image_index];
         let io = &mut self.io.unwrap_mut();
         let category = decoding_item.category;
@@ -1782,6 +2163,22 @@ impl Decoder {
                 return next_image_result;
             }
         }
+        self.copy_decoded_tile(decoding_item, tile_index)
+    }
+
+    // Post-processes the image decoded for the given tile and copies it into the image of the
+    // corresponding category.
+    fn copy_decoded_tile(
+        &mut self,
+        decoding_item: DecodingItem,
+        tile_index: usize,
+    ) -> AvifResult<()> {
+        // Split the tiles array into two mutable arrays so that we can validate the
+        // properties of tiles with index > 0 with that of the first tile.
+        let (tiles_slice1, tiles_slice2) =
+            self.tiles[decoding_item.usize()].split_at_mut(tile_index);
+        let tile = &mut tiles_slice2[0];
+        let category = decoding_item.category;
 
         checked_incr!(self.tile_info[decoding_item.usize()].decoded_tile_count, 1);
 
@@ -1794,6 +2191,7 @@ impl Decoder {
             Category::Color | Category::Alpha if (decoding_item.item_idx == 0) => &mut self.image,
             Category::Color | Category::Alpha => &mut self.extra_inputs[decoding_item.item_idx - 1],
             Category::Gainmap => &mut self.gainmap.image,
+            Category::Depth => &mut self.depth_map.image,
         };
 
         if self.tile_info[decoding_item.usize()].is_grid() {
@@ -1805,7 +2203,7 @@ impl Decoder {
                 let grid = &self.tile_info[decoding_item.usize()].grid;
                 validate_grid_image_dimensions(&tile.image, grid)?;
                 match category {
-                    Category::Color | Category::Gainmap => {
+                    Category::Color | Category::Gainmap | Category::Depth => {
                         dst_image.width = grid.width;
                         dst_image.height = grid.height;
                         dst_image.copy_properties_from(&tile.image, &tile.codec_config);
@@ -1838,7 +2236,7 @@ impl Decoder {
                 let canvas_fill_values =
                     dst_image.convert_rgba16_to_yuva(overlay.canvas_fill_value);
                 match category {
-                    Category::Color | Category::Gainmap => {
+                    Category::Color | Category::Gainmap | Category::Depth => {
                         dst_image.width = overlay.width;
                         dst_image.height = overlay.height;
                         dst_image.copy_properties_from(&tile.image, &tile.codec_config);
@@ -1854,10 +2252,9 @@ impl Decoder {
                 }
             }
             if !tiles_slice1.is_empty() {
+                // The tiles of an overlay may have different dimensions.
                 let first_tile_image = &tiles_slice1[0].image;
-                if tile.image.width != first_tile_image.width
-                    || tile.image.height != first_tile_image.height
-                    || tile.image.depth != first_tile_image.depth
+                if tile.image.depth != first_tile_image.depth
                     || tile.image.yuv_format != first_tile_image.yuv_format
                     || tile.image.yuv_range != first_tile_image.yuv_range
                     || tile.image.color_primaries != first_tile_image.color_primaries
@@ -1895,7 +2292,7 @@ impl Decoder {
             //   Decoder::image which owns its buffer.
 
             match category {
-                Category::Color | Category::Gainmap => {
+                Category::Color | Category::Gainmap | Category::Depth => {
                     dst_image.width = tile.image.width;
                     dst_image.height = tile.image.height;
                     dst_image.copy_properties_from(&tile.image, &tile.codec_config);
@@ -1983,10 +2380,10 @@ impl Decoder {
         let category = decoding_item.category;
         let mut grid_image_helper = GridImageHelper {
             grid,
-            image: if category == Category::Gainmap {
-                &mut self.gainmap.image
-            } else {
-                &mut self.image
+            image: match category {
+                Category::Gainmap => &mut self.gainmap.image,
+                Category::Depth => &mut self.depth_map.image,
+                _ => &mut self.image,
             },
             category,
             cell_index: previous_decoded_tile_count,
@@ -2046,7 +2443,118 @@ impl Decoder {
             && cfg!(feature = "dav1d")
     }
 
+    // Returns the tiles that remain to be decoded for the current frame, in decoding order.
+    fn pending_tiles(&self) -> Vec<(DecodingItem, usize)> {
+        let mut pending_tiles = Vec::new();
+        for decoding_item in self.settings.image_content_to_decode.decoding_items() {
+            let decoded_tile_count =
+                self.tile_info[decoding_item.usize()].decoded_tile_count as usize;
+            for tile_index in decoded_tile_count..self.tiles[decoding_item.usize()].len() {
+                pending_tiles.push((decoding_item, tile_index));
+            }
+        }
+        pending_tiles
+    }
+
+    // Decodes the given tiles concurrently on up to max_threads threads. Tiles that share a codec
+    // instance are decoded sequentially on the same thread. The decoded images are then copied
+    // into their destination images in order.
+    fn decode_tiles_in_parallel(
+        &mut self,
+        image_index: usize,
+        pending_tiles: &[(DecodingItem, usize)],
+    ) -> AvifResult<()> {
+        // The payloads are read upfront since the IO cannot be shared across threads.
+        let mut payloads: Vec<Vec<u8>> = create_vec_exact(pending_tiles.len())?;
+        for (decoding_item, tile_index) in pending_tiles {
+            let sample = &self.tiles[decoding_item.usize()][*tile_index].input.samples[image_index];
+            let item = if sample.item_id == 0 { None } else { self.items.get(&sample.item_id) };
+            let data_buffer = if let Some(item) = item { &item.data_buffer } else { &None };
+            payloads.push(sample.data(self.io.unwrap_mut(), data_buffer)?.to_vec());
+        }
+
+        let mut tiles: Vec<Vec<Option<&mut Tile>>> = self
+            .tiles
+            .iter_mut()
+            .map(|tiles| tiles.iter_mut().map(Some).collect())
+            .collect();
+        let mut codec_jobs: Vec<CodecJobs> = self
+            .codecs
+            .iter_mut()
+            .map(|codec| CodecJobs {
+                codec,
+                tiles: Vec::new(),
+            })
+            .collect();
+        for ((decoding_item, tile_index), data) in pending_tiles.iter().zip(&payloads) {
+            let tile =
+                tiles[decoding_item.usize()][*tile_index]
+                    .take()
+                    .ok_or(AvifError::UnknownError(
+                        "tile scheduled for decoding more than once".into(),
+                    ))?;
+            let sample = &tile.input.samples[image_index];
+            let item = if sample.item_id == 0 { None } else { self.items.get(&sample.item_id) };
+            codec_jobs
+                .get_mut(tile.codec_index)
+                .ok_or(AvifError::UnknownError(
+                    "tile refers to a codec that was not created".into(),
+                ))?
+                .tiles
+                .push(TileJob {
+                    spatial_id: sample.spatial_id,
+                    tile,
+                    category: decoding_item.category,
+                    data,
+                    item,
+                });
+        }
+        codec_jobs.retain(|jobs| !jobs.tiles.is_empty());
+
+        let thread_count = min(usize_from_u32(self.settings.max_threads)?, codec_jobs.len());
+        let mut workers: Vec<Vec<CodecJobs>> = (0..thread_count).map(|_| Vec::new()).collect();
+        for (index, jobs) in codec_jobs.into_iter().enumerate() {
+            workers[index % thread_count].push(jobs);
+        }
+        let results: Vec<AvifResult<()>> = std::thread::scope(|scope| {
+            let handles: Vec<_> = workers
+                .into_iter()
+                .map(|jobs| scope.spawn(move || CodecJobs::decode_all(jobs)))
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| {
+                    handle.join().unwrap_or(Err(AvifError::UnknownError(
+                        "decoding thread panicked".into(),
+                    )))
+                })
+                .collect()
+        });
+        for result in results {
+            result?;
+        }
+
+        for (decoding_item, tile_index) in pending_tiles {
+            self.copy_decoded_tile(*decoding_item, *tile_index)?;
+        }
+        Ok(())
+    }
+
     fn decode_tiles(&mut self, image_index: usize) -> AvifResult<()> {
+        if self.can_decode_in_parallel() {
+            let pending_tiles = self.pending_tiles();
+            let mut codec_indices: Vec<_> = pending_tiles
+                .iter()
+                .map(|(decoding_item, tile_index)| {
+                    self.tiles[decoding_item.usize()][*tile_index].codec_index
+                })
+                .collect();
+            codec_indices.sort_unstable();
+            codec_indices.dedup();
+            if codec_indices.len() > 1 {
+                return self.decode_tiles_in_parallel(image_index, &pending_tiles);
+            }
+        }
         let mut decoded_something = false;
         for decoding_item in self.settings.image_content_to_decode.decoding_items() {
             let tile_count = self.tiles[decoding_item.usize()].len();
@@ -2108,6 +2616,9 @@ impl Decoder {
         }
 
         let next_image_index = checked_add!(self.image_index, 1)?;
+        if next_image_index >= i32_from_u32(self.image_count)? {
+            self.parse_more_fragments()?;
+        }
         self.create_codecs()?;
         match (
             self.settings.allow_progressive,
@@ -2138,6 +2649,77 @@ impl Decoder {
         Ok(())
     }
 
+    // Parses the movie fragments that became available since parse() or the previous call, if
+    // any, and makes their samples available for decoding.
+    fn parse_more_fragments(&mut self) -> AvifResult<()> {
+        let Some(offset) = self.fragment_offset else {
+            return Ok(());
+        };
+        if self.source != Source::Tracks {
+            return Ok(());
+        }
+        self.fragment_offset =
+            mp4box::parse_fragments(self.io.unwrap_mut(), offset, &mut self.tracks)?;
+        if self.fragment_offset == Some(offset) {
+            // No new fragment.
+            return Ok(());
+        }
+        let Some(color_track_id) = self.color_track_id else {
+            return Ok(());
+        };
+        let size_hint = self.io.unwrap_ref().size_hint();
+        for decoding_item in [
+            DecodingItem::COLOR,
+            DecodingItem::ALPHA,
+            DecodingItem::DEPTH,
+        ] {
+            if self.tiles[decoding_item.usize()].is_empty() {
+                continue;
+            }
+            let track = match decoding_item {
+                DecodingItem::COLOR => self.tracks.iter().find(|x| x.id == color_track_id),
+                DecodingItem::ALPHA => self
+                    .tracks
+                    .iter()
+                    .find(|x| Some(x.id) == self.alpha_track_id),
+                _ => self
+                    .tracks
+                    .iter()
+                    .find(|x| x.is_aux(color_track_id) && x.is_auxiliary_depth()),
+            }
+            .ok_or(AvifError::UnknownError(
+                "decoded track not found after parsing fragments".into(),
+            ))?;
+            let tile = Tile::create_from_track(
+                track,
+                self.settings.image_count_limit,
+                size_hint,
+                decoding_item,
+            )?;
+            self.tiles[decoding_item.usize()][0].input.samples = tile.input.samples;
+        }
+        // Only the frames whose samples are available in all the decoded tracks can be decoded.
+        self.image_count = self
+            .tiles
+            .iter()
+            .filter(|tiles| !tiles.is_empty())
+            .map(|tiles| u32_from_usize(tiles[0].input.samples.len()))
+            .collect::<AvifResult<Vec<_>>>()?
+            .into_iter()
+            .min()
+            .unwrap_or(0);
+        let color_track = self
+            .tracks
+            .iter()
+            .find(|x| x.id == color_track_id)
+            .ok_or(AvifError::NoContent)?;
+        self.duration_in_timescales = color_track.presentation_duration()?;
+        if self.timescale != 0 {
+            self.duration = (self.duration_in_timescales as f64) / (self.timescale as f64);
+        }
+        Ok(())
+    }
+
     fn is_current_frame_fully_decoded(&self) -> bool {
         if !self.parsing_complete() {
             return false;
@@ -2155,7 +2737,10 @@ impl Decoder {
             return AvifError::no_content();
         }
         if index >= self.image_count {
-            return AvifError::no_images_remaining();
+            self.parse_more_fragments()?;
+            if index >= self.image_count {
+                return AvifError::no_images_remaining();
+            }
         }
         let requested_index = i32_from_u32(index)?;
         if requested_index == checked_add!(self.image_index, 1)? {
@@ -2238,6 +2823,165 @@ impl Decoder {
         Ok(())
     }
 
+    // Decodes the cells of the grid of decoding_item that intersect rect and copies them into
+    // region.
+    fn decode_region_cells(
+        &mut self,
+        decoding_item: DecodingItem,
+        rect: &CropRect,
+        region: &mut Image,
+    ) -> AvifResult<()> {
+        let category = decoding_item.category;
+        let grid = self.tile_info[decoding_item.usize()].grid;
+        let cell_width = self.tiles[decoding_item.usize()][0].width;
+        let cell_height = self.tiles[decoding_item.usize()][0].height;
+        if cell_width == 0 || cell_height == 0 {
+            return AvifError::invalid_image_grid("invalid grid cell dimensions");
+        }
+        let columns = (rect.x / cell_width)..=((rect.x + rect.width - 1) / cell_width);
+        let rows = (rect.y / cell_height)..=((rect.y + rect.height - 1) / cell_height);
+        let mut cells = Vec::new();
+        for row in rows {
+            for column in columns.clone() {
+                if row >= grid.rows || column >= grid.columns {
+                    return AvifError::invalid_image_grid("grid cells do not cover the image");
+                }
+                let tile_index =
+                    usize_from_u32(checked_add!(checked_mul!(row, grid.columns)?, column)?)?;
+                cells.push((row, column, tile_index));
+            }
+        }
+        let first_tile_index = cells[0].2;
+        for (cell_index, &(row, column, tile_index)) in cells.iter().enumerate() {
+            self.prepare_sample(0, decoding_item, tile_index, None)?;
+            // signal_eos is used only by Android MediaCodec.
+            let signal_eos = cfg!(feature = "android_mediacodec") && cell_index == cells.len() - 1;
+            let (tiles_slice1, tiles_slice2) =
+                self.tiles[decoding_item.usize()].split_at_mut(tile_index);
+            let tile = &mut tiles_slice2[0];
+            let sample = &tile.input.samples[0];
+            let item = if sample.item_id == 0 { None } else { self.items.get(&sample.item_id) };
+            let data_buffer = if let Some(item) = item { &item.data_buffer } else { &None };
+            let data = sample.data(self.io.unwrap_mut(), data_buffer)?;
+            self.codecs[tile.codec_index].get_next_image(
+                data,
+                sample.spatial_id,
+                &mut tile.image,
+                category,
+                item,
+                signal_eos,
+            )?;
+            if category == Category::Alpha && tile.image.yuv_range == YuvRange::Limited {
+                tile.image.alpha_to_full_range()?;
+            }
+            tile.image.scale(tile.width, tile.height, category)?;
+            if cell_index == 0 {
+                validate_grid_image_dimensions(&tile.image, &grid)?;
+                if category != Category::Alpha {
+                    region.copy_properties_from(&tile.image, &tile.codec_config);
+                }
+                region.allocate_planes(category)?;
+            } else if !tile
+                .image
+                .has_same_properties_and_cicp(&tiles_slice1[first_tile_index].image)
+            {
+                return AvifError::invalid_image_grid("grid image contains mismatched tiles");
+            }
+            region.copy_from_region(
+                &tile.image,
+                checked_mul!(column, cell_width)?,
+                checked_mul!(row, cell_height)?,
+                rect,
+                category,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Decodes the region `rect` of the first image and returns it as a new image. The
+    /// coordinates of `rect` are in the output image, after the transformative properties (clap,
+    /// irot, imir) are applied (see [`Image::transformed_image`]). The returned image has these
+    /// properties applied to its pixels, so it has no clean aperture, rotation nor mirroring.
+    ///
+    /// For grid images, only the grid cells that intersect `rect` are decoded and
+    /// [`Decoder::image`] is left untouched. Other images are fully decoded with
+    /// [`Decoder::nth_image`] before being cropped. Gain maps are not decoded.
+    pub fn decode_region(&mut self, rect: &CropRect) -> AvifResult<Image> {
+        if self.io.is_none() {
+            return AvifError::io_not_set();
+        }
+        if !self.parsing_complete() {
+            return AvifError::no_content();
+        }
+        let is_transformed = self.image.clap.is_some()
+            || self.image.irot_angle.is_some()
+            || self.image.imir_axis.is_some();
+        // Region of the reconstructed image that contains the pixels of rect.
+        let source = if is_transformed {
+            self.image.transformed_region_source(rect)?
+        } else {
+            if !rect.is_valid(self.image.width, self.image.height, self.image.yuv_format) {
+                return AvifError::invalid_argument();
+            }
+            *rect
+        };
+        let decoding_items: Vec<DecodingItem> = [DecodingItem::COLOR, DecodingItem::ALPHA]
+            .into_iter()
+            .filter(|decoding_item| {
+                self.settings
+                    .image_content_to_decode
+                    .decoding_items()
+                    .contains(decoding_item)
+                    && !self.tiles[decoding_item.usize()].is_empty()
+            })
+            .collect();
+        if !decoding_items.contains(&DecodingItem::COLOR) {
+            return AvifError::no_content();
+        }
+        let decode_cells = self.image_count == 1
+            && !self.tile_info[DecodingItem::COLOR.usize()].is_sample_transform()
+            && decoding_items
+                .iter()
+                .all(|decoding_item| self.tile_info[decoding_item.usize()].is_grid());
+        if !decode_cells {
+            self.nth_image(0)?;
+        }
+        let mut region = self.image.shallow_clone();
+        region.width = source.width;
+        region.height = source.height;
+        region.clap = None;
+        if decode_cells {
+            self.create_codecs()?;
+            for decoding_item in decoding_items {
+                self.decode_region_cells(decoding_item, &source, &mut region)?;
+            }
+        } else {
+            for category in [Category::Color, Category::Alpha] {
+                if self.image.has_plane(category.planes()[0]) {
+                    region.allocate_planes(category)?;
+                    region.copy_from_region(&self.image, 0, 0, &source, category)?;
+                }
+            }
+        }
+        if is_transformed {
+            // Properties of the whole image the region belongs to.
+            let mut image = region.shallow_clone();
+            image.width = self.image.width;
+            image.height = self.image.height;
+            image.clap = self.image.clap;
+            region = image.transformed_image_region(&region, &source, rect)?;
+        }
+        region.exif = self.image.exif.try_clone()?;
+        region.xmp = self.image.xmp.try_clone()?;
+        region.icc = self.image.icc.try_clone()?;
+        if is_transformed && (self.image.irot_angle.is_some() || self.image.imir_axis.is_some()) {
+            // The orientation is now baked into the pixels. Ignore failures since Exif metadata
+            // without an orientation tag is fine.
+            let _ = exif::set_orientation(&mut region.exif, 1);
+        }
+        Ok(region)
+    }
+
     pub fn image(&self) -> Option<&Image> {
         if self.parsing_complete() {
             Some(&self.image)
@@ -2291,27 +3035,31 @@ impl Decoder {
                 continue;
             }
             let first_tile_height = self.tiles[decoding_item_usize][0].height;
-            let row_count = if decoding_item.category == Category::Gainmap
-                && self.gainmap_present()
-                && self.settings.image_content_to_decode.gainmap()
-                && self.gainmap.image.height != 0
-                && self.gainmap.image.height != self.image.height
-            {
+            // The gain map and the depth map may have different dimensions than the color image.
+            let item_height = match decoding_item.category {
+                Category::Gainmap
+                    if self.gainmap_present()
+                        && self.settings.image_content_to_decode.gainmap() =>
+                {
+                    self.gainmap.image.height
+                }
+                Category::Depth => self.depth_map.image.height,
+                _ => self.image.height,
+            };
+            let row_count = if item_height != 0 && item_height != self.image.height {
                 if self.tile_info[decoding_item_usize].is_fully_decoded() {
                     self.image.height
                 } else {
-                    let gainmap_row_count = self.tile_info[decoding_item_usize]
-                        .decoded_row_count(self.gainmap.image.height, first_tile_height);
+                    let item_row_count = self.tile_info[decoding_item_usize]
+                        .decoded_row_count(item_height, first_tile_height);
                     // row_count fits for sure in 32 bits because heights do.
-                    let row_count = (gainmap_row_count as u64 * self.image.height as u64
-                        / self.gainmap.image.height as u64)
-                        as u32;
+                    let row_count = (item_row_count as u64 * self.image.height as u64
+                        / item_height as u64) as u32;
 
                     // Make sure it satisfies the C API guarantee.
                     assert!(
-                        gainmap_row_count
-                            >= (row_count as f32 / self.image.height as f32
-                                * self.gainmap.image.height as f32)
+                        item_row_count
+                            >= (row_count as f32 / self.image.height as f32 * item_height as f32)
                                 .round() as u32
                     );
                     row_count
@@ -2341,6 +3089,56 @@ impl Decoder {
         true
     }
 
+    /// Returns the encoded payloads of the `category` image at `index` (frame of an image sequence
+    /// or layer of a progressive image), without decoding them. There is one payload per tile,
+    /// that is per cell in raster order for grid images. AV1 payloads can be converted into a
+    /// bitstream with [`crate::utils::av1::join_temporal_units`].
+    pub fn encoded_payloads(&mut self, category: Category, index: u32) -> AvifResult<Vec<Vec<u8>>> {
+        if !self.parsing_complete() {
+            return AvifError::no_content();
+        }
+        let decoding_item = match category {
+            Category::Color => DecodingItem::COLOR,
+            Category::Alpha => DecodingItem::ALPHA,
+            Category::Gainmap => DecodingItem::GAINMAP,
+            Category::Depth => DecodingItem::DEPTH,
+        };
+        let image_index = usize_from_u32(index)?;
+        let tile_count = self.tiles[decoding_item.usize()].len();
+        if tile_count == 0 {
+            return AvifError::no_content();
+        }
+        let mut payloads: Vec<Vec<u8>> = create_vec_exact(tile_count)?;
+        for tile_index in 0..tile_count {
+            self.prepare_sample(image_index, decoding_item, tile_index, None)?;
+            let sample = &self.tiles[decoding_item.usize()][tile_index].input.samples[image_index];
+            let item = if sample.item_id == 0 { None } else { self.items.get(&sample.item_id) };
+            let data_buffer = if let Some(item) = item { &item.data_buffer } else { &None };
+            payloads.push(sample.data(self.io.unwrap_mut(), data_buffer)?.to_vec());
+        }
+        Ok(payloads)
+    }
+
+    /// Decodes the image item `item_id` (one of [`Decoder::items()`]) as if it was the primary
+    /// item, with its alpha auxiliary image if any, and returns a copy of it. The item can for
+    /// example be a thumbnail, a hidden item or an item of a multi-image container.
+    ///
+    /// The decoder is parsed again afterwards, so any previously decoded image is discarded and
+    /// the next call to next_image() returns the first image again.
+    pub fn decode_item(&mut self, item_id: u32) -> AvifResult<Image> {
+        if !self.parsing_complete() {
+            return AvifError::no_content();
+        }
+        self.requested_item_id = Some(item_id);
+        let image = self
+            .parse()
+            .and_then(|_| self.next_image())
+            .and_then(|_| self.image.try_deep_clone());
+        self.requested_item_id = None;
+        self.parse()?;
+        image
+    }
+
     pub fn nearest_keyframe(&self, mut index: u32) -> u32 {
         if !self.parsing_complete() {
             return 0;
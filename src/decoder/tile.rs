@@ -14,8 +14,11 @@
 
 use crate::decoder::*;
 use crate::internal_utils::sampletransform::*;
+use crate::utils::clap::CropRect;
 use crate::*;
 
+use std::cmp::max;
+use std::cmp::min;
 use std::num::NonZero;
 
 #[derive(Debug, Default)]
@@ -431,6 +434,68 @@ impl Image {
         Ok(())
     }
 
+    // Copies the pixels of `src` that are within `rect` into self, which has the dimensions of
+    // `rect`. `src` is located at (`src_x`, `src_y`) in the coordinate system of `rect`.
+    pub(crate) fn copy_from_region(
+        &mut self,
+        src: &Image,
+        src_x: u32,
+        src_y: u32,
+        rect: &CropRect,
+        category: Category,
+    ) -> AvifResult<()> {
+        let x0 = max(rect.x, src_x);
+        let y0 = max(rect.y, src_y);
+        let x1 = min(
+            checked_add!(rect.x, rect.width)?,
+            checked_add!(src_x, src.width)?,
+        );
+        let y1 = min(
+            checked_add!(rect.y, rect.height)?,
+            checked_add!(src_y, src.height)?,
+        );
+        if x0 >= x1 || y0 >= y1 {
+            return Ok(());
+        }
+        let yuv_format = self.yuv_format;
+        for plane in category.planes() {
+            let plane = *plane;
+            if !src.has_plane(plane) || !self.has_plane(plane) {
+                continue;
+            }
+            let is_chroma = plane == Plane::U || plane == Plane::V;
+            // Converts luma coordinates to plane coordinates. Exclusive ends are rounded up.
+            let plane_x = |x: u32| if is_chroma { yuv_format.apply_chroma_shift_x(x) } else { x };
+            let plane_y = |y: u32| if is_chroma { yuv_format.apply_chroma_shift_y(y) } else { y };
+            let (round_x, round_y) = if is_chroma {
+                (
+                    (1 << yuv_format.chroma_shift_x().0) - 1,
+                    (1 << yuv_format.chroma_shift_y()) - 1,
+                )
+            } else {
+                (0, 0)
+            };
+            let x_start = plane_x(x0);
+            let x_end = plane_x(checked_add!(x1, round_x)?);
+            let src_x_start = usize_from_u32(x_start - plane_x(src_x))?;
+            let src_x_end = usize_from_u32(x_end - plane_x(src_x))?;
+            let dst_x_start = usize_from_u32(x_start - plane_x(rect.x))?;
+            let dst_x_end = usize_from_u32(x_end - plane_x(rect.x))?;
+            for y in plane_y(y0)..plane_y(checked_add!(y1, round_y)?) {
+                let src_y = y - plane_y(src_y);
+                let dst_y = y - plane_y(rect.y);
+                if self.depth == 8 {
+                    self.row_mut(plane, dst_y)?[dst_x_start..dst_x_end]
+                        .copy_from_slice(&src.row(plane, src_y)?[src_x_start..src_x_end]);
+                } else {
+                    self.row16_mut(plane, dst_y)?[dst_x_start..dst_x_end]
+                        .copy_from_slice(&src.row16(plane, src_y)?[src_x_start..src_x_end]);
+                }
+            }
+        }
+        Ok(())
+    }
+
     pub(crate) fn copy_and_overlay_from_tile(
         &mut self,
         tile: &Image,
@@ -19,11 +19,25 @@ use crate::*;
 
 use std::num::NonZero;
 
+// Section 8.6.6.2 of ISO/IEC 14496-12.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct EditListEntry {
+    /// Duration of this edit in movie timescale units.
+    pub segment_duration: u64,
+    /// Starting time within the media of this edit in media timescale units, or -1 for an empty
+    /// edit.
+    pub media_time: i64,
+    pub media_rate_integer: i16,
+    pub media_rate_fraction: i16,
+}
+
 #[derive(Debug, Default)]
 pub struct Track {
     pub id: u32,
     pub aux_for_id: Option<u32>,
     pub prem_by_id: Option<u32>,
+    // Timescale of the 'mvhd' box, used by the 'tkhd' and 'elst' boxes.
+    pub movie_timescale: u32,
     pub media_timescale: u32,
     pub media_duration: u64,
     pub track_duration: u64,
@@ -33,8 +47,12 @@ pub struct Track {
     pub height: u32,
     pub sample_table: Option<SampleTable>,
     pub elst_seen: bool,
+    pub edit_list: Vec<EditListEntry>,
     pub meta: Option<MetaBox>,
     pub handler_type: String,
+    // Some if the 'moov' box has an 'mvex' box with a 'trex' box for this track, in which case
+    // samples of this track may be found in movie fragments.
+    pub track_extends: Option<TrackExtends>,
 }
 
 impl Track {
@@ -57,24 +75,30 @@ impl Track {
         // Handler types known to be associated with video content.
         self.handler_type == "pict" || self.handler_type == "vide" || self.handler_type == "auxv"
     }
+    pub(crate) fn is_decodable(&self) -> bool {
+        self.is_video_handler() && self.has_av1_samples()
+    }
     pub(crate) fn is_aux(&self, primary_track_id: u32) -> bool {
-        self.is_video_handler()
-            && self.has_av1_samples()
-            && self.aux_for_id == Some(primary_track_id)
+        self.is_decodable() && self.aux_for_id == Some(primary_track_id)
     }
     pub(crate) fn is_color(&self) -> bool {
-        self.is_video_handler() && self.has_av1_samples() && self.aux_for_id.is_none()
+        self.is_decodable() && self.aux_for_id.is_none()
     }
 
     pub(crate) fn is_auxiliary_alpha(&self) -> bool {
         if let Some(properties) = self.get_properties() {
             if let Some(aux_type) = &find_property!(properties, AuxiliaryType) {
-                return is_auxiliary_type_alpha(aux_type);
+                return is_auxiliary_type_alpha(&aux_type.aux_type);
             }
         }
         true // Assume alpha if no type is present
     }
 
+    pub(crate) fn is_auxiliary_depth(&self) -> bool {
+        matches!(self.get_properties().and_then(|properties| find_property!(properties, AuxiliaryType)),
+                 Some(aux_type) if is_auxiliary_type_depth(&aux_type.aux_type))
+    }
+
     pub(crate) fn get_properties(&self) -> Option<&Vec<ItemProperty>> {
         self.sample_table.as_ref()?.get_properties()
     }
@@ -111,6 +135,68 @@ impl Track {
         Ok(RepetitionCount::Finite(0))
     }
 
+    pub(crate) fn append_run(&mut self, run: &TrackRun) -> AvifResult<()> {
+        let Some(sample_table) = &mut self.sample_table else {
+            return AvifError::bmff_parse_failed("track fragment found for a track without stbl");
+        };
+        sample_table.append_run(run)?;
+        for sample in &run.samples {
+            checked_incr!(self.media_duration, sample.duration as u64);
+        }
+        Ok(())
+    }
+
+    fn movie_to_media_duration(&self, duration: u64) -> AvifResult<u64> {
+        // movie_timescale is checked to be non-zero by the callers.
+        u64_from_u128(
+            duration as u128 * self.media_timescale as u128 / self.movie_timescale as u128,
+        )
+    }
+
+    // Returns the first edit that maps media time to presentation time, if any, along with the
+    // duration of the empty edits preceding it in media timescale units. Only edit lists made of
+    // empty edits followed by a single edit at normal rate are honored. Other edit lists are
+    // ignored (in that case, the presentation time is the composition time).
+    fn first_media_edit(&self) -> AvifResult<Option<(&EditListEntry, u64)>> {
+        if self.movie_timescale == 0 {
+            return Ok(None);
+        }
+        let mut empty_duration: u64 = 0;
+        for (index, edit) in self.edit_list.iter().enumerate() {
+            // Section 8.6.6.3 of ISO/IEC 14496-12: media_time equal to -1 specifies an empty edit.
+            if edit.media_time == -1 {
+                checked_incr!(empty_duration, edit.segment_duration);
+                continue;
+            }
+            if edit.media_time < 0
+                || edit.media_rate_integer != 1
+                || edit.media_rate_fraction != 0
+                || index != self.edit_list.len() - 1
+            {
+                return Ok(None);
+            }
+            return Ok(Some((edit, self.movie_to_media_duration(empty_duration)?)));
+        }
+        Ok(None)
+    }
+
+    // Returns the duration of a single presentation of the track in media timescale units, which
+    // is the duration of the edit list if it is honored, and the duration of the media otherwise.
+    // Repetitions are signalled by repetition_count().
+    pub(crate) fn presentation_duration(&self) -> AvifResult<u64> {
+        let Some((edit, empty_duration)) = self.first_media_edit()? else {
+            return Ok(self.media_duration);
+        };
+        let edit_duration = if edit.segment_duration == 0 {
+            // A segment_duration of 0 extends the edit to the end of the media, for example when
+            // the duration is not known in advance in fragmented files.
+            self.media_duration.saturating_sub(edit.media_time as u64)
+        } else {
+            self.movie_to_media_duration(edit.segment_duration)?
+        };
+        checked_add!(empty_duration, edit_duration)
+    }
+
     pub(crate) fn image_timing(&self, image_index: u32) -> AvifResult<ImageTiming> {
         let sample_table = self.sample_table.unwrap_ref();
         let mut image_timing = ImageTiming {
@@ -118,12 +204,21 @@ impl Track {
             pts_in_timescales: 0,
             ..ImageTiming::default()
         };
+        let mut decoding_time: u64 = 0;
         for i in 0..image_index as usize {
-            checked_incr!(
-                image_timing.pts_in_timescales,
-                sample_table.image_delta(i)? as u64
-            );
+            checked_incr!(decoding_time, sample_table.image_delta(i)? as u64);
+        }
+        let mut presentation_time = checked_add!(
+            i64_from_u64(decoding_time)?,
+            sample_table.composition_offset(image_index as usize)?
+        )?;
+        if let Some((edit, empty_duration)) = self.first_media_edit()? {
+            presentation_time = checked_sub!(presentation_time, edit.media_time)?;
+            checked_incr!(presentation_time, i64_from_u64(empty_duration)?);
         }
+        // Samples composed before the start of the edit are not presented. Report them at the
+        // start of the presentation.
+        image_timing.pts_in_timescales = presentation_time.max(0) as u64;
         image_timing.duration_in_timescales =
             sample_table.image_delta(image_index as usize)? as u64;
         if image_timing.timescale > 0 {
@@ -184,6 +279,39 @@ impl Default for SampleSize {
     }
 }
 
+// Section 8.8.3 of ISO/IEC 14496-12.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TrackExtends {
+    pub default_sample_description_index: u32,
+    pub default_sample_duration: u32,
+    pub default_sample_size: u32,
+    pub default_sample_flags: u32,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FragmentSample {
+    pub size: u32,
+    pub duration: u32,
+    pub sync: bool,
+    pub composition_offset: i64,
+}
+
+// Contiguous samples of a track found in a movie fragment, as described by a 'trun' box.
+#[derive(Debug, Default)]
+pub struct TrackRun {
+    pub track_id: u32,
+    pub offset: u64,
+    pub sample_description_index: u32, // 1-based
+    pub samples: Vec<FragmentSample>,
+}
+
+// Section 8.6.1.3.2 of ISO/IEC 14496-12.
+#[derive(Debug)]
+pub struct CompositionOffset {
+    pub sample_count: u32,
+    pub sample_offset: i64,
+}
+
 #[derive(Debug, Default)]
 pub struct SampleTable {
     pub chunk_offsets: Vec<u64>,
@@ -191,6 +319,8 @@ pub struct SampleTable {
     pub sample_size: SampleSize,
     pub sync_samples: Vec<u32>,
     pub time_to_sample: Vec<TimeToSample>,
+    // Empty if the composition times are equal to the decoding times.
+    pub composition_offsets: Vec<CompositionOffset>,
     pub sample_descriptions: Vec<SampleDescription>,
 }
 
@@ -233,6 +363,81 @@ impl SampleTable {
         })
     }
 
+    // Appends the samples of run as a new chunk.
+    pub(crate) fn append_run(&mut self, run: &TrackRun) -> AvifResult<()> {
+        if run.samples.is_empty() {
+            return Ok(());
+        }
+        let mut sample_count = match &self.sample_size {
+            SampleSize::Sizes(sizes) => sizes.len(),
+            SampleSize::FixedSize(size) => {
+                let mut sample_count: usize = 0;
+                for chunk_index in 0..self.chunk_offsets.len() {
+                    let chunk_sample_count = self.get_sample_count_of_chunk(chunk_index as u32);
+                    checked_incr!(sample_count, usize_from_u32(chunk_sample_count)?);
+                }
+                let mut sizes: Vec<u32> = create_vec_exact(sample_count)?;
+                sizes.resize(sample_count, *size);
+                self.sample_size = SampleSize::Sizes(sizes);
+                sample_count
+            }
+        };
+        let samples_per_chunk = u32_from_usize(run.samples.len())?;
+        if !matches!(self.sample_to_chunk.last(), Some(last)
+            if last.samples_per_chunk == samples_per_chunk
+                && last.sample_description_index == run.sample_description_index)
+        {
+            self.sample_to_chunk.push(SampleToChunk {
+                first_chunk: u32_from_usize(self.chunk_offsets.len() + 1)?,
+                samples_per_chunk,
+                sample_description_index: run.sample_description_index,
+            });
+        }
+        self.chunk_offsets.push(run.offset);
+        let SampleSize::Sizes(sizes) = &mut self.sample_size else {
+            unreachable!();
+        };
+        for sample in &run.samples {
+            sizes.push(sample.size);
+            checked_incr!(sample_count, 1);
+            if sample.sync {
+                // sync_samples is 1-based.
+                self.sync_samples.push(u32_from_usize(sample_count)?);
+            }
+            match self.time_to_sample.last_mut() {
+                Some(last) if last.sample_delta == sample.duration => {
+                    checked_incr!(last.sample_count, 1);
+                }
+                _ => self.time_to_sample.push(TimeToSample {
+                    sample_count: 1,
+                    sample_delta: sample.duration,
+                }),
+            }
+            if sample.composition_offset != 0 && self.composition_offsets.is_empty() {
+                // All the previous samples have a composition offset of 0.
+                let previous_sample_count = u32_from_usize(sample_count - 1)?;
+                if previous_sample_count != 0 {
+                    self.composition_offsets.push(CompositionOffset {
+                        sample_count: previous_sample_count,
+                        sample_offset: 0,
+                    });
+                }
+            }
+            if !self.composition_offsets.is_empty() || sample.composition_offset != 0 {
+                match self.composition_offsets.last_mut() {
+                    Some(last) if last.sample_offset == sample.composition_offset => {
+                        checked_incr!(last.sample_count, 1);
+                    }
+                    _ => self.composition_offsets.push(CompositionOffset {
+                        sample_count: 1,
+                        sample_offset: sample.composition_offset,
+                    }),
+                }
+            }
+        }
+        Ok(())
+    }
+
     pub(crate) fn image_delta(&self, index: usize) -> AvifResult<u32> {
         let mut max_index: u32 = 0;
         for (i, time_to_sample) in self.time_to_sample.iter().enumerate() {
@@ -243,6 +448,18 @@ impl SampleTable {
         }
         Ok(1)
     }
+
+    // Returns the difference between the composition time and the decoding time of the sample.
+    pub(crate) fn composition_offset(&self, index: usize) -> AvifResult<i64> {
+        let mut max_index: u32 = 0;
+        for composition_offset in &self.composition_offsets {
+            checked_incr!(max_index, composition_offset.sample_count);
+            if index < max_index as usize {
+                return Ok(composition_offset.sample_offset);
+            }
+        }
+        Ok(0)
+    }
 }
 
 /// cbindgen:rename-all=CamelCase
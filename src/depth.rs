@@ -0,0 +1,49 @@
+// Copyright 2025 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::image::Image;
+
+/// Semantics of the samples of a depth map, as defined by depth_representation_type in Section
+/// F.14.3.4 of ITU-T H.265.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum DepthRepresentationType {
+    #[default]
+    UniformInverseZ = 0,
+    UniformDisparity = 1,
+    UniformZ = 2,
+    NonuniformDisparity = 3,
+}
+
+/// Depth representation information of a depth map, as defined in Section F.14.3.4 of
+/// ITU-T H.265. The values that are not signalled are None.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DepthRepresentationInfo {
+    pub z_near: Option<f64>,
+    pub z_far: Option<f64>,
+    pub d_min: Option<f64>,
+    pub d_max: Option<f64>,
+    pub depth_representation_type: DepthRepresentationType,
+    pub disparity_reference_view: u32,
+    /// Only set when depth_representation_type is NonuniformDisparity.
+    pub depth_nonlinear_representation_model: Vec<u32>,
+}
+
+/// Depth auxiliary image of the color image.
+#[derive(Default)]
+pub struct DepthMap {
+    pub image: Image,
+    /// Set if the 'auxC' property of the depth auxiliary image (or the 'auxi' box of the depth
+    /// track) contains a depth_representation_info() structure in its aux_subtype field.
+    pub representation_info: Option<DepthRepresentationInfo>,
+}
@@ -14,7 +14,6 @@
 
 use crate::encoder::*;
 use crate::internal_utils::stream::*;
-use crate::utils::clap::CleanAperture;
 use crate::utils::pixels::ChannelIdc;
 use crate::*;
 
@@ -26,6 +25,9 @@ pub(crate) struct Item {
     // True if Sample Transforms derived image item input used as the least
     // significant bits of the bit depth extension.
     pub is_sato_least_significant_input: bool,
+    // True if this item is a thumbnail of the primary item (or the alpha auxiliary item of such a
+    // thumbnail).
+    pub is_thumbnail: bool,
     pub codec: Option<Codec>,
     pub samples: Vec<Sample>,
     pub codec_configuration: Option<CodecConfiguration>,
@@ -37,6 +39,9 @@ pub(crate) struct Item {
     pub iref_to_id: Option<u16>, // If some, then make an iref from this id to iref_to_id.
     pub iref_type: Option<String>,
     pub grid: Option<Grid>,
+    // If some, the dimensions written in the 'ispe' property. Used by the cells of overlays since
+    // they may have different dimensions.
+    pub dimensions: Option<(u32, u32)>,
     pub associations: Vec<(
         u8,   // 1-based property_index
         bool, // essential
@@ -44,6 +49,12 @@ pub(crate) struct Item {
     pub extra_layer_count: u32,
     pub dimg_from_id: Option<u16>, // If some, then make an iref from dimg_from_id to this id.
     pub metadata_payload: Vec<u8>,
+    // Chunks already written to the output by Encoder::flush_to_writer(), as (file offset, sample
+    // count). The payload of an item without codec is a single chunk of 0 samples.
+    pub streamed_chunks: Vec<(u64, usize)>,
+    // Sizes of the streamed samples. Their data is released once written, except for the first
+    // sample which is needed to generate the codec configuration.
+    pub streamed_sample_sizes: Vec<usize>,
 }
 
 impl fmt::Debug for Item {
@@ -60,7 +71,11 @@ impl fmt::Debug for Item {
 
 impl Item {
     pub(crate) fn has_ipma(&self) -> bool {
-        self.grid.is_some() || self.codec.is_some() || self.is_tmap() || self.is_sato()
+        self.grid.is_some()
+            || self.codec.is_some()
+            || self.is_tmap()
+            || self.is_sato()
+            || self.is_overlay()
     }
 
     pub(crate) fn is_metadata(&self) -> bool {
@@ -81,22 +96,23 @@ impl Item {
         self.item_type == "sato"
     }
 
+    pub(crate) fn is_overlay(&self) -> bool {
+        self.item_type == "iovl"
+    }
+
     pub(crate) fn write_ispe(
         &mut self,
         stream: &mut OStream,
         image_metadata: &Image,
     ) -> AvifResult<()> {
         stream.start_full_box("ispe", (0, 0))?;
-        let width = match self.grid {
-            Some(grid) => grid.width,
-            None => image_metadata.width,
+        let (width, height) = match (self.dimensions, self.grid) {
+            (Some(dimensions), _) => dimensions,
+            (None, Some(grid)) => (grid.width, grid.height),
+            (None, None) => (image_metadata.width, image_metadata.height),
         };
         // unsigned int(32) image_width;
         stream.write_u32(width)?;
-        let height = match self.grid {
-            Some(grid) => grid.height,
-            None => image_metadata.height,
-        };
         // unsigned int(32) image_height;
         stream.write_u32(height)?;
         stream.finish_box()
@@ -129,7 +145,7 @@ impl Item {
             // See ISO/IEC 23008-12 DAM 2.
             for i in 0..num_color_channels {
                 let channel_idc = match self.category {
-                    Category::Color | Category::Gainmap => {
+                    Category::Color | Category::Gainmap | Category::Depth => {
                         ChannelIdc::FirstColorChannel as u32 + i as u32
                     }
                     Category::Alpha => ChannelIdc::Alpha as u32,
@@ -141,8 +157,8 @@ impl Item {
                 stream.write_bits(0, 2)?; // unsigned int(2) component_format;
 
                 let subsampling_type = match (self.category, i) {
-                    (Category::Color | Category::Gainmap | Category::Alpha, 0) => 0, // 4:4:4
-                    (Category::Color | Category::Gainmap, 1 | 2) => {
+                    (_, 0) => 0, // 4:4:4
+                    (Category::Color | Category::Gainmap | Category::Depth, 1 | 2) => {
                         match image_metadata.yuv_format {
                             PixelFormat::Yuv444 => 0,
                             PixelFormat::Yuv422 => 1,
@@ -153,8 +169,8 @@ impl Item {
                     _ => unreachable!(),
                 };
                 let subsampling_location = match (self.category, i) {
-                    (Category::Color | Category::Gainmap | Category::Alpha, 0) => Some(0),
-                    (Category::Color | Category::Gainmap, 1 | 2) => {
+                    (_, 0) => Some(0),
+                    (Category::Color | Category::Gainmap | Category::Depth, 1 | 2) => {
                         match (image_metadata.chroma_sample_position, subsampling_type) {
                             (ChromaSamplePosition::Unknown, 0) => Some(2), // 4:4:4 so (0, 0) is fine
                             (ChromaSamplePosition::Unknown, _) => None,
@@ -355,74 +371,6 @@ impl Item {
         stream.finish_box()
     }
 
-    fn write_pasp(&self, stream: &mut OStream, pasp: &PixelAspectRatio) -> AvifResult<()> {
-        stream.start_box("pasp")?;
-        // unsigned int(32) hSpacing;
-        stream.write_u32(pasp.h_spacing)?;
-        // unsigned int(32) vSpacing;
-        stream.write_u32(pasp.v_spacing)?;
-        stream.finish_box()
-    }
-
-    fn write_clli(
-        &self,
-        stream: &mut OStream,
-        clli: &ContentLightLevelInformation,
-    ) -> AvifResult<()> {
-        stream.start_box("clli")?;
-        // unsigned int(16) max_content_light_level
-        stream.write_u16(clli.max_cll)?;
-        // unsigned int(16) max_pic_average_light_level
-        stream.write_u16(clli.max_pall)?;
-        stream.finish_box()
-    }
-
-    fn write_clap(&self, stream: &mut OStream, clap: &CleanAperture) -> AvifResult<()> {
-        stream.start_box("clap")?;
-        // unsigned int(32) cleanApertureWidthN;
-        // unsigned int(32) cleanApertureWidthD;
-        stream.write_ufraction(clap.width)?;
-        // unsigned int(32) cleanApertureHeightN;
-        // unsigned int(32) cleanApertureHeightD;
-        stream.write_ufraction(clap.height)?;
-        // unsigned int(32) horizOffN;
-        // unsigned int(32) horizOffD;
-        stream.write_ufraction(clap.horiz_off)?;
-        // unsigned int(32) vertOffN;
-        // unsigned int(32) vertOffD;
-        stream.write_ufraction(clap.vert_off)?;
-        stream.finish_box()
-    }
-
-    fn write_irot(&self, stream: &mut OStream, angle: u8) -> AvifResult<()> {
-        stream.start_box("irot")?;
-        // unsigned int(6) reserved = 0;
-        stream.write_bits(0, 6)?;
-        // unsigned int(2) angle;
-        stream.write_bits((angle & 0x03).into(), 2)?;
-        stream.finish_box()
-    }
-
-    fn write_imir(&self, stream: &mut OStream, axis: u8) -> AvifResult<()> {
-        stream.start_box("imir")?;
-        // unsigned int(7) reserved = 0;
-        stream.write_bits(0, 7)?;
-        // unsigned int(1) axis;
-        stream.write_bits((axis & 0x01).into(), 1)?;
-        stream.finish_box()
-    }
-
-    fn write_icc(&self, stream: &mut OStream, image_metadata: &Image) -> AvifResult<()> {
-        if image_metadata.icc.is_empty() {
-            return Ok(());
-        }
-        stream.start_box("colr")?;
-        // unsigned int(32) colour_type;
-        stream.write_str("prof")?;
-        stream.write_slice(&image_metadata.icc)?;
-        stream.finish_box()
-    }
-
     fn write_transformative_properties(
         &mut self,
         streams: &mut Vec<OStream>,
@@ -430,19 +378,19 @@ impl Item {
     ) -> AvifResult<()> {
         if let Some(clap) = metadata.clap {
             streams.push(OStream::default());
-            self.write_clap(streams.last_mut().unwrap(), &clap)?;
+            mp4box::write_clap(streams.last_mut().unwrap(), &clap)?;
             self.associations
                 .push((u8_from_usize(streams.len())?, true));
         }
         if let Some(angle) = metadata.irot_angle {
             streams.push(OStream::default());
-            self.write_irot(streams.last_mut().unwrap(), angle)?;
+            mp4box::write_irot(streams.last_mut().unwrap(), angle)?;
             self.associations
                 .push((u8_from_usize(streams.len())?, true));
         }
         if let Some(axis) = metadata.imir_axis {
             streams.push(OStream::default());
-            self.write_imir(streams.last_mut().unwrap(), axis)?;
+            mp4box::write_imir(streams.last_mut().unwrap(), axis)?;
             self.associations
                 .push((u8_from_usize(streams.len())?, true));
         }
@@ -503,7 +451,7 @@ impl Item {
                 // Note a derived 'grid' or 'sato' item can have any category.
                 if !item_metadata.icc.is_empty() {
                     streams.push(OStream::default());
-                    self.write_icc(streams.last_mut().unwrap(), item_metadata)?;
+                    mp4box::write_icc(streams.last_mut().unwrap(), item_metadata)?;
                     self.associations
                         .push((u8_from_usize(streams.len())?, false));
                 }
@@ -513,14 +461,14 @@ impl Item {
                     .push((u8_from_usize(streams.len())?, false));
                 if let Some(pasp) = item_metadata.pasp {
                     streams.push(OStream::default());
-                    self.write_pasp(streams.last_mut().unwrap(), &pasp)?;
+                    mp4box::write_pasp(streams.last_mut().unwrap(), &pasp)?;
                     self.associations
                         .push((u8_from_usize(streams.len())?, false));
                 }
                 // HDR properties.
                 if let Some(clli) = item_metadata.clli {
                     streams.push(OStream::default());
-                    self.write_clli(streams.last_mut().unwrap(), &clli)?;
+                    mp4box::write_clli(streams.last_mut().unwrap(), &clli)?;
                     self.associations
                         .push((u8_from_usize(streams.len())?, false));
                 }
@@ -538,7 +486,7 @@ impl Item {
                     .push((u8_from_usize(streams.len())?, false));
                 if let Some(pasp) = image_metadata.pasp {
                     streams.push(OStream::default());
-                    self.write_pasp(streams.last_mut().unwrap(), &pasp)?;
+                    mp4box::write_pasp(streams.last_mut().unwrap(), &pasp)?;
                     self.associations
                         .push((u8_from_usize(streams.len())?, false));
                 }
@@ -548,6 +496,8 @@ impl Item {
                     );
                 }
             }
+            // Depth auxiliary images are not encoded.
+            Category::Depth => return AvifError::not_implemented(),
         }
         if self.extra_layer_count > 0 {
             streams.push(OStream::default());
@@ -564,7 +514,7 @@ impl Item {
         //   Writers should arrange the descriptive properties specified in 6.5 prior to
         //   any other properties in the sequence associating properties with an item.
         match self.category {
-            Category::Color | Category::Alpha => {
+            Category::Color | Category::Alpha | Category::Depth => {
                 self.write_transformative_properties(streams, item_metadata)?;
             }
             Category::Gainmap => {
@@ -772,7 +722,7 @@ impl Item {
 
             self.write_codec_config_box(stream)?;
             if self.category == Category::Color {
-                self.write_icc(stream, image_metadata)?;
+                mp4box::write_icc(stream, image_metadata)?;
                 self.write_nclx(stream, image_metadata)?;
                 // TODO: Determine if HDR and transformative properties have to be written here or
                 // not.
@@ -825,16 +775,36 @@ impl Item {
         stream.finish_box()
     }
 
+    pub(crate) fn sample_size(&self, index: usize) -> usize {
+        match self.streamed_sample_sizes.get(index) {
+            Some(size) => *size,
+            None => self.samples[index].data.len(),
+        }
+    }
+
     pub(crate) fn write_stsc(&self, stream: &mut OStream) -> AvifResult<()> {
+        // Runs of chunks with the same sample count, as (first_chunk, samples_per_chunk).
+        let mut entries: Vec<(usize, usize)> = Vec::new();
+        if self.streamed_chunks.is_empty() {
+            entries.push((1, self.samples.len()));
+        } else {
+            for (index, (_, sample_count)) in self.streamed_chunks.iter().enumerate() {
+                if entries.last().map(|entry| entry.1) != Some(*sample_count) {
+                    entries.push((index + 1, *sample_count));
+                }
+            }
+        }
         stream.start_full_box("stsc", (0, 0))?;
         // unsigned int(32) entry_count;
-        stream.write_u32(1)?;
-        // unsigned int(32) first_chunk;
-        stream.write_u32(1)?;
-        // unsigned int(32) samples_per_chunk;
-        stream.write_u32(u32_from_usize(self.samples.len())?)?;
-        // unsigned int(32) sample_description_index;
-        stream.write_u32(1)?;
+        stream.write_u32(u32_from_usize(entries.len())?)?;
+        for (first_chunk, samples_per_chunk) in entries {
+            // unsigned int(32) first_chunk;
+            stream.write_u32(u32_from_usize(first_chunk)?)?;
+            // unsigned int(32) samples_per_chunk;
+            stream.write_u32(u32_from_usize(samples_per_chunk)?)?;
+            // unsigned int(32) sample_description_index;
+            stream.write_u32(1)?;
+        }
         stream.finish_box()
     }
 
@@ -844,14 +814,34 @@ impl Item {
         stream.write_u32(0)?;
         // unsigned int(32) sample_count;
         stream.write_u32(u32_from_usize(self.samples.len())?)?;
-        for sample in &self.samples {
+        for index in 0..self.samples.len() {
             // unsigned int(32) entry_size;
-            stream.write_u32(u32_from_usize(sample.data.len())?)?;
+            stream.write_u32(u32_from_usize(self.sample_size(index))?)?;
         }
         stream.finish_box()
     }
 
     pub(crate) fn write_stco(&mut self, stream: &mut OStream) -> AvifResult<()> {
+        if !self.streamed_chunks.is_empty() {
+            // The chunk offsets are already known.
+            let large_offsets = self
+                .streamed_chunks
+                .iter()
+                .any(|(offset, _)| *offset > u32::MAX as u64);
+            stream.start_full_box(if large_offsets { "co64" } else { "stco" }, (0, 0))?;
+            // unsigned int(32) entry_count;
+            stream.write_u32(u32_from_usize(self.streamed_chunks.len())?)?;
+            for (offset, _) in &self.streamed_chunks {
+                if large_offsets {
+                    // unsigned int(64) chunk_offset;
+                    stream.write_u64(*offset)?;
+                } else {
+                    // unsigned int(32) chunk_offset;
+                    stream.write_u32(*offset as u32)?;
+                }
+            }
+            return stream.finish_box();
+        }
         stream.start_full_box("stco", (0, 0))?;
         // unsigned int(32) entry_count;
         stream.write_u32(1)?;
@@ -886,9 +876,26 @@ impl Item {
         stream: &mut OStream,
         image_metadata: &Image,
         duration_in_timescales: &Vec<u64>,
+        fragmented: bool,
     ) -> AvifResult<()> {
         stream.start_box("stbl")?;
         self.write_stsd(stream, image_metadata)?;
+        if fragmented {
+            // The samples are described in the movie fragments.
+            for box_type in ["stts", "stsc", "stco"] {
+                stream.start_full_box(box_type, (0, 0))?;
+                // unsigned int(32) entry_count;
+                stream.write_u32(0)?;
+                stream.finish_box()?;
+            }
+            stream.start_full_box("stsz", (0, 0))?;
+            // unsigned int(32) sample_size;
+            stream.write_u32(0)?;
+            // unsigned int(32) sample_count;
+            stream.write_u32(0)?;
+            stream.finish_box()?;
+            return stream.finish_box();
+        }
         self.write_stts(stream, duration_in_timescales)?;
         self.write_stsc(stream)?;
         self.write_stsz(stream)?;
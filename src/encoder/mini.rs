@@ -23,6 +23,10 @@ pub fn is_mini_compatible(enc: &Encoder) -> bool {
     if enc.settings.extra_layer_count != 0 || enc.is_sequence() {
         return false;
     }
+    // There is no thumbnail in the MinimizedImageBox syntax.
+    if enc.has_thumbnail() {
+        return false;
+    }
 
     // TODO: b/456440247 - Implement with JPEG XL.
     if enc.settings.codec_choice.actual() != CodecChoice::Aom {
@@ -108,8 +112,8 @@ pub fn is_mini_compatible(enc: &Encoder) -> bool {
 
     let mut color_item = None;
     for item in &enc.items {
-        // Grids are not supported by a MinimizedImageBox.
-        if item.grid.is_some() {
+        // Grids and overlays are not supported by a MinimizedImageBox.
+        if item.grid.is_some() || item.is_overlay() {
             return false;
         }
 
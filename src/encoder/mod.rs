@@ -15,12 +15,14 @@
 pub mod item;
 pub mod mini;
 pub mod mp4box;
+pub mod rewrite;
 mod sampletransform;
 
 use crate::encoder::item::*;
 use crate::encoder::mp4box::*;
 
 use crate::codecs::EncoderConfig;
+use crate::decoder::CompressionFormat;
 use crate::gainmap::GainMap;
 use crate::image::*;
 use crate::internal_utils::stream::IStream;
@@ -29,6 +31,7 @@ use crate::internal_utils::*;
 use crate::parser::exif;
 use crate::parser::mp4box::*;
 use crate::utils::clap::CropRect;
+use crate::utils::metrics::Metric;
 use crate::utils::IFraction;
 use crate::*;
 
@@ -41,8 +44,14 @@ use crate::codecs::avm::Avm;
 #[cfg(feature = "jpegxl")]
 use crate::codecs::libjxl::Libjxl;
 
+use crate::codecs::remux::Remux;
+use crate::parser::obu::Av1SequenceHeader;
+
 use std::collections::HashMap;
 use std::fmt;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::io::Write;
 use std::time::SystemTime;
 use std::time::UNIX_EPOCH;
 
@@ -121,7 +130,7 @@ impl Default for MutableSettings {
 impl MutableSettings {
     fn quality(&self, category: Category) -> f32 {
         match category {
-            Category::Color => self.quality,
+            Category::Color | Category::Depth => self.quality,
             Category::Alpha => self.quality_alpha,
             Category::Gainmap => self.quality_gainmap,
         }
@@ -166,6 +175,37 @@ pub enum Recipe {
     BitDepthExtension12b8bOverlap4b,
 }
 
+/// Size budget of an encoded file. See [`Encoder::encode_with_target_size`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TargetSize {
+    /// Maximum number of bytes of the encoded file.
+    Bytes(usize),
+    /// Maximum number of bits of the encoded file per pixel of the primary image.
+    BitsPerPixel(f32),
+}
+
+/// Perceptual quality that the encoded images must reach. See [`Settings::target_quality`].
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TargetQuality {
+    pub metric: Metric,
+    /// Minimum score, as returned by [`Metric::compute`], of each decoded image compared with the
+    /// input image.
+    pub min_score: f64,
+}
+
+/// Layout of an overlay derived image item ('iovl'). See [`Encoder::add_image_overlay`].
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct OverlaySettings {
+    /// Dimensions of the canvas.
+    pub width: u32,
+    pub height: u32,
+    /// R, G, B and A values of the canvas, scaled to the full 16-bit range regardless of the
+    /// depth of the images.
+    pub canvas_fill_value: [u16; 4],
+    /// Horizontal and vertical offsets of the top-left corner of each image on the canvas.
+    pub offsets: Vec<(i32, i32)>,
+}
+
 impl CodecChoice {
     // Returns the chosen or default codec.
     pub(crate) fn actual(self) -> Self {
@@ -195,14 +235,30 @@ pub struct Settings {
     pub threads: u32,
     pub speed: Option<u32>,
     pub header_format: HeaderFormat,
+    /// If positive, every keyframe_interval-th frame of an image sequence is forced to be a
+    /// keyframe.
     pub keyframe_interval: i32,
     pub timescale: u64,
     pub repetition_count: RepetitionCount,
+    /// If true, image sequences are written as fragmented MP4: the 'moov' box only describes the
+    /// tracks and the samples are stored in 'moof' and 'mdat' box pairs. [`Encoder::finish`]
+    /// writes one fragment per frame and [`Encoder::flush_to_writer`] writes one fragment per
+    /// call. The total duration and the repetition count are not written in that case, so
+    /// `repetition_count` must not be [`RepetitionCount::Finite`].
+    pub fragmented: bool,
     pub extra_layer_count: u32,
     pub recipe: Recipe,
     pub force_write_extended_pixi: bool,
     pub creation_time: Option<u64>,
     pub modification_time: Option<u64>,
+    /// If set, the color and alpha qualities of `mutable` are ignored. Instead, the image is
+    /// encoded and decoded with several qualities, and the lowest color and alpha qualities whose
+    /// decoded planes reach the target score are chosen separately. Quality 100 is used if the
+    /// target cannot be reached. The chosen qualities can be read back with
+    /// [`Encoder::settings`]. Only still images without grids or layers are supported. This
+    /// requires a decoder for the encoded format (such as dav1d for AV1), otherwise adding an
+    /// image fails with [`AvifError::NotImplemented`].
+    pub target_quality: Option<TargetQuality>,
     pub mutable: MutableSettings,
 }
 
@@ -216,11 +272,13 @@ impl Default for Settings {
             keyframe_interval: 0,
             timescale: 1,
             repetition_count: RepetitionCount::Infinite,
+            fragmented: false,
             extra_layer_count: 0,
             recipe: Recipe::None,
             force_write_extended_pixi: false,
             creation_time: None,
             modification_time: None,
+            target_quality: None,
             mutable: Default::default(),
         }
     }
@@ -228,7 +286,11 @@ impl Default for Settings {
 
 impl Settings {
     pub(crate) fn is_valid(&self) -> bool {
-        self.extra_layer_count < MAX_AV1_LAYER_COUNT as u32 && self.timescale > 0
+        self.extra_layer_count < MAX_AV1_LAYER_COUNT as u32
+            && self.timescale > 0
+            // The edit list conveying a finite repetition count needs the total duration, which
+            // is not known when the 'moov' box of a fragmented sequence is written.
+            && !(self.fragmented && matches!(self.repetition_count, RepetitionCount::Finite(_)))
     }
 
     pub(crate) fn must_write_extended_pixi(&self) -> bool {
@@ -279,11 +341,20 @@ pub struct Encoder {
     image_metadata: Image,
     gainmap_image_metadata: Image,
     alt_image_metadata: Image,
+    thumbnail_image_metadata: Image,
     primary_item_id: u16,
     alternative_item_ids: Vec<u16>,
     alpha_present: bool,
     duration_in_timescales: Vec<u64>,
     codec_specific_options: CodecSpecificOptions,
+    // If some, the position of the file in the writer given to flush_to_writer() and the offset
+    // of the 'mdat' box in the file.
+    streaming: Option<(u64, u64)>,
+    // Number of movie fragments written so far if settings.fragmented is true.
+    fragment_count: u32,
+    // True if the images were added with add_encoded_image() or
+    // add_encoded_image_for_sequence().
+    remux: bool,
     final_recipe: Option<Recipe>, // Decided when the first image is added.
                                   // Guaranteed not to be Recipe::Auto.
 }
@@ -304,6 +375,10 @@ impl Encoder {
         Ok(())
     }
 
+    pub fn settings(&self) -> &Settings {
+        &self.settings
+    }
+
     pub fn set_codec_specific_option(
         &mut self,
         category: Option<Category>,
@@ -375,6 +450,46 @@ impl Encoder {
         Ok(top_level_item_id)
     }
 
+    fn add_overlay_items(
+        &mut self,
+        overlay: &OverlaySettings,
+        cell_images: &[&Image],
+        category: Category,
+    ) -> AvifResult<u16> {
+        let mut stream = OStream::default();
+        write_iovl(&mut stream, overlay)?;
+        let overlay_item = Item {
+            id: u16_from_usize(self.items.len() + 1)?,
+            item_type: "iovl".into(),
+            infe_name: category.infe_name(),
+            category,
+            dimensions: Some((overlay.width, overlay.height)),
+            metadata_payload: stream.data,
+            ..Default::default()
+        };
+        let overlay_item_id = overlay_item.id;
+        self.items.push(overlay_item);
+        for (cell_index, image) in cell_images.iter().enumerate() {
+            let (item_type, codec) = self
+                .settings
+                .codec_choice
+                .get_item_type_and_encoder_codec()?;
+            self.items.push(Item {
+                id: u16_from_usize(self.items.len() + 1)?,
+                item_type: item_type.into(),
+                infe_name: category.infe_name(),
+                cell_index,
+                category,
+                dimg_from_id: Some(overlay_item_id),
+                hidden_image: true,
+                dimensions: Some((image.width, image.height)),
+                codec: Some(codec),
+                ..Default::default()
+            });
+        }
+        Ok(overlay_item_id)
+    }
+
     fn add_exif_item(&mut self) -> AvifResult<()> {
         if self.image_metadata.exif.is_empty() {
             return Ok(());
@@ -515,6 +630,7 @@ impl Encoder {
         Ok(())
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn add_image_impl(
         &mut self,
         grid_columns: u32,
@@ -522,6 +638,7 @@ impl Encoder {
         cell_images: &[&Image],
         mut duration: u64,
         is_single_image: bool,
+        mut force_keyframe: bool,
         gainmaps: Option<&[&GainMap]>,
     ) -> AvifResult<()> {
         let cell_count: usize = usize_from_u32(grid_rows * grid_columns)?;
@@ -531,7 +648,28 @@ impl Encoder {
         if duration == 0 {
             duration = 1;
         }
+        if !is_single_image
+            && self.settings.extra_layer_count == 0
+            && self.settings.keyframe_interval > 0
+        {
+            let frame_index = self.duration_in_timescales.len() as u64;
+            force_keyframe |= frame_index % self.settings.keyframe_interval as u64 == 0;
+        }
         let first_image = cell_images[0];
+        if let Some(target_quality) = self.settings.target_quality {
+            if cell_count != 1
+                || !is_single_image
+                || self.settings.extra_layer_count != 0
+                || !self.items.is_empty()
+            {
+                return AvifError::not_implemented();
+            }
+            self.choose_target_qualities(
+                first_image,
+                gainmaps.map(|gainmaps| gainmaps[0]),
+                target_quality,
+            )?;
+        }
         let final_recipe = self
             .settings
             .recipe
@@ -629,6 +767,10 @@ impl Encoder {
             if gainmaps.is_some() {
                 return AvifError::not_implemented();
             }
+            if self.has_thumbnail() {
+                // Thumbnails are only supported for still images.
+                return AvifError::invalid_argument();
+            }
             // Another frame in an image sequence, or layer in a layered image.
             let first_image = cell_images[0];
             if !first_image.has_same_cicp(&self.image_metadata)
@@ -673,7 +815,7 @@ impl Encoder {
                 image = &padded_image;
             }
             let mut quality = match item.category {
-                Category::Color => self.settings.mutable.quality,
+                Category::Color | Category::Depth => self.settings.mutable.quality,
                 Category::Alpha => self.settings.mutable.quality_alpha,
                 Category::Gainmap => self.settings.mutable.quality_gainmap,
             };
@@ -722,6 +864,7 @@ impl Encoder {
                 quality,
                 disable_lagged_output: self.alpha_present,
                 is_single_image,
+                force_keyframe,
                 speed: self.settings.speed,
                 extra_layer_count: self.settings.extra_layer_count,
                 threads: self.settings.threads,
@@ -747,6 +890,7 @@ impl Encoder {
             &[image],
             0,
             self.settings.extra_layer_count == 0,
+            false,
             None,
         )
     }
@@ -756,7 +900,151 @@ impl Encoder {
             return AvifError::invalid_argument();
         }
         // TODO: this and add_image cannot be used on the same instance.
-        self.add_image_impl(1, 1, &[image], duration, false, None)
+        self.add_image_impl(1, 1, &[image], duration, false, false, None)
+    }
+
+    /// Same as [`Encoder::add_image_for_sequence`] but forces the frame to be encoded as a
+    /// keyframe, so that it is a sync sample of the sequence track and can be used as a random
+    /// access point.
+    pub fn add_keyframe_for_sequence(&mut self, image: &Image, duration: u64) -> AvifResult<()> {
+        if self.settings.extra_layer_count != 0 {
+            return AvifError::invalid_argument();
+        }
+        self.add_image_impl(1, 1, &[image], duration, false, true, None)
+    }
+
+    fn add_encoded_image_impl(
+        &mut self,
+        image: &Image,
+        color: &[u8],
+        alpha: Option<&[u8]>,
+        mut duration: u64,
+    ) -> AvifResult<()> {
+        if self.settings.codec_choice.actual() != CodecChoice::Aom
+            || self.settings.extra_layer_count != 0
+            || self.settings.target_quality.is_some()
+        {
+            return AvifError::not_implemented();
+        }
+        if (!self.items.is_empty() && !self.remux) || image.width == 0 || image.height == 0 {
+            // Pre-encoded and regular images cannot be mixed.
+            return AvifError::invalid_argument();
+        }
+        if duration == 0 {
+            duration = 1;
+        }
+        let mut payloads = vec![(color, image.yuv_format)];
+        if let Some(alpha) = alpha {
+            payloads.push((alpha, PixelFormat::Yuv400));
+        }
+        let mut sync_samples = Vec::new();
+        for (payload, yuv_format) in &payloads {
+            let sync = Av1SequenceHeader::is_sync_sample(payload)?;
+            if sync {
+                // The properties of the item must match its AV1 sequence header.
+                let sequence_header = Av1SequenceHeader::parse_from_obus(payload)?;
+                if sequence_header.bit_depth != image.depth
+                    || sequence_header.yuv_format != *yuv_format
+                    || sequence_header.max_width < image.width
+                    || sequence_header.max_height < image.height
+                {
+                    return AvifError::invalid_argument();
+                }
+            } else if self.items.is_empty() {
+                // The first sample must contain the sequence header to generate 'av1C'.
+                return AvifError::invalid_argument();
+            }
+            sync_samples.push(sync);
+        }
+
+        if self.items.is_empty() {
+            self.remux = true;
+            self.final_recipe = Some(Recipe::None);
+            self.image_metadata = image.shallow_clone();
+            self.image_metadata.alpha_present = alpha.is_some();
+            self.image_metadata.exif = image.exif.try_clone()?;
+            self.image_metadata.xmp = image.xmp.try_clone()?;
+            self.image_metadata.icc = image.icc.try_clone()?;
+            let color_item_id = self.add_remux_item(Category::Color)?;
+            self.primary_item_id = color_item_id;
+            self.alpha_present = alpha.is_some();
+            if self.alpha_present {
+                let alpha_item_id = self.add_remux_item(Category::Alpha)?;
+                let alpha_item = &mut self.items[alpha_item_id as usize - 1];
+                alpha_item.iref_type = Some(String::from("auxl"));
+                alpha_item.iref_to_id = Some(color_item_id);
+                if self.image_metadata.alpha_premultiplied {
+                    let color_item = &mut self.items[color_item_id as usize - 1];
+                    color_item.iref_type = Some(String::from("prem"));
+                    color_item.iref_to_id = Some(alpha_item_id);
+                }
+            }
+            self.add_exif_item()?;
+            self.add_xmp_item()?;
+        } else if self.has_thumbnail()
+            || !image.has_same_cicp(&self.image_metadata)
+            || image.width != self.image_metadata.width
+            || image.height != self.image_metadata.height
+            || alpha.is_some() != self.alpha_present
+        {
+            // Another frame in an image sequence.
+            return AvifError::invalid_argument();
+        }
+
+        let mut items = self.items.iter_mut().filter(|item| item.codec.is_some());
+        for ((payload, _), sync) in payloads.iter().zip(sync_samples) {
+            items
+                .next()
+                .unwrap()
+                .samples
+                .push(Sample::create_from(payload, sync)?);
+        }
+        self.duration_in_timescales.push(duration);
+        Ok(())
+    }
+
+    fn add_remux_item(&mut self, category: Category) -> AvifResult<u16> {
+        let item = Item {
+            id: u16_from_usize(self.items.len() + 1)?,
+            item_type: "av01".into(),
+            infe_name: category.infe_name(),
+            category,
+            codec: Some(Box::<Remux>::default()),
+            ..Default::default()
+        };
+        let item_id = item.id;
+        self.items.push(item);
+        Ok(item_id)
+    }
+
+    /// Adds an image whose AV1 payloads were encoded outside of this library. `color` and `alpha`
+    /// are temporal units in the low overhead bitstream format, as returned by
+    /// [`crate::utils::av1::split_temporal_units`]. The `av1C` property is derived from the
+    /// sequence header OBU of `color`. `image` only provides the metadata of the image (dimensions,
+    /// depth, format, color properties, ICC, Exif, XMP, etc.). Its planes are ignored.
+    ///
+    /// Images added with this function cannot be mixed with images added with the other
+    /// functions of this encoder.
+    pub fn add_encoded_image(
+        &mut self,
+        image: &Image,
+        color: &[u8],
+        alpha: Option<&[u8]>,
+    ) -> AvifResult<()> {
+        self.add_encoded_image_impl(image, color, alpha, 0)
+    }
+
+    /// Same as [`Encoder::add_encoded_image`] for a frame of an image sequence. The first frame and
+    /// all the frames containing a sequence header OBU followed by a shown key frame are written
+    /// as sync samples.
+    pub fn add_encoded_image_for_sequence(
+        &mut self,
+        image: &Image,
+        color: &[u8],
+        alpha: Option<&[u8]>,
+        duration: u64,
+    ) -> AvifResult<()> {
+        self.add_encoded_image_impl(image, color, alpha, duration)
     }
 
     pub fn add_image_grid(
@@ -774,6 +1062,7 @@ impl Encoder {
             images,
             0,
             self.settings.extra_layer_count == 0,
+            false,
             None,
         )
     }
@@ -782,7 +1071,7 @@ impl Encoder {
         if self.settings.extra_layer_count != 0 {
             return AvifError::not_implemented();
         }
-        self.add_image_impl(1, 1, &[image], 0, true, Some(&[gainmap]))
+        self.add_image_impl(1, 1, &[image], 0, true, false, Some(&[gainmap]))
     }
 
     pub fn add_image_gainmap_grid(
@@ -798,10 +1087,370 @@ impl Encoder {
         if self.settings.extra_layer_count != 0 {
             return AvifError::not_implemented();
         }
-        self.add_image_impl(grid_columns, grid_rows, images, 0, true, Some(gainmaps))
+        self.add_image_impl(
+            grid_columns,
+            grid_rows,
+            images,
+            0,
+            true,
+            false,
+            Some(gainmaps),
+        )
     }
 
-    pub fn finish(&mut self) -> AvifResult<Vec<u8>> {
+    /// Encodes the given images as the cells of an overlay derived image item ('iovl'). The
+    /// images are drawn in order on a canvas of `overlay.width` by `overlay.height` pixels, at
+    /// the offsets of the same index in `overlay.offsets`. The canvas is initialized with
+    /// `overlay.canvas_fill_value`. The images may have different dimensions but must otherwise
+    /// share the same properties. Only still images without gain maps are supported.
+    pub fn add_image_overlay(
+        &mut self,
+        overlay: &OverlaySettings,
+        images: &[&Image],
+    ) -> AvifResult<()> {
+        if !self.items.is_empty() {
+            return AvifError::invalid_argument();
+        }
+        if self.settings.extra_layer_count != 0 || self.settings.target_quality.is_some() {
+            return AvifError::not_implemented();
+        }
+        if images.is_empty()
+            || images.len() > usize::from(u16::MAX)
+            || overlay.offsets.len() != images.len()
+            || overlay.width == 0
+            || overlay.height == 0
+        {
+            return AvifError::invalid_argument();
+        }
+        let first_image = images[0];
+        if self
+            .settings
+            .recipe
+            .self_or_auto_choose_depending_on(first_image)
+            != Recipe::None
+        {
+            return AvifError::not_implemented();
+        }
+        for image in images {
+            if !matches!(image.depth, 8 | 10 | 12)
+                || (image.matrix_coefficients == MatrixCoefficients::Identity
+                    && image.yuv_format != PixelFormat::Yuv444)
+            {
+                return AvifError::invalid_argument();
+            }
+            if !image.has_plane(Plane::Y) {
+                return AvifError::no_content();
+            }
+            if !image.has_same_cicp(first_image)
+                || image.has_alpha() != first_image.has_alpha()
+                || image.alpha_premultiplied != first_image.alpha_premultiplied
+            {
+                return AvifError::invalid_image_grid("all cells do not have the same properties");
+            }
+        }
+        if let Some(clap) = &first_image.clap {
+            if !CropRect::create_from(clap, overlay.width, overlay.height, first_image.yuv_format)?
+                .is_valid(overlay.width, overlay.height, first_image.yuv_format)
+            {
+                return AvifError::invalid_argument();
+            }
+        }
+        self.final_recipe = Some(Recipe::None);
+        self.image_metadata = first_image.shallow_clone();
+        self.image_metadata.width = overlay.width;
+        self.image_metadata.height = overlay.height;
+        self.image_metadata.exif = first_image.exif.try_clone()?;
+        self.image_metadata.xmp = first_image.xmp.try_clone()?;
+        self.image_metadata.icc = first_image.icc.try_clone()?;
+
+        let color_item_id = self.add_overlay_items(overlay, images, Category::Color)?;
+        self.primary_item_id = color_item_id;
+        self.alpha_present =
+            first_image.has_alpha() && !images.iter().all(|image| image.is_opaque());
+        if self.alpha_present && !self.settings.codec_supports_native_alpha_channel() {
+            let alpha_item_id = self.add_overlay_items(overlay, images, Category::Alpha)?;
+            let alpha_item = &mut self.items[alpha_item_id as usize - 1];
+            alpha_item.iref_type = Some(String::from("auxl"));
+            alpha_item.iref_to_id = Some(color_item_id);
+            if self.image_metadata.alpha_premultiplied {
+                let color_item = &mut self.items[color_item_id as usize - 1];
+                color_item.iref_type = Some(String::from("prem"));
+                color_item.iref_to_id = Some(alpha_item_id);
+            }
+        }
+        self.add_exif_item()?;
+        self.add_xmp_item()?;
+
+        for item in self.items.iter_mut().filter(|item| item.codec.is_some()) {
+            let image = images[item.cell_index];
+            let (tile_rows_log2, tile_columns_log2) = self
+                .settings
+                .mutable
+                .tiling_mode
+                .log2(image.width, image.height);
+            let encoder_config = EncoderConfig {
+                tile_rows_log2,
+                tile_columns_log2,
+                quality: self.settings.mutable.quality(item.category),
+                disable_lagged_output: self.alpha_present,
+                is_single_image: true,
+                force_keyframe: false,
+                speed: self.settings.speed,
+                extra_layer_count: 0,
+                threads: self.settings.threads,
+                scaling_mode: self.settings.mutable.scaling_mode,
+                codec_specific_options: self.codec_specific_options.clone(),
+            };
+            item.codec.unwrap_mut().encode_image(
+                image,
+                item.category,
+                &encoder_config,
+                &mut item.samples,
+            )?;
+        }
+        self.duration_in_timescales.push(1);
+        Ok(())
+    }
+
+    pub(crate) fn has_thumbnail(&self) -> bool {
+        self.items.iter().any(|item| item.is_thumbnail)
+    }
+
+    fn add_thumbnail_item(&mut self, category: Category) -> AvifResult<u16> {
+        let (item_type, codec) = self
+            .settings
+            .codec_choice
+            .get_item_type_and_encoder_codec()?;
+        let item = Item {
+            id: u16_from_usize(self.items.len() + 1)?,
+            item_type: item_type.into(),
+            infe_name: category.infe_name(),
+            category,
+            is_thumbnail: true,
+            codec: Some(codec),
+            ..Default::default()
+        };
+        let item_id = item.id;
+        self.items.push(item);
+        Ok(item_id)
+    }
+
+    /// Adds a downscaled version of the still image previously given to [`Encoder::add_image`],
+    /// [`Encoder::add_image_grid`], [`Encoder::add_image_gainmap`] or
+    /// [`Encoder::add_image_gainmap_grid`]. It is encoded as a separate item with a 'thmb'
+    /// reference to the primary item, so that decoders can use it as a preview (see
+    /// [`crate::decoder::Source::Thumbnail`]). Thumbnails with premultiplied alpha are not
+    /// supported.
+    pub fn add_thumbnail(&mut self, image: &Image) -> AvifResult<()> {
+        if self.items.is_empty()
+            || self.duration_in_timescales.len() != 1
+            || self.settings.extra_layer_count != 0
+            || self.has_thumbnail()
+        {
+            return AvifError::invalid_argument();
+        }
+        let grid = Grid {
+            rows: 1,
+            columns: 1,
+            width: image.width,
+            height: image.height,
+        };
+        Self::validate_image_grid(&grid, &[image], Recipe::None)?;
+        let alpha_present = image.has_alpha() && !image.is_opaque();
+        if alpha_present && image.alpha_premultiplied {
+            // The 'prem' reference would conflict with the 'thmb' reference of the color item.
+            return AvifError::not_implemented();
+        }
+        self.thumbnail_image_metadata = image.shallow_clone();
+        self.thumbnail_image_metadata.icc = image.icc.try_clone()?;
+        self.thumbnail_image_metadata.alpha_present = alpha_present;
+
+        let color_item_id = self.add_thumbnail_item(Category::Color)?;
+        let color_item = &mut self.items[color_item_id as usize - 1];
+        color_item.iref_type = Some("thmb".into());
+        color_item.iref_to_id = Some(self.primary_item_id);
+        if alpha_present && !self.settings.codec_supports_native_alpha_channel() {
+            let alpha_item_id = self.add_thumbnail_item(Category::Alpha)?;
+            let alpha_item = &mut self.items[alpha_item_id as usize - 1];
+            alpha_item.iref_type = Some("auxl".into());
+            alpha_item.iref_to_id = Some(color_item_id);
+        }
+
+        let (tile_rows_log2, tile_columns_log2) = self
+            .settings
+            .mutable
+            .tiling_mode
+            .log2(image.width, image.height);
+        for item in self.items.iter_mut().filter(|item| item.is_thumbnail) {
+            let encoder_config = EncoderConfig {
+                tile_rows_log2,
+                tile_columns_log2,
+                quality: self.settings.mutable.quality(item.category),
+                disable_lagged_output: alpha_present,
+                is_single_image: true,
+                force_keyframe: false,
+                speed: self.settings.speed,
+                extra_layer_count: 0,
+                threads: self.settings.threads,
+                scaling_mode: self.settings.mutable.scaling_mode,
+                codec_specific_options: self.codec_specific_options.clone(),
+            };
+            item.codec.unwrap_mut().encode_image(
+                image,
+                item.category,
+                &encoder_config,
+                &mut item.samples,
+            )?;
+        }
+        Ok(())
+    }
+
+    // Searches the lowest color and alpha qualities for which the decoded image reaches
+    // target_quality, by bisection. Both searches share the trial encodes since the color and
+    // alpha items are encoded independently.
+    fn choose_target_qualities(
+        &mut self,
+        image: &Image,
+        gainmap: Option<&GainMap>,
+        target_quality: TargetQuality,
+    ) -> AvifResult<()> {
+        // The trial encodes must be decoded to be scored.
+        let compression_format = match self.settings.codec_choice.actual() {
+            #[cfg(feature = "avm")]
+            CodecChoice::Avm => CompressionFormat::Avif2,
+            #[cfg(feature = "jpegxl")]
+            CodecChoice::Libjxl => CompressionFormat::JpegXl,
+            _ => CompressionFormat::Avif,
+        };
+        if CodecChoice::Auto
+            .get_decoder_codec(compression_format)
+            .is_none()
+        {
+            return AvifError::not_implemented();
+        }
+        let mut settings = self.settings;
+        settings.target_quality = None;
+        // (min, max, chosen) quality for color and alpha.
+        let mut searches = [(0, 100, 100), (0, 100, 100)];
+        if !image.has_alpha() || image.is_opaque() {
+            searches[1].0 = 101;
+        }
+        while searches.iter().any(|search| search.0 <= search.1) {
+            let qualities = searches.map(|(min_quality, max_quality, chosen_quality)| {
+                if min_quality <= max_quality {
+                    (min_quality + max_quality) / 2
+                } else {
+                    chosen_quality
+                }
+            });
+            settings.mutable.quality = qualities[0] as f32;
+            settings.mutable.quality_alpha = qualities[1] as f32;
+            let mut encoder = Encoder::create_with_settings(&settings)?;
+            encoder.codec_specific_options = self.codec_specific_options.clone();
+            match gainmap {
+                Some(gainmap) => encoder.add_image_gainmap(image, gainmap)?,
+                None => encoder.add_image(image)?,
+            }
+            let mut decoder = crate::decoder::Decoder::default();
+            // The trial encodes have the dimensions of the input image, which is already in memory.
+            decoder.settings.image_size_limit = None;
+            decoder.settings.image_dimension_limit = None;
+            decoder.set_io_vec(encoder.finish()?);
+            decoder.parse()?;
+            decoder.next_image()?;
+            let Some(decoded) = decoder.image() else {
+                return AvifError::unknown_error("trial encode could not be decoded");
+            };
+            let planes = [&YUV_PLANES[..], &[Plane::A]];
+            for ((min_quality, max_quality, chosen_quality), (quality, planes)) in
+                searches.iter_mut().zip(qualities.into_iter().zip(planes))
+            {
+                if *min_quality > *max_quality {
+                    continue;
+                }
+                let score = target_quality
+                    .metric
+                    .compute_planes(image, decoded, planes)?
+                    .combined;
+                if score >= target_quality.min_score {
+                    *chosen_quality = quality;
+                    *max_quality = quality - 1;
+                } else {
+                    *min_quality = quality + 1;
+                }
+            }
+        }
+        self.settings.mutable.quality = searches[0].2 as f32;
+        self.settings.mutable.quality_alpha = searches[1].2 as f32;
+        Ok(())
+    }
+
+    fn primary_image_dimensions(&self) -> (u32, u32) {
+        let primary_item = self
+            .items
+            .iter()
+            .find(|item| item.id == self.primary_item_id);
+        match primary_item.map(|item| (item.dimensions, item.grid)) {
+            Some((Some(dimensions), _)) => dimensions,
+            Some((None, Some(grid))) => (grid.width, grid.height),
+            _ => (self.image_metadata.width, self.image_metadata.height),
+        }
+    }
+
+    /// Encodes a still image with the highest color quality (in integer steps from 0 to 100)
+    /// such that the encoded file fits in `target_size`. The quality is searched by bisection,
+    /// encoding the image once per step. For each step, `add_images` is called with a new encoder
+    /// created with `settings` and must add the image, for example with [`Encoder::add_image`],
+    /// [`Encoder::add_image_grid`] or [`Encoder::add_image_gainmap`]. The alpha and gain map
+    /// qualities are not changed.
+    ///
+    /// Returns the encoded file and the chosen quality, or [`AvifError::InvalidArgument`] if the
+    /// file does not fit in `target_size` even with the lowest quality.
+    pub fn encode_with_target_size(
+        settings: &Settings,
+        target_size: TargetSize,
+        mut add_images: impl FnMut(&mut Encoder) -> AvifResult<()>,
+    ) -> AvifResult<(Vec<u8>, f32)> {
+        if match target_size {
+            TargetSize::Bytes(bytes) => bytes == 0,
+            TargetSize::BitsPerPixel(bits_per_pixel) => {
+                !bits_per_pixel.is_finite() || bits_per_pixel <= 0.0
+            }
+        } {
+            return AvifError::invalid_argument();
+        }
+        let mut settings = *settings;
+        let mut best = None;
+        let mut min_quality = 0;
+        let mut max_quality = 100;
+        while min_quality <= max_quality {
+            let quality = (min_quality + max_quality) / 2;
+            settings.mutable.quality = quality as f32;
+            let mut encoder = Encoder::create_with_settings(&settings)?;
+            add_images(&mut encoder)?;
+            if encoder.duration_in_timescales.len() != 1 || settings.extra_layer_count != 0 {
+                return AvifError::invalid_argument();
+            }
+            let (width, height) = encoder.primary_image_dimensions();
+            let data = encoder.finish()?;
+            let max_size = match target_size {
+                TargetSize::Bytes(bytes) => bytes,
+                TargetSize::BitsPerPixel(bits_per_pixel) => {
+                    (bits_per_pixel as f64 * width as f64 * height as f64 / 8.0) as usize
+                }
+            };
+            if data.len() <= max_size {
+                best = Some((data, quality as f32));
+                min_quality = quality + 1;
+            } else {
+                max_quality = quality - 1;
+            }
+        }
+        best.ok_or(AvifError::InvalidArgument)
+    }
+
+    // Finishes the encoding of all items and generates their codec configurations.
+    fn finish_items(&mut self) -> AvifResult<()> {
         if self.items.is_empty() {
             return AvifError::no_content();
         }
@@ -816,21 +1465,50 @@ impl Encoder {
                 return AvifError::invalid_argument();
             }
             // TODO: check if sample count == duration count.
+        }
+        self.generate_codec_configurations()
+    }
 
-            if !item.samples.is_empty() {
-                assert_eq!(item.codec_configuration, None);
+    // Generates the codec configurations of the items that have samples, if not done already.
+    fn generate_codec_configurations(&mut self) -> AvifResult<()> {
+        for item in &mut self.items {
+            if item.codec.is_some()
+                && !item.samples.is_empty()
+                && item.codec_configuration.is_none()
+            {
                 let is_single_image = self.duration_in_timescales.len() < 2;
                 let is_lossless = self.settings.mutable.quality(item.category) == 100.0;
+                let image_metadata = if item.is_thumbnail {
+                    &self.thumbnail_image_metadata
+                } else {
+                    &self.image_metadata
+                };
                 item.codec_configuration = Some(item.codec.unwrap_ref().get_codec_config(
-                    &self.image_metadata,
+                    image_metadata,
                     is_single_image,
                     is_lossless,
                     &item.samples,
                 )?);
             }
         }
+        Ok(())
+    }
+
+    pub fn finish(&mut self) -> AvifResult<Vec<u8>> {
+        if self.streaming.is_some() || self.fragment_count != 0 {
+            return AvifError::invalid_argument();
+        }
+        self.finish_items()?;
         let mut stream = OStream::default();
 
+        if self.settings.fragmented && self.is_sequence() {
+            self.write_fragments(&mut stream, /*fragment_per_frame=*/ true)?;
+            if self.fragment_count == 0 {
+                return AvifError::unknown_error("a track has no sample");
+            }
+            return Ok(stream.data);
+        }
+
         if self.settings.header_format == HeaderFormat::Mini && mini::is_mini_compatible(self) {
             self.write_ftyp_and_mini(&mut stream)?;
             return Ok(stream.data);
@@ -846,6 +1524,203 @@ impl Encoder {
         self.write_mdat(&mut stream)?;
         Ok(stream.data)
     }
+
+    /// Writes the samples of the image sequence encoded so far to `writer`, so that they do not
+    /// have to be kept in memory until the end of the encoding. The file starts at the position of
+    /// `writer` when this function is first called. Subsequent calls to this function and the
+    /// encoding must then be done with the same `writer`, using [`Encoder::finish_to_writer`].
+    /// The metadata boxes are written after the samples in this case, unless
+    /// [`Settings::fragmented`] is true, in which case each call writes a movie fragment after the
+    /// metadata boxes.
+    ///
+    /// Does nothing until at least two frames were added with
+    /// [`Encoder::add_image_for_sequence`], since still images are written entirely by
+    /// [`Encoder::finish_to_writer`].
+    pub fn flush_to_writer<W: Write + Seek>(&mut self, writer: &mut W) -> AvifResult<()> {
+        if !self.is_sequence() {
+            return Ok(());
+        }
+        if self.settings.fragmented {
+            let mut stream = OStream::default();
+            self.write_fragments(&mut stream, /*fragment_per_frame=*/ false)?;
+            return writer
+                .write_all(&stream.data)
+                .map_err(AvifError::map_io_error);
+        }
+        let file_start = match self.streaming {
+            Some((file_start, _)) => file_start,
+            None => {
+                let file_start = writer.stream_position().map_err(AvifError::map_io_error)?;
+                let mut stream = OStream::default();
+                self.write_ftyp(&mut stream)?;
+                let mdat_offset = stream.offset() as u64;
+                // The size of the 'mdat' box is written by finish_to_writer().
+                // unsigned int(32) size;
+                stream.write_u32(1)?;
+                // unsigned int(32) type;
+                stream.write_str("mdat")?;
+                // unsigned int(64) largesize;
+                stream.write_u64(0)?;
+                // The payloads of the items without codec are all known once the first image is
+                // added, so write them first.
+                for item in &mut self.items {
+                    if item.codec.is_none() && !item.metadata_payload.is_empty() {
+                        item.streamed_chunks.push((stream.offset() as u64, 0));
+                        stream.write_slice(&item.metadata_payload)?;
+                    }
+                }
+                writer
+                    .write_all(&stream.data)
+                    .map_err(AvifError::map_io_error)?;
+                self.streaming = Some((file_start, mdat_offset));
+                file_start
+            }
+        };
+        let position = writer.stream_position().map_err(AvifError::map_io_error)?;
+        let mut offset = checked_sub!(position, file_start)?;
+        for item in &mut self.items {
+            let streamed_sample_count = item.streamed_sample_sizes.len();
+            if item.codec.is_none() || item.samples.len() <= streamed_sample_count {
+                continue;
+            }
+            item.streamed_chunks
+                .push((offset, item.samples.len() - streamed_sample_count));
+            for (index, sample) in item
+                .samples
+                .iter_mut()
+                .enumerate()
+                .skip(streamed_sample_count)
+            {
+                writer
+                    .write_all(&sample.data)
+                    .map_err(AvifError::map_io_error)?;
+                offset = checked_add!(offset, sample.data.len() as u64)?;
+                item.streamed_sample_sizes.push(sample.data.len());
+                if index != 0 {
+                    sample.data = Vec::new();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Same as [`Encoder::finish`] but writes the encoded file to `writer` instead of returning
+    /// it. The item payloads are written directly to `writer` and the offsets referring to them
+    /// are patched afterwards. See also [`Encoder::flush_to_writer`].
+    pub fn finish_to_writer<W: Write + Seek>(&mut self, writer: &mut W) -> AvifResult<()> {
+        self.finish_items()?;
+        if self.settings.fragmented && self.is_sequence() {
+            self.flush_to_writer(writer)?;
+            if self.fragment_count == 0 {
+                return AvifError::unknown_error("a track has no sample");
+            }
+            return Ok(());
+        }
+        let Some((file_start, mdat_offset)) = self.streaming else {
+            return self.write_to_writer(writer);
+        };
+        self.flush_to_writer(writer)?;
+        let end = writer.stream_position().map_err(AvifError::map_io_error)?;
+        let mdat_start = checked_add!(file_start, mdat_offset)?;
+        let mdat_size = checked_sub!(end, mdat_start)?;
+        write_at_position(writer, mdat_start + 8, &mdat_size.to_be_bytes())?;
+        writer
+            .seek(SeekFrom::Start(end))
+            .map_err(AvifError::map_io_error)?;
+        let mut stream = OStream::default();
+        self.write_meta(&mut stream)?;
+        self.write_moov(
+            &mut stream,
+            self.settings.creation_time,
+            self.settings.modification_time,
+        )?;
+        writer
+            .write_all(&stream.data)
+            .map_err(AvifError::map_io_error)
+    }
+
+    // Writes the whole file to writer, with the same layout as finish().
+    fn write_to_writer<W: Write + Seek>(&mut self, writer: &mut W) -> AvifResult<()> {
+        let file_start = writer.stream_position().map_err(AvifError::map_io_error)?;
+        let mut stream = OStream::default();
+        if self.settings.header_format == HeaderFormat::Mini && mini::is_mini_compatible(self) {
+            self.write_ftyp_and_mini(&mut stream)?;
+            return writer
+                .write_all(&stream.data)
+                .map_err(AvifError::map_io_error);
+        }
+        self.write_ftyp(&mut stream)?;
+        self.write_meta(&mut stream)?;
+        self.write_moov(
+            &mut stream,
+            self.settings.creation_time,
+            self.settings.modification_time,
+        )?;
+        let mdat_offset = stream.offset() as u64;
+        // Use a 32-bit box size unless the payloads may not fit in it.
+        let payloads_size: usize = self
+            .items
+            .iter()
+            .flat_map(|item| item.samples.iter().map(|sample| sample.data.len()))
+            .chain(self.items.iter().map(|item| item.metadata_payload.len()))
+            .sum();
+        let large_size = payloads_size as u64 + 8 > u32::MAX as u64;
+        // The size of the 'mdat' box is patched once the payloads are written.
+        // unsigned int(32) size;
+        stream.write_u32(if large_size { 1 } else { 0 })?;
+        // unsigned int(32) type;
+        stream.write_str("mdat")?;
+        if large_size {
+            // unsigned int(64) largesize;
+            stream.write_u64(0)?;
+        }
+        writer
+            .write_all(&stream.data)
+            .map_err(AvifError::map_io_error)?;
+        let mut sink = WriterMdatSink {
+            writer,
+            offset: stream.offset() as u64,
+            payloads: HashMap::with_hasher(NonRandomHasherState),
+        };
+        let patches = self.write_mdat_payloads(&mut sink)?;
+        let end = sink.offset;
+        for (location, chunk_offset) in patches {
+            write_at_position(
+                writer,
+                file_start + location as u64,
+                &u32_from_u64(chunk_offset)?.to_be_bytes(),
+            )?;
+        }
+        let mdat_size = end - mdat_offset;
+        if large_size {
+            write_at_position(
+                writer,
+                file_start + mdat_offset + 8,
+                &mdat_size.to_be_bytes(),
+            )?;
+        } else {
+            write_at_position(
+                writer,
+                file_start + mdat_offset,
+                &u32_from_u64(mdat_size)?.to_be_bytes(),
+            )?;
+        }
+        writer
+            .seek(SeekFrom::Start(file_start + end))
+            .map_err(AvifError::map_io_error)?;
+        Ok(())
+    }
+}
+
+fn write_at_position<W: Write + Seek>(
+    writer: &mut W,
+    position: u64,
+    data: &[u8],
+) -> AvifResult<()> {
+    writer
+        .seek(SeekFrom::Start(position))
+        .map_err(AvifError::map_io_error)?;
+    writer.write_all(data).map_err(AvifError::map_io_error)
 }
 
 #[cfg(test)]
@@ -17,8 +17,22 @@ use crate::encoder::*;
 use crate::gainmap::GainMapMetadata;
 use crate::internal_utils::stream::OStream;
 use crate::internal_utils::*;
+use crate::parser::mp4box::ItemInfo;
+use crate::parser::mp4box::ItemPropertyAssociation;
+use crate::parser::mp4box::ItemReference;
+use crate::utils::clap::CleanAperture;
 use crate::*;
 
+use std::collections::HashMap;
+use std::io::Write;
+use std::ops::Range;
+
+// Values of the sample_flags field of ISO/IEC 14496-12 Section 8.8.3.1. The samples of a sync
+// sample do not depend on others (sample_depends_on = 2). The other samples depend on others
+// (sample_depends_on = 1) and have sample_is_non_sync_sample set.
+const SYNC_SAMPLE_FLAGS: u32 = 2 << 24;
+const NON_SYNC_SAMPLE_FLAGS: u32 = (1 << 24) | (1 << 16);
+
 pub(crate) const UNITY_MATRIX: [u8; 9 * 4] = [
     0x00, 0x01, 0x00, 0x00, //
     0x00, 0x00, 0x00, 0x00, //
@@ -53,6 +67,73 @@ pub(crate) fn write_pitm(stream: &mut OStream, item_id: u16) -> AvifResult<()> {
     stream.finish_box()
 }
 
+pub(crate) fn write_pasp(stream: &mut OStream, pasp: &PixelAspectRatio) -> AvifResult<()> {
+    stream.start_box("pasp")?;
+    // unsigned int(32) hSpacing;
+    stream.write_u32(pasp.h_spacing)?;
+    // unsigned int(32) vSpacing;
+    stream.write_u32(pasp.v_spacing)?;
+    stream.finish_box()
+}
+
+pub(crate) fn write_clli(
+    stream: &mut OStream,
+    clli: &ContentLightLevelInformation,
+) -> AvifResult<()> {
+    stream.start_box("clli")?;
+    // unsigned int(16) max_content_light_level
+    stream.write_u16(clli.max_cll)?;
+    // unsigned int(16) max_pic_average_light_level
+    stream.write_u16(clli.max_pall)?;
+    stream.finish_box()
+}
+
+pub(crate) fn write_clap(stream: &mut OStream, clap: &CleanAperture) -> AvifResult<()> {
+    stream.start_box("clap")?;
+    // unsigned int(32) cleanApertureWidthN;
+    // unsigned int(32) cleanApertureWidthD;
+    stream.write_ufraction(clap.width)?;
+    // unsigned int(32) cleanApertureHeightN;
+    // unsigned int(32) cleanApertureHeightD;
+    stream.write_ufraction(clap.height)?;
+    // unsigned int(32) horizOffN;
+    // unsigned int(32) horizOffD;
+    stream.write_ufraction(clap.horiz_off)?;
+    // unsigned int(32) vertOffN;
+    // unsigned int(32) vertOffD;
+    stream.write_ufraction(clap.vert_off)?;
+    stream.finish_box()
+}
+
+pub(crate) fn write_irot(stream: &mut OStream, angle: u8) -> AvifResult<()> {
+    stream.start_box("irot")?;
+    // unsigned int(6) reserved = 0;
+    stream.write_bits(0, 6)?;
+    // unsigned int(2) angle;
+    stream.write_bits((angle & 0x03).into(), 2)?;
+    stream.finish_box()
+}
+
+pub(crate) fn write_imir(stream: &mut OStream, axis: u8) -> AvifResult<()> {
+    stream.start_box("imir")?;
+    // unsigned int(7) reserved = 0;
+    stream.write_bits(0, 7)?;
+    // unsigned int(1) axis;
+    stream.write_bits((axis & 0x01).into(), 1)?;
+    stream.finish_box()
+}
+
+pub(crate) fn write_icc(stream: &mut OStream, image_metadata: &Image) -> AvifResult<()> {
+    if image_metadata.icc.is_empty() {
+        return Ok(());
+    }
+    stream.start_box("colr")?;
+    // unsigned int(32) colour_type;
+    stream.write_str("prof")?;
+    stream.write_slice(&image_metadata.icc)?;
+    stream.finish_box()
+}
+
 pub(crate) fn write_grid(stream: &mut OStream, grid: &Grid) -> AvifResult<()> {
     // ISO/IEC 23008-12 6.6.2.3.2
     // aligned(8) class ImageGrid {
@@ -85,6 +166,60 @@ pub(crate) fn write_grid(stream: &mut OStream, grid: &Grid) -> AvifResult<()> {
     Ok(())
 }
 
+pub(crate) fn write_iovl(stream: &mut OStream, overlay: &OverlaySettings) -> AvifResult<()> {
+    // ISO/IEC 23008-12 6.6.2.2.2
+    // aligned(8) class ImageOverlay {
+    //     unsigned int(8) version = 0;
+    //     unsigned int(8) flags;
+    //     for (j=0; j<4; j++) {
+    //         unsigned int(16) canvas_fill_value;
+    //     }
+    //     FieldLength = ((flags & 1) + 1) * 16;
+    //     unsigned int(FieldLength) output_width;
+    //     unsigned int(FieldLength) output_height;
+    //     for (i=0; i<reference_count; i++) {
+    //         signed int(FieldLength) horizontal_offset;
+    //         signed int(FieldLength) vertical_offset;
+    //     }
+    // }
+    let large = overlay.width > 65535
+        || overlay.height > 65535
+        || !overlay
+            .offsets
+            .iter()
+            .all(|(x, y)| i16::try_from(*x).is_ok() && i16::try_from(*y).is_ok());
+    let flags = if large { 1 } else { 0 };
+    // unsigned int(8) version = 0;
+    stream.write_u8(0)?;
+    // unsigned int(8) flags;
+    stream.write_u8(flags)?;
+    for value in overlay.canvas_fill_value {
+        // unsigned int(16) canvas_fill_value;
+        stream.write_u16(value)?;
+    }
+    // unsigned int(FieldLength) output_width;
+    // unsigned int(FieldLength) output_height;
+    if flags == 1 {
+        stream.write_u32(overlay.width)?;
+        stream.write_u32(overlay.height)?;
+    } else {
+        stream.write_u16(overlay.width as u16)?;
+        stream.write_u16(overlay.height as u16)?;
+    }
+    for (horizontal_offset, vertical_offset) in &overlay.offsets {
+        // signed int(FieldLength) horizontal_offset;
+        // signed int(FieldLength) vertical_offset;
+        if flags == 1 {
+            stream.write_i32(*horizontal_offset)?;
+            stream.write_i32(*vertical_offset)?;
+        } else {
+            stream.write_i16(*horizontal_offset as i16)?;
+            stream.write_i16(*vertical_offset as i16)?;
+        }
+    }
+    Ok(())
+}
+
 pub(crate) fn write_tmap(metadata: &GainMapMetadata) -> AvifResult<Vec<u8>> {
     let mut stream = OStream::default();
     // ToneMapImage syntax as per section 6.6.2.4.2 of ISO/IEC 23008-12:2024
@@ -128,6 +263,301 @@ pub(crate) fn write_tmap(metadata: &GainMapMetadata) -> AvifResult<Vec<u8>> {
     Ok(stream.data)
 }
 
+// An item of the 'iloc' box.
+pub(crate) struct ItemLocation {
+    pub item_id: u32,
+    pub construction_method: u8,
+    // (offset, length) of each extent.
+    pub extents: Vec<(u64, u64)>,
+}
+
+// Writes the 'iloc' box with 32-bit extent_offset fields, so that its size does not depend on the
+// offsets. Returns the position in stream of the extent_offset field of each extent of each item,
+// for the offsets that are only known once the 'mdat' box is placed.
+pub(crate) fn write_iloc(
+    stream: &mut OStream,
+    items: &[ItemLocation],
+) -> AvifResult<Vec<Vec<usize>>> {
+    let version = if items.iter().any(|x| x.item_id > u16::MAX as u32) {
+        2
+    } else if items.iter().any(|x| x.construction_method != 0) {
+        1
+    } else {
+        0
+    };
+    let large_lengths = items
+        .iter()
+        .flat_map(|x| &x.extents)
+        .any(|x| x.1 > u32::MAX as u64);
+    stream.start_full_box("iloc", (version, 0))?;
+    // unsigned int(4) offset_size;
+    // unsigned int(4) length_size;
+    stream.write_u8(if large_lengths { 0x48 } else { 0x44 })?;
+    // unsigned int(4) base_offset_size;
+    // unsigned int(4) index_size or reserved;
+    stream.write_u8(0)?;
+    if version < 2 {
+        // unsigned int(16) item_count;
+        stream.write_u16(u16_from_usize(items.len())?)?;
+    } else {
+        // unsigned int(32) item_count;
+        stream.write_u32(u32_from_usize(items.len())?)?;
+    }
+    let mut offset_locations = create_vec_exact(items.len())?;
+    for item in items {
+        if version < 2 {
+            // unsigned int(16) item_ID;
+            stream.write_u16(item.item_id as u16)?;
+        } else {
+            // unsigned int(32) item_ID;
+            stream.write_u32(item.item_id)?;
+        }
+        if version >= 1 {
+            // unsigned int(12) reserved = 0;
+            // unsigned int(4) construction_method;
+            stream.write_u16(item.construction_method.into())?;
+        }
+        // unsigned int(16) data_reference_index;
+        stream.write_u16(0)?;
+        // unsigned int(16) extent_count;
+        stream.write_u16(u16_from_usize(item.extents.len())?)?;
+        let mut item_offset_locations = create_vec_exact(item.extents.len())?;
+        for (offset, length) in &item.extents {
+            item_offset_locations.push(stream.offset());
+            // unsigned int(offset_size*8) extent_offset;
+            stream.write_u32(u32_from_u64(*offset)?)?;
+            if large_lengths {
+                // unsigned int(length_size*8) extent_length;
+                stream.write_u64(*length)?;
+            } else {
+                // unsigned int(length_size*8) extent_length;
+                stream.write_u32(*length as u32)?;
+            }
+        }
+        offset_locations.push(item_offset_locations);
+    }
+    stream.finish_box()?;
+    Ok(offset_locations)
+}
+
+pub(crate) fn write_iinf(stream: &mut OStream, items: &[ItemInfo]) -> AvifResult<()> {
+    if items.len() <= u16::MAX as usize {
+        stream.start_full_box("iinf", (0, 0))?;
+        // unsigned int(16) entry_count;
+        stream.write_u16(items.len() as u16)?;
+    } else {
+        stream.start_full_box("iinf", (1, 0))?;
+        // unsigned int(32) entry_count;
+        stream.write_u32(u32_from_usize(items.len())?)?;
+    }
+
+    for item in items {
+        let flags = if item.hidden { 1 } else { 0 };
+        if item.item_id <= u16::MAX as u32 {
+            stream.start_full_box("infe", (2, flags))?;
+            // unsigned int(16) item_ID;
+            stream.write_u16(item.item_id as u16)?;
+        } else {
+            stream.start_full_box("infe", (3, flags))?;
+            // unsigned int(32) item_ID;
+            stream.write_u32(item.item_id)?;
+        }
+        // unsigned int(16) item_protection_index;
+        stream.write_u16(item.item_protection_index)?;
+        // unsigned int(32) item_type;
+        stream.write_string(&item.item_type)?;
+        // utf8string item_name;
+        stream.write_string_with_nul(&item.item_name)?;
+        match item.item_type.as_str() {
+            "mime" => {
+                // utf8string content_type;
+                stream.write_string_with_nul(&item.content_type)?;
+                if !item.content_encoding.is_empty() {
+                    // utf8string content_encoding; //optional
+                    stream.write_string_with_nul(&item.content_encoding)?;
+                }
+            }
+            "uri " => {
+                // utf8string item_uri_type;
+                return AvifError::not_implemented();
+            }
+            _ => {}
+        }
+        stream.finish_box()?;
+    }
+
+    stream.finish_box()
+}
+
+// Writes the 'iref' box, if there is any reference. Consecutive references of the same type from
+// the same item, with increasing indices starting at 0, are written in a single box.
+pub(crate) fn write_iref(stream: &mut OStream, references: &[ItemReference]) -> AvifResult<()> {
+    if references.is_empty() {
+        return Ok(());
+    }
+    let version = if references
+        .iter()
+        .any(|x| x.from_item_id > u16::MAX as u32 || x.to_item_id > u16::MAX as u32)
+    {
+        1
+    } else {
+        0
+    };
+    let write_item_id = |stream: &mut OStream, item_id: u32| {
+        if version == 0 {
+            stream.write_u16(item_id as u16)
+        } else {
+            stream.write_u32(item_id)
+        }
+    };
+    stream.start_full_box("iref", (version, 0))?;
+    let mut first = 0;
+    while first < references.len() {
+        let reference = &references[first];
+        let mut end = first + 1;
+        while end < references.len()
+            && references[end].reference_type == reference.reference_type
+            && references[end].from_item_id == reference.from_item_id
+            && references[end].index != 0
+        {
+            end += 1;
+        }
+        stream.start_box(&reference.reference_type)?;
+        // unsigned int(16 or 32) from_item_ID;
+        write_item_id(stream, reference.from_item_id)?;
+        // unsigned int(16) reference_count;
+        stream.write_u16(u16_from_usize(end - first)?)?;
+        for reference in &references[first..end] {
+            // unsigned int(16 or 32) to_item_ID;
+            write_item_id(stream, reference.to_item_id)?;
+        }
+        stream.finish_box()?;
+        first = end;
+    }
+    stream.finish_box()
+}
+
+// Writes the 'iprp' box with the given property boxes and their associations, whose property
+// indices are 1-based.
+pub(crate) fn write_iprp(
+    stream: &mut OStream,
+    properties: &[&[u8]],
+    associations: &[ItemPropertyAssociation],
+) -> AvifResult<()> {
+    stream.start_box("iprp")?;
+    // ipco
+    stream.start_box("ipco")?;
+    for property in properties {
+        stream.write_slice(property)?;
+    }
+    stream.finish_box()?;
+    // end of ipco
+
+    // ipma
+    let max_index = associations
+        .iter()
+        .flat_map(|x| x.associations.iter().map(|association| association.0))
+        .max()
+        .unwrap_or(0);
+    if max_index > 0x7fff {
+        return AvifError::not_implemented();
+    }
+    let version = if associations.iter().any(|x| x.item_id > u16::MAX as u32) { 1 } else { 0 };
+    let flags = if max_index > 0x7f { 1 } else { 0 };
+    stream.start_full_box("ipma", (version, flags))?;
+    // unsigned int(32) entry_count;
+    stream.write_u32(u32_from_usize(associations.len())?)?;
+    for entry in associations {
+        if version == 0 {
+            // unsigned int(16) item_ID;
+            stream.write_u16(entry.item_id as u16)?;
+        } else {
+            // unsigned int(32) item_ID;
+            stream.write_u32(entry.item_id)?;
+        }
+        // unsigned int(8) association_count;
+        stream.write_u8(u8_from_usize(entry.associations.len())?)?;
+        for (property_index, essential) in &entry.associations {
+            // bit(1) essential;
+            stream.write_bool(*essential)?;
+            if flags == 1 {
+                // unsigned int(15) property_index;
+                stream.write_bits((*property_index).into(), 15)?;
+            } else {
+                // unsigned int(7) property_index;
+                stream.write_bits((*property_index).into(), 7)?;
+            }
+        }
+    }
+    stream.finish_box()?;
+    // end of ipma
+
+    stream.finish_box()
+}
+
+// Destination of the item payloads of the 'mdat' box.
+pub(crate) trait MdatSink<'a> {
+    // Returns the offset in the file of the next written byte.
+    fn offset(&self) -> u64;
+    fn write(&mut self, data: &'a [u8]) -> AvifResult<()>;
+    // Same as write() but only writes data if it was not written already. Returns the offset of
+    // data in the file.
+    fn write_dedupe(&mut self, data: &'a [u8]) -> AvifResult<u64>;
+}
+
+pub(crate) struct OStreamMdatSink<'s> {
+    pub stream: &'s mut OStream,
+    pub mdat_start_offset: usize,
+}
+
+impl<'a> MdatSink<'a> for OStreamMdatSink<'_> {
+    fn offset(&self) -> u64 {
+        self.stream.offset() as u64
+    }
+
+    fn write(&mut self, data: &'a [u8]) -> AvifResult<()> {
+        self.stream.write_slice(data)
+    }
+
+    fn write_dedupe(&mut self, data: &'a [u8]) -> AvifResult<u64> {
+        Ok(self
+            .stream
+            .write_slice_dedupe(self.mdat_start_offset, data)? as u64)
+    }
+}
+
+pub(crate) struct WriterMdatSink<'a, 'w, W> {
+    pub writer: &'w mut W,
+    pub offset: u64,
+    // The payloads written so far, with their offsets in the file. Keyed by content so that
+    // write_dedupe() only compares bytes on a hash hit.
+    pub payloads: HashMap<&'a [u8], u64, NonRandomHasherState>,
+}
+
+impl<'a, W: Write> MdatSink<'a> for WriterMdatSink<'a, '_, W> {
+    fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    fn write(&mut self, data: &'a [u8]) -> AvifResult<()> {
+        self.writer
+            .write_all(data)
+            .map_err(AvifError::map_io_error)?;
+        self.payloads.entry(data).or_insert(self.offset);
+        self.offset = checked_add!(self.offset, data.len() as u64)?;
+        Ok(())
+    }
+
+    fn write_dedupe(&mut self, data: &'a [u8]) -> AvifResult<u64> {
+        if let Some(offset) = self.payloads.get(data) {
+            return Ok(*offset);
+        }
+        let offset = self.offset;
+        self.write(data)?;
+        Ok(offset)
+    }
+}
+
 impl Encoder {
     pub(crate) fn write_avif_ftyp(&self, stream: &mut OStream) -> AvifResult<()> {
         let mut compatible_brands = vec![
@@ -263,131 +693,80 @@ impl Encoder {
     }
 
     pub(crate) fn write_iloc(stream: &mut OStream, items: &mut Vec<&mut Item>) -> AvifResult<()> {
-        stream.start_full_box("iloc", (0, 0))?;
-        // unsigned int(4) offset_size;
-        // unsigned int(4) length_size;
-        stream.write_u8(0x44)?;
-        // unsigned int(4) base_offset_size;
-        // unsigned int(4) reserved;
-        stream.write_u8(0)?;
-        // unsigned int(16) item_count;
-        stream.write_u16(u16_from_usize(items.len())?)?;
-
-        for item in items {
-            // unsigned int(16) item_ID;
-            stream.write_u16(item.id)?;
-            // unsigned int(16) data_reference_index;
-            stream.write_u16(0)?;
-
-            if item.extra_layer_count > 0 {
-                let layer_count = item.extra_layer_count as u16 + 1;
-                // unsigned int(16) extent_count;
-                stream.write_u16(layer_count)?;
-                for i in 0..layer_count as usize {
-                    item.mdat_offset_locations.push(stream.offset());
-                    // unsigned int(offset_size*8) extent_offset;
-                    stream.write_u32(0)?;
-                    // unsigned int(length_size*8) extent_length;
-                    stream.write_u32(u32_from_usize(item.samples[i].data.len())?)?;
+        let mut locations = create_vec_exact(items.len())?;
+        for item in items.iter() {
+            let extents = if item.extra_layer_count > 0 {
+                let mut extents = create_vec_exact(item.extra_layer_count as usize + 1)?;
+                for sample in &item.samples[..=item.extra_layer_count as usize] {
+                    extents.push((0, u64_from_usize(sample.data.len())?));
                 }
+                extents
             } else {
-                // unsigned int(16) extent_count;
-                stream.write_u16(1)?;
-                item.mdat_offset_locations.push(stream.offset());
-                // unsigned int(offset_size*8) extent_offset;
-                stream.write_u32(0)?;
+                let extent_offset = item.streamed_chunks.first().map_or(0, |x| x.0);
                 let extent_length = if item.samples.is_empty() {
-                    u32_from_usize(item.metadata_payload.len())?
+                    item.metadata_payload.len()
                 } else {
-                    u32_from_usize(item.samples[0].data.len())?
+                    item.samples[0].data.len()
                 };
-                // unsigned int(length_size*8) extent_length;
-                stream.write_u32(extent_length)?;
+                vec![(extent_offset, u64_from_usize(extent_length)?)]
+            };
+            locations.push(ItemLocation {
+                item_id: item.id.into(),
+                construction_method: 0,
+                extents,
+            });
+        }
+        let offset_locations = write_iloc(stream, &locations)?;
+        for (item, offset_locations) in items.iter_mut().zip(offset_locations) {
+            // The offsets of the streamed chunks are already written.
+            if item.extra_layer_count > 0 || item.streamed_chunks.is_empty() {
+                item.mdat_offset_locations.extend(offset_locations);
             }
         }
-
-        stream.finish_box()
+        Ok(())
     }
 
     pub(crate) fn write_iinf(stream: &mut OStream, items: &Vec<&mut Item>) -> AvifResult<()> {
-        stream.start_full_box("iinf", (0, 0))?;
-
-        // unsigned int(16) entry_count;
-        stream.write_u16(u16_from_usize(items.len())?)?;
-
-        for item in items {
-            let flags = if item.hidden_image { 1 } else { 0 };
-            stream.start_full_box("infe", (2, flags))?;
-            // unsigned int(16) item_ID;
-            stream.write_u16(item.id)?;
-            // unsigned int(16) item_protection_index;
-            stream.write_u16(0)?;
-            // unsigned int(32) item_type;
-            stream.write_string(&item.item_type)?;
-            // utf8string item_name;
-            stream.write_string_with_nul(&item.infe_name)?;
-            match item.item_type.as_str() {
-                "mime" => {
-                    // utf8string content_type;
-                    stream.write_string_with_nul(&item.infe_content_type)?
-                    // utf8string content_encoding; //optional
-                }
-                "uri " => {
-                    // utf8string item_uri_type;
-                    return AvifError::not_implemented();
-                }
-                _ => {}
-            }
-            stream.finish_box()?;
-        }
-
-        stream.finish_box()
+        let item_infos: Vec<_> = items
+            .iter()
+            .map(|item| ItemInfo {
+                item_id: item.id.into(),
+                item_type: item.item_type.clone(),
+                item_name: item.infe_name.clone(),
+                content_type: item.infe_content_type.clone(),
+                hidden: item.hidden_image,
+                ..Default::default()
+            })
+            .collect();
+        write_iinf(stream, &item_infos)
     }
 
     pub(crate) fn write_iref(&self, stream: &mut OStream) -> AvifResult<()> {
-        let mut box_started = false;
+        let mut references = Vec::new();
         for item in &self.items {
-            let dimg_item_ids: Vec<_> = self
+            let dimg_item_ids = self
                 .items
                 .iter()
                 .filter(|dimg_item| dimg_item.dimg_from_id.unwrap_or_default() == item.id)
-                .map(|dimg_item| dimg_item.id)
-                .collect();
-            if !dimg_item_ids.is_empty() {
-                if !box_started {
-                    stream.start_full_box("iref", (0, 0))?;
-                    box_started = true;
-                }
-                stream.start_box("dimg")?;
-                // unsigned int(16) from_item_ID;
-                stream.write_u16(item.id)?;
-                // unsigned int(16) reference_count;
-                stream.write_u16(u16_from_usize(dimg_item_ids.len())?)?;
-                for dimg_item_id in dimg_item_ids {
-                    // unsigned int(16) to_item_ID;
-                    stream.write_u16(dimg_item_id)?;
-                }
-                stream.finish_box()?;
+                .map(|dimg_item| dimg_item.id);
+            for (index, dimg_item_id) in dimg_item_ids.enumerate() {
+                references.push(ItemReference {
+                    from_item_id: item.id.into(),
+                    to_item_id: dimg_item_id.into(),
+                    reference_type: "dimg".into(),
+                    index: u32_from_usize(index)?,
+                });
             }
             if let Some(iref_to_id) = item.iref_to_id {
-                if !box_started {
-                    stream.start_full_box("iref", (0, 0))?;
-                    box_started = true;
-                }
-                stream.start_box(item.iref_type.as_ref().unwrap().as_str())?;
-                // unsigned int(16) from_item_ID;
-                stream.write_u16(item.id)?;
-                // unsigned int(16) reference_count;
-                stream.write_u16(1)?;
-                // unsigned int(16) to_item_ID;
-                stream.write_u16(iref_to_id)?;
-                stream.finish_box()?;
+                references.push(ItemReference {
+                    from_item_id: item.id.into(),
+                    to_item_id: iref_to_id.into(),
+                    reference_type: item.iref_type.clone().unwrap(),
+                    index: 0,
+                });
             }
         }
-        if box_started {
-            stream.finish_box()?;
-        }
-        Ok(())
+        write_iref(stream, &references)
     }
 
     pub(crate) fn write_grpl(&mut self, stream: &mut OStream) -> AvifResult<()> {
@@ -415,14 +794,13 @@ impl Encoder {
     }
 
     pub(crate) fn write_iprp(&mut self, stream: &mut OStream) -> AvifResult<()> {
-        stream.start_box("iprp")?;
-        // ipco
-        stream.start_box("ipco")?;
         let mut property_streams = Vec::new();
         for item in &mut self.items {
             let mut bit_depth_extension_metadata;
             let item_metadata = if item.is_tmap() {
                 &self.alt_image_metadata
+            } else if item.is_thumbnail {
+                &self.thumbnail_image_metadata
             } else if item.category == Category::Gainmap {
                 &self.gainmap_image_metadata
             } else {
@@ -459,8 +837,8 @@ impl Encoder {
             )?;
         }
         // Deduplicate the property streams.
+        let mut properties: Vec<&[u8]> = Vec::new();
         let mut property_index_map = Vec::new();
-        let mut last_written_property_index = 0u8;
         for i in 0..property_streams.len() {
             let current_data = &property_streams[i].data;
             match property_streams[0..i]
@@ -474,50 +852,30 @@ impl Encoder {
                 }
                 None => {
                     // No duplicate streams were found. Write this stream and store its index.
-                    stream.write_slice(current_data)?;
-                    last_written_property_index += 1;
-                    property_index_map.push(last_written_property_index);
+                    properties.push(current_data);
+                    property_index_map.push(u16_from_usize(properties.len())?);
                 }
             }
         }
-        stream.finish_box()?;
-        // end of ipco
-
-        // ipma
-        stream.start_full_box("ipma", (0, 0))?;
-        let entry_count = u32_from_usize(
-            self.items
-                .iter()
-                .filter(|&item| !item.associations.is_empty())
-                .count(),
-        )?;
-        // unsigned int(32) entry_count;
-        stream.write_u32(entry_count)?;
+        let mut associations = Vec::new();
         for item in &self.items {
             if item.associations.is_empty() {
                 continue;
             }
-            // unsigned int(16) item_ID;
-            stream.write_u16(item.id)?;
-            // unsigned int(8) association_count;
-            stream.write_u8(u8_from_usize(item.associations.len())?)?;
-            for (property_index, essential) in &item.associations {
-                // bit(1) essential;
-                stream.write_bool(*essential)?;
-                // property_index_map is 0-indexed whereas the index stored in item.associations is
-                // 1-indexed.
-                let index = property_index_map[*property_index as usize - 1];
-                if index >= (1 << 7) {
-                    return AvifError::unknown_error("");
-                }
-                // unsigned int(7) property_index;
-                stream.write_bits(index.into(), 7)?;
-            }
+            associations.push(ItemPropertyAssociation {
+                item_id: item.id.into(),
+                // property_index_map is 0-indexed whereas the index stored in item.associations
+                // is 1-indexed.
+                associations: item
+                    .associations
+                    .iter()
+                    .map(|(property_index, essential)| {
+                        (property_index_map[*property_index as usize - 1], *essential)
+                    })
+                    .collect(),
+            });
         }
-        stream.finish_box()?;
-        // end of ipma
-
-        stream.finish_box()
+        write_iprp(stream, &properties, &associations)
     }
 
     pub(crate) fn write_mvhd(
@@ -591,11 +949,13 @@ impl Encoder {
                 modification_time,
             )?;
             item.write_tref(stream)?;
-            item.write_edts(
-                stream,
-                self.settings.repetition_count.loop_count(),
-                duration,
-            )?;
+            if !self.settings.fragmented {
+                item.write_edts(
+                    stream,
+                    self.settings.repetition_count.loop_count(),
+                    duration,
+                )?;
+            }
             if item.category == Category::Color {
                 self.write_track_meta(stream)?;
             }
@@ -634,6 +994,7 @@ impl Encoder {
                         stream,
                         &self.image_metadata,
                         &self.duration_in_timescales,
+                        self.settings.fragmented,
                     )?;
                     stream.finish_box()?;
                 }
@@ -646,10 +1007,27 @@ impl Encoder {
 
     pub(crate) fn write_mdat(&self, stream: &mut OStream) -> AvifResult<()> {
         stream.start_box("mdat")?;
+        let mut sink = OStreamMdatSink {
+            mdat_start_offset: stream.offset(),
+            stream,
+        };
+        for (location, chunk_offset) in self.write_mdat_payloads(&mut sink)? {
+            stream.write_u32_at_offset(u32_from_u64(chunk_offset)?, location)?;
+        }
+        stream.finish_box()
+    }
+
+    // Writes the payloads of all items to sink and returns the locations of the offsets to patch
+    // in the header boxes, with their values.
+    pub(crate) fn write_mdat_payloads<'a>(
+        &'a self,
+        sink: &mut impl MdatSink<'a>,
+    ) -> AvifResult<Vec<(usize, u64)>> {
+        let mut patches = Vec::new();
         let mut layered_item_ids = [Vec::new(), Vec::new()];
         // Use multiple passes to pack the items in the following order:
         //   * Pass 0: metadata (Exif/XMP/gain map metadata)
-        //   * Pass 1: alpha, gain map image (AV1)
+        //   * Pass 1: thumbnails, alpha, gain map image (AV1)
         //   * Pass 2: all other item data (AV1 color)
         //
         // See here for the discussion on alpha coming before color:
@@ -658,7 +1036,6 @@ impl Encoder {
         // Exif and XMP are packed first as they're required to be fully available by
         // Decoder::parse() before it returns AVIF_RESULT_OK, unless ignore_xmp and ignore_exif are
         // enabled.
-        let mdat_start_offset = stream.offset();
         for pass in 0..=2 {
             for item in &self.items {
                 if pass == 0
@@ -668,10 +1045,13 @@ impl Encoder {
                 {
                     continue;
                 }
-                if pass == 1 && !matches!(item.category, Category::Alpha | Category::Gainmap) {
+                if pass == 1
+                    && !item.is_thumbnail
+                    && !matches!(item.category, Category::Alpha | Category::Gainmap)
+                {
                     continue;
                 }
-                if pass == 2 && item.category != Category::Color {
+                if pass == 2 && (item.is_thumbnail || item.category != Category::Color) {
                     continue;
                 }
                 if self.settings.extra_layer_count > 0 && !item.samples.is_empty() {
@@ -683,29 +1063,25 @@ impl Encoder {
                     continue;
                 }
 
-                let mut chunk_offset = stream.offset();
+                let chunk_offset;
                 if !item.samples.is_empty() {
                     if item.samples.len() > 1 {
                         // If there is more than 1 sample, then we do not de-duplicate the chunks.
+                        chunk_offset = sink.offset();
                         for sample in &item.samples {
-                            stream.write_slice(&sample.data)?;
+                            sink.write(&sample.data)?;
                         }
                     } else {
-                        chunk_offset =
-                            stream.write_slice_dedupe(mdat_start_offset, &item.samples[0].data)?;
+                        chunk_offset = sink.write_dedupe(&item.samples[0].data)?;
                     }
                 } else if !item.metadata_payload.is_empty() {
-                    chunk_offset =
-                        stream.write_slice_dedupe(mdat_start_offset, &item.metadata_payload)?;
+                    chunk_offset = sink.write_dedupe(&item.metadata_payload)?;
                 } else {
                     // Empty item, ignore it.
                     continue;
                 }
                 for mdat_offset_location in &item.mdat_offset_locations {
-                    stream.write_u32_at_offset(
-                        u32_from_usize(chunk_offset)?,
-                        *mdat_offset_location,
-                    )?;
+                    patches.push((*mdat_offset_location, chunk_offset));
                 }
             }
         }
@@ -727,12 +1103,9 @@ impl Encoder {
                         has_more_samples = true;
                     }
 
-                    let chunk_offset = stream.offset();
-                    stream.write_slice(&item.samples[layer_index].data)?;
-                    stream.write_u32_at_offset(
-                        u32_from_usize(chunk_offset)?,
-                        item.mdat_offset_locations[layer_index],
-                    )?;
+                    let chunk_offset = sink.offset();
+                    sink.write(&item.samples[layer_index].data)?;
+                    patches.push((item.mdat_offset_locations[layer_index], chunk_offset));
                 }
                 layer_index += 1;
                 if !has_more_samples {
@@ -740,7 +1113,7 @@ impl Encoder {
                 }
             }
         }
-        stream.finish_box()
+        Ok(patches)
     }
 
     pub(crate) fn write_meta(&mut self, stream: &mut OStream) -> AvifResult<()> {
@@ -774,11 +1147,15 @@ impl Encoder {
         if !self.is_sequence() {
             return Ok(());
         }
-        let frames_duration_in_timescales = self
-            .duration_in_timescales
-            .iter()
-            .try_fold(0u64, |acc, &x| acc.checked_add(x))
-            .ok_or(AvifError::UnknownError("".into()))?;
+        // The duration of a fragmented sequence is not known when the 'moov' box is written.
+        let frames_duration_in_timescales = if self.settings.fragmented {
+            0
+        } else {
+            self.duration_in_timescales
+                .iter()
+                .try_fold(0u64, |acc, &x| acc.checked_add(x))
+                .ok_or(AvifError::UnknownError("".into()))?
+        };
         let creation_time =
             Self::convert_unix_epoch_to_iso_bmff_epoch(creation_time.unwrap_or_else(|| {
                 SystemTime::now()
@@ -788,7 +1165,9 @@ impl Encoder {
             }));
         let modification_time =
             Self::convert_unix_epoch_to_iso_bmff_epoch(modification_time.unwrap_or(creation_time));
-        let total_duration_in_timescales = if self.settings.repetition_count.is_infinite() {
+        let total_duration_in_timescales = if self.settings.fragmented {
+            0
+        } else if self.settings.repetition_count.is_infinite() {
             u64::MAX
         } else {
             let loop_count = self.settings.repetition_count.loop_count();
@@ -811,6 +1190,196 @@ impl Encoder {
             creation_time,
             modification_time,
         )?;
+        if self.settings.fragmented {
+            self.write_mvex(stream)?;
+        }
+        stream.finish_box()
+    }
+
+    // Writes the samples that were not written yet to stream as movie fragments, one per frame if
+    // fragment_per_frame is true or a single one otherwise. If no fragment was written before, the
+    // header boxes and the payloads of the items without codec are written first, unless some
+    // track does not have any sample yet, in which case nothing is written.
+    pub(crate) fn write_fragments(
+        &mut self,
+        stream: &mut OStream,
+        fragment_per_frame: bool,
+    ) -> AvifResult<()> {
+        let track_indices: Vec<usize> = (0..self.items.len())
+            .filter(|index| self.items[*index].codec.is_some())
+            .collect();
+        if self.fragment_count == 0 {
+            if track_indices
+                .iter()
+                .any(|index| self.items[*index].samples.is_empty())
+            {
+                return Ok(());
+            }
+            self.generate_codec_configurations()?;
+            self.write_ftyp(stream)?;
+            self.write_meta(stream)?;
+            self.write_moov(
+                stream,
+                self.settings.creation_time,
+                self.settings.modification_time,
+            )?;
+            if self
+                .items
+                .iter()
+                .any(|item| item.codec.is_none() && !item.metadata_payload.is_empty())
+            {
+                stream.start_box("mdat")?;
+                for item in &self.items {
+                    if item.codec.is_some() || item.metadata_payload.is_empty() {
+                        continue;
+                    }
+                    let offset = u32_from_usize(stream.offset())?;
+                    stream.write_slice(&item.metadata_payload)?;
+                    for mdat_offset_location in &item.mdat_offset_locations {
+                        stream.write_u32_at_offset(offset, *mdat_offset_location)?;
+                    }
+                }
+                stream.finish_box()?;
+            }
+        }
+        let runs: Vec<(usize, Range<usize>)> = track_indices
+            .iter()
+            .map(|index| {
+                let item = &self.items[*index];
+                (*index, item.streamed_sample_sizes.len()..item.samples.len())
+            })
+            .filter(|(_, range)| !range.is_empty())
+            .collect();
+        let fragments: Vec<Vec<(usize, Range<usize>)>> = if fragment_per_frame {
+            let first_frame = runs.iter().map(|(_, range)| range.start).min().unwrap_or(0);
+            let last_frame = runs.iter().map(|(_, range)| range.end).max().unwrap_or(0);
+            (first_frame..last_frame)
+                .map(|frame| {
+                    runs.iter()
+                        .filter(|(_, range)| range.contains(&frame))
+                        .map(|(index, _)| (*index, frame..frame + 1))
+                        .collect()
+                })
+                .collect()
+        } else {
+            vec![runs]
+        };
+        for runs in fragments {
+            if runs.is_empty() {
+                continue;
+            }
+            self.fragment_count = checked_add!(self.fragment_count, 1)?;
+            let sample_offsets = self.write_fragment(stream, self.fragment_count, &runs)?;
+            for ((index, range), sample_offset) in runs.into_iter().zip(sample_offsets) {
+                let item = &mut self.items[index];
+                if range.start == 0 {
+                    // The first sample is also the payload of the item. It is written in the same
+                    // stream as the 'meta' box since the header is written with the first samples.
+                    let offset = u32_from_usize(sample_offset)?;
+                    for mdat_offset_location in &item.mdat_offset_locations {
+                        stream.write_u32_at_offset(offset, *mdat_offset_location)?;
+                    }
+                }
+                for sample_index in range {
+                    let sample = &mut item.samples[sample_index];
+                    item.streamed_sample_sizes.push(sample.data.len());
+                    if sample_index != 0 {
+                        sample.data = Vec::new();
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Writes a 'moof' box and its 'mdat' box made of the samples in the given ranges of the items
+    // at the given indices. Returns the offsets in stream of the first sample of each range.
+    fn write_fragment(
+        &self,
+        stream: &mut OStream,
+        sequence_number: u32,
+        runs: &[(usize, Range<usize>)],
+    ) -> AvifResult<Vec<usize>> {
+        let moof_offset = stream.offset();
+        stream.start_box("moof")?;
+        stream.start_full_box("mfhd", (0, 0))?;
+        // unsigned int(32) sequence_number;
+        stream.write_u32(sequence_number)?;
+        stream.finish_box()?;
+        let mut data_offset_locations = Vec::new();
+        for (index, range) in runs {
+            let item = &self.items[*index];
+            stream.start_box("traf")?;
+            // Flags: default-base-is-moof.
+            stream.start_full_box("tfhd", (0, 0x020000))?;
+            // unsigned int(32) track_ID;
+            stream.write_u32(item.id as u32)?;
+            stream.finish_box()?;
+            let base_media_decode_time = self.duration_in_timescales[..range.start]
+                .iter()
+                .try_fold(0u64, |acc, &x| acc.checked_add(x))
+                .ok_or(AvifError::UnknownError("".into()))?;
+            stream.start_full_box("tfdt", (1, 0))?;
+            // unsigned int(64) baseMediaDecodeTime;
+            stream.write_u64(base_media_decode_time)?;
+            stream.finish_box()?;
+            // Flags: data-offset-present, sample-duration-present, sample-size-present and
+            // sample-flags-present.
+            stream.start_full_box("trun", (0, 0x000701))?;
+            // unsigned int(32) sample_count;
+            stream.write_u32(u32_from_usize(range.len())?)?;
+            data_offset_locations.push(stream.offset());
+            // signed int(32) data_offset;
+            stream.write_u32(0)?;
+            for sample_index in range.clone() {
+                // unsigned int(32) sample_duration;
+                stream.write_u32(u32_from_u64(self.duration_in_timescales[sample_index])?)?;
+                // unsigned int(32) sample_size;
+                stream.write_u32(u32_from_usize(item.sample_size(sample_index))?)?;
+                // unsigned int(32) sample_flags;
+                stream.write_u32(if item.samples[sample_index].sync {
+                    SYNC_SAMPLE_FLAGS
+                } else {
+                    NON_SYNC_SAMPLE_FLAGS
+                })?;
+            }
+            stream.finish_box()?;
+            stream.finish_box()?;
+        }
+        stream.finish_box()?;
+        stream.start_box("mdat")?;
+        let mut sample_offsets = Vec::new();
+        for ((index, range), data_offset_location) in runs.iter().zip(data_offset_locations) {
+            let sample_offset = stream.offset();
+            stream.write_u32_at_offset(
+                u32_from_usize(sample_offset - moof_offset)?,
+                data_offset_location,
+            )?;
+            for sample in &self.items[*index].samples[range.clone()] {
+                stream.write_slice(&sample.data)?;
+            }
+            sample_offsets.push(sample_offset);
+        }
+        stream.finish_box()?;
+        Ok(sample_offsets)
+    }
+
+    fn write_mvex(&self, stream: &mut OStream) -> AvifResult<()> {
+        stream.start_box("mvex")?;
+        for item in self.items.iter().filter(|item| !item.samples.is_empty()) {
+            stream.start_full_box("trex", (0, 0))?;
+            // unsigned int(32) track_ID;
+            stream.write_u32(item.id as u32)?;
+            // unsigned int(32) default_sample_description_index;
+            stream.write_u32(1)?;
+            // unsigned int(32) default_sample_duration;
+            stream.write_u32(0)?;
+            // unsigned int(32) default_sample_size;
+            stream.write_u32(0)?;
+            // unsigned int(32) default_sample_flags;
+            stream.write_u32(0)?;
+            stream.finish_box()?;
+        }
         stream.finish_box()
     }
 }
@@ -0,0 +1,1058 @@
+// Copyright 2025 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::decoder::track::SampleSize;
+use crate::decoder::track::SampleTable;
+use crate::decoder::CompressionFormat;
+use crate::decoder::Decoder;
+use crate::decoder::GenericIO;
+use crate::decoder::Source;
+use crate::encoder::mp4box::*;
+use crate::image::Image;
+use crate::internal_utils::io::DecoderMemoryIO;
+use crate::internal_utils::stream::IStream;
+use crate::internal_utils::stream::OStream;
+use crate::internal_utils::*;
+use crate::parser::exif;
+use crate::parser::mp4box;
+use crate::parser::mp4box::BoxRange;
+use crate::parser::mp4box::ColorInformation;
+use crate::parser::mp4box::ItemInfo;
+use crate::parser::mp4box::ItemProperty;
+use crate::parser::mp4box::ItemPropertyAssociation;
+use crate::parser::mp4box::ItemReference;
+use crate::parser::mp4box::MetaBox;
+use crate::*;
+
+use std::borrow::Cow;
+use std::collections::HashSet;
+
+/// Rewrites the AVIF file `data` with edited metadata, without decoding and re-encoding it. The
+/// coded payloads are copied byte for byte.
+///
+/// `edit` is called with the properties and metadata of the primary item (the planes are not
+/// set). It can modify or clear the `exif`, `xmp`, `icc`, `irot_angle`, `imir_axis`, `clap`,
+/// `pasp` and `clli` fields. Modifying any other field is an error.
+///
+/// Only the changed fields are rewritten: the Exif and XMP items describing the primary item are
+/// replaced, and the matching properties of the primary item are replaced. The transformative
+/// properties of its auxiliary images and gain map, and the pixel aspect ratio of its gain map,
+/// are kept in sync with those of the primary item. All the other items, properties, references,
+/// entity groups and tracks are kept as is. For image sequences, the tracks are not edited.
+///
+/// Returns an error instead of dropping anything it cannot carry over to the rewritten file, for
+/// example movie fragments or a replaced Exif item that is also referenced by other items. As in
+/// files written by the encoder, the item payloads must be within the first 4 GiB of the file.
+pub fn rewrite_metadata<F>(data: &[u8], edit: F) -> AvifResult<Vec<u8>>
+where
+    F: FnOnce(&mut Image) -> AvifResult<()>,
+{
+    let mut decoder = create_decoder(data)?;
+    decoder.parse()?;
+    if decoder.compression_format() != CompressionFormat::Avif {
+        return AvifError::not_implemented();
+    }
+    let image = decoder.image().unwrap();
+    let mut edited_image = image.shallow_clone();
+    edited_image.exif = image.exif.try_clone()?;
+    edited_image.xmp = image.xmp.try_clone()?;
+    edited_image.icc = image.icc.try_clone()?;
+    edit(&mut edited_image)?;
+    // Only the metadata can be edited.
+    if !edited_image.has_same_properties_and_cicp(image)
+        || edited_image.alpha_present != image.alpha_present
+        || edited_image.alpha_premultiplied != image.alpha_premultiplied
+        || edited_image.image_sequence_track_present != image.image_sequence_track_present
+        || edited_image.progressive_state != image.progressive_state
+        || edited_image.planes.iter().any(|plane| plane.is_some())
+    {
+        return AvifError::invalid_argument();
+    }
+
+    let mut io: GenericIO = Box::new(DecoderMemoryIO {
+        data: data.to_vec(),
+    });
+    let avif_boxes = mp4box::parse(&mut io)?;
+    let meta = &avif_boxes.meta;
+    let color_item_id = find_color_item_id(meta, decoder.selected_item_id())?;
+    let mut rewriter = MetaRewriter::create(data, meta, &avif_boxes.tracks)?;
+    if image.exif != edited_image.exif {
+        rewriter.replace_metadata_item(
+            color_item_id,
+            "Exif",
+            "Exif",
+            "",
+            exif_payload(&edited_image)?,
+        )?;
+    }
+    if image.xmp != edited_image.xmp {
+        rewriter.replace_metadata_item(
+            color_item_id,
+            "mime",
+            "XMP",
+            "application/rdf+xml",
+            edited_image.xmp.try_clone()?,
+        )?;
+    }
+    for (item_id, edited_properties) in edited_items(meta, color_item_id) {
+        for &property in edited_properties {
+            if property.has_changed(image, &edited_image) {
+                rewriter.replace_property(item_id, property, property.write(&edited_image)?)?;
+            }
+        }
+    }
+    let rewritten_data = rewriter.write(data, &avif_boxes.tracks)?;
+
+    // Make sure that the rewritten file is read back as expected.
+    let mut rewritten_decoder = create_decoder(&rewritten_data)?;
+    rewritten_decoder.parse()?;
+    let rewritten_image = rewritten_decoder.image().unwrap();
+    if !rewritten_image.has_same_properties_and_cicp(&edited_image)
+        || rewritten_image.alpha_present != edited_image.alpha_present
+        || rewritten_image.exif != edited_image.exif
+        || rewritten_image.xmp != edited_image.xmp
+        || EditableProperty::ALL
+            .iter()
+            .any(|property| property.has_changed(rewritten_image, &edited_image))
+        || rewritten_decoder.image_count() != decoder.image_count()
+        || rewritten_decoder.gainmap_present() != decoder.gainmap_present()
+    {
+        return AvifError::unknown_error("the rewritten file does not match the edited metadata");
+    }
+    Ok(rewritten_data)
+}
+
+fn create_decoder(data: &[u8]) -> AvifResult<Decoder> {
+    let mut decoder = Decoder::default();
+    decoder.settings.source = Source::PrimaryItem;
+    decoder.settings.image_size_limit = None;
+    decoder.settings.image_dimension_limit = None;
+    decoder.settings.image_count_limit = None;
+    decoder.set_io_vec(data.to_vec());
+    Ok(decoder)
+}
+
+fn exif_payload(image: &Image) -> AvifResult<Vec<u8>> {
+    if image.exif.is_empty() {
+        return Ok(Vec::new());
+    }
+    // Same layout as the Exif item written by the encoder.
+    let mut stream = IStream::create(&image.exif);
+    let tiff_header_offset = exif::parse_exif_tiff_header_offset(&mut stream)?;
+    let mut payload: Vec<u8> = create_vec_exact(4 + image.exif.len())?;
+    payload.extend_from_slice(&tiff_header_offset.to_be_bytes());
+    payload.extend_from_slice(&image.exif);
+    Ok(payload)
+}
+
+fn item_type(meta: &MetaBox, item_id: u32) -> Option<&str> {
+    meta.iinf
+        .iter()
+        .find(|item| item.item_id == item_id)
+        .map(|item| item.item_type.as_str())
+}
+
+// Returns the ids of the input images of the derived image item_id, in order.
+fn dimg_item_ids(meta: &MetaBox, item_id: u32) -> Vec<u32> {
+    let mut references: Vec<&ItemReference> = meta
+        .iref
+        .iter()
+        .filter(|x| x.reference_type == "dimg" && x.from_item_id == item_id)
+        .collect();
+    references.sort_by_key(|x| x.index);
+    references.iter().map(|x| x.to_item_id).collect()
+}
+
+// Returns the id of the item whose properties and metadata are read by the decoder, given the
+// item it selected.
+fn find_color_item_id(meta: &MetaBox, selected_item_id: u32) -> AvifResult<u32> {
+    match item_type(meta, selected_item_id) {
+        // The base image of a tone mapped image carries the properties and metadata.
+        Some("tmap") => match dimg_item_ids(meta, selected_item_id).first() {
+            Some(base_item_id) => Ok(*base_item_id),
+            None => AvifError::invalid_tone_mapped_image(""),
+        },
+        Some("iden") => AvifError::not_implemented(),
+        Some(_) => Ok(selected_item_id),
+        None => AvifError::missing_image_item(),
+    }
+}
+
+// Returns the items whose properties are edited along with the properties of the color item,
+// and the properties that are edited for each of them.
+fn edited_items(meta: &MetaBox, color_item_id: u32) -> Vec<(u32, &'static [EditableProperty])> {
+    const TRANSFORMATIVE: &[EditableProperty] = &[
+        EditableProperty::Clap,
+        EditableProperty::Irot,
+        EditableProperty::Imir,
+    ];
+    const GAINMAP: &[EditableProperty] = &[
+        EditableProperty::Pasp,
+        EditableProperty::Clap,
+        EditableProperty::Irot,
+        EditableProperty::Imir,
+    ];
+    let mut items = vec![(color_item_id, EditableProperty::ALL)];
+    // The decoder requires the transformative properties of the alpha auxiliary image to match
+    // those of the color item, and the same for the gain map with also the pixel aspect ratio.
+    for reference in &meta.iref {
+        if reference.reference_type == "auxl" && reference.to_item_id == color_item_id {
+            items.push((reference.from_item_id, TRANSFORMATIVE));
+        }
+    }
+    for item in meta.iinf.iter().filter(|item| item.item_type == "tmap") {
+        let input_item_ids = dimg_item_ids(meta, item.item_id);
+        if input_item_ids.len() == 2 && input_item_ids[0] == color_item_id {
+            items.push((input_item_ids[1], GAINMAP));
+        }
+    }
+    items
+}
+
+// The item properties that rewrite_metadata() can edit, in the order in which they are
+// associated with an item.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum EditableProperty {
+    Icc,
+    Pasp,
+    Clli,
+    Clap,
+    Irot,
+    Imir,
+}
+
+impl EditableProperty {
+    const ALL: &'static [EditableProperty] = &[
+        Self::Icc,
+        Self::Pasp,
+        Self::Clli,
+        Self::Clap,
+        Self::Irot,
+        Self::Imir,
+    ];
+
+    fn from(property: &ItemProperty) -> Option<Self> {
+        match property {
+            ItemProperty::ColorInformation(ColorInformation::Icc(_)) => Some(Self::Icc),
+            ItemProperty::PixelAspectRatio(_) => Some(Self::Pasp),
+            ItemProperty::ContentLightLevelInformation(_) => Some(Self::Clli),
+            ItemProperty::CleanAperture(_) => Some(Self::Clap),
+            ItemProperty::ImageRotation(_) => Some(Self::Irot),
+            ItemProperty::ImageMirror(_) => Some(Self::Imir),
+            _ => None,
+        }
+    }
+
+    fn is_transformative(self) -> bool {
+        matches!(self, Self::Clap | Self::Irot | Self::Imir)
+    }
+
+    fn has_changed(self, image: &Image, edited_image: &Image) -> bool {
+        match self {
+            Self::Icc => image.icc != edited_image.icc,
+            Self::Pasp => image.pasp != edited_image.pasp,
+            Self::Clli => image.clli != edited_image.clli,
+            Self::Clap => image.clap != edited_image.clap,
+            Self::Irot => image.irot_angle != edited_image.irot_angle,
+            Self::Imir => image.imir_axis != edited_image.imir_axis,
+        }
+    }
+
+    // Returns the property box of image, or None if image does not have this property.
+    fn write(self, image: &Image) -> AvifResult<Option<Vec<u8>>> {
+        let mut stream = OStream::default();
+        match self {
+            Self::Icc if !image.icc.is_empty() => write_icc(&mut stream, image)?,
+            Self::Pasp if image.pasp.is_some() => write_pasp(&mut stream, image.pasp.unwrap_ref())?,
+            Self::Clli if image.clli.is_some() => write_clli(&mut stream, image.clli.unwrap_ref())?,
+            Self::Clap if image.clap.is_some() => write_clap(&mut stream, image.clap.unwrap_ref())?,
+            Self::Irot if image.irot_angle.is_some() => {
+                write_irot(&mut stream, image.irot_angle.unwrap())?
+            }
+            Self::Imir if image.imir_axis.is_some() => {
+                write_imir(&mut stream, image.imir_axis.unwrap())?
+            }
+            _ => return Ok(None),
+        }
+        Ok(Some(stream.data))
+    }
+}
+
+struct Property<'a> {
+    // The whole property box.
+    data: Cow<'a, [u8]>,
+    editable: Option<EditableProperty>,
+    is_unknown: bool,
+}
+
+struct NewItem {
+    item_id: u32,
+    item_type: &'static str,
+    item_name: &'static str,
+    content_type: &'static str,
+    describes_item_id: u32,
+    payload: Vec<u8>,
+}
+
+// Rewrites the boxes of a file with an edited 'meta' box and a single 'mdat' box.
+struct MetaRewriter<'a> {
+    // The top-level boxes of the file.
+    boxes: Vec<BoxRange>,
+    meta: &'a MetaBox,
+    // Where the children of the 'meta' box start in the file.
+    meta_children_offset: usize,
+    meta_children: Vec<BoxRange>,
+    removed_item_ids: HashSet<u32>,
+    new_items: Vec<NewItem>,
+    next_item_id: u32,
+    properties: Vec<Property<'a>>,
+    associations: Vec<ItemPropertyAssociation>,
+}
+
+impl<'a> MetaRewriter<'a> {
+    fn create(
+        data: &'a [u8],
+        meta: &'a MetaBox,
+        tracks: &[crate::decoder::track::Track],
+    ) -> AvifResult<Self> {
+        let boxes = mp4box::parse_box_ranges(data, /*top_level=*/ true)?;
+        let mut meta_box = None;
+        for box_range in &boxes {
+            match box_range.box_type.as_str() {
+                "meta" if meta_box.is_none() => meta_box = Some(box_range),
+                // The offsets in these boxes cannot be updated.
+                "meta" | "mini" | "moof" | "mfra" | "sidx" | "ssix" => {
+                    return AvifError::not_implemented()
+                }
+                _ => {}
+            }
+        }
+        let meta_box = meta_box.ok_or(AvifError::NotImplemented)?;
+        // The 'meta' box is a full box.
+        let meta_children_offset = checked_add!(meta_box.payload.start, 4)?;
+        let meta_children = mp4box::parse_box_ranges(
+            &data[meta_children_offset..meta_box.payload.end],
+            /*top_level=*/ false,
+        )?;
+        let ipco = match Self::find_child(&meta_children, "iprp") {
+            Some(iprp) => {
+                let iprp_offset = meta_children_offset + iprp.payload.start;
+                let iprp_children = mp4box::parse_box_ranges(
+                    &data[iprp_offset..meta_children_offset + iprp.payload.end],
+                    /*top_level=*/ false,
+                )?;
+                let ipco = match iprp_children.first() {
+                    Some(ipco) if ipco.box_type == "ipco" => ipco,
+                    _ => return AvifError::bmff_parse_failed("first box in iprp is not ipco"),
+                };
+                let ipco_offset = iprp_offset + ipco.payload.start;
+                let ipco_data = &data[ipco_offset..iprp_offset + ipco.payload.end];
+                mp4box::parse_box_ranges(ipco_data, /*top_level=*/ false)?
+                    .into_iter()
+                    .map(|property| &ipco_data[property.range])
+                    .collect()
+            }
+            None => Vec::new(),
+        };
+        if ipco.len() != meta.iprp.properties.len() {
+            return AvifError::bmff_parse_failed("unexpected number of properties in ipco");
+        }
+        let properties = ipco
+            .into_iter()
+            .zip(&meta.iprp.properties)
+            .map(|(data, property)| Property {
+                data: Cow::Borrowed(data),
+                editable: EditableProperty::from(property),
+                is_unknown: matches!(property, ItemProperty::Unknown(_)),
+            })
+            .collect();
+        let associations = meta.iprp.associations.clone();
+
+        // ISO/IEC 14496-12 Section 8.18.3.3: group_id shall not be equal to any item_ID or
+        // track_ID, so avoid all of them for the new items.
+        let max_id = meta
+            .iinf
+            .iter()
+            .map(|x| x.item_id)
+            .chain(meta.grpl.iter().map(|x| x.group_id))
+            .chain(tracks.iter().map(|x| x.id))
+            .max()
+            .unwrap_or(0);
+        Ok(Self {
+            boxes,
+            meta,
+            meta_children_offset,
+            meta_children,
+            removed_item_ids: HashSet::new(),
+            new_items: Vec::new(),
+            next_item_id: checked_add!(max_id, 1)?,
+            properties,
+            associations,
+        })
+    }
+
+    fn find_child<'b>(children: &'b [BoxRange], box_type: &str) -> Option<&'b BoxRange> {
+        children.iter().find(|x| x.box_type == box_type)
+    }
+
+    // Replaces the metadata items of the given type describing item_id by one with the given
+    // payload, if not empty.
+    fn replace_metadata_item(
+        &mut self,
+        item_id: u32,
+        item_type: &'static str,
+        item_name: &'static str,
+        content_type: &'static str,
+        payload: Vec<u8>,
+    ) -> AvifResult<()> {
+        for item in &self.meta.iinf {
+            if item.item_type != item_type
+                || item.content_type != content_type
+                || !self.meta.iref.iter().any(|x| {
+                    x.reference_type == "cdsc"
+                        && x.from_item_id == item.item_id
+                        && x.to_item_id == item_id
+                })
+            {
+                continue;
+            }
+            // The item can only be removed if nothing else depends on it.
+            if self.meta.iref.iter().any(|x| {
+                (x.from_item_id == item.item_id || x.to_item_id == item.item_id)
+                    && !(x.reference_type == "cdsc"
+                        && x.from_item_id == item.item_id
+                        && x.to_item_id == item_id)
+            }) || self
+                .meta
+                .grpl
+                .iter()
+                .any(|x| x.entity_ids.contains(&item.item_id))
+            {
+                return AvifError::not_implemented();
+            }
+            self.removed_item_ids.insert(item.item_id);
+        }
+        if !payload.is_empty() {
+            self.new_items.push(NewItem {
+                item_id: self.next_item_id,
+                item_type,
+                item_name,
+                content_type,
+                describes_item_id: item_id,
+                payload,
+            });
+            checked_incr!(self.next_item_id, 1);
+        }
+        Ok(())
+    }
+
+    // Replaces the association of item_id with the given kind of property by an association with
+    // the given property box, if any.
+    fn replace_property(
+        &mut self,
+        item_id: u32,
+        editable: EditableProperty,
+        property: Option<Vec<u8>>,
+    ) -> AvifResult<()> {
+        let property_index = match property {
+            Some(data) => {
+                // Share identical new properties between items.
+                let index = match self
+                    .properties
+                    .iter()
+                    .position(|x| x.editable == Some(editable) && *x.data == data[..])
+                {
+                    Some(index) => index,
+                    None => {
+                        self.properties.push(Property {
+                            data: Cow::Owned(data),
+                            editable: Some(editable),
+                            is_unknown: false,
+                        });
+                        self.properties.len() - 1
+                    }
+                };
+                Some(u16_from_usize(index + 1)?)
+            }
+            None => None,
+        };
+        let entry_index = match self.associations.iter().position(|x| x.item_id == item_id) {
+            Some(entry_index) => entry_index,
+            None => {
+                if property_index.is_none() {
+                    return Ok(());
+                }
+                let entry_index = self
+                    .associations
+                    .iter()
+                    .position(|x| x.item_id > item_id)
+                    .unwrap_or(self.associations.len());
+                self.associations.insert(
+                    entry_index,
+                    ItemPropertyAssociation {
+                        item_id,
+                        associations: Vec::new(),
+                    },
+                );
+                entry_index
+            }
+        };
+        let properties = &self.properties;
+        let editable_at = |index: u16| -> Option<EditableProperty> {
+            properties
+                .get((index as usize).checked_sub(1)?)
+                .and_then(|x| x.editable)
+        };
+        let associations = &mut self.associations[entry_index].associations;
+        match associations
+            .iter()
+            .position(|x| editable_at(x.0) == Some(editable))
+        {
+            Some(position) => {
+                // Replace the first association in place, keeping its position and essential flag,
+                // and remove the others.
+                let mut index = 0;
+                associations.retain(|x| {
+                    index += 1;
+                    index - 1 == position || editable_at(x.0) != Some(editable)
+                });
+                match property_index {
+                    Some(property_index) => associations[position].0 = property_index,
+                    None => {
+                        associations.remove(position);
+                    }
+                }
+            }
+            None => {
+                if let Some(property_index) = property_index {
+                    // ISO/IEC 23008-12 Section 6.5.1: descriptive properties come before the
+                    // first transformative or unrecognized property. The transformative
+                    // properties are kept in the order clap, irot, imir.
+                    let position = associations
+                        .iter()
+                        .position(|x| match editable_at(x.0) {
+                            Some(other) => other.is_transformative() && other > editable,
+                            None => {
+                                !editable.is_transformative()
+                                    && properties
+                                        .get((x.0 as usize).wrapping_sub(1))
+                                        .is_some_and(|x| x.is_unknown)
+                            }
+                        })
+                        .unwrap_or(associations.len());
+                    associations.insert(position, (property_index, editable.is_transformative()));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Removes the editable properties that are no longer associated with any item, and updates
+    // the property indices accordingly.
+    fn remove_unused_properties(&mut self) -> AvifResult<()> {
+        let removed_item_ids = &self.removed_item_ids;
+        self.associations
+            .retain(|x| !removed_item_ids.contains(&x.item_id));
+        let used_indices = |associations: &[ItemPropertyAssociation]| -> HashSet<u16> {
+            associations
+                .iter()
+                .flat_map(|x| x.associations.iter().map(|association| association.0))
+                .collect()
+        };
+        let previously_used_indices = used_indices(&self.meta.iprp.associations);
+        let used_indices = used_indices(&self.associations);
+        let mut new_indices: Vec<u16> = create_vec_exact(self.properties.len())?;
+        let mut properties = Vec::new();
+        for (index, property) in std::mem::take(&mut self.properties).into_iter().enumerate() {
+            let index = u16_from_usize(index + 1)?;
+            if property.editable.is_some()
+                && previously_used_indices.contains(&index)
+                && !used_indices.contains(&index)
+            {
+                new_indices.push(0);
+                continue;
+            }
+            properties.push(property);
+            new_indices.push(u16_from_usize(properties.len())?);
+        }
+        self.properties = properties;
+        for association in self
+            .associations
+            .iter_mut()
+            .flat_map(|x| x.associations.iter_mut())
+        {
+            // Index 0 means that no property is associated.
+            if association.0 != 0 {
+                association.0 = *new_indices
+                    .get(association.0 as usize - 1)
+                    .ok_or(AvifError::BmffParseFailed("invalid property index".into()))?;
+            }
+        }
+        Ok(())
+    }
+
+    // Returns the data of the given child of the 'meta' box, header included.
+    fn child_data<'b>(&self, data: &'b [u8], child: &BoxRange) -> &'b [u8] {
+        &data[self.meta_children_offset + child.range.start
+            ..self.meta_children_offset + child.range.end]
+    }
+
+    // Returns the payload of the given child of the 'meta' box.
+    fn child_payload<'b>(&self, data: &'b [u8], child: &BoxRange) -> &'b [u8] {
+        &data[self.meta_children_offset + child.payload.start
+            ..self.meta_children_offset + child.payload.end]
+    }
+
+    fn write_iinf(&self, stream: &mut OStream) -> AvifResult<()> {
+        let mut items: Vec<ItemInfo> = self
+            .meta
+            .iinf
+            .iter()
+            .filter(|x| !self.removed_item_ids.contains(&x.item_id))
+            .cloned()
+            .collect();
+        for item in &self.new_items {
+            items.push(ItemInfo {
+                item_id: item.item_id,
+                item_type: item.item_type.into(),
+                item_name: item.item_name.into(),
+                content_type: item.content_type.into(),
+                ..Default::default()
+            });
+        }
+        write_iinf(stream, &items)
+    }
+
+    fn write_iref(
+        &self,
+        stream: &mut OStream,
+        data: &[u8],
+        iref: Option<&BoxRange>,
+    ) -> AvifResult<()> {
+        if let Some(iref) = iref {
+            // parse_iref() ignores the versions it does not support.
+            if self
+                .child_payload(data, iref)
+                .first()
+                .is_some_and(|version| *version > 1)
+            {
+                return AvifError::not_implemented();
+            }
+        }
+        let mut references: Vec<ItemReference> = self
+            .meta
+            .iref
+            .iter()
+            .filter(|x| !self.removed_item_ids.contains(&x.from_item_id))
+            .cloned()
+            .collect();
+        for item in &self.new_items {
+            references.push(ItemReference {
+                from_item_id: item.item_id,
+                to_item_id: item.describes_item_id,
+                reference_type: "cdsc".into(),
+                index: 0,
+            });
+        }
+        write_iref(stream, &references)
+    }
+
+    fn write_iprp(&self, stream: &mut OStream) -> AvifResult<()> {
+        let properties: Vec<&[u8]> = self.properties.iter().map(|x| &*x.data).collect();
+        write_iprp(stream, &properties, &self.associations)
+    }
+
+    // Writes the edited 'meta' box and returns the position in stream of the extent_offset fields
+    // of locations.
+    fn write_meta(
+        &self,
+        stream: &mut OStream,
+        data: &[u8],
+        locations: &[ItemLocation],
+        idat: &[u8],
+    ) -> AvifResult<Vec<Vec<usize>>> {
+        let mut offset_locations = Vec::new();
+        stream.start_full_box("meta", (0, 0))?;
+        for child in &self.meta_children {
+            match child.box_type.as_str() {
+                "iloc" => offset_locations = write_iloc(stream, locations)?,
+                "iinf" => self.write_iinf(stream)?,
+                "iref" => self.write_iref(stream, data, Some(child))?,
+                "iprp" => self.write_iprp(stream)?,
+                "idat" => {
+                    if !idat.is_empty() {
+                        stream.start_box("idat")?;
+                        stream.write_slice(idat)?;
+                        stream.finish_box()?;
+                    }
+                }
+                _ => stream.write_slice(self.child_data(data, child))?,
+            }
+        }
+        // Add the boxes that are needed by the new items.
+        let has_child = |box_type: &str| Self::find_child(&self.meta_children, box_type).is_some();
+        if !has_child("iloc") && !locations.is_empty() {
+            offset_locations = write_iloc(stream, locations)?;
+        }
+        if !has_child("iinf") && !self.new_items.is_empty() {
+            self.write_iinf(stream)?;
+        }
+        if !has_child("iref") && !self.new_items.is_empty() {
+            self.write_iref(stream, data, None)?;
+        }
+        if !has_child("iprp") && !self.associations.is_empty() {
+            self.write_iprp(stream)?;
+        }
+        stream.finish_box()?;
+        Ok(offset_locations)
+    }
+
+    // Returns a copy of the 'moov' box with the chunk offsets and the item offsets of the track
+    // level 'meta' boxes pointing to the new 'mdat' box.
+    fn write_moov(
+        data: &[u8],
+        moov: &BoxRange,
+        track_offsets: &[TrackOffsets],
+        mdat_offset: u64,
+    ) -> AvifResult<Vec<u8>> {
+        let mut moov_data = data[moov.range.clone()].to_vec();
+        let payload = moov.payload.start - moov.range.start..moov.payload.end - moov.range.start;
+        let children =
+            mp4box::parse_box_ranges(&moov_data[payload.clone()], /*top_level=*/ false)?;
+        if children.iter().any(|x| x.box_type == "meta") {
+            // The item offsets of a movie level 'meta' box are not parsed.
+            return AvifError::not_implemented();
+        }
+        let traks: Vec<&BoxRange> = children.iter().filter(|x| x.box_type == "trak").collect();
+        if traks.len() != track_offsets.len() {
+            return AvifError::bmff_parse_failed("unexpected number of trak boxes");
+        }
+        for (trak, track_offsets) in traks.iter().zip(track_offsets) {
+            let trak_payload = payload.start + trak.payload.start..payload.start + trak.payload.end;
+            if let Some(meta) = find_payload(&moov_data, trak_payload.clone(), &["meta"])? {
+                // The 'meta' box is a full box.
+                let meta_children = meta.start + 4..meta.end;
+                if let Some(iloc) = find_payload(&moov_data, meta_children, &["iloc"])? {
+                    patch_iloc(
+                        &mut moov_data[iloc],
+                        &track_offsets.item_extent_offsets,
+                        mdat_offset,
+                    )?;
+                }
+            }
+            let chunk_offsets = &track_offsets.chunk_offsets;
+            let Some(stbl) = find_payload(&moov_data, trak_payload, &["mdia", "minf", "stbl"])?
+            else {
+                if chunk_offsets.is_empty() {
+                    continue;
+                }
+                return AvifError::bmff_parse_failed("missing stbl box");
+            };
+            let stco =
+                mp4box::parse_box_ranges(&moov_data[stbl.clone()], /*top_level=*/ false)?
+                    .into_iter()
+                    .find(|x| x.box_type == "stco" || x.box_type == "co64");
+            let Some(stco) = stco else {
+                if chunk_offsets.is_empty() {
+                    continue;
+                }
+                return AvifError::bmff_parse_failed("missing stco box");
+            };
+            let large_offset = stco.box_type == "co64";
+            // Skip the version, flags and entry_count fields.
+            let mut offset = checked_add!(stbl.start + stco.payload.start, 8)?;
+            let entry_count =
+                (stco.payload.len().saturating_sub(8)) / if large_offset { 8 } else { 4 };
+            if entry_count != chunk_offsets.len() {
+                return AvifError::bmff_parse_failed("unexpected number of chunk offsets");
+            }
+            for chunk_offset in chunk_offsets {
+                let chunk_offset = checked_add!(mdat_offset, *chunk_offset)?;
+                if large_offset {
+                    moov_data[offset..offset + 8].copy_from_slice(&chunk_offset.to_be_bytes());
+                    offset += 8;
+                } else {
+                    let chunk_offset = u32_from_u64(chunk_offset)?;
+                    moov_data[offset..offset + 4].copy_from_slice(&chunk_offset.to_be_bytes());
+                    offset += 4;
+                }
+            }
+        }
+        Ok(moov_data)
+    }
+
+    // Writes the rewritten file, with all the payloads of the items and tracks in a single 'mdat'
+    // box at the end.
+    fn write(
+        mut self,
+        data: &[u8],
+        tracks: &[crate::decoder::track::Track],
+    ) -> AvifResult<Vec<u8>> {
+        self.remove_unused_properties()?;
+
+        // Offsets are relative to the start of the payload of the new 'mdat' box or 'idat' box.
+        let mut mdat: Vec<u8> = Vec::new();
+        let mut idat: Vec<u8> = Vec::new();
+        let mut locations = Vec::new();
+        for entry in &self.meta.iloc.items {
+            if self.removed_item_ids.contains(&entry.item_id) {
+                continue;
+            }
+            let mut extents = create_vec_exact(entry.extents.len())?;
+            for extent in &entry.extents {
+                if extent.size == 0 {
+                    // The extent goes until the end of the file or idat box.
+                    return AvifError::not_implemented();
+                }
+                let offset = checked_add!(entry.base_offset, extent.offset)?;
+                let new_offset = match entry.construction_method {
+                    0 => copy_range(data, offset, extent.size, &mut mdat)?,
+                    1 => copy_range(&self.meta.idat, offset, extent.size, &mut idat)?,
+                    _ => return AvifError::not_implemented(),
+                };
+                extents.push((new_offset, u64_from_usize(extent.size)?));
+            }
+            locations.push(ItemLocation {
+                item_id: entry.item_id,
+                construction_method: entry.construction_method,
+                extents,
+            });
+        }
+        for item in &self.new_items {
+            let offset = copy_range(&item.payload, 0, item.payload.len(), &mut mdat)?;
+            locations.push(ItemLocation {
+                item_id: item.item_id,
+                construction_method: 0,
+                extents: vec![(offset, u64_from_usize(item.payload.len())?)],
+            });
+        }
+        let mut track_offsets = Vec::new();
+        for track in tracks {
+            let mut offsets = TrackOffsets::default();
+            if let Some(sample_table) = &track.sample_table {
+                for (offset, size) in sample_table
+                    .chunk_offsets
+                    .iter()
+                    .zip(chunk_sizes(sample_table)?)
+                {
+                    offsets
+                        .chunk_offsets
+                        .push(copy_range(data, *offset, size, &mut mdat)?);
+                }
+            }
+            for entry in track.meta.iter().flat_map(|meta| &meta.iloc.items) {
+                let mut extent_offsets = Vec::new();
+                if entry.construction_method == 0 {
+                    for extent in &entry.extents {
+                        if extent.size == 0 {
+                            // The extent goes until the end of the file.
+                            return AvifError::not_implemented();
+                        }
+                        let offset = checked_add!(entry.base_offset, extent.offset)?;
+                        extent_offsets.push(copy_range(data, offset, extent.size, &mut mdat)?);
+                    }
+                }
+                offsets.item_extent_offsets.push(extent_offsets);
+            }
+            track_offsets.push(offsets);
+        }
+
+        // The sizes of the 'meta' and 'moov' boxes do not depend on the offset of the 'mdat' box,
+        // so the offsets are written once all the other boxes are.
+        let mut stream = OStream::default();
+        let mut offset_locations = Vec::new();
+        let mut moov_boxes = Vec::new();
+        for box_range in &self.boxes {
+            match box_range.box_type.as_str() {
+                "meta" => {
+                    offset_locations = self.write_meta(&mut stream, data, &locations, &idat)?
+                }
+                "moov" => {
+                    moov_boxes.push((stream.offset(), box_range));
+                    stream.write_slice(&data[box_range.range.clone()])?;
+                }
+                // The payloads are all moved to the new 'mdat' box.
+                "mdat" | "free" | "skip" => {}
+                _ => stream.write_slice(&data[box_range.range.clone()])?,
+            }
+        }
+        let mdat_size = checked_add!(u64_from_usize(mdat.len())?, 8)?;
+        if mdat_size <= u32::MAX as u64 {
+            // unsigned int(32) size;
+            stream.write_u32(mdat_size as u32)?;
+            // unsigned int(32) type = boxtype;
+            stream.write_str("mdat")?;
+        } else {
+            // unsigned int(32) size = 1;
+            stream.write_u32(1)?;
+            // unsigned int(32) type = boxtype;
+            stream.write_str("mdat")?;
+            // unsigned int(64) largesize;
+            stream.write_u64(checked_add!(mdat_size, 8)?)?;
+        }
+        let mdat_offset = u64_from_usize(stream.offset())?;
+        for (location, offset_locations) in locations.iter().zip(offset_locations) {
+            if location.construction_method != 0 {
+                continue;
+            }
+            for ((offset, _), offset_location) in location.extents.iter().zip(offset_locations) {
+                let offset = u32_from_u64(checked_add!(mdat_offset, *offset)?)?;
+                stream.write_u32_at_offset(offset, offset_location)?;
+            }
+        }
+        for (position, moov) in moov_boxes {
+            let moov_data = Self::write_moov(data, moov, &track_offsets, mdat_offset)?;
+            stream.data[position..position + moov_data.len()].copy_from_slice(&moov_data);
+        }
+        stream.write_slice(&mdat)?;
+        Ok(stream.data)
+    }
+}
+
+// Offsets relative to the payload of the new 'mdat' box.
+#[derive(Default)]
+struct TrackOffsets {
+    chunk_offsets: Vec<u64>,
+    // Offsets of the extents of each item of the track level 'meta' box, in 'iloc' order. Empty
+    // for the items that are not stored at file offsets.
+    item_extent_offsets: Vec<Vec<u64>>,
+}
+
+// Appends the size bytes at offset in src to dst and returns where they were appended in dst.
+fn copy_range(src: &[u8], offset: u64, size: usize, dst: &mut Vec<u8>) -> AvifResult<u64> {
+    let start = usize_from_u64(offset)?;
+    let bytes = src
+        .get(start..checked_add!(start, size)?)
+        .ok_or(AvifError::TruncatedData)?;
+    let dst_offset = u64_from_usize(dst.len())?;
+    dst.try_reserve(size)
+        .map_err(AvifError::map_out_of_memory)?;
+    dst.extend_from_slice(bytes);
+    Ok(dst_offset)
+}
+
+// Updates in place the payload of an 'iloc' box so that the extents of the items stored at file
+// offsets point to the given offsets relative to mdat_offset.
+fn patch_iloc(
+    iloc: &mut [u8],
+    item_extent_offsets: &[Vec<u64>],
+    mdat_offset: u64,
+) -> AvifResult<()> {
+    // (position, size, value) of the fields to update.
+    let mut fields: Vec<(usize, u8, u64)> = Vec::new();
+    {
+        // Section 8.11.3.2 of ISO/IEC 14496-12. The field values were validated by parse_iloc().
+        let mut stream = IStream::create(iloc);
+        let (version, _flags) = stream.read_version_and_flags()?;
+        let offset_size = stream.read_bits(4)? as u8;
+        let length_size = stream.read_bits(4)? as u8;
+        let base_offset_size = stream.read_bits(4)? as u8;
+        let index_size = if version == 1 || version == 2 {
+            stream.read_bits(4)? as u8
+        } else {
+            stream.skip_bits(4)?;
+            0
+        };
+        let item_count = if version < 2 { stream.read_u16()? as u32 } else { stream.read_u32()? };
+        if usize_from_u32(item_count)? != item_extent_offsets.len() {
+            return AvifError::bmff_parse_failed("unexpected number of items in iloc");
+        }
+        for extent_offsets in item_extent_offsets {
+            // item_ID
+            stream.skip(if version < 2 { 2 } else { 4 })?;
+            let construction_method =
+                if version == 1 || version == 2 { stream.read_u16()? & 0xf } else { 0 };
+            // data_reference_index
+            stream.skip(2)?;
+            let base_offset_position = stream.offset;
+            stream.skip(base_offset_size as usize)?;
+            let extent_count = stream.read_u16()? as usize;
+            if construction_method == 0 && extent_count != extent_offsets.len() {
+                return AvifError::bmff_parse_failed("unexpected number of extents in iloc");
+            }
+            let mut extent_offsets = extent_offsets.iter();
+            for _ in 0..extent_count {
+                stream.skip(index_size as usize)?;
+                let offset_position = stream.offset;
+                stream.skip(offset_size as usize + length_size as usize)?;
+                if construction_method != 0 {
+                    continue;
+                }
+                let offset = checked_add!(mdat_offset, *extent_offsets.next().unwrap())?;
+                if offset_size != 0 {
+                    fields.push((offset_position, offset_size, offset));
+                } else if base_offset_size != 0 && extent_count == 1 {
+                    fields.push((base_offset_position, base_offset_size, offset));
+                } else {
+                    return AvifError::not_implemented();
+                }
+            }
+            if construction_method == 0 && offset_size != 0 && base_offset_size != 0 {
+                fields.push((base_offset_position, base_offset_size, 0));
+            }
+        }
+    }
+    for (position, size, value) in fields {
+        if size == 4 {
+            iloc[position..position + 4].copy_from_slice(&u32_from_u64(value)?.to_be_bytes());
+        } else {
+            iloc[position..position + 8].copy_from_slice(&value.to_be_bytes());
+        }
+    }
+    Ok(())
+}
+
+// Returns the size of each chunk of the sample table.
+fn chunk_sizes(sample_table: &SampleTable) -> AvifResult<Vec<usize>> {
+    let mut sizes = create_vec_exact(sample_table.chunk_offsets.len())?;
+    let mut sample_index: usize = 0;
+    for chunk_index in 0..sample_table.chunk_offsets.len() {
+        let sample_count =
+            sample_table.get_sample_count_of_chunk(u32_from_usize(chunk_index)?) as usize;
+        let size = match &sample_table.sample_size {
+            SampleSize::FixedSize(size) => sample_count
+                .checked_mul(*size as usize)
+                .ok_or(AvifError::BmffParseFailed("".into()))?,
+            SampleSize::Sizes(sizes) => sizes
+                .get(sample_index..checked_add!(sample_index, sample_count)?)
+                .ok_or(AvifError::BmffParseFailed("invalid sample count".into()))?
+                .iter()
+                .map(|size| *size as usize)
+                .sum(),
+        };
+        sizes.push(size);
+        checked_incr!(sample_index, sample_count);
+    }
+    Ok(sizes)
+}
+
+// Returns the payload range in data of the box found by following path from the boxes in
+// payload.
+fn find_payload(
+    data: &[u8],
+    payload: std::ops::Range<usize>,
+    path: &[&str],
+) -> AvifResult<Option<std::ops::Range<usize>>> {
+    let mut payload = payload;
+    for box_type in path {
+        let children = mp4box::parse_box_ranges(&data[payload.clone()], /*top_level=*/ false)?;
+        match children.iter().find(|x| x.box_type == *box_type) {
+            Some(child) => {
+                payload = payload.start + child.payload.start..payload.start + child.payload.end
+            }
+            None => return Ok(None),
+        }
+    }
+    Ok(Some(payload))
+}
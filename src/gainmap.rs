@@ -13,7 +13,11 @@
 // limitations under the License.
 
 use crate::image::YuvRange;
-use crate::internal_utils::TryClone;
+use crate::internal_utils::*;
+use crate::reformat::coeffs::*;
+use crate::reformat::icc::IccProfile;
+use crate::reformat::rgb;
+use crate::reformat::transfer::SDR_WHITE_NITS;
 use crate::utils::*;
 use crate::*;
 
@@ -46,6 +50,20 @@ impl GainMapMetadata {
         Ok(())
     }
 
+    // Returns the weight to apply to the gain map for a display with the given HDR headroom, as
+    // described in Section 5.2.5 of ISO 21496-1.
+    pub(crate) fn weight(&self, hdr_headroom: f32) -> AvifResult<f32> {
+        let base_hdr_headroom = self.base_hdr_headroom.as_f64()? as f32;
+        let alternate_hdr_headroom = self.alternate_hdr_headroom.as_f64()? as f32;
+        if base_hdr_headroom == alternate_hdr_headroom {
+            return Ok(0.0);
+        }
+        Ok(
+            ((hdr_headroom - base_hdr_headroom) / (alternate_hdr_headroom - base_hdr_headroom))
+                .clamp(0.0, 1.0),
+        )
+    }
+
     #[cfg(feature = "encoder")]
     fn identical_channels(&self) -> bool {
         self.min[0] == self.min[1]
@@ -101,6 +119,130 @@ impl PartialEq for GainMap {
     }
 }
 
+/// Settings used by [`GainMap::compute`].
+#[derive(Clone, Copy, Debug)]
+pub struct GainMapComputeSettings {
+    /// Dimensions of the gain map image. 0 means the dimension of the base image. The gain map
+    /// cannot be larger than the base image.
+    pub width: u32,
+    pub height: u32,
+    /// Bit depth of the gain map image (8, 10 or 12).
+    pub depth: u8,
+    /// PixelFormat::Yuv400 produces a single channel gain map computed from the luminance. Any
+    /// other format produces a gain map with one channel per RGB component.
+    pub yuv_format: PixelFormat,
+    pub gamma: f32,
+    pub base_offset: f32,
+    pub alternate_offset: f32,
+    /// Whether the gain map is computed in the color space of the base image (true) or of the
+    /// alternate image (false).
+    pub use_base_color_space: bool,
+}
+
+impl Default for GainMapComputeSettings {
+    fn default() -> Self {
+        Self {
+            width: 0,
+            height: 0,
+            depth: 8,
+            yuv_format: PixelFormat::Yuv400,
+            gamma: 1.0,
+            base_offset: 1.0 / 64.0,
+            alternate_offset: 1.0 / 64.0,
+            use_base_color_space: true,
+        }
+    }
+}
+
+// Returns the ICC profile of the image, if any. Profiles that are not matrix/TRC based are
+// rejected with AvifError::NotImplemented since the colors of the image cannot be derived from them.
+fn icc_profile(image: &Image) -> AvifResult<Option<IccProfile>> {
+    if image.icc.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(IccProfile::parse(&image.icc)?))
+}
+
+// Returns the color primaries of the image, taking its ICC profile into account.
+fn color_primaries(image: &Image, icc_profile: Option<&IccProfile>) -> AvifResult<ColorPrimaries> {
+    match icc_profile {
+        // Gray profiles do not describe primaries.
+        Some(profile) if !profile.gray => {
+            profile.color_primaries().ok_or(AvifError::NotImplemented)
+        }
+        _ => Ok(image.color_primaries),
+    }
+}
+
+// Returns the interleaved linear RGB values of the image in the given color primaries. Linear values
+// are expressed relative to the SDR white luminance. The ICC profile of the image, if any, takes
+// precedence over its CICP values.
+fn linear_rgb_values(
+    image: &Image,
+    icc_profile: Option<&IccProfile>,
+    color_primaries: ColorPrimaries,
+) -> AvifResult<Vec<f32>> {
+    let mut rgb = rgb::Image::create_from_yuv(image);
+    rgb.depth = 16;
+    rgb.format = rgb::Format::Rgb;
+    rgb.allocate()?;
+    rgb.convert_from_yuv(image)?;
+    let (src_color_primaries, transfer_characteristics) = match icc_profile {
+        Some(profile) => match profile.to_cicp() {
+            Some((src_color_primaries, transfer_characteristics)) => (
+                if profile.gray { image.color_primaries } else { src_color_primaries },
+                transfer_characteristics,
+            ),
+            None => {
+                // Linearize with the curves of the profile and convert to the requested primaries
+                // with its colorants.
+                rgb.convert_from_icc(profile, color_primaries, TransferCharacteristics::Linear)?;
+                (
+                    if profile.gray { image.color_primaries } else { color_primaries },
+                    TransferCharacteristics::Linear,
+                )
+            }
+        },
+        None => (image.color_primaries, image.transfer_characteristics),
+    };
+    let scale = transfer_characteristics.nominal_peak_nits() / SDR_WHITE_NITS;
+    let matrix = primaries_conversion_matrix(src_color_primaries, color_primaries);
+    let width = usize_from_u32(image.width)?;
+    let mut values: Vec<f32> = create_vec_exact(checked_mul!(
+        width,
+        usize_from_u32(checked_mul!(image.height, 3)?)?
+    )?)?;
+    for y in 0..image.height {
+        let row = rgb.row16(y)?;
+        for pixel in row[..width * 3].chunks_exact(3) {
+            let mut linear: [f32; 3] = std::array::from_fn(|c| {
+                transfer_characteristics.gamma_to_linear(pixel[c] as f32 / 65535.0) * scale
+            });
+            if let Some(matrix) = &matrix {
+                linear = matrix3_apply(matrix, linear);
+            }
+            values.extend_from_slice(&linear);
+        }
+    }
+    Ok(values)
+}
+
+// Returns the bilinearly interpolated RGB value at (x, y) of a buffer of interleaved RGB values.
+fn sample_bilinear(values: &[f32], width: u32, height: u32, x: f32, y: f32) -> [f32; 3] {
+    let x = x.clamp(0.0, (width - 1) as f32);
+    let y = y.clamp(0.0, (height - 1) as f32);
+    let (x0, y0) = (x as usize, y as usize);
+    let x1 = (x0 + 1).min(width as usize - 1);
+    let y1 = (y0 + 1).min(height as usize - 1);
+    let (tx, ty) = (x - x0 as f32, y - y0 as f32);
+    let at = |x: usize, y: usize, c: usize| values[(y * width as usize + x) * 3 + c];
+    std::array::from_fn(|c| {
+        let top = at(x0, y0, c) * (1.0 - tx) + at(x1, y0, c) * tx;
+        let bottom = at(x0, y1, c) * (1.0 - tx) + at(x1, y1, c) * tx;
+        top * (1.0 - ty) + bottom * ty
+    })
+}
+
 impl GainMap {
     pub(crate) fn try_deep_clone(&self) -> AvifResult<Self> {
         Ok(GainMap {
@@ -110,12 +252,693 @@ impl GainMap {
             ..*self
         })
     }
+
+    /// Computes the gain map that maps `base_image` to `alternate_image`, as specified in
+    /// ISO 21496-1. Both images must have the same dimensions. The color spaces of the images are
+    /// derived from their ICC profiles if any, or from their CICP values otherwise. Only
+    /// matrix/TRC ICC profiles are supported, and the gain map color space must have primaries
+    /// expressible with CICP. AvifError::NotImplemented is returned otherwise. The ICC profile of
+    /// the alternate image, if any, is copied into `alt_icc` as is. The returned gain map can be
+    /// passed to `Encoder::add_image_gainmap()` along with `base_image`.
+    pub fn compute(
+        base_image: &Image,
+        alternate_image: &Image,
+        settings: &GainMapComputeSettings,
+    ) -> AvifResult<GainMap> {
+        if base_image.width != alternate_image.width
+            || base_image.height != alternate_image.height
+            || !matches!(settings.depth, 8 | 10 | 12)
+            || settings.gamma <= 0.0
+        {
+            return AvifError::invalid_argument();
+        }
+        let width = if settings.width == 0 { base_image.width } else { settings.width };
+        let height = if settings.height == 0 { base_image.height } else { settings.height };
+        if width > base_image.width || height > base_image.height {
+            return AvifError::invalid_argument();
+        }
+        let base_icc_profile = icc_profile(base_image)?;
+        let alternate_icc_profile = icc_profile(alternate_image)?;
+        let gainmap_primaries = if settings.use_base_color_space {
+            color_primaries(base_image, base_icc_profile.as_ref())?
+        } else {
+            color_primaries(alternate_image, alternate_icc_profile.as_ref())?
+        };
+        let base_values =
+            linear_rgb_values(base_image, base_icc_profile.as_ref(), gainmap_primaries)?;
+        let alternate_values = linear_rgb_values(
+            alternate_image,
+            alternate_icc_profile.as_ref(),
+            gainmap_primaries,
+        )?;
+        let base_max = base_values.iter().fold(0.0f32, |a, b| a.max(*b));
+        let alternate_max = alternate_values.iter().fold(0.0f32, |a, b| a.max(*b));
+
+        // Compute the log2 ratios between both images, averaged over the area covered by each
+        // pixel of the gain map.
+        let channel_count = if settings.yuv_format == PixelFormat::Yuv400 { 1 } else { 3 };
+        let luma_coeffs = gainmap_primaries.y_coeffs();
+        let src_width = usize_from_u32(base_image.width)?;
+        let src_height = usize_from_u32(base_image.height)?;
+        let dst_width = usize_from_u32(width)?;
+        let dst_height = usize_from_u32(height)?;
+        let mut log2_ratios: Vec<f32> =
+            create_vec_exact(checked_mul!(dst_width, dst_height * channel_count)?)?;
+        for dst_y in 0..dst_height {
+            let src_y_start = dst_y * src_height / dst_height;
+            let src_y_end = ((dst_y + 1) * src_height / dst_height).max(src_y_start + 1);
+            for dst_x in 0..dst_width {
+                let src_x_start = dst_x * src_width / dst_width;
+                let src_x_end = ((dst_x + 1) * src_width / dst_width).max(src_x_start + 1);
+                let mut sums = [0.0f64; 3];
+                for src_y in src_y_start..src_y_end {
+                    for src_x in src_x_start..src_x_end {
+                        let i = (src_y * src_width + src_x) * 3;
+                        let base = &base_values[i..i + 3];
+                        let alternate = &alternate_values[i..i + 3];
+                        let ratio = |b: f32, a: f32| {
+                            ((a + settings.alternate_offset).max(f32::MIN_POSITIVE)
+                                / (b + settings.base_offset).max(f32::MIN_POSITIVE))
+                            .log2()
+                        };
+                        if channel_count == 1 {
+                            let luma = |v: &[f32]| {
+                                v[0] * luma_coeffs[0]
+                                    + v[1] * luma_coeffs[1]
+                                    + v[2] * luma_coeffs[2]
+                            };
+                            sums[0] += ratio(luma(base), luma(alternate)) as f64;
+                        } else {
+                            for c in 0..3 {
+                                sums[c] += ratio(base[c], alternate[c]) as f64;
+                            }
+                        }
+                    }
+                }
+                let count = ((src_y_end - src_y_start) * (src_x_end - src_x_start)) as f64;
+                for sum in &sums[..channel_count] {
+                    log2_ratios.push((sum / count) as f32);
+                }
+            }
+        }
+
+        let mut min = [f32::MAX; 3];
+        let mut max = [f32::MIN; 3];
+        for pixel in log2_ratios.chunks_exact(channel_count) {
+            for c in 0..channel_count {
+                min[c] = min[c].min(pixel[c]);
+                max[c] = max[c].max(pixel[c]);
+            }
+        }
+
+        let mut gainmap_rgb = rgb::Image {
+            width,
+            height,
+            depth: settings.depth,
+            format: if channel_count == 1 { rgb::Format::Gray } else { rgb::Format::Rgb },
+            ..Default::default()
+        };
+        gainmap_rgb.allocate()?;
+        let max_channel_f = gainmap_rgb.max_channel_f();
+        for y in 0..height {
+            let ratios = &log2_ratios[usize_from_u32(y)? * dst_width * channel_count..]
+                [..dst_width * channel_count];
+            let encode = |value: f32, c: usize| {
+                let range = max[c] - min[c];
+                let normalized =
+                    if range > 0.0 { ((value - min[c]) / range).clamp(0.0, 1.0) } else { 0.0 };
+                0.5 + normalized.powf(settings.gamma) * max_channel_f
+            };
+            if settings.depth == 8 {
+                let row = gainmap_rgb.row_mut(y)?;
+                for (i, value) in ratios.iter().enumerate() {
+                    row[i] = encode(*value, i % channel_count) as u8;
+                }
+            } else {
+                let row = gainmap_rgb.row16_mut(y)?;
+                for (i, value) in ratios.iter().enumerate() {
+                    row[i] = encode(*value, i % channel_count) as u16;
+                }
+            }
+        }
+        let mut image = Image {
+            width,
+            height,
+            depth: settings.depth,
+            yuv_format: settings.yuv_format,
+            yuv_range: YuvRange::Full,
+            matrix_coefficients: MatrixCoefficients::Bt601,
+            ..Default::default()
+        };
+        gainmap_rgb.convert_to_yuv(&mut image)?;
+
+        let headroom = |image: &Image, max_linear: f32| -> f32 {
+            let peak = match image.clli {
+                Some(clli) if clli.max_cll > 0 => clli.max_cll as f32 / SDR_WHITE_NITS,
+                _ => max_linear,
+            };
+            peak.max(1.0).log2()
+        };
+        let mut metadata = GainMapMetadata {
+            base_hdr_headroom: UFraction::from(headroom(base_image, base_max) as f64),
+            alternate_hdr_headroom: UFraction::from(headroom(alternate_image, alternate_max) as f64),
+            use_base_color_space: settings.use_base_color_space,
+            ..Default::default()
+        };
+        for c in 0..3 {
+            let channel = if channel_count == 1 { 0 } else { c };
+            metadata.min[c] = Fraction::from(min[channel] as f64);
+            metadata.max[c] = Fraction::from(max[channel] as f64);
+            metadata.gamma[c] = UFraction::from(settings.gamma as f64);
+            metadata.base_offset[c] = Fraction::from(settings.base_offset as f64);
+            metadata.alternate_offset[c] = Fraction::from(settings.alternate_offset as f64);
+        }
+        metadata.is_valid()?;
+
+        let alt_clli = alternate_image
+            .clli
+            .unwrap_or(ContentLightLevelInformation {
+                max_cll: (alternate_max * SDR_WHITE_NITS)
+                    .round()
+                    .min(u16::MAX as f32) as u16,
+                max_pall: 0,
+            });
+        Ok(GainMap {
+            image,
+            metadata,
+            alt_icc: alternate_image.icc.try_clone()?,
+            alt_color_primaries: alternate_image.color_primaries,
+            alt_transfer_characteristics: alternate_image.transfer_characteristics,
+            alt_matrix_coefficients: alternate_image.matrix_coefficients,
+            alt_yuv_range: alternate_image.yuv_range,
+            alt_plane_count: if alternate_image.yuv_format == PixelFormat::Yuv400 { 1 } else { 3 },
+            alt_plane_depth: alternate_image.depth,
+            alt_clli,
+        })
+    }
+
+    /// Applies this gain map to `base_image` to produce a rendition for a display with the given
+    /// `hdr_headroom` (log2 of the ratio between the display peak and the SDR white luminance), as
+    /// specified in ISO 21496-1. A headroom equal to `metadata.base_hdr_headroom` yields the base
+    /// rendition and a headroom equal to `metadata.alternate_hdr_headroom` yields the alternate
+    /// rendition.
+    ///
+    /// The result is written into `output`, which must already be allocated with the dimensions of
+    /// `base_image`, using the given output color primaries and transfer characteristics. These
+    /// are typically those of the base image or the `alt_` fields of this gain map. Returns the
+    /// content light level information of the output.
+    pub fn apply(
+        &self,
+        base_image: &Image,
+        hdr_headroom: f32,
+        output_color_primaries: ColorPrimaries,
+        output_transfer_characteristics: TransferCharacteristics,
+        output: &mut rgb::Image,
+    ) -> AvifResult<ContentLightLevelInformation> {
+        self.metadata.is_valid()?;
+        if !output.depth_valid() {
+            return AvifError::reformat_failed();
+        }
+        if output.format.is_gray()
+            || matches!(
+                output.format,
+                rgb::Format::Rgb565 | rgb::Format::Rgba1010102
+            )
+        {
+            return AvifError::not_implemented();
+        }
+        if output.width != base_image.width || output.height != base_image.height {
+            return AvifError::invalid_argument();
+        }
+        let weight = self.metadata.weight(hdr_headroom)?;
+
+        let has_alpha = output.has_alpha();
+        let mut base_rgb = rgb::Image::create_from_yuv(base_image);
+        base_rgb.depth = 16;
+        base_rgb.format = if has_alpha { rgb::Format::Rgba } else { rgb::Format::Rgb };
+        base_rgb.allocate()?;
+        base_rgb.convert_from_yuv(base_image)?;
+
+        let mut gainmap_rgb = rgb::Image::create_from_yuv(&self.image);
+        gainmap_rgb.depth = 16;
+        gainmap_rgb.format = rgb::Format::Rgb;
+        gainmap_rgb.allocate()?;
+        gainmap_rgb.convert_from_yuv(&self.image)?;
+        let mut gainmap_values: Vec<f32> = Vec::new();
+        for y in 0..gainmap_rgb.height {
+            let row =
+                &gainmap_rgb.row16(y)?[..usize_from_u32(checked_mul!(gainmap_rgb.width, 3)?)?];
+            gainmap_values.extend(row.iter().map(|v| *v as f32 / 65535.0));
+        }
+
+        // The gain map is applied in linear light, in the color space of either the base or the
+        // alternate image.
+        let base_primaries = base_image.color_primaries;
+        let gainmap_primaries = if self.metadata.use_base_color_space {
+            base_primaries
+        } else {
+            self.alt_color_primaries
+        };
+        let to_gainmap_primaries = primaries_conversion_matrix(base_primaries, gainmap_primaries);
+        let to_output_primaries =
+            primaries_conversion_matrix(gainmap_primaries, output_color_primaries);
+        // Linear values are expressed relative to the SDR white luminance.
+        let base_transfer_characteristics = base_image.transfer_characteristics;
+        let base_scale = base_transfer_characteristics.nominal_peak_nits() / SDR_WHITE_NITS;
+        let output_scale = output_transfer_characteristics.nominal_peak_nits() / SDR_WHITE_NITS;
+        // Half-float linear output can represent values above SDR white, so it is not clamped to
+        // [0, 1]. Otherwise the headroom of the alternate rendition would be lost.
+        let unclamped_output =
+            output.is_float && output_transfer_characteristics == TransferCharacteristics::Linear;
+
+        let mut min = [0.0f32; 3];
+        let mut max = [0.0f32; 3];
+        let mut gamma_inv = [0.0f32; 3];
+        let mut base_offset = [0.0f32; 3];
+        let mut alternate_offset = [0.0f32; 3];
+        for c in 0..3 {
+            min[c] = self.metadata.min[c].as_f64()? as f32;
+            max[c] = self.metadata.max[c].as_f64()? as f32;
+            gamma_inv[c] = (1.0 / self.metadata.gamma[c].as_f64()?) as f32;
+            base_offset[c] = self.metadata.base_offset[c].as_f64()? as f32;
+            alternate_offset[c] = self.metadata.alternate_offset[c].as_f64()? as f32;
+        }
+
+        let base_channel_count = base_rgb.channel_count() as usize;
+        let output_channel_count = output.channel_count() as usize;
+        let output_offsets = output.format.offsets();
+        let output_max_channel_f = output.max_channel_f();
+        let x_ratio = gainmap_rgb.width as f32 / base_image.width as f32;
+        let y_ratio = gainmap_rgb.height as f32 / base_image.height as f32;
+        let mut max_cll = 0.0f32;
+        let mut sum_pall = 0.0f64;
+        for y in 0..output.height {
+            let base_row = base_rgb.row16(y)?;
+            let gainmap_y = (y as f32 + 0.5) * y_ratio - 0.5;
+            for x in 0..usize_from_u32(output.width)? {
+                let base_pixel = &base_row[x * base_channel_count..];
+                let mut rgb: [f32; 3] = std::array::from_fn(|c| {
+                    base_transfer_characteristics.gamma_to_linear(base_pixel[c] as f32 / 65535.0)
+                        * base_scale
+                });
+                if weight != 0.0 {
+                    if let Some(matrix) = &to_gainmap_primaries {
+                        rgb = matrix3_apply(matrix, rgb);
+                    }
+                    let gainmap_x = (x as f32 + 0.5) * x_ratio - 0.5;
+                    let gain = sample_bilinear(
+                        &gainmap_values,
+                        gainmap_rgb.width,
+                        gainmap_rgb.height,
+                        gainmap_x,
+                        gainmap_y,
+                    );
+                    for c in 0..3 {
+                        let gain = gain[c].powf(gamma_inv[c]);
+                        let log2_recovery = min[c] * (1.0 - gain) + max[c] * gain;
+                        rgb[c] = (rgb[c] + base_offset[c]) * (log2_recovery * weight).exp2()
+                            - alternate_offset[c];
+                    }
+                    if let Some(matrix) = &to_output_primaries {
+                        rgb = matrix3_apply(matrix, rgb);
+                    }
+                } else if let Some(matrix) =
+                    &primaries_conversion_matrix(base_primaries, output_color_primaries)
+                {
+                    rgb = matrix3_apply(matrix, rgb);
+                }
+                let pixel_max = rgb.iter().fold(0.0f32, |a, b| a.max(*b));
+                max_cll = max_cll.max(pixel_max);
+                sum_pall += pixel_max as f64;
+
+                let alpha = if has_alpha { base_pixel[3] as f32 / 65535.0 } else { 1.0 };
+                let mut values: [f32; 4] = [0.0, 0.0, 0.0, alpha];
+                for c in 0..3 {
+                    values[c] = if unclamped_output {
+                        rgb[c].max(0.0)
+                    } else {
+                        output_transfer_characteristics.linear_to_gamma(rgb[c] / output_scale)
+                    };
+                    if output.premultiply_alpha {
+                        values[c] *= alpha;
+                    }
+                }
+                let channel_count = if has_alpha { 4 } else { 3 };
+                let pixel_offset = x * output_channel_count;
+                if output.depth == 8 {
+                    let output_row = output.row_mut(y)?;
+                    for c in 0..channel_count {
+                        output_row[pixel_offset + output_offsets[c]] =
+                            (0.5 + values[c] * output_max_channel_f) as u8;
+                    }
+                } else {
+                    let is_float = output.is_float;
+                    let output_row = output.row16_mut(y)?;
+                    for c in 0..channel_count {
+                        output_row[pixel_offset + output_offsets[c]] = if is_float {
+                            rgb::half_float_from_f32(values[c])
+                        } else {
+                            (0.5 + values[c] * output_max_channel_f) as u16
+                        };
+                    }
+                }
+            }
+        }
+        let pixel_count = (output.width as f64) * (output.height as f64);
+        let to_nits = |v: f64| {
+            (v * SDR_WHITE_NITS as f64)
+                .round()
+                .clamp(0.0, u16::MAX as f64) as u16
+        };
+        Ok(ContentLightLevelInformation {
+            max_cll: to_nits(max_cll as f64),
+            max_pall: to_nits(sum_pall / pixel_count),
+        })
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    #[cfg(feature = "encoder")]
     use super::*;
+    use crate::image::Plane;
+
+    use test_case::test_case;
+
+    #[test_case(0.0, 0.0)]
+    #[test_case(1.0, 0.5)]
+    #[test_case(2.0, 1.0)]
+    #[test_case(3.0, 1.0)]
+    #[test_case(-1.0, 0.0)]
+    fn weight(hdr_headroom: f32, expected_weight: f32) -> AvifResult<()> {
+        let metadata = GainMapMetadata {
+            base_hdr_headroom: UFraction(0, 1),
+            alternate_hdr_headroom: UFraction(2, 1),
+            ..Default::default()
+        };
+        assert_eq!(metadata.weight(hdr_headroom)?, expected_weight);
+        // Swapping the headrooms swaps the weights.
+        let metadata = GainMapMetadata {
+            base_hdr_headroom: UFraction(2, 1),
+            alternate_hdr_headroom: UFraction(0, 1),
+            ..Default::default()
+        };
+        assert_eq!(metadata.weight(hdr_headroom)?, 1.0 - expected_weight);
+        Ok(())
+    }
+
+    fn create_image(
+        width: u32,
+        height: u32,
+        yuv_format: PixelFormat,
+        matrix_coefficients: MatrixCoefficients,
+        value: u8,
+    ) -> AvifResult<Image> {
+        let mut image = Image {
+            width,
+            height,
+            depth: 8,
+            yuv_format,
+            yuv_range: YuvRange::Full,
+            matrix_coefficients,
+            ..Default::default()
+        };
+        image.allocate_planes(Category::Color)?;
+        for plane in [Plane::Y, Plane::U, Plane::V] {
+            if image.has_plane(plane) {
+                for y in 0..image.height(plane) {
+                    image.row_mut(plane, y as u32)?.fill(value);
+                }
+            }
+        }
+        Ok(image)
+    }
+
+    #[test_case(0.0, 55)]
+    #[test_case(1.0, 110)]
+    #[test_case(2.0, 220)]
+    #[test_case(3.0, 220)]
+    fn apply(hdr_headroom: f32, expected_value: u8) -> AvifResult<()> {
+        let base_image =
+            create_image(4, 4, PixelFormat::Yuv444, MatrixCoefficients::Identity, 128)?;
+        let gainmap = GainMap {
+            // A gain map twice as small as the base image, with the maximum value.
+            image: create_image(2, 2, PixelFormat::Yuv400, MatrixCoefficients::Bt601, 255)?,
+            metadata: GainMapMetadata {
+                min: [Fraction(0, 1); 3],
+                max: [Fraction(2, 1); 3],
+                gamma: [UFraction(1, 1); 3],
+                base_offset: [Fraction(0, 1); 3],
+                alternate_offset: [Fraction(0, 1); 3],
+                base_hdr_headroom: UFraction(0, 1),
+                alternate_hdr_headroom: UFraction(2, 1),
+                use_base_color_space: true,
+            },
+            ..Default::default()
+        };
+        let mut output = rgb::Image::create_from_yuv(&base_image);
+        output.format = rgb::Format::Rgb;
+        output.allocate()?;
+        let clli = gainmap.apply(
+            &base_image,
+            hdr_headroom,
+            ColorPrimaries::Srgb,
+            TransferCharacteristics::Linear,
+            &mut output,
+        )?;
+        // Linear value of an sRGB value of 128/255 (0.2158), multiplied by the gain
+        // 2^(2 * weight).
+        for y in 0..output.height {
+            assert!(output.row(y)?.iter().all(|v| *v == expected_value));
+        }
+        assert_eq!(clli.max_cll, clli.max_pall);
+        Ok(())
+    }
+
+    #[test]
+    fn apply_float() -> AvifResult<()> {
+        // White base image, which is 1.0 in linear light.
+        let base_image =
+            create_image(4, 4, PixelFormat::Yuv444, MatrixCoefficients::Identity, 255)?;
+        let gainmap = GainMap {
+            image: create_image(4, 4, PixelFormat::Yuv400, MatrixCoefficients::Bt601, 255)?,
+            metadata: GainMapMetadata {
+                min: [Fraction(0, 1); 3],
+                max: [Fraction(2, 1); 3],
+                gamma: [UFraction(1, 1); 3],
+                base_offset: [Fraction(0, 1); 3],
+                alternate_offset: [Fraction(0, 1); 3],
+                base_hdr_headroom: UFraction(0, 1),
+                alternate_hdr_headroom: UFraction(2, 1),
+                use_base_color_space: true,
+            },
+            ..Default::default()
+        };
+        for is_float in [true, false] {
+            let mut output = rgb::Image::create_from_yuv(&base_image);
+            output.format = rgb::Format::Rgb;
+            output.depth = 16;
+            output.is_float = is_float;
+            output.allocate()?;
+            let clli = gainmap.apply(
+                &base_image,
+                /*hdr_headroom=*/ 2.0,
+                ColorPrimaries::Srgb,
+                TransferCharacteristics::Linear,
+                &mut output,
+            )?;
+            // The alternate rendition is 4 times brighter than SDR white.
+            assert_eq!(clli.max_cll, 4 * SDR_WHITE_NITS as u16);
+            // 0x4400 is 4.0 as a half float. Integer output is clamped to SDR white.
+            let expected_value = if is_float { 0x4400 } else { u16::MAX };
+            for y in 0..output.height {
+                assert!(output.row16(y)?.iter().all(|v| *v == expected_value));
+            }
+        }
+        Ok(())
+    }
+
+    fn create_gradient_image(depth: u8, value: impl Fn(u32) -> u16) -> AvifResult<Image> {
+        let mut image = Image {
+            width: 8,
+            height: 2,
+            depth,
+            yuv_format: PixelFormat::Yuv444,
+            yuv_range: YuvRange::Full,
+            matrix_coefficients: MatrixCoefficients::Identity,
+            color_primaries: ColorPrimaries::Srgb,
+            transfer_characteristics: TransferCharacteristics::Srgb,
+            ..Default::default()
+        };
+        image.allocate_planes(Category::Color)?;
+        for plane in [Plane::Y, Plane::U, Plane::V] {
+            for y in 0..image.height {
+                for x in 0..image.width {
+                    if depth == 8 {
+                        image.row_mut(plane, y)?[x as usize] = value(x) as u8;
+                    } else {
+                        image.row16_mut(plane, y)?[x as usize] = value(x);
+                    }
+                }
+            }
+        }
+        Ok(image)
+    }
+
+    #[test_case(PixelFormat::Yuv400)]
+    #[test_case(PixelFormat::Yuv444)]
+    fn compute_and_apply(yuv_format: PixelFormat) -> AvifResult<()> {
+        let base_value = |x: u32| (x * 36) as u16;
+        let base_image = create_gradient_image(8, base_value)?;
+        // The alternate image is twice as bright as the base image, encoded with PQ.
+        let alternate_value = |x: u32| {
+            let linear =
+                TransferCharacteristics::Srgb.gamma_to_linear(base_value(x) as f32 / 255.0);
+            let pq = TransferCharacteristics::Pq
+                .linear_to_gamma(linear * 2.0 * SDR_WHITE_NITS / 10000.0);
+            (0.5 + pq * 1023.0) as u16
+        };
+        let mut alternate_image = create_gradient_image(10, alternate_value)?;
+        alternate_image.transfer_characteristics = TransferCharacteristics::Pq;
+
+        let settings = GainMapComputeSettings {
+            yuv_format,
+            ..Default::default()
+        };
+        let gainmap = GainMap::compute(&base_image, &alternate_image, &settings)?;
+        assert_eq!(gainmap.image.width, base_image.width);
+        assert_eq!(gainmap.image.height, base_image.height);
+        assert_eq!(gainmap.image.yuv_format, yuv_format);
+        assert_eq!(gainmap.metadata.base_hdr_headroom, UFraction(0, 1));
+        assert!(gainmap.metadata.alternate_hdr_headroom.as_f64()? > 0.9);
+        assert_eq!(
+            gainmap.alt_transfer_characteristics,
+            TransferCharacteristics::Pq
+        );
+        assert_eq!(gainmap.alt_plane_depth, 10);
+
+        // Applying the gain map at the alternate headroom must give back the alternate image.
+        let mut output = rgb::Image::create_from_yuv(&alternate_image);
+        output.format = rgb::Format::Rgb;
+        output.allocate()?;
+        gainmap.apply(
+            &base_image,
+            gainmap.metadata.alternate_hdr_headroom.as_f64()? as f32,
+            ColorPrimaries::Srgb,
+            TransferCharacteristics::Pq,
+            &mut output,
+        )?;
+        for y in 0..output.height {
+            let row = output.row16(y)?;
+            for x in 0..output.width {
+                let expected = alternate_value(x) as i32;
+                for c in 0..3 {
+                    let actual = row[(x * 3 + c) as usize] as i32;
+                    assert!((actual - expected).abs() <= 4, "{x} {actual} {expected}");
+                }
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn compute_with_icc() -> AvifResult<()> {
+        use crate::reformat::icc::tests::*;
+
+        // The alternate image has the same colors as the base image but is encoded with a gamma
+        // of 1.8, which is only described by its ICC profile. Its CICP values are left unchanged.
+        let gamma = 461.0 / 256.0; // The closest u8Fixed8Number to 1.8.
+        let base_value = |x: u32| (x * 36) as u16;
+        let alternate_value = |x: u32| {
+            let linear =
+                TransferCharacteristics::Srgb.gamma_to_linear(base_value(x) as f32 / 255.0);
+            (0.5 + linear.powf(1.0 / gamma) * 1023.0) as u16
+        };
+        let base_image = create_gradient_image(8, base_value)?;
+        let mut alternate_image = create_gradient_image(10, alternate_value)?;
+        alternate_image.icc = rgb_profile(ColorPrimaries::Srgb, gamma_tag(1.8));
+
+        let gainmap = GainMap::compute(
+            &base_image,
+            &alternate_image,
+            &GainMapComputeSettings::default(),
+        )?;
+        // Both images have the same colors so the gain map is an identity.
+        assert!(gainmap.metadata.min[0].as_f64()?.abs() < 0.01);
+        assert!(gainmap.metadata.max[0].as_f64()?.abs() < 0.01);
+        assert_eq!(gainmap.alt_icc, alternate_image.icc);
+
+        // LUT based profiles are not supported.
+        alternate_image.icc[20..24].copy_from_slice(b"Lab ");
+        assert_eq!(
+            GainMap::compute(
+                &base_image,
+                &alternate_image,
+                &GainMapComputeSettings::default(),
+            )
+            .err(),
+            Some(AvifError::NotImplemented)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn compute_downscaled() -> AvifResult<()> {
+        let base_image = create_gradient_image(8, |x| (x * 30) as u16)?;
+        let alternate_image = create_gradient_image(8, |x| (x * 32) as u16)?;
+        let settings = GainMapComputeSettings {
+            width: 4,
+            height: 1,
+            depth: 10,
+            ..Default::default()
+        };
+        let gainmap = GainMap::compute(&base_image, &alternate_image, &settings)?;
+        assert_eq!(gainmap.image.width, 4);
+        assert_eq!(gainmap.image.height, 1);
+        assert_eq!(gainmap.image.depth, 10);
+        // Both images are SDR.
+        assert_eq!(gainmap.metadata.base_hdr_headroom, UFraction(0, 1));
+        assert_eq!(gainmap.metadata.alternate_hdr_headroom, UFraction(0, 1));
+
+        let settings = GainMapComputeSettings {
+            width: 16,
+            ..Default::default()
+        };
+        assert_eq!(
+            GainMap::compute(&base_image, &alternate_image, &settings).err(),
+            Some(AvifError::InvalidArgument)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn apply_invalid_output() -> AvifResult<()> {
+        let base_image =
+            create_image(4, 4, PixelFormat::Yuv444, MatrixCoefficients::Identity, 128)?;
+        let gainmap = GainMap {
+            image: create_image(2, 2, PixelFormat::Yuv400, MatrixCoefficients::Bt601, 255)?,
+            metadata: GainMapMetadata {
+                gamma: [UFraction(1, 1); 3],
+                base_hdr_headroom: UFraction(0, 1),
+                alternate_hdr_headroom: UFraction(1, 1),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut output = rgb::Image::create_from_yuv(&base_image);
+        output.width = 2;
+        output.allocate()?;
+        assert_eq!(
+            gainmap.apply(
+                &base_image,
+                1.0,
+                ColorPrimaries::Srgb,
+                TransferCharacteristics::Srgb,
+                &mut output,
+            ),
+            Err(AvifError::InvalidArgument)
+        );
+        Ok(())
+    }
 
     #[test]
     #[cfg(feature = "encoder")]
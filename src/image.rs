@@ -406,7 +406,7 @@ impl Image {
         self.free_planes(&[Plane::U, Plane::V])
     }
 
-    fn allocate_plane(&mut self, plane: Plane) -> AvifResult<()> {
+    pub(crate) fn allocate_plane(&mut self, plane: Plane) -> AvifResult<()> {
         // Rust has no idiomatic way to allocate memory without initializing it.
         const DEFAULT_VALUE: u16 = 0; // The default value does not matter.
         self.allocate_plane_with_default_value(plane, DEFAULT_VALUE)
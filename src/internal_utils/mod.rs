@@ -44,9 +44,10 @@ conversion_function!(u16_from_usize, u16, usize);
 #[cfg(feature = "encoder")]
 conversion_function!(u8_from_usize, u8, usize);
 conversion_function!(u32_from_u64, u32, u64);
+conversion_function!(u64_from_u128, u64, u128);
+conversion_function!(i64_from_u64, i64, u64);
 conversion_function!(u32_from_i32, u32, i32);
 conversion_function!(i32_from_u32, i32, u32);
-#[cfg(feature = "encoder")]
 conversion_function!(u16_from_u32, u16, u32);
 #[cfg(feature = "android_mediacodec")]
 conversion_function!(isize_from_i32, isize, i32);
@@ -243,6 +244,11 @@ pub(crate) fn is_auxiliary_type_alpha(aux_type: &str) -> bool {
     aux_type == AUXI_ALPHA_URN || aux_type == "urn:mpeg:hevc:2015:auxid:1"
 }
 
+pub(crate) fn is_auxiliary_type_depth(aux_type: &str) -> bool {
+    aux_type == "urn:mpeg:mpegB:cicp:systems:auxiliary:depth"
+        || aux_type == "urn:mpeg:hevc:2015:auxid:2"
+}
+
 pub(crate) fn validate_grid_image_dimensions(image: &Image, grid: &Grid) -> AvifResult<()> {
     if checked_mul!(image.width, grid.columns)? < grid.width
         || checked_mul!(image.height, grid.rows)? < grid.height
@@ -325,6 +331,11 @@ pub struct PointerSlice<T> {
     ptr: *mut [T],
 }
 
+// SAFETY: The pointed memory is only accessed through this struct (see create()) and is not tied
+// to the thread that created it, so it can be accessed from another thread like a `&mut [T]`.
+// This lets decoded images be moved to the threads that decode tiles in parallel.
+unsafe impl<T: Send> Send for PointerSlice<T> {}
+
 impl<T> PointerSlice<T> {
     /// # Safety
     /// `ptr` must live at least as long as the struct, and not be accessed other than through this
@@ -310,7 +310,7 @@ impl SampleTransform {
         let max_stack_size = self.tokens.len().div_ceil(2);
         let mut stack: Vec<StackItem> = create_vec_exact(max_stack_size)?;
         for plane in match category {
-            Category::Color | Category::Gainmap => YUV_PLANES.as_slice(),
+            Category::Color | Category::Gainmap | Category::Depth => YUV_PLANES.as_slice(),
             Category::Alpha => &[Plane::A],
         } {
             self.apply_internal(*plane, extra_inputs, output, &mut stack)?;
@@ -120,13 +120,11 @@ impl IStream<'_> {
         Ok(u64::from_be_bytes(self.get_slice(8)?.try_into().unwrap()))
     }
 
-    #[cfg(any(feature = "png", feature = "jpeg"))]
     pub(crate) fn read_u16_le(&mut self) -> AvifResult<u16> {
         assert_eq!(self.num_bits, 0);
         Ok(u16::from_le_bytes(self.get_slice(2)?.try_into().unwrap()))
     }
 
-    #[cfg(any(feature = "png", feature = "jpeg"))]
     pub(crate) fn read_u32_le(&mut self) -> AvifResult<u32> {
         assert_eq!(self.num_bits, 0);
         Ok(u32::from_le_bytes(self.get_slice(4)?.try_into().unwrap()))
@@ -152,7 +150,6 @@ impl IStream<'_> {
         Ok(self.read_u64()? as i64)
     }
 
-    #[cfg(feature = "jpeg")]
     pub(crate) fn skip_u16(&mut self) -> AvifResult<()> {
         assert_eq!(self.num_bits, 0);
         self.skip(2)
@@ -328,7 +325,6 @@ impl IStream<'_> {
         Ok(())
     }
 
-    #[cfg(feature = "avm")]
     // Variable Length Coding.
     pub(crate) fn read_uvlc(&mut self) -> AvifResult<u32> {
         let mut num_bits = 0;
@@ -570,7 +566,11 @@ impl<const LITTLE_ENDIAN: bool> OStreamImpl<LITTLE_ENDIAN> {
         self.write_u32(value.1)
     }
 
-    fn write_i32(&mut self, value: i32) -> AvifResult<()> {
+    pub(crate) fn write_i16(&mut self, value: i16) -> AvifResult<()> {
+        self.write_u16(value as u16)
+    }
+
+    pub(crate) fn write_i32(&mut self, value: i32) -> AvifResult<()> {
         self.write_u32(value as u32)
     }
 
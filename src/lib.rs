@@ -24,6 +24,7 @@
 mod internal_utils;
 
 pub mod decoder;
+pub mod depth;
 #[cfg(feature = "encoder")]
 pub mod encoder;
 pub mod gainmap;
@@ -477,10 +478,12 @@ pub enum Category {
     Color,
     Alpha,
     Gainmap,
+    Depth,
 }
 
 impl Category {
     const COUNT: usize = 3;
+    // Depth is not part of ALL: it is opt-in only, see ImageContentType::DepthMap.
     const ALL: [Category; Category::COUNT] = [Self::Color, Self::Alpha, Self::Gainmap];
 
     pub fn planes(&self) -> &[Plane] {
@@ -496,6 +499,7 @@ impl Category {
             Self::Color => "Color",
             Self::Alpha => "Alpha",
             Self::Gainmap => "GMap",
+            Self::Depth => "Depth",
         }
         .into()
     }
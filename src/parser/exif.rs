@@ -13,7 +13,6 @@
 // limitations under the License.
 
 use crate::internal_utils::stream::*;
-#[cfg(feature = "png")]
 use crate::internal_utils::*;
 use crate::parser::mp4box::BoxSize;
 use crate::*;
@@ -55,7 +54,6 @@ pub(crate) fn parse(stream: &mut IStream) -> AvifResult<()> {
     Ok(())
 }
 
-#[cfg(feature = "png")]
 pub(crate) fn get_orientation_offset(exif: &[u8]) -> AvifResult<Option<usize>> {
     let mut temp_stream = IStream::create(exif);
     let tiff_offset = usize_from_u32(parse_exif_tiff_header_offset(&mut temp_stream)?)?;
@@ -119,7 +117,6 @@ pub(crate) fn get_orientation(exif: &[u8]) -> AvifResult<(Option<u8>, Option<u8>
     })
 }
 
-#[cfg(feature = "png")]
 pub(crate) fn set_orientation(exif: &mut [u8], orientation: u8) -> AvifResult<()> {
     match get_orientation_offset(exif)? {
         Some(offset) => {
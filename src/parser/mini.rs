@@ -400,7 +400,10 @@ pub(crate) fn parse_mini(stream: &mut IStream, offset: usize) -> AvifResult<Meta
         },
         // entry 7
         if alpha_item_data_size != 0 {
-            ItemProperty::AuxiliaryType("urn:mpeg:mpegB:cicp:systems:auxiliary:alpha".into())
+            ItemProperty::AuxiliaryType(AuxiliaryType {
+                aux_type: "urn:mpeg:mpegB:cicp:systems:auxiliary:alpha".into(),
+                aux_subtype: vec![],
+            })
         } else {
             ItemProperty::Unused
         },
@@ -653,7 +656,7 @@ pub(crate) fn parse_mini(stream: &mut IStream, offset: usize) -> AvifResult<Meta
     // HDR items
     let tmap_item_id = 3;
     let gainmap_item_id = 4;
-    let _alternative_group_id = 5;
+    let alternative_group_id = 5;
     if has_gainmap {
         meta.iinf.push(ItemInfo {
             item_id: tmap_item_id,
@@ -667,8 +670,8 @@ pub(crate) fn parse_mini(stream: &mut IStream, offset: usize) -> AvifResult<Meta
             index: meta.iref.len() as u32,
         });
         meta.grpl.push(EntityGroup {
-            // id: _alternative_group_id
             grouping_type: "altr".into(),
+            group_id: alternative_group_id,
             entity_ids: vec![tmap_item_id, color_item_id],
         });
 
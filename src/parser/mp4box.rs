@@ -16,6 +16,7 @@ use crate::decoder::track::*;
 use crate::decoder::CompressionFormat;
 use crate::decoder::Extent;
 use crate::decoder::GenericIO;
+use crate::depth::*;
 use crate::gainmap::GainMapMetadata;
 use crate::image::YuvRange;
 use crate::image::MAX_PLANE_COUNT;
@@ -441,6 +442,13 @@ impl CodecConfiguration {
     }
 }
 
+// Section 6.5.8 of ISO/IEC 23008-12.
+#[derive(Clone, Debug, Default)]
+pub struct AuxiliaryType {
+    pub aux_type: String,
+    pub aux_subtype: Vec<u8>,
+}
+
 #[derive(Clone, Debug)]
 pub enum ColorInformation {
     Icc(Vec<u8>),
@@ -466,7 +474,7 @@ pub enum ItemProperty {
     CodecConfiguration(CodecConfiguration),
     ColorInformation(ColorInformation),
     PixelAspectRatio(PixelAspectRatio),
-    AuxiliaryType(String),
+    AuxiliaryType(AuxiliaryType),
     CleanAperture(CleanAperture),
     ImageRotation(u8),
     ImageMirror(u8),
@@ -479,7 +487,7 @@ pub enum ItemProperty {
 }
 
 // Section 8.11.14 of ISO/IEC 14496-12.
-#[derive(Debug, Default)]
+#[derive(Clone, Debug, Default)]
 pub struct ItemPropertyAssociation {
     pub item_id: u32,
     pub associations: Vec<(
@@ -488,22 +496,29 @@ pub struct ItemPropertyAssociation {
     )>,
 }
 
-#[derive(Debug, Default)]
+#[derive(Clone, Debug, Default)]
 pub struct ItemInfo {
     pub item_id: u32,
     pub item_protection_index: u16,
     pub item_type: String,
     pub item_name: String,
     pub content_type: String,
+    pub content_encoding: String,
+    pub hidden: bool,
 }
 
 #[derive(Debug, Default)]
 pub struct ItemPropertyBox {
     pub properties: Vec<ItemProperty>,
+    // Offset of the payload of 'ipco' in the file. Only set for the top-level 'meta' box.
+    pub ipco_offset: u64,
+    // Type and location of each property box in 'ipco', relative to ipco_offset, in the same order
+    // as properties. The payloads are only copied when needed, see describe_items().
+    pub property_ranges: Vec<BoxRange>,
     pub associations: Vec<ItemPropertyAssociation>,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct ItemReference {
     // Read this reference as "{from_item_id} is a {reference_type} for {to_item_id}"
     // (except for dimg where it is in the opposite direction).
@@ -516,6 +531,7 @@ pub struct ItemReference {
 #[derive(Debug)]
 pub struct EntityGroup {
     pub grouping_type: String,
+    pub group_id: u32,
     pub entity_ids: Vec<u32>,
 }
 
@@ -530,11 +546,26 @@ pub struct MetaBox {
     pub grpl: Vec<EntityGroup>,
 }
 
+impl MetaBox {
+    // Returns the first entity group of the given type that contains entity_id.
+    pub(crate) fn find_entity_group(
+        &self,
+        grouping_type: &str,
+        entity_id: u32,
+    ) -> Option<&EntityGroup> {
+        self.grpl
+            .iter()
+            .find(|g| g.grouping_type == grouping_type && g.entity_ids.contains(&entity_id))
+    }
+}
+
 #[derive(Debug)]
 pub struct AvifBoxes {
     pub ftyp: FileTypeBox,
     pub meta: MetaBox,
     pub tracks: Vec<Track>,
+    // Offset at which parse_fragments() must resume to find more movie fragments, if any.
+    pub fragment_offset: Option<u64>,
 }
 
 fn parse_header(stream: &mut IStream, top_level: bool) -> AvifResult<BoxHeader> {
@@ -1195,11 +1226,94 @@ fn parse_pasp(stream: &mut IStream) -> AvifResult<ItemProperty> {
 fn parse_auxC(stream: &mut IStream) -> AvifResult<ItemProperty> {
     // Section 6.5.8.2 of ISO/IEC 23008-12.
     let (_version, _flags) = stream.read_and_enforce_version_and_flags(0)?;
-    // string aux_type;
-    let auxiliary_type = stream.read_c_string()?;
-    // template unsigned int(8) aux_subtype[];
-    // until the end of the box, the semantics depend on the aux_type value
-    Ok(ItemProperty::AuxiliaryType(auxiliary_type))
+    Ok(ItemProperty::AuxiliaryType(AuxiliaryType {
+        // string aux_type;
+        aux_type: stream.read_c_string()?,
+        // template unsigned int(8) aux_subtype[];
+        // until the end of the box, the semantics depend on the aux_type value
+        aux_subtype: stream.get_slice(stream.bytes_left()?)?.to_vec(),
+    }))
+}
+
+// Reads depth_representation_info_element() as defined in Section F.14.2.4 of ITU-T H.265.
+fn parse_depth_representation_info_element(stream: &mut IStream) -> AvifResult<f64> {
+    // da_sign_flag u(1)
+    let sign = stream.read_bool()?;
+    // da_exponent u(7)
+    let exponent = stream.read_bits(7)? as i32;
+    // da_mantissa_len_minus1 u(5)
+    let mantissa_len = stream.read_bits(5)? as i32 + 1;
+    // da_mantissa u(v)
+    let mantissa = if mantissa_len > 16 {
+        let high_bits = stream.read_bits(16)? as u64;
+        (high_bits << (mantissa_len - 16)) | stream.read_bits(mantissa_len as usize - 16)? as u64
+    } else {
+        stream.read_bits(mantissa_len as usize)? as u64
+    } as f64;
+    // Section F.14.3.4 of ITU-T H.265.
+    let value = match exponent {
+        0 => 2f64.powi(-(30 + mantissa_len)) * mantissa,
+        1..=126 => 2f64.powi(exponent - 31) * (1.0 + mantissa / 2f64.powi(mantissa_len)),
+        _ => {
+            return AvifError::bmff_parse_failed(
+                "invalid exponent in depth_representation_info_element",
+            )
+        }
+    };
+    Ok(if sign { -value } else { value })
+}
+
+// Parses the depth_representation_info() structure as defined in Section F.14.2.4 of
+// ITU-T H.265. It is expected in the aux_subtype field of the 'auxC' property of depth auxiliary
+// images.
+pub(crate) fn parse_depth_representation_info(
+    stream: &mut IStream,
+) -> AvifResult<DepthRepresentationInfo> {
+    // z_near_flag u(1)
+    let z_near_flag = stream.read_bool()?;
+    // z_far_flag u(1)
+    let z_far_flag = stream.read_bool()?;
+    // d_min_flag u(1)
+    let d_min_flag = stream.read_bool()?;
+    // d_max_flag u(1)
+    let d_max_flag = stream.read_bool()?;
+    let mut info = DepthRepresentationInfo {
+        // depth_representation_type ue(v)
+        depth_representation_type: match stream.read_uvlc()? {
+            0 => DepthRepresentationType::UniformInverseZ,
+            1 => DepthRepresentationType::UniformDisparity,
+            2 => DepthRepresentationType::UniformZ,
+            3 => DepthRepresentationType::NonuniformDisparity,
+            _ => return AvifError::bmff_parse_failed("invalid depth_representation_type"),
+        },
+        ..Default::default()
+    };
+    if d_min_flag || d_max_flag {
+        // disparity_ref_view_id ue(v)
+        info.disparity_reference_view = stream.read_uvlc()?;
+    }
+    if z_near_flag {
+        info.z_near = Some(parse_depth_representation_info_element(stream)?);
+    }
+    if z_far_flag {
+        info.z_far = Some(parse_depth_representation_info_element(stream)?);
+    }
+    if d_min_flag {
+        info.d_min = Some(parse_depth_representation_info_element(stream)?);
+    }
+    if d_max_flag {
+        info.d_max = Some(parse_depth_representation_info_element(stream)?);
+    }
+    if info.depth_representation_type == DepthRepresentationType::NonuniformDisparity {
+        // depth_nonlinear_representation_num_minus1 ue(v)
+        let model_count = checked_add!(stream.read_uvlc()?, 1)?;
+        for _ in 0..model_count {
+            // depth_nonlinear_representation_model[i] ue(v)
+            info.depth_nonlinear_representation_model
+                .push(stream.read_uvlc()?);
+        }
+    }
+    Ok(info)
 }
 
 fn parse_clap(stream: &mut IStream) -> AvifResult<ItemProperty> {
@@ -1396,6 +1510,35 @@ fn parse_ipma(stream: &mut IStream) -> AvifResult<Vec<ItemPropertyAssociation>>
     Ok(ipma)
 }
 
+// Location of a box whose payload was not parsed. The ranges are relative to the data passed to
+// parse_box_ranges().
+#[derive(Debug)]
+pub struct BoxRange {
+    pub box_type: String,
+    // The whole box, header included.
+    pub range: std::ops::Range<usize>,
+    // The payload of the box, header excluded.
+    pub payload: std::ops::Range<usize>,
+}
+
+// Returns the type and location of all the boxes in data, without parsing the payloads.
+pub(crate) fn parse_box_ranges(data: &[u8], top_level: bool) -> AvifResult<Vec<BoxRange>> {
+    let mut stream = IStream::create(data);
+    let mut boxes = Vec::new();
+    while stream.has_bytes_left()? {
+        let start = stream.offset;
+        let header = parse_header(&mut stream, top_level)?;
+        let payload_start = stream.offset;
+        stream.sub_stream(&header.size)?;
+        boxes.push(BoxRange {
+            box_type: header.box_type,
+            range: start..stream.offset,
+            payload: payload_start..stream.offset,
+        });
+    }
+    Ok(boxes)
+}
+
 fn parse_iprp(stream: &mut IStream) -> AvifResult<ItemPropertyBox> {
     // Section 8.11.14.2 of ISO/IEC 14496-12.
     let header = parse_header(stream, /*top_level=*/ false)?;
@@ -1405,7 +1548,9 @@ fn parse_iprp(stream: &mut IStream) -> AvifResult<ItemPropertyBox> {
     let mut iprp = ItemPropertyBox::default();
     // Parse ipco box.
     {
+        iprp.ipco_offset = u64_from_usize(stream.offset)?;
         let mut sub_stream = stream.sub_stream(&header.size)?;
+        iprp.property_ranges = parse_box_ranges(sub_stream.data, /*top_level=*/ false)?;
         iprp.properties = parse_ipco(&mut sub_stream, /*is_track=*/ false)?;
     }
     // Parse ipma boxes.
@@ -1422,12 +1567,12 @@ fn parse_iprp(stream: &mut IStream) -> AvifResult<ItemPropertyBox> {
 
 fn parse_infe(stream: &mut IStream) -> AvifResult<ItemInfo> {
     // Section 8.11.6.2 of ISO/IEC 14496-12.
-    let (version, _flags) = stream.read_version_and_flags()?;
+    let (version, flags) = stream.read_version_and_flags()?;
     if version != 2 && version != 3 {
         return AvifError::bmff_parse_failed("infe box version 2 or 3 expected.");
     }
 
-    // TODO: check flags. ISO/IEC 23008-12:2017, Section 9.2 says:
+    // ISO/IEC 23008-12:2017, Section 9.2 says:
     // The flags field of ItemInfoEntry with version greater than or equal to 2 is specified
     // as follows:
     //   (flags & 1) equal to 1 indicates that the item is not intended to be a part of the
@@ -1436,7 +1581,10 @@ fn parse_infe(stream: &mut IStream) -> AvifResult<ItemInfo> {
     //   is intended to be a part of the presentation.
     //
     // See also Section 6.4.2.
-    let mut entry = ItemInfo::default();
+    let mut entry = ItemInfo {
+        hidden: flags & 1 == 1,
+        ..Default::default()
+    };
     if version == 2 {
         // unsigned int(16) item_ID;
         entry.item_id = stream.read_u16()? as u32;
@@ -1461,7 +1609,10 @@ fn parse_infe(stream: &mut IStream) -> AvifResult<ItemInfo> {
     if entry.item_type == "mime" {
         // utf8string content_type;
         entry.content_type = stream.read_c_string()?;
-        // utf8string content_encoding; //optional
+        if stream.has_bytes_left()? {
+            // utf8string content_encoding; //optional
+            entry.content_encoding = stream.read_c_string()?;
+        }
     }
     // if (item_type == 'uri ') {
     //  utf8string item_uri_type;
@@ -1551,7 +1702,7 @@ fn parse_grpl(stream: &mut IStream) -> AvifResult<Vec<EntityGroup>> {
         let header = parse_header(stream, /*top_level=*/ false)?;
         let (_version, _flags) = stream.read_version_and_flags()?;
         // unsigned int(32) group_id;
-        stream.skip_u32()?;
+        let group_id = stream.read_u32()?;
         let num_entities_in_group = stream.read_u32()?;
         let mut entity_ids: Vec<u32> = create_vec_exact(usize_from_u32(num_entities_in_group)?)?;
         for _ in 0..num_entities_in_group {
@@ -1560,6 +1711,7 @@ fn parse_grpl(stream: &mut IStream) -> AvifResult<Vec<EntityGroup>> {
         }
         grpl.push(EntityGroup {
             grouping_type: header.box_type.clone(),
+            group_id,
             entity_ids,
         })
     }
@@ -1604,11 +1756,15 @@ fn parse_meta(stream: &mut IStream) -> AvifResult<MetaBox> {
             }
             _ => {}
         }
+        let payload_offset = u64_from_usize(stream.offset)?;
         let mut sub_stream = stream.sub_stream(&header.size)?;
         match header.box_type.as_str() {
             "iloc" => meta.iloc = parse_iloc(&mut sub_stream)?,
             "pitm" => meta.primary_item_id = parse_pitm(&mut sub_stream)?,
-            "iprp" => meta.iprp = parse_iprp(&mut sub_stream)?,
+            "iprp" => {
+                meta.iprp = parse_iprp(&mut sub_stream)?;
+                checked_incr!(meta.iprp.ipco_offset, payload_offset);
+            }
             "iinf" => meta.iinf = parse_iinf(&mut sub_stream)?,
             "iref" => meta.iref = parse_iref(&mut sub_stream)?,
             "idat" => meta.idat = parse_idat(&mut sub_stream)?,
@@ -1822,6 +1978,32 @@ fn parse_stts(stream: &mut IStream, sample_table: &mut SampleTable) -> AvifResul
     Ok(())
 }
 
+fn parse_ctts(stream: &mut IStream, sample_table: &mut SampleTable) -> AvifResult<()> {
+    // Section 8.6.1.3.2 of ISO/IEC 14496-12.
+    let (version, _flags) = stream.read_version_and_flags()?;
+    if version > 1 {
+        return AvifError::bmff_parse_failed(format!("unsupported version ({version}) in ctts"));
+    }
+    // unsigned int(32) entry_count;
+    let entry_count = usize_from_u32(stream.read_u32()?)?;
+    sample_table.composition_offsets = create_vec_exact(entry_count)?;
+    for _ in 0..entry_count {
+        let ctts = CompositionOffset {
+            // unsigned int(32) sample_count;
+            sample_count: stream.read_u32()?,
+            // unsigned int(32) sample_offset; (version 0)
+            // signed int(32) sample_offset; (version 1)
+            sample_offset: if version == 0 {
+                stream.read_u32()? as i64
+            } else {
+                stream.read_i32()? as i64
+            },
+        };
+        sample_table.composition_offsets.push(ctts);
+    }
+    Ok(())
+}
+
 fn parse_sample_entry(stream: &mut IStream, format: String) -> AvifResult<SampleDescription> {
     // Section 8.5.2.2 of ISO/IEC 14496-12.
     let mut sample_entry = SampleDescription {
@@ -1971,6 +2153,7 @@ fn parse_stbl(stream: &mut IStream, track: &mut Track) -> AvifResult<()> {
             "stsz" => parse_stsz(&mut sub_stream, &mut sample_table)?,
             "stss" => parse_stss(&mut sub_stream, &mut sample_table)?,
             "stts" => parse_stts(&mut sub_stream, &mut sample_table)?,
+            "ctts" => parse_ctts(&mut sub_stream, &mut sample_table)?,
             "stsd" => parse_stsd(&mut sub_stream, &mut sample_table)?,
             _ => skipped_box = true,
         }
@@ -2044,43 +2227,46 @@ fn parse_elst(stream: &mut IStream, track: &mut Track) -> AvifResult<()> {
 
     // Section 8.6.6.2 of ISO/IEC 14496-12.
     let (version, flags) = stream.read_version_and_flags()?;
+    if version > 1 {
+        return AvifError::bmff_parse_failed("unsupported version in elst");
+    }
+
+    // unsigned int(32) entry_count;
+    let entry_count = stream.read_u32()?;
+    for _ in 0..entry_count {
+        let (segment_duration, media_time) = if version == 1 {
+            // unsigned int(64) segment_duration;
+            // int(64) media_time;
+            (stream.read_u64()?, stream.read_i64()?)
+        } else {
+            // unsigned int(32) segment_duration;
+            // int(32) media_time;
+            (stream.read_u32()? as u64, stream.read_i32()? as i64)
+        };
+        track.edit_list.push(EditListEntry {
+            segment_duration,
+            media_time,
+            // int(16) media_rate_integer;
+            media_rate_integer: stream.read_u16()? as i16,
+            // int(16) media_rate_fraction;
+            media_rate_fraction: stream.read_u16()? as i16,
+        });
+    }
 
     // Section 8.6.6.3 of ISO/IEC 14496-12:
     //   flags - the following values are defined. The values of flags greater than 1 are reserved
     //     RepeatEdits 1
     if (flags & 1) == 0 {
-        // The only EditList feature that we support is repetition count for animated images. So in
-        // this case, we know that the repetition count is zero and we do not care about the rest
-        // of this box.
         track.is_repeating = false;
         return Ok(());
     }
     track.is_repeating = true;
 
-    // unsigned int(32) entry_count;
-    let entry_count = stream.read_u32()?;
-    if entry_count != 1 {
-        return AvifError::bmff_parse_failed(format!("elst has entry_count ({entry_count}) != 1"));
+    // The entire edit list is repeated, so its total duration is needed for the repetition count.
+    track.segment_duration = 0;
+    for edit in &track.edit_list {
+        checked_incr!(track.segment_duration, edit.segment_duration);
     }
-
-    if version == 1 {
-        // unsigned int(64) segment_duration;
-        track.segment_duration = stream.read_u64()?;
-        // int(64) media_time;
-        stream.skip(8)?;
-    } else if version == 0 {
-        // unsigned int(32) segment_duration;
-        track.segment_duration = stream.read_u32()? as u64;
-        // int(32) media_time;
-        stream.skip(4)?;
-    } else {
-        return AvifError::bmff_parse_failed("unsupported version in elst");
-    }
-    // int(16) media_rate_integer;
-    stream.skip(2)?;
-    // int(16) media_rate_fraction;
-    stream.skip(2)?;
-
     if track.segment_duration == 0 {
         return AvifError::bmff_parse_failed("invalid value for segment_duration (0)");
     }
@@ -2137,32 +2323,393 @@ fn parse_trak(stream: &mut IStream) -> AvifResult<Track> {
     Ok(track)
 }
 
+fn parse_mvex(stream: &mut IStream) -> AvifResult<Vec<(u32, TrackExtends)>> {
+    let mut track_extends = Vec::new();
+    // Section 8.8.1.2 of ISO/IEC 14496-12.
+    while stream.has_bytes_left()? {
+        let header = parse_header(stream, /*top_level=*/ false)?;
+        let mut sub_stream = stream.sub_stream(&header.size)?;
+        if header.box_type == "trex" {
+            // Section 8.8.3.2 of ISO/IEC 14496-12.
+            let (_version, _flags) = sub_stream.read_and_enforce_version_and_flags(0)?;
+            // unsigned int(32) track_ID;
+            let track_id = sub_stream.read_u32()?;
+            track_extends.push((
+                track_id,
+                TrackExtends {
+                    // unsigned int(32) default_sample_description_index;
+                    default_sample_description_index: sub_stream.read_u32()?,
+                    // unsigned int(32) default_sample_duration;
+                    default_sample_duration: sub_stream.read_u32()?,
+                    // unsigned int(32) default_sample_size;
+                    default_sample_size: sub_stream.read_u32()?,
+                    // unsigned int(32) default_sample_flags;
+                    default_sample_flags: sub_stream.read_u32()?,
+                },
+            ));
+        }
+    }
+    Ok(track_extends)
+}
+
+fn parse_mvhd(stream: &mut IStream) -> AvifResult<u32> {
+    // Section 8.2.2.2 of ISO/IEC 14496-12.
+    let (version, _flags) = stream.read_version_and_flags()?;
+    if version == 1 {
+        // unsigned int(64) creation_time;
+        stream.skip_u64()?;
+        // unsigned int(64) modification_time;
+        stream.skip_u64()?;
+    } else if version == 0 {
+        // unsigned int(32) creation_time;
+        stream.skip_u32()?;
+        // unsigned int(32) modification_time;
+        stream.skip_u32()?;
+    } else {
+        return AvifError::bmff_parse_failed(format!("unsupported version ({version}) in mvhd"));
+    }
+    // unsigned int(32) timescale;
+    // The remaining fields are not needed.
+    stream.read_u32()
+}
+
 fn parse_moov(stream: &mut IStream) -> AvifResult<Vec<Track>> {
     let mut tracks: Vec<Track> = Vec::new();
+    let mut track_extends = Vec::new();
+    let mut movie_timescale = 0;
     // Section 8.2.1.2 of ISO/IEC 14496-12.
     while stream.has_bytes_left()? {
         let header = parse_header(stream, /*top_level=*/ false)?;
         let mut sub_stream = stream.sub_stream(&header.size)?;
-        if header.box_type == "trak" {
-            let track = parse_trak(&mut sub_stream)?;
-            if track.is_video_handler() && (track.width == 0 || track.height == 0) {
-                return AvifError::bmff_parse_failed("invalid track dimensions");
+        match header.box_type.as_str() {
+            "trak" => {
+                let track = parse_trak(&mut sub_stream)?;
+                if track.is_video_handler() && (track.width == 0 || track.height == 0) {
+                    return AvifError::bmff_parse_failed("invalid track dimensions");
+                }
+                tracks.push(track);
             }
-            tracks.push(track);
+            "mvex" => {
+                if !track_extends.is_empty() {
+                    return AvifError::bmff_parse_failed("moov box contains multiple mvex boxes");
+                }
+                track_extends = parse_mvex(&mut sub_stream)?;
+            }
+            "mvhd" => movie_timescale = parse_mvhd(&mut sub_stream)?,
+            _ => {}
         }
     }
     if tracks.is_empty() {
         return AvifError::bmff_parse_failed("moov box does not contain any tracks");
     }
+    for track in &mut tracks {
+        track.movie_timescale = movie_timescale;
+    }
+    for (track_id, extends) in track_extends {
+        if let Some(track) = tracks.iter_mut().find(|track| track.id == track_id) {
+            track.track_extends = Some(extends);
+        }
+    }
     Ok(tracks)
 }
 
+// Values of the flags of the 'tfhd' and 'trun' boxes. Section 8.8.7.1 and 8.8.8.1 of
+// ISO/IEC 14496-12.
+const TFHD_BASE_DATA_OFFSET_PRESENT: u32 = 0x000001;
+const TFHD_SAMPLE_DESCRIPTION_INDEX_PRESENT: u32 = 0x000002;
+const TFHD_DEFAULT_SAMPLE_DURATION_PRESENT: u32 = 0x000008;
+const TFHD_DEFAULT_SAMPLE_SIZE_PRESENT: u32 = 0x000010;
+const TFHD_DEFAULT_SAMPLE_FLAGS_PRESENT: u32 = 0x000020;
+const TFHD_DEFAULT_BASE_IS_MOOF: u32 = 0x020000;
+const TRUN_DATA_OFFSET_PRESENT: u32 = 0x000001;
+const TRUN_FIRST_SAMPLE_FLAGS_PRESENT: u32 = 0x000004;
+const TRUN_SAMPLE_DURATION_PRESENT: u32 = 0x000100;
+const TRUN_SAMPLE_SIZE_PRESENT: u32 = 0x000200;
+const TRUN_SAMPLE_FLAGS_PRESENT: u32 = 0x000400;
+const TRUN_SAMPLE_COMPOSITION_TIME_OFFSET_PRESENT: u32 = 0x000800;
+// sample_is_non_sync_sample bit of the sample flags. Section 8.8.3.1 of ISO/IEC 14496-12.
+const SAMPLE_IS_NON_SYNC_SAMPLE: u32 = 0x010000;
+
+// Returns the runs of samples described by the given 'moof' box, which starts at moof_offset in
+// the file.
+pub(crate) fn parse_moof(
+    stream: &mut IStream,
+    moof_offset: u64,
+    tracks: &[Track],
+) -> AvifResult<Vec<TrackRun>> {
+    let mut runs = Vec::new();
+    // The default base data offset of the first track fragment is the start of the 'moof' box,
+    // and the end of the data of the previous track fragment for the others.
+    let mut data_end = moof_offset;
+    // Section 8.8.4.2 of ISO/IEC 14496-12.
+    while stream.has_bytes_left()? {
+        let header = parse_header(stream, /*top_level=*/ false)?;
+        let mut sub_stream = stream.sub_stream(&header.size)?;
+        if header.box_type == "traf" {
+            data_end = parse_traf(&mut sub_stream, moof_offset, data_end, tracks, &mut runs)?;
+        }
+    }
+    Ok(runs)
+}
+
+// Appends the runs of samples of the given 'traf' box to runs and returns the offset of the end
+// of their data.
+fn parse_traf(
+    stream: &mut IStream,
+    moof_offset: u64,
+    default_base_data_offset: u64,
+    tracks: &[Track],
+    runs: &mut Vec<TrackRun>,
+) -> AvifResult<u64> {
+    let mut track_fragment: Option<(u32, TrackExtends)> = None;
+    let mut base_data_offset = default_base_data_offset;
+    let mut data_end = default_base_data_offset;
+    // Section 8.8.6.2 of ISO/IEC 14496-12.
+    while stream.has_bytes_left()? {
+        let header = parse_header(stream, /*top_level=*/ false)?;
+        let mut sub_stream = stream.sub_stream(&header.size)?;
+        match header.box_type.as_str() {
+            "tfhd" => {
+                if track_fragment.is_some() {
+                    return AvifError::bmff_parse_failed("traf box contains multiple tfhd boxes");
+                }
+                // Section 8.8.7.2 of ISO/IEC 14496-12.
+                let (_version, flags) = sub_stream.read_and_enforce_version_and_flags(0)?;
+                // unsigned int(32) track_ID;
+                let track_id = sub_stream.read_u32()?;
+                let Some(mut defaults) = tracks
+                    .iter()
+                    .find(|track| track.id == track_id)
+                    .and_then(|track| track.track_extends)
+                else {
+                    return AvifError::bmff_parse_failed(format!(
+                        "track fragment found for track {track_id} which has no trex box"
+                    ));
+                };
+                if (flags & TFHD_BASE_DATA_OFFSET_PRESENT) != 0 {
+                    // unsigned int(64) base_data_offset;
+                    base_data_offset = sub_stream.read_u64()?;
+                } else if (flags & TFHD_DEFAULT_BASE_IS_MOOF) != 0 {
+                    base_data_offset = moof_offset;
+                }
+                data_end = base_data_offset;
+                if (flags & TFHD_SAMPLE_DESCRIPTION_INDEX_PRESENT) != 0 {
+                    // unsigned int(32) sample_description_index;
+                    defaults.default_sample_description_index = sub_stream.read_u32()?;
+                }
+                if (flags & TFHD_DEFAULT_SAMPLE_DURATION_PRESENT) != 0 {
+                    // unsigned int(32) default_sample_duration;
+                    defaults.default_sample_duration = sub_stream.read_u32()?;
+                }
+                if (flags & TFHD_DEFAULT_SAMPLE_SIZE_PRESENT) != 0 {
+                    // unsigned int(32) default_sample_size;
+                    defaults.default_sample_size = sub_stream.read_u32()?;
+                }
+                if (flags & TFHD_DEFAULT_SAMPLE_FLAGS_PRESENT) != 0 {
+                    // unsigned int(32) default_sample_flags;
+                    defaults.default_sample_flags = sub_stream.read_u32()?;
+                }
+                if defaults.default_sample_description_index == 0 {
+                    return AvifError::bmff_parse_failed("invalid sample_description_index 0");
+                }
+                track_fragment = Some((track_id, defaults));
+            }
+            "trun" => {
+                let Some((track_id, defaults)) = track_fragment else {
+                    return AvifError::bmff_parse_failed("trun box found before tfhd box");
+                };
+                // The base data offset of the 'tfhd' box applies to the first 'trun' box only,
+                // the data of the next ones follows.
+                let run = parse_trun(
+                    &mut sub_stream,
+                    track_id,
+                    &defaults,
+                    base_data_offset,
+                    data_end,
+                )?;
+                data_end = run.offset;
+                for sample in &run.samples {
+                    data_end = checked_add!(data_end, sample.size as u64)?;
+                }
+                runs.push(run);
+            }
+            _ => {}
+        }
+    }
+    Ok(data_end)
+}
+
+fn parse_trun(
+    stream: &mut IStream,
+    track_id: u32,
+    defaults: &TrackExtends,
+    base_data_offset: u64,
+    default_offset: u64,
+) -> AvifResult<TrackRun> {
+    // Section 8.8.8.2 of ISO/IEC 14496-12.
+    let (version, flags) = stream.read_version_and_flags()?;
+    if version > 1 {
+        return AvifError::bmff_parse_failed(format!("unsupported version ({version}) in trun"));
+    }
+    // unsigned int(32) sample_count;
+    let sample_count = usize_from_u32(stream.read_u32()?)?;
+    let offset = if (flags & TRUN_DATA_OFFSET_PRESENT) != 0 {
+        // signed int(32) data_offset;
+        let data_offset = stream.read_i32()?;
+        base_data_offset
+            .checked_add_signed(data_offset as i64)
+            .ok_or(AvifError::BmffParseFailed(
+                "invalid data_offset in trun".into(),
+            ))?
+    } else {
+        default_offset
+    };
+    let first_sample_flags = if (flags & TRUN_FIRST_SAMPLE_FLAGS_PRESENT) != 0 {
+        // unsigned int(32) first_sample_flags;
+        Some(stream.read_u32()?)
+    } else {
+        None
+    };
+    // Each sample takes at least one byte in the file, so sample_count cannot be greater than
+    // the number of bytes left if all fields are absent. Otherwise reading the fields fails.
+    let mut samples: Vec<FragmentSample> =
+        create_vec_exact(std::cmp::min(sample_count, stream.bytes_left()?))?;
+    for index in 0..sample_count {
+        let duration = if (flags & TRUN_SAMPLE_DURATION_PRESENT) != 0 {
+            // unsigned int(32) sample_duration;
+            stream.read_u32()?
+        } else {
+            defaults.default_sample_duration
+        };
+        let size = if (flags & TRUN_SAMPLE_SIZE_PRESENT) != 0 {
+            // unsigned int(32) sample_size;
+            stream.read_u32()?
+        } else {
+            defaults.default_sample_size
+        };
+        let sample_flags = if (flags & TRUN_SAMPLE_FLAGS_PRESENT) != 0 {
+            // unsigned int(32) sample_flags;
+            stream.read_u32()?
+        } else if index == 0 && first_sample_flags.is_some() {
+            first_sample_flags.unwrap()
+        } else {
+            defaults.default_sample_flags
+        };
+        let composition_offset = if (flags & TRUN_SAMPLE_COMPOSITION_TIME_OFFSET_PRESENT) != 0 {
+            if version == 0 {
+                // unsigned int(32) sample_composition_time_offset;
+                stream.read_u32()? as i64
+            } else {
+                // signed int(32) sample_composition_time_offset;
+                stream.read_i32()? as i64
+            }
+        } else {
+            0
+        };
+        if size == 0 {
+            return AvifError::bmff_parse_failed("invalid sample size 0 in trun");
+        }
+        samples.push(FragmentSample {
+            size,
+            duration,
+            sync: (sample_flags & SAMPLE_IS_NON_SYNC_SAMPLE) == 0,
+            composition_offset,
+        });
+    }
+    Ok(TrackRun {
+        track_id,
+        offset,
+        sample_description_index: defaults.default_sample_description_index,
+        samples,
+    })
+}
+
+// Parses the 'moof' boxes starting at offset and appends their runs of samples to tracks. Parsing
+// stops at the first fragment whose boxes or samples are not available yet. Returns the offset at
+// which parsing must resume once more data is available, or None if the end of the stream was
+// reached.
+pub(crate) fn parse_fragments(
+    io: &mut GenericIO,
+    offset: u64,
+    tracks: &mut [Track],
+) -> AvifResult<Option<u64>> {
+    let mut parse_offset = offset;
+    loop {
+        let size_hint = io.size_hint();
+        if size_hint != 0 && parse_offset >= size_hint {
+            // More data may be appended to the stream later.
+            return Ok(Some(parse_offset));
+        }
+        // Read just enough to get the longest possible valid box header (4+4+8+16 bytes).
+        let header_data = match io.read(parse_offset, 32) {
+            Ok(header_data) => header_data,
+            Err(AvifError::WaitingOnIo) => return Ok(Some(parse_offset)),
+            Err(err) => return Err(err),
+        };
+        if header_data.is_empty() {
+            return Ok(Some(parse_offset));
+        }
+        let header_data_size = header_data.len();
+        let mut header_stream = IStream::create(header_data);
+        let header = match parse_header(&mut header_stream, /*top_level=*/ true) {
+            Ok(header) => header,
+            // The box header may be incomplete.
+            Err(_) if header_data_size < 32 => return Ok(Some(parse_offset)),
+            Err(err) => return Err(err),
+        };
+        let box_offset = checked_add!(parse_offset, header_stream.offset as u64)?;
+        if header.box_type == "moof" {
+            let box_data = match header.size {
+                BoxSize::UntilEndOfStream => io.read(box_offset, usize::MAX),
+                BoxSize::FixedSize(size) => io.read_exact(box_offset, size),
+            };
+            let box_data = match box_data {
+                Ok(box_data) => box_data,
+                // The box is not fully available yet.
+                Err(AvifError::WaitingOnIo | AvifError::TruncatedData) => {
+                    return Ok(Some(parse_offset))
+                }
+                Err(err) => return Err(err),
+            };
+            let mut box_stream = IStream::create(box_data);
+            let runs = parse_moof(&mut box_stream, parse_offset, tracks)?;
+            let mut data_end = 0;
+            for run in &runs {
+                let mut run_end = run.offset;
+                for sample in &run.samples {
+                    run_end = checked_add!(run_end, sample.size as u64)?;
+                }
+                data_end = std::cmp::max(data_end, run_end);
+            }
+            let size_hint = io.size_hint();
+            if size_hint != 0 && data_end > size_hint {
+                // The samples of this fragment are not available yet.
+                return Ok(Some(parse_offset));
+            }
+            for run in &runs {
+                if let Some(track) = tracks.iter_mut().find(|track| track.id == run.track_id) {
+                    track.append_run(run)?;
+                }
+            }
+        }
+        if header.size == BoxSize::UntilEndOfStream {
+            // There is no other box after this one because it goes till the end of the stream.
+            return Ok(None);
+        }
+        parse_offset = box_offset
+            .checked_add(header.size() as u64)
+            .ok_or(AvifError::BmffParseFailed("invalid parse offset".into()))?;
+    }
+}
+
 pub(crate) fn parse(io: &mut GenericIO) -> AvifResult<AvifBoxes> {
     let mut ftyp: Option<FileTypeBox> = None;
     let mut meta: Option<MetaBox> = None;
     let mut seen_mini = false;
     let mut tracks: Option<Vec<Track>> = None;
     let mut parse_offset: u64 = 0;
+    // True once all the required boxes were parsed, if the tracks may have movie fragments.
+    let mut has_fragments = false;
     loop {
         // Read just enough to get the longest possible valid box header (4+4+8+16 bytes).
         let header_data = io.read(parse_offset, 32)?;
@@ -2200,7 +2747,11 @@ pub(crate) fn parse(io: &mut GenericIO) -> AvifResult<AvifBoxes> {
                             return AvifError::invalid_ftyp();
                         }
                     }
-                    "meta" => meta = Some(parse_meta(&mut box_stream)?),
+                    "meta" => {
+                        let mut meta_box = parse_meta(&mut box_stream)?;
+                        checked_incr!(meta_box.iprp.ipco_offset, parse_offset);
+                        meta = Some(meta_box);
+                    }
                     "moov" => tracks = Some(parse_moov(&mut box_stream)?),
                     "mini" => {
                         seen_mini = true;
@@ -2229,8 +2780,15 @@ pub(crate) fn parse(io: &mut GenericIO) -> AvifResult<AvifBoxes> {
                         enough_information = false;
                     }
                     if enough_information {
-                        // Enough information has been parsed to consider parse a success.
-                        break;
+                        // Enough information has been parsed to consider parse a success. Movie
+                        // fragments, if any, are looked for in the rest of the file below.
+                        has_fragments = tracks
+                            .iter()
+                            .flatten()
+                            .any(|track| track.track_extends.is_some());
+                        if !has_fragments {
+                            break;
+                        }
                     }
                 }
             }
@@ -2238,11 +2796,15 @@ pub(crate) fn parse(io: &mut GenericIO) -> AvifResult<AvifBoxes> {
         }
         if header.size == BoxSize::UntilEndOfStream {
             // There is no other box after this one because it goes till the end of the stream.
+            has_fragments = false;
             break;
         }
         parse_offset = parse_offset
             .checked_add(header.size() as u64)
             .ok_or(AvifError::BmffParseFailed("invalid parse offset".into()))?;
+        if has_fragments {
+            break;
+        }
     }
     if ftyp.is_none() {
         return AvifError::invalid_ftyp();
@@ -2257,10 +2819,17 @@ pub(crate) fn parse(io: &mut GenericIO) -> AvifResult<AvifBoxes> {
     {
         return AvifError::truncated_data();
     }
+    let mut tracks = tracks.unwrap_or_default();
+    let fragment_offset = if has_fragments {
+        parse_fragments(io, parse_offset, &mut tracks)?
+    } else {
+        None
+    };
     Ok(AvifBoxes {
         ftyp,
         meta: meta.unwrap_or_default(),
-        tracks: tracks.unwrap_or_default(),
+        tracks,
+        fragment_offset,
     })
 }
 
@@ -2417,8 +2986,16 @@ pub(crate) fn parse_sato(stream: &mut IStream, num_inputs: usize) -> AvifResult<
 
 #[cfg(test)]
 mod tests {
+    use crate::decoder::track::*;
+    use crate::decoder::GenericIO;
+    use crate::depth::*;
+    use crate::internal_utils::io::DecoderMemoryIO;
+    use crate::internal_utils::stream::IStream;
     use crate::parser::mp4box;
     use crate::AvifResult;
+    use crate::OptionExtension;
+    use crate::RepetitionCount;
+    use test_case::test_case;
 
     #[test]
     fn peek_compatible_file_type() -> AvifResult<()> {
@@ -2444,4 +3021,344 @@ mod tests {
         }
         Ok(())
     }
+
+    fn full_box(box_type: &str, version_and_flags: u32, payload: &[u32]) -> Vec<u8> {
+        let size = 12 + 4 * payload.len() as u32;
+        let mut data = size.to_be_bytes().to_vec();
+        data.extend_from_slice(box_type.as_bytes());
+        data.extend_from_slice(&version_and_flags.to_be_bytes());
+        for value in payload {
+            data.extend_from_slice(&value.to_be_bytes());
+        }
+        data
+    }
+
+    #[test]
+    fn parse_depth_representation_info() -> AvifResult<()> {
+        // z_near_flag=1, z_far_flag=1, d_min_flag=0, d_max_flag=0, depth_representation_type=2,
+        // z_near=1.0 (exponent 31, mantissa 0b0), z_far=-3.0 (exponent 32, mantissa 0b1).
+        let data = [0xc6, 0x3e, 0x05, 0x00, 0x20];
+        let info = mp4box::parse_depth_representation_info(&mut IStream::create(&data))?;
+        assert_eq!(
+            info,
+            DepthRepresentationInfo {
+                z_near: Some(1.0),
+                z_far: Some(-3.0),
+                depth_representation_type: DepthRepresentationType::UniformZ,
+                ..Default::default()
+            }
+        );
+
+        // z_near_flag=0, z_far_flag=0, d_min_flag=1, d_max_flag=1, depth_representation_type=3,
+        // disparity_ref_view_id=0, d_min=0.0 (exponent 0, mantissa 0b0), d_max=2^-31 (exponent 0,
+        // mantissa 0b1), depth_nonlinear_representation_model=[0, 4].
+        let data = [0x32, 0x40, 0x00, 0x00, 0x05, 0x4a];
+        let info = mp4box::parse_depth_representation_info(&mut IStream::create(&data))?;
+        assert_eq!(
+            info,
+            DepthRepresentationInfo {
+                d_min: Some(0.0),
+                d_max: Some(2f64.powi(-31)),
+                depth_representation_type: DepthRepresentationType::NonuniformDisparity,
+                depth_nonlinear_representation_model: vec![0, 4],
+                ..Default::default()
+            }
+        );
+
+        // Truncated.
+        assert!(mp4box::parse_depth_representation_info(&mut IStream::create(&data[..3])).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn parse_moof() -> AvifResult<()> {
+        let tfhd = full_box("tfhd", 0x020000, &[/*track_ID=*/ 1]);
+        #[rustfmt::skip]
+        let trun1 = full_box("trun", 0x000701, &[
+            /*sample_count=*/ 2, /*data_offset=*/ 100,
+            /*duration, size, flags=*/ 10, 20, 0x02000000,
+            /*duration, size, flags=*/ 10, 30, 0x01010000,
+        ]);
+        let trun2 = full_box("trun", 0x000300, &[/*sample_count=*/ 1, 20, 40]);
+        let traf_size = 8 + tfhd.len() + trun1.len() + trun2.len();
+        let mut data = full_box("mfhd", 0, &[/*sequence_number=*/ 1]);
+        data.extend_from_slice(&(traf_size as u32).to_be_bytes());
+        data.extend_from_slice(b"traf");
+        data.extend_from_slice(&tfhd);
+        data.extend_from_slice(&trun1);
+        data.extend_from_slice(&trun2);
+
+        let mut track = Track {
+            id: 1,
+            sample_table: Some(SampleTable::default()),
+            ..Default::default()
+        };
+        // There is no 'trex' box for this track.
+        assert!(mp4box::parse_moof(
+            &mut IStream::create(&data),
+            1000,
+            std::slice::from_ref(&track)
+        )
+        .is_err());
+
+        track.track_extends = Some(TrackExtends {
+            default_sample_description_index: 1,
+            ..Default::default()
+        });
+        let runs = mp4box::parse_moof(
+            &mut IStream::create(&data),
+            1000,
+            std::slice::from_ref(&track),
+        )?;
+        assert_eq!(runs.len(), 2);
+        assert_eq!(runs[0].offset, 1100);
+        assert_eq!(runs[0].samples.len(), 2);
+        assert!(runs[0].samples[0].sync);
+        assert!(!runs[0].samples[1].sync);
+        // The data of the second run follows the data of the first run.
+        assert_eq!(runs[1].offset, 1150);
+        assert_eq!(runs[1].samples.len(), 1);
+        assert!(runs[1].samples[0].sync);
+
+        for run in &runs {
+            track.append_run(run)?;
+        }
+        assert_eq!(track.media_duration, 40);
+        let sample_table = track.sample_table.unwrap();
+        assert_eq!(sample_table.chunk_offsets, [1100, 1150]);
+        assert_eq!(sample_table.get_sample_count_of_chunk(0), 2);
+        assert_eq!(sample_table.get_sample_count_of_chunk(1), 1);
+        assert_eq!(sample_table.sample_size(2)?, 40);
+        assert_eq!(sample_table.sync_samples, [1, 3]);
+        assert_eq!(sample_table.image_delta(1)?, 10);
+        assert_eq!(sample_table.image_delta(2)?, 20);
+        Ok(())
+    }
+
+    // Returns a 'moof' box followed by an 'mdat' box containing a single sample of track_id.
+    fn fragment(track_id: u32) -> Vec<u8> {
+        const SAMPLE_SIZE: u32 = 4;
+        let tfhd = full_box("tfhd", 0x020000, &[track_id]);
+        let mfhd = full_box("mfhd", 0, &[/*sequence_number=*/ 1]);
+        // The 'trun' box has 4 payload values. The 'moof', 'traf' and 'mdat' box headers are 8
+        // bytes each.
+        let data_offset = (mfhd.len() + tfhd.len() + 12 + 4 * 4 + 3 * 8) as u32;
+        #[rustfmt::skip]
+        let trun = full_box("trun", 0x000301, &[
+            /*sample_count=*/ 1, data_offset, /*duration, size=*/ 10, SAMPLE_SIZE,
+        ]);
+        let traf_size = 8 + tfhd.len() + trun.len();
+        let moof_size = 8 + mfhd.len() + traf_size;
+        let mut data = (moof_size as u32).to_be_bytes().to_vec();
+        data.extend_from_slice(b"moof");
+        data.extend_from_slice(&mfhd);
+        data.extend_from_slice(&(traf_size as u32).to_be_bytes());
+        data.extend_from_slice(b"traf");
+        data.extend_from_slice(&tfhd);
+        data.extend_from_slice(&trun);
+        assert_eq!(data.len() + 8, data_offset as usize);
+        data.extend_from_slice(&(8 + SAMPLE_SIZE).to_be_bytes());
+        data.extend_from_slice(b"mdat");
+        data.extend_from_slice(&[0; SAMPLE_SIZE as usize]);
+        data
+    }
+
+    #[test]
+    fn parse_fragments() -> AvifResult<()> {
+        let fragment_size = fragment(1).len() as u64;
+        let data = [fragment(1), fragment(1)].concat();
+        let mut tracks = [Track {
+            id: 1,
+            sample_table: Some(SampleTable::default()),
+            track_extends: Some(TrackExtends {
+                default_sample_description_index: 1,
+                ..Default::default()
+            }),
+            ..Default::default()
+        }];
+        let sample_count =
+            |tracks: &[Track]| tracks[0].sample_table.unwrap_ref().chunk_offsets.len();
+        let io = |size: usize| -> GenericIO {
+            Box::new(DecoderMemoryIO {
+                data: data[..size].to_vec(),
+            })
+        };
+
+        // The sample of the second fragment is not available yet.
+        let offset = mp4box::parse_fragments(&mut io(data.len() - 1), 0, &mut tracks)?;
+        assert_eq!(offset, Some(fragment_size));
+        assert_eq!(sample_count(&tracks), 1);
+        // Neither is its 'moof' box.
+        let offset = mp4box::parse_fragments(
+            &mut io(fragment_size as usize + 10),
+            fragment_size,
+            &mut tracks,
+        )?;
+        assert_eq!(offset, Some(fragment_size));
+        assert_eq!(sample_count(&tracks), 1);
+        // Parsing resumes where it stopped once more data is available.
+        let offset = mp4box::parse_fragments(&mut io(data.len()), fragment_size, &mut tracks)?;
+        assert_eq!(offset, Some(data.len() as u64));
+        assert_eq!(sample_count(&tracks), 2);
+        assert_eq!(
+            tracks[0].sample_table.unwrap_ref().chunk_offsets,
+            [fragment_size - 4, data.len() as u64 - 4]
+        );
+
+        // Invalid fragments are errors.
+        let data = [fragment(1), fragment(2)].concat();
+        let mut io: GenericIO = Box::new(DecoderMemoryIO { data });
+        assert!(mp4box::parse_fragments(&mut io, 0, &mut tracks).is_err());
+        Ok(())
+    }
+
+    // Size of the box header preceding the version and flags.
+    const BOX_HEADER_SIZE: usize = 8;
+
+    // Four samples of 10 media timescale units in decoding order I P B B, presented in the order
+    // I B B P.
+    fn sequence_track(elst: &[u8]) -> AvifResult<Track> {
+        let mut sample_table = SampleTable::default();
+        let stts = full_box("stts", 0, &[/*entry_count=*/ 1, 4, 10]);
+        mp4box::parse_stts(
+            &mut IStream::create(&stts[BOX_HEADER_SIZE..]),
+            &mut sample_table,
+        )?;
+        #[rustfmt::skip]
+        let ctts = full_box("ctts", 0, &[
+            /*entry_count=*/ 3,
+            /*sample_count, sample_offset=*/ 1, 10,
+            /*sample_count, sample_offset=*/ 1, 30,
+            /*sample_count, sample_offset=*/ 2, 0,
+        ]);
+        mp4box::parse_ctts(
+            &mut IStream::create(&ctts[BOX_HEADER_SIZE..]),
+            &mut sample_table,
+        )?;
+        let mut track = Track {
+            id: 1,
+            movie_timescale: 1000,
+            media_timescale: 100,
+            media_duration: 40,
+            track_duration: 2800,
+            sample_table: Some(sample_table),
+            ..Default::default()
+        };
+        if !elst.is_empty() {
+            mp4box::parse_elst(&mut IStream::create(&elst[BOX_HEADER_SIZE..]), &mut track)?;
+        }
+        Ok(track)
+    }
+
+    const EMPTY_EDIT: [u32; 3] = [
+        /*segment_duration=*/ 1000,
+        /*media_time=*/ u32::MAX,
+        0x00010000,
+    ];
+
+    #[test_case(None, &[], [10, 40, 20, 30], 40, RepetitionCount::Unknown ; "no edit list")]
+    #[test_case(Some(0), &[&[400, 10, 0x00010000]], [0, 30, 10, 20], 40, RepetitionCount::Finite(0) ;
+        "media time")]
+    #[test_case(Some(0), &[&EMPTY_EDIT, &[400, 10, 0x00010000]], [100, 130, 110, 120], 140,
+        RepetitionCount::Finite(0) ; "empty edit")]
+    #[test_case(Some(1), &[&EMPTY_EDIT, &[400, 10, 0x00010000]], [100, 130, 110, 120], 140,
+        RepetitionCount::Finite(1) ; "repeated")]
+    #[test_case(Some(0), &[&[0, 10, 0x00010000]], [0, 30, 10, 20], 30, RepetitionCount::Finite(0) ;
+        "edit until the end of the media")]
+    #[test_case(Some(0), &[&[400, 20, 0x00010000]], [0, 20, 0, 10], 40, RepetitionCount::Finite(0) ;
+        "sample before the edit")]
+    // Only edits at normal rate are honored.
+    #[test_case(Some(0), &[&[400, 10, 0x00020000]], [10, 40, 20, 30], 40, RepetitionCount::Finite(0) ;
+        "unsupported rate")]
+    fn edit_list_timing(
+        flags: Option<u32>,
+        edits: &[&[u32]],
+        expected_pts: [u64; 4],
+        expected_duration: u64,
+        expected_repetition_count: RepetitionCount,
+    ) -> AvifResult<()> {
+        let elst = match flags {
+            Some(flags) => {
+                let mut payload = vec![edits.len() as u32];
+                for edit in edits {
+                    payload.extend_from_slice(edit);
+                }
+                full_box("elst", flags, &payload)
+            }
+            None => Vec::new(),
+        };
+        let track = sequence_track(&elst)?;
+        assert_eq!(track.edit_list.len(), edits.len());
+        for (index, expected_pts) in expected_pts.iter().enumerate() {
+            let image_timing = track.image_timing(index as u32)?;
+            assert_eq!(image_timing.pts_in_timescales, *expected_pts);
+            assert_eq!(image_timing.duration_in_timescales, 10);
+        }
+        assert_eq!(track.presentation_duration()?, expected_duration);
+        assert_eq!(track.repetition_count()?, expected_repetition_count);
+        Ok(())
+    }
+
+    #[test]
+    fn parse_elst_invalid() {
+        let mut track = Track::default();
+        // Repeated edit list of duration 0.
+        let elst = full_box("elst", 1, &[/*entry_count=*/ 1, 0, 0, 0x00010000]);
+        assert!(
+            mp4box::parse_elst(&mut IStream::create(&elst[BOX_HEADER_SIZE..]), &mut track).is_err()
+        );
+        let mut track = Track::default();
+        let elst = full_box("elst", 0x02000000, &[/*entry_count=*/ 0]);
+        assert!(
+            mp4box::parse_elst(&mut IStream::create(&elst[BOX_HEADER_SIZE..]), &mut track).is_err()
+        );
+    }
+
+    #[test]
+    fn parse_trun_composition_offsets() -> AvifResult<()> {
+        let tfhd = full_box("tfhd", 0x020000, &[/*track_ID=*/ 1]);
+        #[rustfmt::skip]
+        let trun = full_box("trun", 0x01000b01, &[
+            /*sample_count=*/ 4, /*data_offset=*/ 100,
+            /*duration, size, composition offset=*/ 10, 20, 0,
+            /*duration, size, composition offset=*/ 10, 20, 20,
+            /*duration, size, composition offset=*/ 10, 20, -10i32 as u32,
+            /*duration, size, composition offset=*/ 10, 20, -10i32 as u32,
+        ]);
+        let traf_size = 8 + tfhd.len() + trun.len();
+        let mut data = full_box("mfhd", 0, &[/*sequence_number=*/ 1]);
+        data.extend_from_slice(&(traf_size as u32).to_be_bytes());
+        data.extend_from_slice(b"traf");
+        data.extend_from_slice(&tfhd);
+        data.extend_from_slice(&trun);
+
+        let mut track = Track {
+            id: 1,
+            media_timescale: 100,
+            sample_table: Some(SampleTable::default()),
+            track_extends: Some(TrackExtends {
+                default_sample_description_index: 1,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let runs = mp4box::parse_moof(
+            &mut IStream::create(&data),
+            1000,
+            std::slice::from_ref(&track),
+        )?;
+        assert_eq!(runs.len(), 1);
+        track.append_run(&runs[0])?;
+        let sample_table = track.sample_table.as_ref().unwrap();
+        for (index, expected_offset) in [0, 20, -10, -10].iter().enumerate() {
+            assert_eq!(sample_table.composition_offset(index)?, *expected_offset);
+        }
+        // The composition offsets are run-length encoded.
+        assert_eq!(sample_table.composition_offsets.len(), 3);
+        let pts: Vec<u64> = (0..4)
+            .map(|index| Ok(track.image_timing(index)?.pts_in_timescales))
+            .collect::<AvifResult<_>>()?;
+        assert_eq!(pts, [0, 30, 10, 20]);
+        Ok(())
+    }
 }
@@ -27,10 +27,10 @@ struct ObuHeader {
 #[derive(Debug, Default)]
 pub struct Av1SequenceHeader {
     reduced_still_picture_header: bool,
-    max_width: u32,
-    max_height: u32,
-    bit_depth: u8,
-    yuv_format: PixelFormat,
+    pub(crate) max_width: u32,
+    pub(crate) max_height: u32,
+    pub(crate) bit_depth: u8,
+    pub(crate) yuv_format: PixelFormat,
     pub color_primaries: ColorPrimaries,
     pub transfer_characteristics: TransferCharacteristics,
     pub matrix_coefficients: MatrixCoefficients,
@@ -297,24 +297,65 @@ impl Av1SequenceHeader {
                 stream.skip(usize_from_u32(obu.size)?)?;
                 continue;
             }
-            let mut stream = stream.sub_stream(&BoxSize::FixedSize(usize_from_u32(obu.size)?))?;
-            let mut sequence_header = Av1SequenceHeader::default();
-            sequence_header.parse_profile(&mut stream)?;
-            sequence_header.parse_frame_max_dimensions(&mut stream)?;
-            sequence_header.parse_enabled_features(&mut stream)?;
-            // enable_superres
-            stream.skip_bits(1)?;
-            // enable_cdef
-            stream.skip_bits(1)?;
-            // enable_restoration
-            stream.skip_bits(1)?;
-            sequence_header.parse_color_config(&mut stream)?;
-            // film_grain_params_present
-            stream.skip_bits(1)?;
-            return Ok(sequence_header);
+            return Self::parse_sequence_header_obu(
+                &mut stream.sub_stream(&BoxSize::FixedSize(usize_from_u32(obu.size)?))?,
+            );
         }
         AvifError::bmff_parse_failed("could not parse sequence header")
     }
+
+    fn parse_sequence_header_obu(stream: &mut IStream) -> AvifResult<Self> {
+        let mut sequence_header = Av1SequenceHeader::default();
+        sequence_header.parse_profile(stream)?;
+        sequence_header.parse_frame_max_dimensions(stream)?;
+        sequence_header.parse_enabled_features(stream)?;
+        // enable_superres
+        stream.skip_bits(1)?;
+        // enable_cdef
+        stream.skip_bits(1)?;
+        // enable_restoration
+        stream.skip_bits(1)?;
+        sequence_header.parse_color_config(stream)?;
+        // film_grain_params_present
+        stream.skip_bits(1)?;
+        Ok(sequence_header)
+    }
+
+    // Returns true if the temporal unit in |data| is a sync sample as defined in Section 2.4 of
+    // the AV1 Codec ISO Media File Format Binding: it contains a sequence header OBU followed by
+    // a shown key frame.
+    #[cfg(feature = "encoder")]
+    pub(crate) fn is_sync_sample(data: &[u8]) -> AvifResult<bool> {
+        let mut stream = IStream::create(data);
+        let mut sequence_header = None;
+        while stream.has_bytes_left()? {
+            let obu = Self::parse_obu_header(&mut stream)?;
+            let mut stream = stream.sub_stream(&BoxSize::FixedSize(usize_from_u32(obu.size)?))?;
+            match obu.obu_type {
+                /*OBU_SEQUENCE_HEADER=*/
+                1 => sequence_header = Some(Self::parse_sequence_header_obu(&mut stream)?),
+                /*OBU_FRAME_HEADER=*/ 3 | /*OBU_FRAME=*/ 6 => {
+                    // Section 5.9.2 of AV1 specification.
+                    let Some(sequence_header) = sequence_header else {
+                        return Ok(false);
+                    };
+                    if sequence_header.reduced_still_picture_header {
+                        // Implies a shown key frame.
+                        return Ok(true);
+                    }
+                    let show_existing_frame = stream.read_bool()?;
+                    if show_existing_frame {
+                        return Ok(false);
+                    }
+                    let frame_type = stream.read_bits(2)?;
+                    let show_frame = stream.read_bool()?;
+                    return Ok(frame_type == /*KEY_FRAME=*/ 0 && show_frame);
+                }
+                _ => {}
+            }
+        }
+        Ok(false)
+    }
 }
 
 #[cfg(feature = "avm")]
@@ -43,7 +43,6 @@ impl ColorPrimaries {
         }
     }
 
-    #[cfg(feature = "png")]
     pub(crate) fn values(&self) -> Option<[f32; 8]> {
         // return values in this order: rX, rY, gX, gY, bX, bY, wX, wY
         match self {
@@ -83,6 +82,123 @@ impl ColorPrimaries {
     }
 }
 
+pub(crate) type Matrix3 = [[f64; 3]; 3];
+
+pub(crate) fn matrix3_multiply(a: &Matrix3, b: &Matrix3) -> Matrix3 {
+    let mut m = [[0.0; 3]; 3];
+    for (i, row) in m.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            *value = (0..3).map(|k| a[i][k] * b[k][j]).sum();
+        }
+    }
+    m
+}
+
+pub(crate) fn matrix3_apply(m: &Matrix3, v: [f32; 3]) -> [f32; 3] {
+    let v = v.map(|x| x as f64);
+    m.map(|row| (row[0] * v[0] + row[1] * v[1] + row[2] * v[2]) as f32)
+}
+
+pub(crate) fn matrix3_inverse(m: &Matrix3) -> Option<Matrix3> {
+    let det = m[0][0] * (m[1][1] * m[2][2] - m[2][1] * m[1][2])
+        - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+        + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0]);
+    if det.abs() < 1e-12 {
+        return None;
+    }
+    let inv_det = 1.0 / det;
+    Some([
+        [
+            (m[1][1] * m[2][2] - m[2][1] * m[1][2]) * inv_det,
+            (m[0][2] * m[2][1] - m[0][1] * m[2][2]) * inv_det,
+            (m[0][1] * m[1][2] - m[0][2] * m[1][1]) * inv_det,
+        ],
+        [
+            (m[1][2] * m[2][0] - m[1][0] * m[2][2]) * inv_det,
+            (m[0][0] * m[2][2] - m[0][2] * m[2][0]) * inv_det,
+            (m[1][0] * m[0][2] - m[0][0] * m[1][2]) * inv_det,
+        ],
+        [
+            (m[1][0] * m[2][1] - m[2][0] * m[1][1]) * inv_det,
+            (m[2][0] * m[0][1] - m[0][0] * m[2][1]) * inv_det,
+            (m[0][0] * m[1][1] - m[1][0] * m[0][1]) * inv_det,
+        ],
+    ])
+}
+
+fn xyz_from_xy(x: f64, y: f64) -> [f64; 3] {
+    [x / y, 1.0, (1.0 - x - y) / y]
+}
+
+impl ColorPrimaries {
+    pub(crate) fn white_point_xyz(&self) -> Option<[f64; 3]> {
+        let v = self.values()?;
+        Some(xyz_from_xy(v[6] as f64, v[7] as f64))
+    }
+
+    // Returns the matrix converting linear RGB in these primaries to CIE 1931 XYZ, as derived in
+    // Section 3.3 of SMPTE RP 177.
+    pub(crate) fn rgb_to_xyz(&self) -> Option<Matrix3> {
+        let v = self.values()?.map(|x| x as f64);
+        let (r, g, b, w) = (
+            xyz_from_xy(v[0], v[1]),
+            xyz_from_xy(v[2], v[3]),
+            xyz_from_xy(v[4], v[5]),
+            xyz_from_xy(v[6], v[7]),
+        );
+        let primaries = [[r[0], g[0], b[0]], [r[1], g[1], b[1]], [r[2], g[2], b[2]]];
+        let inverse = matrix3_inverse(&primaries)?;
+        let s = inverse.map(|row| row[0] * w[0] + row[1] * w[1] + row[2] * w[2]);
+        Some(primaries.map(|row| [row[0] * s[0], row[1] * s[1], row[2] * s[2]]))
+    }
+}
+
+// Bradford cone response matrix. See http://www.brucelindbloom.com/Eqn_ChromAdapt.html.
+const BRADFORD: Matrix3 = [
+    [0.8951, 0.2664, -0.1614],
+    [-0.7502, 1.7135, 0.0367],
+    [0.0389, -0.0685, 1.0296],
+];
+
+// Returns the matrix adapting XYZ values from the white point `from` to the white point `to` using
+// the Bradford transform.
+pub(crate) fn chromatic_adaptation_matrix(from: &[f64; 3], to: &[f64; 3]) -> Option<Matrix3> {
+    let cone_response = |xyz: &[f64; 3]| BRADFORD.map(|row| (0..3).map(|i| row[i] * xyz[i]).sum());
+    let from_lms: [f64; 3] = cone_response(from);
+    let to_lms: [f64; 3] = cone_response(to);
+    let mut scale = [[0.0; 3]; 3];
+    for i in 0..3 {
+        scale[i][i] = to_lms[i] / from_lms[i];
+    }
+    Some(matrix3_multiply(
+        &matrix3_inverse(&BRADFORD)?,
+        &matrix3_multiply(&scale, &BRADFORD),
+    ))
+}
+
+// Returns the matrix converting linear RGB from the primaries `from` to the primaries `to`, or None
+// if no conversion is needed or possible. Differing white points are handled with a Bradford
+// chromatic adaptation.
+pub(crate) fn primaries_conversion_matrix(
+    from: ColorPrimaries,
+    to: ColorPrimaries,
+) -> Option<Matrix3> {
+    if from == to {
+        return None;
+    }
+    let mut from_xyz = from.rgb_to_xyz()?;
+    let to_rgb = matrix3_inverse(&to.rgb_to_xyz()?)?;
+    let from_white = from.white_point_xyz()?;
+    let to_white = to.white_point_xyz()?;
+    if from_white != to_white {
+        from_xyz = matrix3_multiply(
+            &chromatic_adaptation_matrix(&from_white, &to_white)?,
+            &from_xyz,
+        );
+    }
+    Some(matrix3_multiply(&to_rgb, &from_xyz))
+}
+
 impl TransferCharacteristics {
     #[cfg(feature = "png")]
     pub(crate) fn gamma(&self) -> Option<f32> {
@@ -140,6 +256,54 @@ mod tests {
     use super::*;
     use crate::internal_utils::assert_eq_f32_array;
 
+    #[test]
+    fn rgb_to_xyz() {
+        // Values from Section 3.2 of ITU-R BT.709 converted as in IEC 61966-2-1.
+        let m = ColorPrimaries::Srgb.rgb_to_xyz().unwrap();
+        let expected = [
+            [0.4124, 0.3576, 0.1805],
+            [0.2126, 0.7152, 0.0722],
+            [0.0193, 0.1192, 0.9505],
+        ];
+        for i in 0..3 {
+            for j in 0..3 {
+                assert!((m[i][j] - expected[i][j]).abs() < 0.001);
+            }
+        }
+    }
+
+    #[test]
+    fn primaries_conversion() {
+        assert!(primaries_conversion_matrix(ColorPrimaries::Srgb, ColorPrimaries::Srgb).is_none());
+        assert!(
+            primaries_conversion_matrix(ColorPrimaries::Srgb, ColorPrimaries::Unspecified)
+                .is_none()
+        );
+        // White is preserved, including across white points.
+        for (from, to) in [
+            (ColorPrimaries::Bt2020, ColorPrimaries::Srgb),
+            (ColorPrimaries::Smpte432, ColorPrimaries::Srgb),
+            (ColorPrimaries::Smpte431, ColorPrimaries::Srgb),
+            (ColorPrimaries::Bt470m, ColorPrimaries::Bt2020),
+        ] {
+            let m = primaries_conversion_matrix(from, to).unwrap();
+            for v in matrix3_apply(&m, [1.0, 1.0, 1.0]) {
+                assert!((v - 1.0).abs() < 0.0001);
+            }
+        }
+        // Round trip.
+        let to = primaries_conversion_matrix(ColorPrimaries::Srgb, ColorPrimaries::Bt2020).unwrap();
+        let from =
+            primaries_conversion_matrix(ColorPrimaries::Bt2020, ColorPrimaries::Srgb).unwrap();
+        let rgb = [0.2, 0.5, 0.9];
+        for (a, b) in matrix3_apply(&from, matrix3_apply(&to, rgb))
+            .iter()
+            .zip(rgb)
+        {
+            assert!((a - b).abs() < 0.0001);
+        }
+    }
+
     #[test]
     fn yuv_coefficients() {
         assert_eq_f32_array(
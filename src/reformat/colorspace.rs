@@ -0,0 +1,330 @@
+// Copyright 2025 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::coeffs::*;
+use super::icc::IccProfile;
+use super::rgb;
+
+use crate::image;
+use crate::internal_utils::*;
+use crate::*;
+
+impl rgb::Image {
+    // Calls `f` on the color channels of each pixel, normalized to [0, 1] and not premultiplied by
+    // alpha. `f` receives one value for gray formats and three values (in R, G, B order)
+    // otherwise. The values written back by `f` are clamped to [0, 1].
+    pub(crate) fn transform_pixels(&mut self, mut f: impl FnMut(&mut [f32])) -> AvifResult<()> {
+        if self.is_float || matches!(self.format, rgb::Format::Rgb565 | rgb::Format::Rgba1010102) {
+            return AvifError::not_implemented();
+        }
+        let unpremultiply = self.premultiply_alpha && self.has_alpha();
+        let channel_count = usize_from_u32(self.channel_count())?;
+        let offsets = self.format.offsets();
+        let color_channels = if self.format.is_gray() { 1 } else { 3 };
+        let max_channel_f = self.max_channel_f();
+        let width = usize_from_u32(self.width)?;
+        let mut pixel = [0.0f32; 4];
+        for y in 0..self.height {
+            for x in 0..width {
+                let base = x * channel_count;
+                if self.depth == 8 {
+                    let row = self.row(y)?;
+                    for (c, value) in pixel.iter_mut().enumerate() {
+                        *value = row[base + offsets[c]] as f32 / max_channel_f;
+                    }
+                } else {
+                    let row = self.row16(y)?;
+                    for (c, value) in pixel.iter_mut().enumerate() {
+                        *value = row[base + offsets[c]] as f32 / max_channel_f;
+                    }
+                }
+                let alpha = if unpremultiply { pixel[3] } else { 1.0 };
+                if alpha <= 0.0 {
+                    continue;
+                }
+                for value in &mut pixel[..color_channels] {
+                    *value = (*value / alpha).min(1.0);
+                }
+                f(&mut pixel[..color_channels]);
+                for value in &mut pixel[..color_channels] {
+                    *value = value.clamp(0.0, 1.0) * alpha;
+                }
+                if self.depth == 8 {
+                    let row = self.row_mut(y)?;
+                    for c in 0..color_channels {
+                        row[base + offsets[c]] = (0.5 + pixel[c] * max_channel_f) as u8;
+                    }
+                } else {
+                    let row = self.row16_mut(y)?;
+                    for c in 0..color_channels {
+                        row[base + offsets[c]] = (0.5 + pixel[c] * max_channel_f) as u16;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Converts the pixels of this image from the source color space to the destination color
+    /// space: the values are linearized with the source transfer function, converted between the
+    /// primaries (with a chromatic adaptation if the white points differ) and encoded with the
+    /// destination transfer function. Luminance is preserved in absolute terms (SDR white being
+    /// 203 cd/m²), so HDR highlights are clipped when converting to SDR. See
+    /// [`rgb::ToneMapping`] to compress them instead.
+    ///
+    /// Primaries conversions are skipped for gray formats and for primaries without known
+    /// chromaticities (such as `ColorPrimaries::Unspecified`).
+    pub fn convert_color_space(
+        &mut self,
+        src_color_primaries: ColorPrimaries,
+        src_transfer_characteristics: TransferCharacteristics,
+        dst_color_primaries: ColorPrimaries,
+        dst_transfer_characteristics: TransferCharacteristics,
+    ) -> AvifResult<()> {
+        if src_color_primaries == dst_color_primaries
+            && src_transfer_characteristics == dst_transfer_characteristics
+        {
+            return Ok(());
+        }
+        let matrix = if self.format.is_gray() {
+            None
+        } else {
+            primaries_conversion_matrix(src_color_primaries, dst_color_primaries)
+        };
+        let scale = src_transfer_characteristics.nominal_peak_nits()
+            / dst_transfer_characteristics.nominal_peak_nits();
+        self.transform_pixels(|pixel| {
+            for value in pixel.iter_mut() {
+                *value = src_transfer_characteristics.gamma_to_linear(*value) * scale;
+            }
+            if let Some(matrix) = &matrix {
+                let rgb = matrix3_apply(matrix, [pixel[0], pixel[1], pixel[2]]);
+                pixel.copy_from_slice(&rgb);
+            }
+            for value in pixel.iter_mut() {
+                *value = dst_transfer_characteristics.linear_to_gamma(*value);
+            }
+        })
+    }
+}
+
+impl image::Image {
+    /// Converts the pixels of this image to the given color primaries and transfer
+    /// characteristics. See [`rgb::Image::convert_color_space`]. If the image has an ICC profile,
+    /// it takes precedence over the CICP values if it is a matrix/TRC profile (see
+    /// [`rgb::Image::convert_from_icc`]). Other ICC profiles are ignored and the CICP values of
+    /// the image are used instead. The CICP values of the image are updated and its ICC profile is
+    /// discarded.
+    pub fn convert_color_space(
+        &mut self,
+        color_primaries: ColorPrimaries,
+        transfer_characteristics: TransferCharacteristics,
+    ) -> AvifResult<()> {
+        let profile = if self.icc.is_empty() {
+            None
+        } else {
+            match IccProfile::parse(&self.icc) {
+                Ok(profile) => Some(profile),
+                // Profiles that cannot be used for conversions are ignored, as if the image had
+                // no ICC profile.
+                Err(AvifError::NotImplemented) => None,
+                Err(err) => return Err(err),
+            }
+        };
+        if profile.is_none()
+            && self.color_primaries == color_primaries
+            && self.transfer_characteristics == transfer_characteristics
+        {
+            return Ok(());
+        }
+        let mut rgb = rgb::Image::create_from_yuv(self);
+        rgb.depth = 16;
+        rgb.format = match (self.yuv_format == PixelFormat::Yuv400, self.has_alpha()) {
+            (true, true) => rgb::Format::GrayA,
+            (true, false) => rgb::Format::Gray,
+            (false, true) => rgb::Format::Rgba,
+            (false, false) => rgb::Format::Rgb,
+        };
+        rgb.allocate()?;
+        rgb.convert_from_yuv(self)?;
+        match &profile {
+            Some(profile) => {
+                rgb.convert_from_icc(profile, color_primaries, transfer_characteristics)?
+            }
+            None => rgb.convert_color_space(
+                self.color_primaries,
+                self.transfer_characteristics,
+                color_primaries,
+                transfer_characteristics,
+            )?,
+        }
+        self.color_primaries = color_primaries;
+        self.transfer_characteristics = transfer_characteristics;
+        self.icc.clear();
+        rgb.convert_to_yuv(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::Plane;
+    use crate::image::YuvRange;
+
+    use test_case::test_case;
+
+    fn rgb_image(format: rgb::Format, values: &[u16]) -> AvifResult<rgb::Image> {
+        let mut rgb = rgb::Image {
+            width: 1,
+            height: 1,
+            depth: 16,
+            format,
+            ..Default::default()
+        };
+        rgb.allocate()?;
+        rgb.row16_mut(0)?.copy_from_slice(values);
+        Ok(rgb)
+    }
+
+    #[test_case(ColorPrimaries::Srgb, TransferCharacteristics::Srgb)]
+    #[test_case(ColorPrimaries::Bt2020, TransferCharacteristics::Pq)]
+    #[test_case(ColorPrimaries::Smpte432, TransferCharacteristics::Hlg)]
+    #[test_case(ColorPrimaries::Bt2020, TransferCharacteristics::Linear)]
+    fn round_trip(
+        color_primaries: ColorPrimaries,
+        transfer_characteristics: TransferCharacteristics,
+    ) -> AvifResult<()> {
+        // Saturated colors of sRGB are within the gamut of all the tested color spaces. Keep them
+        // below 1.0 to avoid clipping when going through PQ or HLG.
+        let values = [30000, 10000, 20000, 65535];
+        let mut rgb = rgb_image(rgb::Format::Rgba, &values)?;
+        rgb.convert_color_space(
+            ColorPrimaries::Srgb,
+            TransferCharacteristics::Srgb,
+            color_primaries,
+            transfer_characteristics,
+        )?;
+        rgb.convert_color_space(
+            color_primaries,
+            transfer_characteristics,
+            ColorPrimaries::Srgb,
+            TransferCharacteristics::Srgb,
+        )?;
+        for (actual, expected) in rgb.row16(0)?.iter().zip(values) {
+            assert!((*actual as i32 - expected as i32).abs() <= 64);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn p3_to_srgb() -> AvifResult<()> {
+        // Pure Display P3 red is out of the sRGB gamut.
+        let mut rgb = rgb_image(rgb::Format::Rgb, &[65535, 0, 0])?;
+        rgb.convert_color_space(
+            ColorPrimaries::Smpte432,
+            TransferCharacteristics::Srgb,
+            ColorPrimaries::Srgb,
+            TransferCharacteristics::Srgb,
+        )?;
+        assert_eq!(rgb.row16(0)?, &[65535, 0, 0]);
+        // White stays white.
+        let mut rgb = rgb_image(rgb::Format::Rgb, &[65535, 65535, 65535])?;
+        rgb.convert_color_space(
+            ColorPrimaries::Smpte432,
+            TransferCharacteristics::Srgb,
+            ColorPrimaries::Srgb,
+            TransferCharacteristics::Srgb,
+        )?;
+        assert_eq!(rgb.row16(0)?, &[65535, 65535, 65535]);
+        Ok(())
+    }
+
+    #[test]
+    fn gray_transfer() -> AvifResult<()> {
+        let mut rgb = rgb_image(rgb::Format::GrayA, &[65535, 32768])?;
+        rgb.convert_color_space(
+            ColorPrimaries::Bt2020,
+            TransferCharacteristics::Srgb,
+            ColorPrimaries::Srgb,
+            TransferCharacteristics::Linear,
+        )?;
+        // White is white in any transfer function and alpha is untouched.
+        assert_eq!(rgb.row16(0)?, &[65535, 32768]);
+        Ok(())
+    }
+
+    #[test]
+    fn unsupported_format() -> AvifResult<()> {
+        let mut rgb = rgb_image(rgb::Format::Rgb, &[0, 0, 0])?;
+        rgb.is_float = true;
+        assert_eq!(
+            rgb.convert_color_space(
+                ColorPrimaries::Srgb,
+                TransferCharacteristics::Srgb,
+                ColorPrimaries::Bt2020,
+                TransferCharacteristics::Pq,
+            ),
+            Err(AvifError::NotImplemented)
+        );
+        Ok(())
+    }
+
+    // Minimal header of an ICC profile with a Lab profile connection space, as used by LUT
+    // based profiles.
+    fn lut_icc() -> Vec<u8> {
+        let mut icc = vec![0u8; 132];
+        icc[..4].copy_from_slice(&132u32.to_be_bytes());
+        icc[16..20].copy_from_slice(b"RGB ");
+        icc[20..24].copy_from_slice(b"Lab ");
+        icc
+    }
+
+    #[test_case(Vec::new() ; "without icc")]
+    #[test_case(lut_icc() ; "with unsupported icc")]
+    fn image_conversion(icc: Vec<u8>) -> AvifResult<()> {
+        let mut image = image::Image {
+            width: 2,
+            height: 2,
+            depth: 10,
+            yuv_format: PixelFormat::Yuv444,
+            yuv_range: YuvRange::Full,
+            color_primaries: ColorPrimaries::Bt2020,
+            transfer_characteristics: TransferCharacteristics::Pq,
+            matrix_coefficients: MatrixCoefficients::Bt2020Ncl,
+            icc,
+            ..Default::default()
+        };
+        image.allocate_planes(Category::Color)?;
+        // Neutral gray at the PQ encoding of about 100 cd/m².
+        for (plane, value) in [(Plane::Y, 520), (Plane::U, 512), (Plane::V, 512)] {
+            for y in 0..image.height {
+                image.row16_mut(plane, y)?.fill(value);
+            }
+        }
+        image.convert_color_space(ColorPrimaries::Srgb, TransferCharacteristics::Srgb)?;
+        assert_eq!(image.color_primaries, ColorPrimaries::Srgb);
+        assert_eq!(
+            image.transfer_characteristics,
+            TransferCharacteristics::Srgb
+        );
+        // About half of the SDR white luminance, in sRGB.
+        let luma = image.row16(Plane::Y, 0)?[0];
+        assert!((740..=760).contains(&luma), "{luma}");
+        for plane in [Plane::U, Plane::V] {
+            assert!((image.row16(plane, 0)?[0] as i32 - 512).abs() <= 1);
+        }
+        assert!(image.icc.is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,655 @@
+// Copyright 2025 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::coeffs::*;
+use super::rgb;
+use super::transfer::SDR_WHITE_NITS;
+
+use crate::internal_utils::stream::IStream;
+use crate::internal_utils::*;
+use crate::*;
+
+// Illuminant of the profile connection space (D50), from Section 7.2.16 of ICC.1:2022.
+const PCS_WHITE_XYZ: [f64; 3] = [0.9642, 1.0, 0.8249];
+
+const HEADER_SIZE: usize = 128;
+
+// Tolerances used when matching the profile against CICP values. They account for the s15.16
+// precision of the profile and for slightly different derivations of the same color space.
+const PRIMARIES_TOLERANCE: f64 = 0.003;
+const CURVE_TOLERANCE: f32 = 0.003;
+
+// Color primaries that can be recognized from the colorants of a profile, in order of preference.
+// ColorPrimaries::Smpte240 is identical to ColorPrimaries::Bt601 and ColorPrimaries::Xyz cannot
+// be represented by colorants.
+const KNOWN_COLOR_PRIMARIES: [ColorPrimaries; 9] = [
+    ColorPrimaries::Srgb,
+    ColorPrimaries::Bt470m,
+    ColorPrimaries::Bt470bg,
+    ColorPrimaries::Bt601,
+    ColorPrimaries::GenericFilm,
+    ColorPrimaries::Bt2020,
+    ColorPrimaries::Smpte431,
+    ColorPrimaries::Smpte432,
+    ColorPrimaries::Ebu3213,
+];
+
+// Transfer characteristics that can be recognized from the curves of a profile, in order of
+// preference.
+const KNOWN_TRANSFER_CHARACTERISTICS: [TransferCharacteristics; 6] = [
+    TransferCharacteristics::Srgb,
+    TransferCharacteristics::Bt709,
+    TransferCharacteristics::Linear,
+    TransferCharacteristics::Bt470m,
+    TransferCharacteristics::Bt470bg,
+    TransferCharacteristics::Smpte240,
+];
+
+/// Tone reproduction curve of an ICC profile, converting a non-linear value in [0, 1] into a
+/// linear value in [0, 1].
+#[derive(Clone, Debug, PartialEq)]
+pub enum IccCurve {
+    /// Pure power function (`curv` with zero or one entry).
+    Gamma(f32),
+    /// Parametric curve (`para`) normalized to the parameters g, a, b, c, d, e and f of function
+    /// type 4 in Table 68 of ICC.1:2022: `Y = (aX + b)^g + e` if `X >= d`, `Y = cX + f` otherwise.
+    Parametric([f32; 7]),
+    /// Sampled curve (`curv` with more than one entry), linearly interpolated. Like in `curv`, an
+    /// empty table is the identity and a single entry is a u8Fixed8Number gamma.
+    Table(Vec<u16>),
+}
+
+impl IccCurve {
+    pub fn evaluate(&self, x: f32) -> f32 {
+        let x = x.clamp(0.0, 1.0);
+        let y = match self {
+            Self::Gamma(gamma) => x.powf(*gamma),
+            Self::Parametric([g, a, b, c, d, e, f]) => {
+                if x >= *d {
+                    (a * x + b).max(0.0).powf(*g) + e
+                } else {
+                    c * x + f
+                }
+            }
+            Self::Table(table) if table.is_empty() => x,
+            Self::Table(table) if table.len() == 1 => x.powf(table[0] as f32 / 256.0),
+            Self::Table(table) => {
+                let position = x * (table.len() - 1) as f32;
+                let index = (position as usize).min(table.len() - 2);
+                let weight = position - index as f32;
+                (table[index] as f32 * (1.0 - weight) + table[index + 1] as f32 * weight) / 65535.0
+            }
+        };
+        y.clamp(0.0, 1.0)
+    }
+
+    fn parse(data: &[u8]) -> AvifResult<Self> {
+        let mut stream = IStream::create(data);
+        let tag_type = stream.read_string(4)?;
+        stream.skip_u32()?; // reserved
+        match tag_type.as_str() {
+            "curv" => {
+                let count = usize_from_u32(stream.read_u32()?)?;
+                match count {
+                    0 => Ok(Self::Gamma(1.0)),
+                    // u8Fixed8Number.
+                    1 => Ok(Self::Gamma(stream.read_u16()? as f32 / 256.0)),
+                    _ => {
+                        let mut table = create_vec_exact(count)?;
+                        for _ in 0..count {
+                            table.push(stream.read_u16()?);
+                        }
+                        Ok(Self::Table(table))
+                    }
+                }
+            }
+            "para" => {
+                let function_type = stream.read_u16()?;
+                stream.skip_u16()?; // reserved
+                let param_count = match function_type {
+                    0 => 1,
+                    1 => 3,
+                    2 => 4,
+                    3 => 5,
+                    4 => 7,
+                    _ => return AvifError::not_implemented(),
+                };
+                let mut params = [0.0f32; 7];
+                for param in params.iter_mut().take(param_count) {
+                    *param = read_s15_fixed16(&mut stream)? as f32;
+                }
+                let [g, a, b, c, d, _, _] = params;
+                if (function_type == 1 || function_type == 2) && a == 0.0 {
+                    // The threshold -b/a of these function types would not be finite.
+                    return AvifError::not_implemented();
+                }
+                Ok(Self::Parametric(match function_type {
+                    0 => [g, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+                    1 => [g, a, b, 0.0, -b / a, 0.0, 0.0],
+                    // The fourth parameter of function type 2 is an offset applied everywhere.
+                    2 => [g, a, b, 0.0, -b / a, c, c],
+                    3 => [g, a, b, c, d, 0.0, 0.0],
+                    _ => params,
+                }))
+            }
+            _ => AvifError::not_implemented(),
+        }
+    }
+
+    fn matches(&self, transfer_characteristics: TransferCharacteristics) -> bool {
+        (0..=64).all(|i| {
+            let x = i as f32 / 64.0;
+            (self.evaluate(x) - transfer_characteristics.gamma_to_linear(x)).abs()
+                <= CURVE_TOLERANCE
+        })
+    }
+}
+
+/// Contents of a matrix/TRC ICC profile (Section F.3 of ICC.1:2022), as found in the `colr` box
+/// of an image.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct IccProfile {
+    /// True for a gray profile (data color space `GRAY`), false for an RGB profile.
+    pub gray: bool,
+    /// XYZ values of the red, green and blue colorants (`rXYZ`, `gXYZ` and `bXYZ`) in the profile
+    /// connection space. None for gray profiles.
+    pub colorants: Option<[[f64; 3]; 3]>,
+    /// Media white point (`wtpt`), if any.
+    pub white_point: Option<[f64; 3]>,
+    /// Chromatic adaptation matrix (`chad`) from the media white point to the profile connection
+    /// space illuminant, if any.
+    pub chromatic_adaptation: Option<[[f64; 3]; 3]>,
+    /// Tone reproduction curves: `rTRC`, `gTRC` and `bTRC` for RGB profiles, `kTRC` for gray
+    /// profiles.
+    pub curves: Vec<IccCurve>,
+    /// Color primaries and transfer characteristics of the `cicp` tag (ICC.1:2022), if any.
+    pub cicp: Option<(ColorPrimaries, TransferCharacteristics)>,
+}
+
+fn read_s15_fixed16(stream: &mut IStream) -> AvifResult<f64> {
+    Ok(stream.read_i32()? as f64 / 65536.0)
+}
+
+fn read_xyz(data: &[u8]) -> AvifResult<[f64; 3]> {
+    let mut stream = IStream::create(data);
+    if stream.read_string(4)? != "XYZ " {
+        return AvifError::unknown_error("Invalid ICC profile: expected XYZ tag type");
+    }
+    stream.skip_u32()?; // reserved
+    Ok([
+        read_s15_fixed16(&mut stream)?,
+        read_s15_fixed16(&mut stream)?,
+        read_s15_fixed16(&mut stream)?,
+    ])
+}
+
+fn read_sf32_matrix(data: &[u8]) -> AvifResult<Matrix3> {
+    let mut stream = IStream::create(data);
+    if stream.read_string(4)? != "sf32" {
+        return AvifError::unknown_error("Invalid ICC profile: expected sf32 tag type");
+    }
+    stream.skip_u32()?; // reserved
+    let mut matrix = [[0.0; 3]; 3];
+    for row in &mut matrix {
+        for value in row.iter_mut() {
+            *value = read_s15_fixed16(&mut stream)?;
+        }
+    }
+    Ok(matrix)
+}
+
+fn read_cicp(data: &[u8]) -> AvifResult<(ColorPrimaries, TransferCharacteristics)> {
+    let mut stream = IStream::create(data);
+    if stream.read_string(4)? != "cicp" {
+        return AvifError::unknown_error("Invalid ICC profile: expected cicp tag type");
+    }
+    stream.skip_u32()?; // reserved
+    let color_primaries = stream.read_u8()? as u16;
+    let transfer_characteristics = stream.read_u8()? as u16;
+    Ok((color_primaries.into(), transfer_characteristics.into()))
+}
+
+// Returns the matrix converting linear RGB in the given primaries to XYZ in the profile connection
+// space.
+fn rgb_to_pcs(color_primaries: ColorPrimaries) -> Option<Matrix3> {
+    Some(matrix3_multiply(
+        &chromatic_adaptation_matrix(&color_primaries.white_point_xyz()?, &PCS_WHITE_XYZ)?,
+        &color_primaries.rgb_to_xyz()?,
+    ))
+}
+
+impl IccProfile {
+    /// Parses an ICC profile. Returns `AvifError::NotImplemented` for profiles that are not
+    /// matrix/TRC based (such as LUT based profiles), since they cannot be used for conversions.
+    pub fn parse(data: &[u8]) -> AvifResult<Self> {
+        let mut stream = IStream::create(data);
+        let size = usize_from_u32(stream.read_u32()?)?;
+        if size < HEADER_SIZE || size > data.len() {
+            return AvifError::unknown_error("Invalid ICC profile size");
+        }
+        let data = &data[..size];
+        stream.skip(12)?; // preferred CMM type, version, profile/device class
+        let gray = match stream.read_string(4)?.as_str() {
+            "RGB " => false,
+            "GRAY" => true,
+            _ => return AvifError::not_implemented(),
+        };
+        if stream.read_string(4)? != "XYZ " {
+            // Lab profile connection spaces are only used by LUT based profiles.
+            return AvifError::not_implemented();
+        }
+        stream.skip(HEADER_SIZE - stream.offset)?;
+        let tag_count = stream.read_u32()?;
+        let mut profile = Self {
+            gray,
+            ..Default::default()
+        };
+        let mut colorants = [None; 3];
+        let mut curves = [None, None, None];
+        for _ in 0..tag_count {
+            let signature = stream.read_string(4)?;
+            let offset = usize_from_u32(stream.read_u32()?)?;
+            let tag_size = usize_from_u32(stream.read_u32()?)?;
+            let end = checked_add!(offset, tag_size)?;
+            if end > data.len() {
+                return AvifError::unknown_error("Invalid ICC profile: tag out of bounds");
+            }
+            let tag = &data[offset..end];
+            match signature.as_str() {
+                "rXYZ" => colorants[0] = Some(read_xyz(tag)?),
+                "gXYZ" => colorants[1] = Some(read_xyz(tag)?),
+                "bXYZ" => colorants[2] = Some(read_xyz(tag)?),
+                "wtpt" => profile.white_point = Some(read_xyz(tag)?),
+                "chad" => profile.chromatic_adaptation = Some(read_sf32_matrix(tag)?),
+                "rTRC" | "kTRC" => curves[0] = Some(IccCurve::parse(tag)?),
+                "gTRC" => curves[1] = Some(IccCurve::parse(tag)?),
+                "bTRC" => curves[2] = Some(IccCurve::parse(tag)?),
+                "cicp" => profile.cicp = Some(read_cicp(tag)?),
+                _ => {}
+            }
+        }
+        let channel_count = if gray { 1 } else { 3 };
+        for curve in curves.iter_mut().take(channel_count) {
+            match curve.take() {
+                Some(curve) => profile.curves.push(curve),
+                None => return AvifError::not_implemented(),
+            }
+        }
+        if !gray {
+            let [Some(r), Some(g), Some(b)] = colorants else {
+                return AvifError::not_implemented();
+            };
+            profile.colorants = Some([r, g, b]);
+        }
+        Ok(profile)
+    }
+
+    // Returns the matrix converting linear RGB described by this profile to XYZ in the profile
+    // connection space. The columns are the colorants.
+    fn rgb_to_pcs(&self) -> Option<Matrix3> {
+        let [r, g, b] = self.colorants?;
+        Some([[r[0], g[0], b[0]], [r[1], g[1], b[1]], [r[2], g[2], b[2]]])
+    }
+
+    /// Returns the color primaries equivalent to the colorants of this profile, if any.
+    /// `ColorPrimaries::Unspecified` is returned for gray profiles.
+    pub fn color_primaries(&self) -> Option<ColorPrimaries> {
+        if let Some((color_primaries, _)) = self.cicp {
+            return Some(color_primaries);
+        }
+        if self.gray {
+            return Some(ColorPrimaries::Unspecified);
+        }
+        let matrix = self.rgb_to_pcs()?;
+        KNOWN_COLOR_PRIMARIES.into_iter().find(|color_primaries| {
+            rgb_to_pcs(*color_primaries).is_some_and(|known| {
+                known
+                    .iter()
+                    .flatten()
+                    .zip(matrix.iter().flatten())
+                    .all(|(a, b)| (a - b).abs() <= PRIMARIES_TOLERANCE)
+            })
+        })
+    }
+
+    /// Returns the transfer characteristics equivalent to the curves of this profile, if any.
+    pub fn transfer_characteristics(&self) -> Option<TransferCharacteristics> {
+        if let Some((_, transfer_characteristics)) = self.cicp {
+            return Some(transfer_characteristics);
+        }
+        KNOWN_TRANSFER_CHARACTERISTICS
+            .into_iter()
+            .find(|tc| self.curves.iter().all(|curve| curve.matches(*tc)))
+    }
+
+    /// Returns the CICP color primaries and transfer characteristics equivalent to this profile,
+    /// or None if the profile cannot be represented with CICP values.
+    pub fn to_cicp(&self) -> Option<(ColorPrimaries, TransferCharacteristics)> {
+        Some((self.color_primaries()?, self.transfer_characteristics()?))
+    }
+}
+
+impl rgb::Image {
+    /// Converts the pixels of this image from the color space described by the matrix/TRC ICC
+    /// `profile` to the given CICP color space. The white of the profile is mapped to the SDR
+    /// reference white (203 cd/m²) of the destination. Gray profiles can only be used with gray
+    /// formats or to convert the transfer function of RGB formats.
+    pub fn convert_from_icc(
+        &mut self,
+        profile: &IccProfile,
+        dst_color_primaries: ColorPrimaries,
+        dst_transfer_characteristics: TransferCharacteristics,
+    ) -> AvifResult<()> {
+        let channel_count = if profile.gray { 1 } else { 3 };
+        if profile.curves.len() != channel_count {
+            return AvifError::invalid_argument();
+        }
+        let matrix = if profile.gray {
+            None
+        } else {
+            if self.format.is_gray() {
+                return AvifError::not_implemented();
+            }
+            let Some(pcs_to_rgb) =
+                rgb_to_pcs(dst_color_primaries).and_then(|m| matrix3_inverse(&m))
+            else {
+                return AvifError::invalid_argument();
+            };
+            Some(matrix3_multiply(
+                &pcs_to_rgb,
+                &profile.rgb_to_pcs().ok_or(AvifError::InvalidArgument)?,
+            ))
+        };
+        let scale = SDR_WHITE_NITS / dst_transfer_characteristics.nominal_peak_nits();
+        let curves = &profile.curves;
+        self.transform_pixels(|pixel| {
+            for (c, value) in pixel.iter_mut().enumerate() {
+                *value = curves[c.min(curves.len() - 1)].evaluate(*value) * scale;
+            }
+            if let Some(matrix) = &matrix {
+                let rgb = matrix3_apply(matrix, [pixel[0], pixel[1], pixel[2]]);
+                pixel.copy_from_slice(&rgb);
+            }
+            for value in pixel.iter_mut() {
+                *value = dst_transfer_characteristics.linear_to_gamma(*value);
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    use test_case::test_case;
+
+    pub(crate) fn s15_fixed16(value: f64) -> [u8; 4] {
+        ((value * 65536.0).round() as i32).to_be_bytes()
+    }
+
+    pub(crate) fn xyz_tag(xyz: [f64; 3]) -> Vec<u8> {
+        let mut tag = b"XYZ \0\0\0\0".to_vec();
+        for value in xyz {
+            tag.extend_from_slice(&s15_fixed16(value));
+        }
+        tag
+    }
+
+    pub(crate) fn gamma_tag(gamma: f32) -> Vec<u8> {
+        let mut tag = b"curv\0\0\0\0\0\0\0\x01".to_vec();
+        tag.extend_from_slice(&((gamma * 256.0).round() as u16).to_be_bytes());
+        tag
+    }
+
+    // Parametric curve of the sRGB transfer function.
+    pub(crate) fn srgb_tag() -> Vec<u8> {
+        let mut tag = b"para\0\0\0\0\0\x03\0\0".to_vec();
+        for value in [2.4, 1.0 / 1.055, 0.055 / 1.055, 1.0 / 12.92, 0.04045] {
+            tag.extend_from_slice(&s15_fixed16(value));
+        }
+        tag
+    }
+
+    pub(crate) fn create_profile(color_space: &[u8; 4], tags: &[(&[u8; 4], Vec<u8>)]) -> Vec<u8> {
+        let mut header = vec![0u8; HEADER_SIZE];
+        header[8] = 4; // version
+        header[12..16].copy_from_slice(b"mntr");
+        header[16..20].copy_from_slice(color_space);
+        header[20..24].copy_from_slice(b"XYZ ");
+        header[36..40].copy_from_slice(b"acsp");
+        let mut table = (tags.len() as u32).to_be_bytes().to_vec();
+        let mut tag_data = Vec::new();
+        let mut offset = HEADER_SIZE + 4 + 12 * tags.len();
+        for (signature, data) in tags {
+            table.extend_from_slice(*signature);
+            table.extend_from_slice(&(offset as u32).to_be_bytes());
+            table.extend_from_slice(&(data.len() as u32).to_be_bytes());
+            tag_data.extend_from_slice(data);
+            offset += data.len();
+        }
+        let mut profile = header;
+        profile.extend_from_slice(&table);
+        profile.extend_from_slice(&tag_data);
+        let size = profile.len() as u32;
+        profile[..4].copy_from_slice(&size.to_be_bytes());
+        profile
+    }
+
+    pub(crate) fn rgb_profile(color_primaries: ColorPrimaries, trc: Vec<u8>) -> Vec<u8> {
+        let m = rgb_to_pcs(color_primaries).unwrap();
+        create_profile(
+            b"RGB ",
+            &[
+                (b"wtpt", xyz_tag(PCS_WHITE_XYZ)),
+                (b"rXYZ", xyz_tag([m[0][0], m[1][0], m[2][0]])),
+                (b"gXYZ", xyz_tag([m[0][1], m[1][1], m[2][1]])),
+                (b"bXYZ", xyz_tag([m[0][2], m[1][2], m[2][2]])),
+                (b"rTRC", trc.clone()),
+                (b"gTRC", trc.clone()),
+                (b"bTRC", trc),
+            ],
+        )
+    }
+
+    #[test_case(ColorPrimaries::Srgb, srgb_tag(), TransferCharacteristics::Srgb)]
+    #[test_case(ColorPrimaries::Smpte432, srgb_tag(), TransferCharacteristics::Srgb)]
+    #[test_case(
+        ColorPrimaries::Bt2020,
+        gamma_tag(1.0),
+        TransferCharacteristics::Linear
+    )]
+    #[test_case(
+        ColorPrimaries::Bt470m,
+        gamma_tag(2.2),
+        TransferCharacteristics::Bt470m
+    )]
+    fn to_cicp(
+        color_primaries: ColorPrimaries,
+        trc: Vec<u8>,
+        transfer_characteristics: TransferCharacteristics,
+    ) -> AvifResult<()> {
+        let profile = IccProfile::parse(&rgb_profile(color_primaries, trc))?;
+        assert!(!profile.gray);
+        let white_point = profile.white_point.unwrap();
+        for (actual, expected) in white_point.iter().zip(PCS_WHITE_XYZ) {
+            assert!((actual - expected).abs() < 0.0001);
+        }
+        assert_eq!(
+            profile.to_cicp(),
+            Some((color_primaries, transfer_characteristics))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn adobe_rgb() -> AvifResult<()> {
+        // Adobe RGB (1998) has no CICP equivalent but its gamma of 563/256 is close enough to
+        // 2.2.
+        let profile = IccProfile::parse(&create_profile(
+            b"RGB ",
+            &[
+                (b"rXYZ", xyz_tag([0.6097, 0.3111, 0.0195])),
+                (b"gXYZ", xyz_tag([0.2053, 0.6257, 0.0609])),
+                (b"bXYZ", xyz_tag([0.1492, 0.0632, 0.7446])),
+                (b"rTRC", gamma_tag(563.0 / 256.0)),
+                (b"gTRC", gamma_tag(563.0 / 256.0)),
+                (b"bTRC", gamma_tag(563.0 / 256.0)),
+            ],
+        ))?;
+        assert_eq!(profile.color_primaries(), None);
+        assert_eq!(
+            profile.transfer_characteristics(),
+            Some(TransferCharacteristics::Bt470m)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn gray() -> AvifResult<()> {
+        let mut table = b"curv\0\0\0\0\0\0\0\x02".to_vec();
+        table.extend_from_slice(&[0, 0, 0xff, 0xff]);
+        let profile = IccProfile::parse(&create_profile(b"GRAY", &[(b"kTRC", table)]))?;
+        assert!(profile.gray);
+        assert_eq!(profile.curves, vec![IccCurve::Table(vec![0, 65535])]);
+        assert_eq!(
+            profile.to_cicp(),
+            Some((ColorPrimaries::Unspecified, TransferCharacteristics::Linear))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn cicp_tag() -> AvifResult<()> {
+        let profile = IccProfile::parse(&create_profile(
+            b"RGB ",
+            &[
+                (b"cicp", b"cicp\0\0\0\0\x09\x10\0\x01".to_vec()),
+                (b"rXYZ", xyz_tag([1.0, 0.0, 0.0])),
+                (b"gXYZ", xyz_tag([0.0, 1.0, 0.0])),
+                (b"bXYZ", xyz_tag([0.0, 0.0, 1.0])),
+                (b"rTRC", gamma_tag(1.0)),
+                (b"gTRC", gamma_tag(1.0)),
+                (b"bTRC", gamma_tag(1.0)),
+            ],
+        ))?;
+        assert_eq!(
+            profile.to_cicp(),
+            Some((ColorPrimaries::Bt2020, TransferCharacteristics::Pq))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(IccProfile::parse(&[]).is_err());
+        let mut profile = rgb_profile(ColorPrimaries::Srgb, srgb_tag());
+        // Truncated.
+        assert!(IccProfile::parse(&profile[..profile.len() - 1]).is_err());
+        // LUT based profiles are not supported.
+        profile[20..24].copy_from_slice(b"Lab ");
+        assert_eq!(IccProfile::parse(&profile), Err(AvifError::NotImplemented));
+        // Missing colorants.
+        let profile = create_profile(b"RGB ", &[(b"rTRC", gamma_tag(1.0))]);
+        assert_eq!(IccProfile::parse(&profile), Err(AvifError::NotImplemented));
+    }
+
+    #[test]
+    fn short_table() {
+        assert_eq!(IccCurve::Table(vec![]).evaluate(0.25), 0.25);
+        assert_eq!(IccCurve::Table(vec![512]).evaluate(0.5), 0.25);
+    }
+
+    #[test]
+    fn invalid_parametric_curve() {
+        // Function type 1 with a = 0.
+        let mut tag = b"para\0\0\0\0\0\x01\0\0".to_vec();
+        for value in [2.2, 0.0, 0.1] {
+            tag.extend_from_slice(&s15_fixed16(value));
+        }
+        assert_eq!(IccCurve::parse(&tag), Err(AvifError::NotImplemented));
+    }
+
+    #[test]
+    fn convert_from_invalid_icc() -> AvifResult<()> {
+        let mut rgb = rgb::Image {
+            width: 1,
+            height: 1,
+            depth: 8,
+            format: rgb::Format::Rgb,
+            ..Default::default()
+        };
+        rgb.allocate()?;
+        let valid = IccProfile::parse(&rgb_profile(ColorPrimaries::Srgb, srgb_tag()))?;
+        for profile in [
+            IccProfile {
+                colorants: None,
+                ..valid.clone()
+            },
+            IccProfile {
+                curves: vec![],
+                ..valid.clone()
+            },
+            IccProfile {
+                gray: true,
+                colorants: None,
+                ..valid
+            },
+        ] {
+            assert_eq!(
+                rgb.convert_from_icc(
+                    &profile,
+                    ColorPrimaries::Srgb,
+                    TransferCharacteristics::Srgb
+                ),
+                Err(AvifError::InvalidArgument)
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn convert_from_icc() -> AvifResult<()> {
+        let profile = IccProfile::parse(&rgb_profile(ColorPrimaries::Smpte432, srgb_tag()))?;
+        let create_image = || -> AvifResult<rgb::Image> {
+            let mut rgb = rgb::Image {
+                width: 2,
+                height: 1,
+                depth: 8,
+                format: rgb::Format::Rgb,
+                ..Default::default()
+            };
+            rgb.allocate()?;
+            rgb.row_mut(0)?
+                .copy_from_slice(&[255, 255, 255, 100, 150, 200]);
+            Ok(rgb)
+        };
+        let mut rgb = create_image()?;
+        let mut expected = create_image()?;
+        expected.convert_color_space(
+            ColorPrimaries::Smpte432,
+            TransferCharacteristics::Srgb,
+            ColorPrimaries::Bt2020,
+            TransferCharacteristics::Pq,
+        )?;
+        rgb.convert_from_icc(
+            &profile,
+            ColorPrimaries::Bt2020,
+            TransferCharacteristics::Pq,
+        )?;
+        for (actual, expected) in rgb.row(0)?.iter().zip(expected.row(0)?) {
+            assert!((*actual as i32 - *expected as i32).abs() <= 1);
+        }
+        Ok(())
+    }
+}
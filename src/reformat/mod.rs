@@ -21,8 +21,14 @@ pub mod sharpyuv;
 
 pub mod alpha;
 pub mod coeffs;
+pub mod colorspace;
+pub mod icc;
+pub mod orientation;
+pub mod resample;
 pub mod rgb;
 pub mod rgb_impl;
+pub mod tonemap;
+pub mod transfer;
 
 // If libyuv is not present, add placeholder functions so that the library will build successfully
 // without it.
@@ -57,12 +63,14 @@ pub mod libyuv {
             &mut self,
             width: u32,
             height: u32,
-            _category: Category,
+            category: Category,
         ) -> AvifResult<()> {
-            if self.width == width && self.height == height {
-                return Ok(());
-            }
-            AvifError::not_implemented()
+            self.scale_planes(
+                width,
+                height,
+                category.planes(),
+                resample::ScalingFilter::default(),
+            )
         }
     }
 }
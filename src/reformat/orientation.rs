@@ -0,0 +1,614 @@
+// Copyright 2025 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::rgb;
+
+use crate::image;
+use crate::image::*;
+use crate::internal_utils::*;
+use crate::parser::exif;
+use crate::utils::clap::CleanAperture;
+use crate::utils::clap::CropRect;
+use crate::*;
+
+// Maps the pixel coordinates of an image on which the transformative properties were applied to
+// the pixel coordinates of the original image. The transformative properties are applied in the
+// order mandated by Section 7.3.6.7 of MIAF (ISO/IEC 23000-22): clap, irot and then imir.
+struct Transform {
+    crop: CropRect,
+    angle: u8,
+    axis: Option<u8>,
+    // Dimensions of the transformed image.
+    width: u32,
+    height: u32,
+}
+
+impl Transform {
+    fn create(
+        width: u32,
+        height: u32,
+        yuv_format: PixelFormat,
+        clap: &Option<CleanAperture>,
+        irot_angle: Option<u8>,
+        imir_axis: Option<u8>,
+    ) -> AvifResult<Self> {
+        let crop = match clap {
+            Some(clap) => CropRect::create_from(clap, width, height, yuv_format)?,
+            None => CropRect {
+                x: 0,
+                y: 0,
+                width,
+                height,
+            },
+        };
+        let angle = irot_angle.unwrap_or(0);
+        if angle > 3 || imir_axis.is_some_and(|axis| axis > 1) {
+            return AvifError::invalid_argument();
+        }
+        let (width, height) = if angle % 2 == 1 {
+            (crop.height, crop.width)
+        } else {
+            (crop.width, crop.height)
+        };
+        Ok(Self {
+            crop,
+            angle,
+            axis: imir_axis,
+            width,
+            height,
+        })
+    }
+
+    fn from_image(image: &image::Image) -> AvifResult<Self> {
+        Self::create(
+            image.width,
+            image.height,
+            image.yuv_format,
+            &image.clap,
+            image.irot_angle,
+            image.imir_axis,
+        )
+    }
+
+    fn changes_orientation(&self) -> bool {
+        self.angle != 0 || self.axis.is_some()
+    }
+
+    fn source(&self, x: u32, y: u32) -> (u32, u32) {
+        // imir with axis 0 exchanges the top and bottom parts of the image and imir with axis 1
+        // exchanges its left and right parts.
+        let (x, y) = match self.axis {
+            Some(0) => (x, self.height - 1 - y),
+            Some(1) => (self.width - 1 - x, y),
+            _ => (x, y),
+        };
+        // irot rotates the image anti-clockwise by angle * 90 degrees.
+        let (x, y) = match self.angle {
+            1 => (self.crop.width - 1 - y, x),
+            2 => (self.crop.width - 1 - x, self.crop.height - 1 - y),
+            3 => (y, self.crop.height - 1 - x),
+            _ => (x, y),
+        };
+        (self.crop.x + x, self.crop.y + y)
+    }
+}
+
+type Row<T> = fn(&image::Image, Plane, u32) -> AvifResult<&[T]>;
+type RowMut<T> = fn(&mut image::Image, Plane, u32) -> AvifResult<&mut [T]>;
+
+fn chroma_shift(yuv_format: PixelFormat, plane: Plane) -> (u32, u32) {
+    match plane {
+        Plane::U | Plane::V => (yuv_format.chroma_shift_x().0, yuv_format.chroma_shift_y()),
+        Plane::Y | Plane::A => (0, 0),
+    }
+}
+
+// Fills the given plane of dst, whose top left pixel is at dst_origin in the transformed image,
+// with the pixels of src, whose top left pixel is at src_origin in the original image.
+#[allow(clippy::too_many_arguments)]
+fn transform_plane<T: Copy>(
+    transform: &Transform,
+    src: &image::Image,
+    src_origin: (u32, u32),
+    dst: &mut image::Image,
+    dst_origin: (u32, u32),
+    plane: Plane,
+    row: Row<T>,
+    row_mut: RowMut<T>,
+) -> AvifResult<()> {
+    // Chroma samples are looked up through the corresponding luma sample positions, which also
+    // upsamples the 4:2:2 chroma planes that become 4:4:4 when rotated by 90 or 270 degrees.
+    let src_shift = chroma_shift(src.yuv_format, plane);
+    let dst_shift = chroma_shift(dst.yuv_format, plane);
+    let width = dst.width(plane);
+    for y in 0..u32_from_usize(dst.height(plane))? {
+        let dst_row = row_mut(dst, plane, y)?;
+        for (x, value) in dst_row[..width].iter_mut().enumerate() {
+            let (src_x, src_y) = transform.source(
+                checked_add!(dst_origin.0, u32_from_usize(x)? << dst_shift.0)?,
+                checked_add!(dst_origin.1, y << dst_shift.1)?,
+            );
+            let src_x = checked_sub!(src_x, src_origin.0)?;
+            let src_y = checked_sub!(src_y, src_origin.1)?;
+            let src_row = row(src, plane, src_y >> src_shift.1)?;
+            *value = src_row[usize_from_u32(src_x >> src_shift.0)?];
+        }
+    }
+    Ok(())
+}
+
+impl image::Image {
+    /// Returns a copy of this image with its clean aperture, rotation and mirroring properties
+    /// applied to the pixels, in this order. The returned image has no `clap`, `irot_angle` nor
+    /// `imir_axis` and its Exif orientation, if any, is reset to 1. 4:2:2 images become 4:4:4
+    /// images when rotated by 90 or 270 degrees. Returns an error if the clean aperture is
+    /// invalid.
+    pub fn transformed_image(&self) -> AvifResult<image::Image> {
+        let transform = Transform::from_image(self)?;
+        let rect = CropRect {
+            x: 0,
+            y: 0,
+            width: transform.width,
+            height: transform.height,
+        };
+        let mut image = self.transformed_region(&transform, self, (0, 0), &rect)?;
+        image.exif = self
+            .exif
+            .try_clone()
+            .map_err(AvifError::map_out_of_memory)?;
+        image.icc = self.icc.try_clone().map_err(AvifError::map_out_of_memory)?;
+        image.xmp = self.xmp.try_clone().map_err(AvifError::map_out_of_memory)?;
+        if transform.changes_orientation() {
+            // The orientation is now baked into the pixels. Ignore failures since Exif
+            // metadata without an orientation tag is fine.
+            let _ = exif::set_orientation(&mut image.exif, 1);
+        }
+        Ok(image)
+    }
+
+    // Returns the rectangle of this image that contains all the pixels of the region rect of the
+    // transformed image (see transformed_image()). The returned rectangle is aligned to the
+    // chroma subsampling of this image. The planes of this image are not used.
+    pub(crate) fn transformed_region_source(&self, rect: &CropRect) -> AvifResult<CropRect> {
+        let transform = Transform::from_image(self)?;
+        if !rect.is_valid(
+            transform.width,
+            transform.height,
+            self.transformed_yuv_format(&transform),
+        ) {
+            return AvifError::invalid_argument();
+        }
+        let corner0 = transform.source(rect.x, rect.y);
+        let corner1 = transform.source(
+            checked_sub!(checked_add!(rect.x, rect.width)?, 1)?,
+            checked_sub!(checked_add!(rect.y, rect.height)?, 1)?,
+        );
+        let (shift_x, shift_y) = (
+            self.yuv_format.chroma_shift_x().0,
+            self.yuv_format.chroma_shift_y(),
+        );
+        let x0 = (corner0.0.min(corner1.0) >> shift_x) << shift_x;
+        let y0 = (corner0.1.min(corner1.1) >> shift_y) << shift_y;
+        let x1 = corner0.0.max(corner1.0) + 1;
+        let y1 = corner0.1.max(corner1.1) + 1;
+        Ok(CropRect {
+            x: x0,
+            y: y0,
+            width: x1 - x0,
+            height: y1 - y0,
+        })
+    }
+
+    // Returns the region rect of the transformed image (see transformed_image()), given the
+    // pixels of the region source of this image as returned by transformed_region_source(). The
+    // planes of this image are not used. The returned image has no metadata.
+    pub(crate) fn transformed_image_region(
+        &self,
+        region: &image::Image,
+        source: &CropRect,
+        rect: &CropRect,
+    ) -> AvifResult<image::Image> {
+        let transform = Transform::from_image(self)?;
+        self.transformed_region(&transform, region, (source.x, source.y), rect)
+    }
+
+    fn transformed_yuv_format(&self, transform: &Transform) -> PixelFormat {
+        if self.yuv_format == PixelFormat::Yuv422 && transform.angle % 2 == 1 {
+            PixelFormat::Yuv444
+        } else {
+            self.yuv_format
+        }
+    }
+
+    // Returns the region rect of the image transformed by transform, given the pixels of the
+    // original image starting at src_origin in src.
+    fn transformed_region(
+        &self,
+        transform: &Transform,
+        src: &image::Image,
+        src_origin: (u32, u32),
+        rect: &CropRect,
+    ) -> AvifResult<image::Image> {
+        if !matches!(
+            self.yuv_format,
+            PixelFormat::Yuv444 | PixelFormat::Yuv422 | PixelFormat::Yuv420 | PixelFormat::Yuv400
+        ) {
+            return AvifError::not_implemented();
+        }
+        let mut image = self.shallow_clone();
+        image.width = rect.width;
+        image.height = rect.height;
+        image.yuv_format = self.transformed_yuv_format(transform);
+        image.clap = None;
+        image.irot_angle = None;
+        image.imir_axis = None;
+        if src.has_plane(Plane::Y) {
+            image.allocate_planes(Category::Color)?;
+        }
+        if src.has_alpha() {
+            image.allocate_planes(Category::Alpha)?;
+        }
+        for plane in ALL_PLANES.iter().filter(|p| src.has_plane(**p)) {
+            if self.depth == 8 {
+                transform_plane(
+                    transform,
+                    src,
+                    src_origin,
+                    &mut image,
+                    (rect.x, rect.y),
+                    *plane,
+                    image::Image::row,
+                    image::Image::row_mut,
+                )?;
+            } else {
+                transform_plane(
+                    transform,
+                    src,
+                    src_origin,
+                    &mut image,
+                    (rect.x, rect.y),
+                    *plane,
+                    image::Image::row16,
+                    image::Image::row16_mut,
+                )?;
+            }
+        }
+        Ok(image)
+    }
+}
+
+impl rgb::Image {
+    /// Returns a copy of this image with the clean aperture, rotation and mirroring properties of
+    /// `image` applied to the pixels, in this order. `image` is the image this one was converted
+    /// from (see [`rgb::Image::create_from_yuv`]) and must have the same dimensions.
+    pub fn transformed_image(&self, image: &image::Image) -> AvifResult<rgb::Image> {
+        if self.width != image.width || self.height != image.height {
+            return AvifError::invalid_argument();
+        }
+        let transform = Transform::from_image(image)?;
+        let mut rgb = rgb::Image {
+            width: transform.width,
+            height: transform.height,
+            depth: self.depth,
+            format: self.format,
+            chroma_upsampling: self.chroma_upsampling,
+            chroma_downsampling: self.chroma_downsampling,
+            premultiply_alpha: self.premultiply_alpha,
+            is_float: self.is_float,
+            max_threads: self.max_threads,
+            pixels: None,
+            row_bytes: 0,
+            tone_mapping: self.tone_mapping,
+        };
+        rgb.allocate()?;
+        // Channels are one or two bytes wide and packed formats use a whole number of them per
+        // pixel, so pixels can be copied as groups of channels.
+        let pixel_size = usize_from_u32(self.pixel_size() / self.channel_size())?;
+        for y in 0..rgb.height {
+            for x in 0..rgb.width {
+                let (src_x, src_y) = transform.source(x, y);
+                let src = usize_from_u32(src_x)? * pixel_size;
+                let dst = usize_from_u32(x)? * pixel_size;
+                if self.channel_size() == 1 {
+                    let src_row = self.row(src_y)?;
+                    rgb.row_mut(y)?[dst..dst + pixel_size]
+                        .copy_from_slice(&src_row[src..src + pixel_size]);
+                } else {
+                    let src_row = self.row16(src_y)?;
+                    rgb.row16_mut(y)?[dst..dst + pixel_size]
+                        .copy_from_slice(&src_row[src..src + pixel_size]);
+                }
+            }
+        }
+        Ok(rgb)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use test_case::test_case;
+
+    // Returns the pixel values of a plane of the given image, row by row.
+    fn plane_values(image: &image::Image, plane: Plane) -> AvifResult<Vec<Vec<u16>>> {
+        let mut values = Vec::new();
+        for y in 0..image.height(plane) as u32 {
+            values.push(if image.depth == 8 {
+                image.row(plane, y)?.iter().map(|v| *v as u16).collect()
+            } else {
+                image.row16(plane, y)?.to_vec()
+            });
+        }
+        Ok(values)
+    }
+
+    // Creates an image whose samples are (10 * y + x) in each plane.
+    fn create_image(
+        width: u32,
+        height: u32,
+        depth: u8,
+        yuv_format: PixelFormat,
+    ) -> AvifResult<image::Image> {
+        let mut image = image::Image {
+            width,
+            height,
+            depth,
+            yuv_format,
+            ..Default::default()
+        };
+        image.allocate_planes(Category::Color)?;
+        image.allocate_planes(Category::Alpha)?;
+        for plane in ALL_PLANES {
+            for y in 0..image.height(plane) as u32 {
+                for x in 0..image.width(plane) {
+                    let value = 10 * y as u16 + x as u16;
+                    if depth == 8 {
+                        image.row_mut(plane, y)?[x] = value as u8;
+                    } else {
+                        image.row16_mut(plane, y)?[x] = value;
+                    }
+                }
+            }
+        }
+        Ok(image)
+    }
+
+    #[test_case(None, None, &[&[0, 1, 2], &[10, 11, 12]] ; "identity")]
+    #[test_case(Some(1), None, &[&[2, 12], &[1, 11], &[0, 10]] ; "rotate 90")]
+    #[test_case(Some(2), None, &[&[12, 11, 10], &[2, 1, 0]] ; "rotate 180")]
+    #[test_case(Some(3), None, &[&[10, 0], &[11, 1], &[12, 2]] ; "rotate 270")]
+    #[test_case(None, Some(0), &[&[10, 11, 12], &[0, 1, 2]] ; "mirror top bottom")]
+    #[test_case(None, Some(1), &[&[2, 1, 0], &[12, 11, 10]] ; "mirror left right")]
+    #[test_case(Some(1), Some(0), &[&[0, 10], &[1, 11], &[2, 12]] ; "rotate then mirror")]
+    fn luma(irot_angle: Option<u8>, imir_axis: Option<u8>, expected: &[&[u16]]) -> AvifResult<()> {
+        for depth in [8, 10] {
+            let mut image = create_image(3, 2, depth, PixelFormat::Yuv444)?;
+            image.irot_angle = irot_angle;
+            image.imir_axis = imir_axis;
+            let transformed = image.transformed_image()?;
+            assert!(transformed.irot_angle.is_none());
+            assert!(transformed.imir_axis.is_none());
+            for plane in ALL_PLANES {
+                assert_eq!(plane_values(&transformed, plane)?, expected);
+            }
+        }
+        Ok(())
+    }
+
+    #[test_case(PixelFormat::Yuv420, Some(1), None, PixelFormat::Yuv420,
+                &[&[2, 12, 22], &[1, 11, 21], &[0, 10, 20]])]
+    #[test_case(PixelFormat::Yuv420, None, Some(1), PixelFormat::Yuv420,
+                &[&[2, 1, 0], &[12, 11, 10], &[22, 21, 20]])]
+    #[test_case(PixelFormat::Yuv422, Some(2), None, PixelFormat::Yuv422,
+                &[&[52, 51, 50], &[42, 41, 40], &[32, 31, 30], &[22, 21, 20], &[12, 11, 10],
+                  &[2, 1, 0]])]
+    #[test_case(PixelFormat::Yuv422, Some(3), None, PixelFormat::Yuv444,
+                &[&[50, 40, 30, 20, 10, 0], &[50, 40, 30, 20, 10, 0], &[51, 41, 31, 21, 11, 1],
+                  &[51, 41, 31, 21, 11, 1], &[52, 42, 32, 22, 12, 2]])]
+    fn chroma(
+        yuv_format: PixelFormat,
+        irot_angle: Option<u8>,
+        imir_axis: Option<u8>,
+        expected_yuv_format: PixelFormat,
+        expected: &[&[u16]],
+    ) -> AvifResult<()> {
+        let mut image = create_image(5, 6, 8, yuv_format)?;
+        image.irot_angle = irot_angle;
+        image.imir_axis = imir_axis;
+        let transformed = image.transformed_image()?;
+        assert_eq!(transformed.yuv_format, expected_yuv_format);
+        for plane in [Plane::U, Plane::V] {
+            assert_eq!(plane_values(&transformed, plane)?, expected);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn clap() -> AvifResult<()> {
+        let mut image = create_image(6, 4, 16, PixelFormat::Yuv420)?;
+        let rect = CropRect {
+            x: 2,
+            y: 0,
+            width: 3,
+            height: 2,
+        };
+        image.clap = Some(CleanAperture::create_from(
+            &rect,
+            image.width,
+            image.height,
+            image.yuv_format,
+        )?);
+        image.irot_angle = Some(1);
+        image.imir_axis = Some(1);
+        let transformed = image.transformed_image()?;
+        assert_eq!((transformed.width, transformed.height), (2, 3));
+        assert!(transformed.clap.is_none());
+        // The image is cropped first, then rotated and finally mirrored.
+        assert_eq!(
+            plane_values(&transformed, Plane::Y)?,
+            [[14, 4], [13, 3], [12, 2]]
+        );
+        assert_eq!(plane_values(&transformed, Plane::U)?, [[2], [1]]);
+        Ok(())
+    }
+
+    #[test_case(PixelFormat::Yuv444)]
+    #[test_case(PixelFormat::Yuv422)]
+    #[test_case(PixelFormat::Yuv420)]
+    fn region(yuv_format: PixelFormat) -> AvifResult<()> {
+        let mut image = create_image(8, 6, 8, yuv_format)?;
+        image.clap = Some(CleanAperture::create_from(
+            &CropRect {
+                x: 2,
+                y: 2,
+                width: 5,
+                height: 3,
+            },
+            image.width,
+            image.height,
+            image.yuv_format,
+        )?);
+        for (irot_angle, imir_axis) in [
+            (None, None),
+            (Some(1), None),
+            (Some(2), Some(0)),
+            (Some(3), Some(1)),
+        ] {
+            image.irot_angle = irot_angle;
+            image.imir_axis = imir_axis;
+            let transformed = image.transformed_image()?;
+            for x in 0..transformed.width {
+                for y in 0..transformed.height {
+                    for width in 1..=transformed.width - x {
+                        for height in 1..=transformed.height - y {
+                            let rect = CropRect {
+                                x,
+                                y,
+                                width,
+                                height,
+                            };
+                            if !rect.is_valid(
+                                transformed.width,
+                                transformed.height,
+                                transformed.yuv_format,
+                            ) {
+                                assert!(image.transformed_region_source(&rect).is_err());
+                                continue;
+                            }
+                            let mut expected = transformed.shallow_clone();
+                            expected.width = width;
+                            expected.height = height;
+                            expected.allocate_planes(Category::Color)?;
+                            expected.allocate_planes(Category::Alpha)?;
+                            expected.copy_from_region(
+                                &transformed,
+                                0,
+                                0,
+                                &rect,
+                                Category::Color,
+                            )?;
+                            expected.copy_from_region(
+                                &transformed,
+                                0,
+                                0,
+                                &rect,
+                                Category::Alpha,
+                            )?;
+
+                            let source = image.transformed_region_source(&rect)?;
+                            let mut region = image.shallow_clone();
+                            region.width = source.width;
+                            region.height = source.height;
+                            region.allocate_planes(Category::Color)?;
+                            region.allocate_planes(Category::Alpha)?;
+                            region.copy_from_region(&image, 0, 0, &source, Category::Color)?;
+                            region.copy_from_region(&image, 0, 0, &source, Category::Alpha)?;
+                            let region = image.transformed_image_region(&region, &source, &rect)?;
+                            assert_eq!(region.yuv_format, expected.yuv_format);
+                            for plane in ALL_PLANES {
+                                assert_eq!(
+                                    plane_values(&region, plane)?,
+                                    plane_values(&expected, plane)?
+                                );
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn invalid() -> AvifResult<()> {
+        let mut image = create_image(4, 4, 8, PixelFormat::Yuv420)?;
+        image.irot_angle = Some(4);
+        assert!(image.transformed_image().is_err());
+        image.irot_angle = None;
+        image.clap = Some(CleanAperture::create_from(
+            &CropRect {
+                x: 0,
+                y: 0,
+                width: 4,
+                height: 4,
+            },
+            4,
+            4,
+            PixelFormat::Yuv420,
+        )?);
+        image.width = 2;
+        assert!(image.transformed_image().is_err());
+        Ok(())
+    }
+
+    #[test_case(rgb::Format::Rgba, 8)]
+    #[test_case(rgb::Format::Gray, 16)]
+    #[test_case(rgb::Format::Rgb565, 8)]
+    fn rgb_image(format: rgb::Format, depth: u8) -> AvifResult<()> {
+        let mut image = create_image(3, 2, 8, PixelFormat::Yuv444)?;
+        image.irot_angle = Some(3);
+        let mut rgb = rgb::Image::create_from_yuv(&image);
+        rgb.format = format;
+        rgb.depth = depth;
+        rgb.allocate()?;
+        let pixel_size = (rgb.pixel_size() / rgb.channel_size()) as usize;
+        for y in 0..rgb.height {
+            for x in 0..rgb.width as usize {
+                let value = 10 * y as u16 + x as u16;
+                if depth == 8 {
+                    rgb.row_mut(y)?[x * pixel_size..(x + 1) * pixel_size].fill(value as u8);
+                } else {
+                    rgb.row16_mut(y)?[x * pixel_size..(x + 1) * pixel_size].fill(value);
+                }
+            }
+        }
+        let transformed = rgb.transformed_image(&image)?;
+        assert_eq!((transformed.width, transformed.height), (2, 3));
+        let expected = [[10, 0], [11, 1], [12, 2]];
+        for (y, expected_row) in expected.iter().enumerate() {
+            for (x, expected_value) in expected_row.iter().enumerate() {
+                let value = if depth == 8 {
+                    transformed.row(y as u32)?[x * pixel_size] as u16
+                } else {
+                    transformed.row16(y as u32)?[x * pixel_size]
+                };
+                assert_eq!(value, *expected_value);
+            }
+        }
+        image.width = 4;
+        assert!(rgb.transformed_image(&image).is_err());
+        Ok(())
+    }
+}
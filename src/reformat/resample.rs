@@ -0,0 +1,500 @@
+// Copyright 2025 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::image;
+use crate::image::*;
+use crate::internal_utils::*;
+use crate::*;
+
+use std::f32::consts::PI;
+
+/// Interpolation filters of [`image::Image::scale_with_filter`]. When scaling down, the filters
+/// other than `Nearest` are stretched so that all the source samples contribute to the output.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ScalingFilter {
+    Nearest,
+    #[default]
+    Bilinear,
+    // Catmull-Rom spline (cubic convolution with a = -0.5).
+    Bicubic,
+    // Lanczos windowed sinc with 3 lobes.
+    Lanczos,
+}
+
+impl ScalingFilter {
+    // Half of the width of the filter kernel, in source samples when scaling up.
+    fn support(&self) -> f32 {
+        match self {
+            Self::Nearest => 0.5,
+            Self::Bilinear => 1.0,
+            Self::Bicubic => 2.0,
+            Self::Lanczos => 3.0,
+        }
+    }
+
+    fn weight(&self, x: f32) -> f32 {
+        let x = x.abs();
+        match self {
+            Self::Nearest => {
+                if x < 0.5 {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            Self::Bilinear => (1.0 - x).max(0.0),
+            Self::Bicubic => {
+                if x < 1.0 {
+                    (1.5 * x - 2.5) * x * x + 1.0
+                } else if x < 2.0 {
+                    ((-0.5 * x + 2.5) * x - 4.0) * x + 2.0
+                } else {
+                    0.0
+                }
+            }
+            Self::Lanczos => {
+                if x < 1e-6 {
+                    1.0
+                } else if x < 3.0 {
+                    let pi_x = PI * x;
+                    3.0 * pi_x.sin() * (pi_x / 3.0).sin() / (pi_x * pi_x)
+                } else {
+                    0.0
+                }
+            }
+        }
+    }
+}
+
+// Layout of the samples of a plane relative to the luma samples.
+struct PlaneLayout {
+    shift_x: u32,
+    shift_y: u32,
+    // Position of the first sample of the plane relative to the first luma sample, in luma
+    // samples.
+    offset_x: f32,
+    offset_y: f32,
+    // Number of interleaved channels in the plane.
+    channels: usize,
+}
+
+impl PlaneLayout {
+    fn create(image: &image::Image, plane: Plane) -> Self {
+        let (shift_x, shift_y, channels) = match (plane, image.yuv_format) {
+            (Plane::Y | Plane::A, _) => (0, 0, 1),
+            (_, PixelFormat::AndroidNv12 | PixelFormat::AndroidNv21 | PixelFormat::AndroidP010) => {
+                (1, 1, 2)
+            }
+            (_, PixelFormat::Yuv420) => (1, 1, 1),
+            (_, PixelFormat::Yuv422) => (1, 0, 1),
+            _ => (0, 0, 1),
+        };
+        // Chroma samples are either co-sited with the luma samples or centered between them.
+        let (cosited_x, cosited_y) = match image.chroma_sample_position {
+            ChromaSamplePosition::Vertical => (true, false),
+            ChromaSamplePosition::Colocated => (true, true),
+            ChromaSamplePosition::Unknown | ChromaSamplePosition::Reserved => (false, false),
+        };
+        Self {
+            shift_x,
+            shift_y,
+            offset_x: if shift_x == 0 || cosited_x { 0.0 } else { 0.5 },
+            offset_y: if shift_y == 0 || cosited_y { 0.0 } else { 0.5 },
+            channels,
+        }
+    }
+}
+
+// Source samples contributing to one destination sample, starting at index `start`.
+struct Contribution {
+    start: usize,
+    weights: Vec<f32>,
+}
+
+fn contributions(
+    src_luma_size: u32,
+    dst_luma_size: u32,
+    src_size: usize,
+    dst_size: usize,
+    shift: u32,
+    offset: f32,
+    filter: ScalingFilter,
+) -> AvifResult<Vec<Contribution>> {
+    let ratio = src_luma_size as f32 / dst_luma_size as f32;
+    let filter_scale = if filter == ScalingFilter::Nearest { 1.0 } else { ratio.max(1.0) };
+    let support = filter.support() * filter_scale;
+    let subsampling = (1 << shift) as f32;
+    let last = src_size as i64 - 1;
+    let mut contributions = create_vec_exact(dst_size)?;
+    for i in 0..dst_size {
+        // Maps the center of the destination sample to the source sample grid, going through the
+        // luma sample grids since they share the same edges.
+        let dst_luma = i as f32 * subsampling + offset;
+        let src_luma = (dst_luma + 0.5) * ratio - 0.5;
+        let center = (src_luma - offset) / subsampling;
+        if filter == ScalingFilter::Nearest {
+            contributions.push(Contribution {
+                start: ((center + 0.5).floor() as i64).clamp(0, last) as usize,
+                weights: vec![1.0],
+            });
+            continue;
+        }
+        let left = (center - support).ceil() as i64;
+        let right = (center + support).floor() as i64;
+        let start = left.clamp(0, last);
+        let end = right.clamp(0, last);
+        let mut weights = vec![0.0; (end - start + 1) as usize];
+        let mut sum = 0.0;
+        for j in left..=right {
+            let weight = filter.weight((j as f32 - center) / filter_scale);
+            // Samples outside of the plane are replaced by the closest edge sample.
+            weights[(j.clamp(start, end) - start) as usize] += weight;
+            sum += weight;
+        }
+        if sum != 0.0 {
+            for weight in &mut weights {
+                *weight /= sum;
+            }
+        }
+        contributions.push(Contribution {
+            start: start as usize,
+            weights,
+        });
+    }
+    Ok(contributions)
+}
+
+type Row<T> = fn(&image::Image, Plane, u32) -> AvifResult<&[T]>;
+type RowMut<T> = fn(&mut image::Image, Plane, u32) -> AvifResult<&mut [T]>;
+
+fn resample_plane<T: Copy + Into<f32>>(
+    src: &image::Image,
+    dst: &mut image::Image,
+    plane: Plane,
+    filter: ScalingFilter,
+    row: Row<T>,
+    row_mut: RowMut<T>,
+    from_f32: impl Fn(f32) -> T,
+) -> AvifResult<()> {
+    let layout = PlaneLayout::create(src, plane);
+    let channels = layout.channels;
+    let src_width = src.width(plane) / channels;
+    let src_height = src.height(plane);
+    let dst_width = dst.width(plane) / channels;
+    let dst_height = dst.height(plane);
+    let horizontal = contributions(
+        src.width,
+        dst.width,
+        src_width,
+        dst_width,
+        layout.shift_x,
+        layout.offset_x,
+        filter,
+    )?;
+    let vertical = contributions(
+        src.height,
+        dst.height,
+        src_height,
+        dst_height,
+        layout.shift_y,
+        layout.offset_y,
+        filter,
+    )?;
+    // Scale the rows first, then the columns.
+    let row_size = checked_mul!(dst_width, channels)?;
+    let mut rows: Vec<f32> = create_vec_exact(checked_mul!(row_size, src_height)?)?;
+    for y in 0..src_height {
+        let src_row = row(src, plane, u32_from_usize(y)?)?;
+        for contribution in &horizontal {
+            for c in 0..channels {
+                let mut sum = 0.0;
+                for (k, weight) in contribution.weights.iter().enumerate() {
+                    sum += weight * src_row[(contribution.start + k) * channels + c].into();
+                }
+                rows.push(sum);
+            }
+        }
+    }
+    for (y, contribution) in vertical.iter().enumerate() {
+        let dst_row = row_mut(dst, plane, u32_from_usize(y)?)?;
+        for (x, value) in dst_row[..row_size].iter_mut().enumerate() {
+            let mut sum = 0.0;
+            for (k, weight) in contribution.weights.iter().enumerate() {
+                sum += weight * rows[(contribution.start + k) * row_size + x];
+            }
+            *value = from_f32(sum);
+        }
+    }
+    Ok(())
+}
+
+impl image::Image {
+    /// Scales the image to the given dimensions using the given filter. Both downscaling and
+    /// upscaling are supported, for all the pixel formats and depths. The alpha plane is scaled
+    /// too and the chroma planes of subsampled images are resampled according to
+    /// `chroma_sample_position`. This does not require libyuv.
+    pub fn scale_with_filter(
+        &mut self,
+        width: u32,
+        height: u32,
+        filter: ScalingFilter,
+    ) -> AvifResult<()> {
+        self.scale_planes(width, height, &ALL_PLANES, filter)
+    }
+
+    // Scales the given planes and updates the dimensions of the image. The other planes are left
+    // untouched.
+    pub(crate) fn scale_planes(
+        &mut self,
+        width: u32,
+        height: u32,
+        planes: &[Plane],
+        filter: ScalingFilter,
+    ) -> AvifResult<()> {
+        if self.width == width && self.height == height {
+            return Ok(());
+        }
+        if width == 0 || height == 0 || !self.depth_valid() {
+            return AvifError::invalid_argument();
+        }
+        let planes: Vec<Plane> = planes
+            .iter()
+            .copied()
+            .filter(|plane| self.has_plane(*plane))
+            .collect();
+        // Resample into new buffers so that the image is left untouched if anything fails.
+        let mut dst = self.shallow_clone();
+        dst.width = width;
+        dst.height = height;
+        // Android P010 samples are stored in the 10 most significant bits.
+        let (max_channel, mask) = if self.yuv_format == PixelFormat::AndroidP010 {
+            (u16::MAX, 0xFFC0)
+        } else {
+            (self.max_channel(), u16::MAX)
+        };
+        let max_channel_f = max_channel as f32;
+        for plane in &planes {
+            dst.allocate_plane(*plane)?;
+            if self.depth == 8 {
+                resample_plane(
+                    self,
+                    &mut dst,
+                    *plane,
+                    filter,
+                    image::Image::row,
+                    image::Image::row_mut,
+                    |value| (value + 0.5).clamp(0.0, max_channel_f) as u8,
+                )?;
+            } else {
+                resample_plane(
+                    self,
+                    &mut dst,
+                    *plane,
+                    filter,
+                    image::Image::row16,
+                    image::Image::row16_mut,
+                    |value| (value + 0.5).clamp(0.0, max_channel_f) as u16 & mask,
+                )?;
+            }
+        }
+        for plane in &planes {
+            let index = plane.as_usize();
+            self.planes[index] = dst.planes[index].take();
+            self.row_bytes[index] = dst.row_bytes[index];
+        }
+        self.width = width;
+        self.height = height;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use test_case::test_case;
+    use test_case::test_matrix;
+
+    fn create_image(
+        width: u32,
+        height: u32,
+        depth: u8,
+        yuv_format: PixelFormat,
+        value: impl Fn(Plane, usize, usize) -> u16,
+    ) -> AvifResult<image::Image> {
+        let mut image = image::Image {
+            width,
+            height,
+            depth,
+            yuv_format,
+            ..Default::default()
+        };
+        image.allocate_planes(Category::Color)?;
+        image.allocate_planes(Category::Alpha)?;
+        for plane in ALL_PLANES {
+            for y in 0..image.height(plane) {
+                for x in 0..image.width(plane) {
+                    if depth == 8 {
+                        image.row_mut(plane, y as u32)?[x] = value(plane, x, y) as u8;
+                    } else {
+                        image.row16_mut(plane, y as u32)?[x] = value(plane, x, y);
+                    }
+                }
+            }
+        }
+        Ok(image)
+    }
+
+    fn sample(image: &image::Image, plane: Plane, x: usize, y: usize) -> AvifResult<u16> {
+        Ok(if image.depth == 8 {
+            image.row(plane, y as u32)?[x] as u16
+        } else {
+            image.row16(plane, y as u32)?[x]
+        })
+    }
+
+    #[test_matrix(
+        [PixelFormat::Yuv444, PixelFormat::Yuv422, PixelFormat::Yuv420, PixelFormat::Yuv400,
+         PixelFormat::AndroidNv12, PixelFormat::AndroidNv21],
+        [8, 10, 16],
+        [ScalingFilter::Nearest, ScalingFilter::Bilinear, ScalingFilter::Bicubic,
+         ScalingFilter::Lanczos],
+        [(3, 5), (37, 23)]
+    )]
+    fn uniform(
+        yuv_format: PixelFormat,
+        depth: u8,
+        filter: ScalingFilter,
+        (width, height): (u32, u32),
+    ) -> AvifResult<()> {
+        if depth != 8
+            && matches!(
+                yuv_format,
+                PixelFormat::AndroidNv12 | PixelFormat::AndroidNv21
+            )
+        {
+            return Ok(());
+        }
+        let values = [100, 50, 150, 200];
+        let mut image = create_image(17, 11, depth, yuv_format, |plane, _, _| {
+            values[plane.as_usize()]
+        })?;
+        image.scale_with_filter(width, height, filter)?;
+        assert_eq!((image.width, image.height), (width, height));
+        for plane in ALL_PLANES.iter().filter(|p| image.has_plane(**p)) {
+            for y in 0..image.height(*plane) {
+                for x in 0..image.width(*plane) {
+                    assert_eq!(sample(&image, *plane, x, y)?, values[plane.as_usize()]);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn nearest() -> AvifResult<()> {
+        let mut image = create_image(4, 2, 8, PixelFormat::Yuv444, |_, x, y| (10 * y + x) as u16)?;
+        image.scale_with_filter(2, 4, ScalingFilter::Nearest)?;
+        for plane in ALL_PLANES {
+            for (y, expected) in [[1, 3], [1, 3], [11, 13], [11, 13]].iter().enumerate() {
+                assert_eq!(image.row(plane, y as u32)?, expected);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn bilinear() -> AvifResult<()> {
+        // A horizontal ramp is preserved when upscaling, except at the edges.
+        let mut image = create_image(4, 1, 16, PixelFormat::Yuv400, |_, x, _| (1000 * x) as u16)?;
+        image.scale_with_filter(8, 1, ScalingFilter::Bilinear)?;
+        assert_eq!(
+            image.row16(Plane::Y, 0)?,
+            [0, 250, 750, 1250, 1750, 2250, 2750, 3000]
+        );
+        // Downscaling by two stretches the filter over four samples.
+        image.scale_with_filter(4, 1, ScalingFilter::Bilinear)?;
+        assert_eq!(image.row16(Plane::Y, 0)?, [188, 1000, 2000, 2813]);
+        Ok(())
+    }
+
+    #[test_case(ChromaSamplePosition::Unknown)]
+    #[test_case(ChromaSamplePosition::Vertical)]
+    #[test_case(ChromaSamplePosition::Colocated)]
+    fn chroma_sample_position(chroma_sample_position: ChromaSamplePosition) -> AvifResult<()> {
+        // A chroma ramp whose values are the horizontal luma coordinates of the chroma samples,
+        // scaled by 100.
+        let cosited = chroma_sample_position != ChromaSamplePosition::Unknown;
+        let mut image = create_image(16, 2, 16, PixelFormat::Yuv420, |_, x, _| {
+            (1000 + 200 * x + if cosited { 0 } else { 50 }) as u16
+        })?;
+        image.chroma_sample_position = chroma_sample_position;
+        image.scale_with_filter(32, 4, ScalingFilter::Bilinear)?;
+        // Away from the edges, the values of the chroma samples of the upscaled image are their
+        // luma coordinates in the original image, scaled by 100.
+        for x in 1..15 {
+            let expected = 1000 + 100 * x - if cosited { 25 } else { 0 };
+            let actual = sample(&image, Plane::U, x, 1)?;
+            assert!(
+                (actual as i32 - expected as i32).abs() <= 1,
+                "{x}: {actual} vs {expected}"
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn p010() -> AvifResult<()> {
+        let mut image = create_image(8, 8, 16, PixelFormat::AndroidP010, |_, x, _| {
+            ((x as u16) * 100) << 6
+        })?;
+        image.alpha_present = false;
+        image.scale_with_filter(3, 3, ScalingFilter::Bicubic)?;
+        for plane in [Plane::Y, Plane::U] {
+            for y in 0..image.height(plane) as u32 {
+                assert!(image.row16(plane, y)?.iter().all(|value| value & 0x3F == 0));
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn invalid() -> AvifResult<()> {
+        let mut image = create_image(4, 4, 8, PixelFormat::Yuv420, |_, _, _| 0)?;
+        assert_eq!(
+            image.scale_with_filter(0, 4, ScalingFilter::Bilinear),
+            Err(AvifError::InvalidArgument)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn failure_leaves_image_untouched() -> AvifResult<()> {
+        let mut image = create_image(4, 4, 8, PixelFormat::Yuv420, |_, x, y| (x + y) as u16)?;
+        // The destination planes are too large to be allocated.
+        assert!(image
+            .scale_with_filter(u32::MAX, u32::MAX, ScalingFilter::Bilinear)
+            .is_err());
+        assert_eq!((image.width, image.height), (4, 4));
+        for plane in ALL_PLANES {
+            assert!(image.has_plane(plane));
+            for y in 0..image.height(plane) {
+                for x in 0..image.width(plane) {
+                    assert_eq!(sample(&image, plane, x, y)?, (x + y) as u16);
+                }
+            }
+        }
+        Ok(())
+    }
+}
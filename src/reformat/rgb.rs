@@ -25,6 +25,7 @@ use crate::utils::pixels::*;
 use crate::AvifError;
 use crate::AvifResult;
 use crate::Category;
+use crate::ColorPrimaries;
 use crate::MatrixCoefficients;
 use crate::PixelFormat;
 
@@ -140,6 +141,26 @@ pub enum ChromaDownsampling {
     SharpYuv,
 }
 
+/// Settings used to convert PQ and HLG images to SDR.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ToneMapping {
+    /// Peak luminance of the SDR output in cd/m². The output is encoded with the sRGB transfer
+    /// function.
+    pub target_nits: f32,
+    /// Color primaries of the output, typically ColorPrimaries::Srgb or ColorPrimaries::Smpte432
+    /// (Display P3).
+    pub color_primaries: ColorPrimaries,
+}
+
+impl Default for ToneMapping {
+    fn default() -> Self {
+        Self {
+            target_nits: super::transfer::SDR_WHITE_NITS,
+            color_primaries: ColorPrimaries::Srgb,
+        }
+    }
+}
+
 #[derive(Default)]
 pub struct Image {
     pub width: u32,
@@ -153,6 +174,9 @@ pub struct Image {
     pub max_threads: i32,
     pub pixels: Option<Pixels>,
     pub row_bytes: u32,
+    /// If set, PQ and HLG images are tone mapped to SDR by convert_from_yuv(). Ignored for other
+    /// transfer characteristics.
+    pub tone_mapping: Option<ToneMapping>,
 }
 
 #[derive(Debug, Default, PartialEq)]
@@ -188,6 +212,13 @@ impl From<&crate::image::Image> for Mode {
     }
 }
 
+// Converts a value to a half float with the same approach as libyuv. Only valid for values in
+// [0, 65504]. The constant comes from libyuv. For details, see here:
+// https://chromium.googlesource.com/libyuv/libyuv/+/2f87e9a7/source/row_common.cc#3537
+pub(crate) fn half_float_from_f32(value: f32) -> u16 {
+    ((value * 1.925_93e-34).to_bits() >> 13) as u16
+}
+
 impl Image {
     pub fn max_channel(&self) -> u16 {
         ((1i32 << self.depth) - 1) as u16
@@ -212,6 +243,7 @@ impl Image {
             max_threads: 1,
             pixels: None,
             row_bytes: 0,
+            tone_mapping: None,
         }
     }
 
@@ -334,14 +366,10 @@ impl Image {
         if libyuv::convert_to_half_float(self, scale)?.is_some() {
             return Ok(());
         }
-        // This constant comes from libyuv. For details, see here:
-        // https://chromium.googlesource.com/libyuv/libyuv/+/2f87e9a7/source/row_common.cc#3537
-        let reinterpret_f32_as_u32 = |f: f32| u32::from_le_bytes(f.to_le_bytes());
-        let multiplier = 1.925_93e-34 * scale;
         for y in 0..self.height {
             let row = self.row16_mut(y)?;
             for pixel in row {
-                *pixel = (reinterpret_f32_as_u32((*pixel as f32) * multiplier) >> 13) as u16;
+                *pixel = half_float_from_f32((*pixel as f32) * scale);
             }
         }
         Ok(())
@@ -379,6 +407,23 @@ impl Image {
                 return AvifError::not_implemented();
             }
         }
+        let tone_map = self.tone_mapping.is_some() && image.transfer_characteristics.is_hdr();
+        if tone_map
+            && (matches!(self.format, Format::Rgb565 | Format::Rgba1010102)
+                || image.yuv_format == PixelFormat::AndroidNv21)
+        {
+            return AvifError::not_implemented();
+        }
+        // Half-float output is already converted at 16-bit precision.
+        if tone_map
+            && self.depth != 16
+            && !matches!(
+                image.matrix_coefficients,
+                MatrixCoefficients::YcgcoRe | MatrixCoefficients::YcgcoRo
+            )
+        {
+            return self.convert_from_yuv_with_16bit_tone_mapping(image);
+        }
         // Android MediaCodec maps all underlying YUV formats to PixelFormat::Yuv420. So do not
         // perform this validation for Android MediaCodec. The libyuv wrapper will simply use Bt601
         // coefficients for this color conversion.
@@ -455,12 +500,54 @@ impl Image {
             AlphaMultiplyMode::UnMultiply => self.unpremultiply_alpha()?,
             AlphaMultiplyMode::NoOp => {}
         }
+        if tone_map {
+            let tone_mapping = self.tone_mapping.unwrap();
+            self.tone_map(image, &tone_mapping)?;
+        }
         if self.is_float {
             self.convert_to_half_float()?;
         }
         Ok(())
     }
 
+    // Converts and tone maps at 16-bit precision and only then rounds to the depth of this image,
+    // so that the PQ or HLG signal is not quantized before being linearized.
+    fn convert_from_yuv_with_16bit_tone_mapping(
+        &mut self,
+        image: &crate::image::Image,
+    ) -> AvifResult<()> {
+        let mut rgb16 = Image {
+            width: self.width,
+            height: self.height,
+            depth: 16,
+            format: self.format,
+            chroma_upsampling: self.chroma_upsampling,
+            chroma_downsampling: self.chroma_downsampling,
+            premultiply_alpha: self.premultiply_alpha,
+            is_float: false,
+            max_threads: self.max_threads,
+            tone_mapping: self.tone_mapping,
+            ..Default::default()
+        };
+        rgb16.allocate()?;
+        rgb16.convert_from_yuv(image)?;
+        let max_channel_f = self.max_channel_f();
+        let sample_count = usize_from_u32(checked_mul!(self.width, self.channel_count())?)?;
+        for y in 0..self.height {
+            let source = &rgb16.row16(y)?[..sample_count];
+            if self.depth == 8 {
+                for (dst, src) in self.row_mut(y)?[..sample_count].iter_mut().zip(source) {
+                    *dst = (0.5 + *src as f32 * max_channel_f / 65535.0) as u8;
+                }
+            } else {
+                for (dst, src) in self.row16_mut(y)?[..sample_count].iter_mut().zip(source) {
+                    *dst = (0.5 + *src as f32 * max_channel_f / 65535.0) as u16;
+                }
+            }
+        }
+        Ok(())
+    }
+
     pub fn convert_to_yuv(&self, image: &mut crate::image::Image) -> AvifResult<()> {
         if self.format == Format::Rgb565 || self.is_float {
             return AvifError::not_implemented();
@@ -575,7 +662,7 @@ mod tests {
     use crate::image::ALL_PLANES;
     use crate::image::MAX_PLANE_COUNT;
     use crate::Category;
-    use crate::ColorPrimaries;
+    use crate::TransferCharacteristics;
 
     use test_case::test_case;
     use test_case::test_matrix;
@@ -724,6 +811,46 @@ mod tests {
         Ok(())
     }
 
+    #[test_case(TransferCharacteristics::Pq, None, 130)]
+    // The exact value is 186.46. Quantizing the PQ signal to 8 bits before tone mapping would give
+    // 187.71.
+    #[test_case(TransferCharacteristics::Pq, Some(ToneMapping::default()), 186)]
+    #[test_case(TransferCharacteristics::Srgb, Some(ToneMapping::default()), 130)]
+    fn tone_mapping(
+        transfer_characteristics: TransferCharacteristics,
+        tone_mapping: Option<ToneMapping>,
+        expected_value: u8,
+    ) -> AvifResult<()> {
+        let mut image = crate::image::Image {
+            width: 2,
+            height: 2,
+            depth: 10,
+            yuv_format: PixelFormat::Yuv444,
+            yuv_range: YuvRange::Full,
+            color_primaries: ColorPrimaries::Srgb,
+            transfer_characteristics,
+            matrix_coefficients: MatrixCoefficients::Identity,
+            ..Default::default()
+        };
+        image.allocate_planes(Category::Color)?;
+        // PQ encoding of about 100 cd/m², which is below the knee point of the tone mapping curve.
+        for plane in [Plane::Y, Plane::U, Plane::V] {
+            for y in 0..image.height {
+                image.row16_mut(plane, y)?.fill(520);
+            }
+        }
+        let mut rgb = Image::create_from_yuv(&image);
+        rgb.depth = 8;
+        rgb.format = Format::Rgb;
+        rgb.tone_mapping = tone_mapping;
+        rgb.allocate()?;
+        rgb.convert_from_yuv(&image)?;
+        for y in 0..rgb.height {
+            assert_eq!(rgb.row(y)?, &[expected_value; 6]);
+        }
+        Ok(())
+    }
+
     #[test_case(Format::Rgba, &[0, 1, 2, 3])]
     #[test_case(Format::Abgr, &[3, 2, 1, 0])]
     #[test_case(Format::Rgb, &[0, 1, 2])]
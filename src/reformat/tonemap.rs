@@ -0,0 +1,154 @@
+// Copyright 2025 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::coeffs::*;
+use super::rgb;
+
+use crate::image;
+use crate::*;
+
+// Peak luminance assumed for HDR content without content light level information. This is the
+// most common mastering display peak luminance.
+const DEFAULT_HDR_PEAK_NITS: f32 = 1000.0;
+
+fn pq(nits: f32) -> f32 {
+    let pq = TransferCharacteristics::Pq;
+    pq.linear_to_gamma(nits / pq.nominal_peak_nits())
+}
+
+fn pq_inverse(v: f32) -> f32 {
+    let pq = TransferCharacteristics::Pq;
+    pq.gamma_to_linear(v) * pq.nominal_peak_nits()
+}
+
+// Electro-electrical transfer function described in Section 5.4.1 of ITU-R BT.2408. It compresses
+// luminance values in [0, source_peak] to [0, target_peak] in the PQ domain, leaving the values
+// below a knee point untouched.
+pub(crate) struct Eetf {
+    source_peak_pq: f32,
+    // Normalized PQ values of the knee point and of the target peak.
+    knee: f32,
+    max_luminance: f32,
+}
+
+impl Eetf {
+    pub(crate) fn create(source_peak_nits: f32, target_peak_nits: f32) -> Option<Self> {
+        if source_peak_nits <= target_peak_nits {
+            return None;
+        }
+        let source_peak_pq = pq(source_peak_nits);
+        let max_luminance = pq(target_peak_nits) / source_peak_pq;
+        Some(Self {
+            source_peak_pq,
+            knee: (1.5 * max_luminance - 0.5).max(0.0),
+            max_luminance,
+        })
+    }
+
+    pub(crate) fn apply(&self, nits: f32) -> f32 {
+        let e1 = (pq(nits) / self.source_peak_pq).min(1.0);
+        if e1 < self.knee {
+            return nits;
+        }
+        // Hermite spline.
+        let t = (e1 - self.knee) / (1.0 - self.knee);
+        let t2 = t * t;
+        let t3 = t2 * t;
+        let e2 = (2.0 * t3 - 3.0 * t2 + 1.0) * self.knee
+            + (t3 - 2.0 * t2 + t) * (1.0 - self.knee)
+            + (-2.0 * t3 + 3.0 * t2) * self.max_luminance;
+        pq_inverse(e2 * self.source_peak_pq)
+    }
+}
+
+impl rgb::Image {
+    // Converts the PQ or HLG encoded pixels of this image (converted from `image`) into SDR pixels
+    // encoded with the sRGB transfer function in the color primaries of `tone_mapping`.
+    pub(crate) fn tone_map(
+        &mut self,
+        image: &image::Image,
+        tone_mapping: &rgb::ToneMapping,
+    ) -> AvifResult<()> {
+        if tone_mapping.target_nits <= 0.0 {
+            return AvifError::invalid_argument();
+        }
+        let transfer_characteristics = image.transfer_characteristics;
+        let nominal_peak_nits = transfer_characteristics.nominal_peak_nits();
+        let source_peak_nits = match image.clli {
+            Some(clli) if clli.max_cll > 0 => clli.max_cll as f32,
+            _ => DEFAULT_HDR_PEAK_NITS,
+        };
+        let eetf = Eetf::create(source_peak_nits, tone_mapping.target_nits);
+        let matrix = if self.format.is_gray() {
+            None
+        } else {
+            primaries_conversion_matrix(image.color_primaries, tone_mapping.color_primaries)
+        };
+        let target_nits = tone_mapping.target_nits;
+        self.transform_pixels(|pixel| {
+            let mut linear = [0.0f32; 3];
+            for (l, v) in linear.iter_mut().zip(pixel.iter()) {
+                *l = transfer_characteristics.gamma_to_linear(*v) * nominal_peak_nits;
+            }
+            if let Some(matrix) = &matrix {
+                linear = matrix3_apply(matrix, linear).map(|v| v.max(0.0));
+            }
+            if let Some(eetf) = &eetf {
+                // Scale all channels by the same factor to preserve the hue.
+                let max = linear[..pixel.len()].iter().fold(0.0f32, |a, b| a.max(*b));
+                if max > 0.0 {
+                    let scale = eetf.apply(max) / max;
+                    linear.iter_mut().for_each(|v| *v *= scale);
+                }
+            }
+            for (v, l) in pixel.iter_mut().zip(linear) {
+                *v = TransferCharacteristics::Srgb.linear_to_gamma(l / target_nits);
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use test_case::test_case;
+
+    #[test]
+    fn eetf_not_needed() {
+        assert!(Eetf::create(203.0, 203.0).is_none());
+        assert!(Eetf::create(100.0, 203.0).is_none());
+    }
+
+    #[test_case(1000.0, 203.0)]
+    #[test_case(4000.0, 203.0)]
+    #[test_case(10000.0, 100.0)]
+    #[test_case(1000.0, 600.0)]
+    fn eetf(source_peak_nits: f32, target_peak_nits: f32) {
+        let eetf = Eetf::create(source_peak_nits, target_peak_nits).unwrap();
+        // Dark values are untouched.
+        assert_eq!(eetf.apply(1.0), 1.0);
+        // The source peak is mapped to the target peak.
+        assert!((eetf.apply(source_peak_nits) - target_peak_nits).abs() < 0.5);
+        // The curve is monotonic and never exceeds the target peak.
+        let mut previous = 0.0;
+        for i in 0..=100 {
+            let nits = source_peak_nits * i as f32 / 100.0;
+            let mapped = eetf.apply(nits);
+            assert!(mapped >= previous);
+            assert!(mapped <= target_peak_nits + 0.5);
+            previous = mapped;
+        }
+    }
+}
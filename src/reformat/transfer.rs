@@ -0,0 +1,229 @@
+// Copyright 2025 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::*;
+
+// Nominal luminance of the SDR reference white, in cd/m². See ITU-R BT.2408.
+pub(crate) const SDR_WHITE_NITS: f32 = 203.0;
+
+const PQ_MAX_NITS: f32 = 10000.0;
+const HLG_MAX_NITS: f32 = 1000.0;
+
+// Constants from Section 5.3 of SMPTE ST 2084.
+const PQ_M1: f32 = 2610.0 / 16384.0;
+const PQ_M2: f32 = 2523.0 / 4096.0 * 128.0;
+const PQ_C1: f32 = 3424.0 / 4096.0;
+const PQ_C2: f32 = 2413.0 / 4096.0 * 32.0;
+const PQ_C3: f32 = 2392.0 / 4096.0 * 32.0;
+
+// Constants from Table 5 of ITU-R BT.2100.
+const HLG_A: f32 = 0.178_832_77;
+const HLG_B: f32 = 0.284_668_92; // 1 - 4 * HLG_A
+const HLG_C: f32 = 0.559_910_7; // 0.5 - HLG_A * ln(4 * HLG_A)
+
+// System gamma of the HLG reference OOTF for a 1000 cd/m² display.
+const HLG_SYSTEM_GAMMA: f32 = 1.2;
+
+fn bt709_to_linear(v: f32) -> f32 {
+    if v < 0.081 {
+        v / 4.5
+    } else {
+        ((v + 0.099) / 1.099).powf(1.0 / 0.45)
+    }
+}
+
+fn bt709_from_linear(l: f32) -> f32 {
+    if l < 0.018 {
+        l * 4.5
+    } else {
+        1.099 * l.powf(0.45) - 0.099
+    }
+}
+
+fn srgb_to_linear(v: f32) -> f32 {
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn srgb_from_linear(l: f32) -> f32 {
+    if l <= 0.003_130_8 {
+        l * 12.92
+    } else {
+        1.055 * l.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+fn pq_to_linear(v: f32) -> f32 {
+    let vp = v.max(0.0).powf(1.0 / PQ_M2);
+    ((vp - PQ_C1).max(0.0) / (PQ_C2 - PQ_C3 * vp)).powf(1.0 / PQ_M1)
+}
+
+fn pq_from_linear(l: f32) -> f32 {
+    let lp = l.max(0.0).powf(PQ_M1);
+    ((PQ_C1 + PQ_C2 * lp) / (1.0 + PQ_C3 * lp)).powf(PQ_M2)
+}
+
+fn hlg_to_linear(v: f32) -> f32 {
+    // Inverse OETF followed by a per-channel approximation of the reference OOTF.
+    let scene = if v <= 0.5 {
+        v * v / 3.0
+    } else {
+        (((v - HLG_C) / HLG_A).exp() + HLG_B) / 12.0
+    };
+    scene.max(0.0).powf(HLG_SYSTEM_GAMMA)
+}
+
+fn hlg_from_linear(l: f32) -> f32 {
+    let scene = l.max(0.0).powf(1.0 / HLG_SYSTEM_GAMMA);
+    if scene <= 1.0 / 12.0 {
+        (3.0 * scene).sqrt()
+    } else {
+        HLG_A * (12.0 * scene - HLG_B).ln() + HLG_C
+    }
+}
+
+impl TransferCharacteristics {
+    // Converts a non-linear value in [0, 1] into a linear light value in [0, 1], where 1.0 is
+    // the nominal peak of the transfer function (see nominal_peak_nits()). Formulas come from
+    // Table 3 of ITU-T H.273. Unknown and unspecified values are treated as sRGB.
+    pub(crate) fn gamma_to_linear(&self, v: f32) -> f32 {
+        let v = v.clamp(0.0, 1.0);
+        match self {
+            Self::Bt709
+            | Self::Bt601
+            | Self::Bt1361
+            | Self::Bt2020_10bit
+            | Self::Bt2020_12bit
+            | Self::Iec61966 => bt709_to_linear(v),
+            Self::Bt470m => v.powf(2.2),
+            Self::Bt470bg => v.powf(2.8),
+            Self::Smpte240 => {
+                if v < 0.0913 {
+                    v / 4.0
+                } else {
+                    ((v + 0.1115) / 1.1115).powf(1.0 / 0.45)
+                }
+            }
+            Self::Linear => v,
+            Self::Log100 => {
+                if v <= 0.0 {
+                    0.0
+                } else {
+                    10f32.powf(2.0 * (v - 1.0))
+                }
+            }
+            Self::Log100Sqrt10 => {
+                if v <= 0.0 {
+                    0.0
+                } else {
+                    10f32.powf(2.5 * (v - 1.0))
+                }
+            }
+            Self::Pq => pq_to_linear(v),
+            Self::Smpte428 => v.powf(2.6) * 52.37 / 48.0,
+            Self::Hlg => hlg_to_linear(v),
+            Self::Unknown | Self::Unspecified | Self::Reserved | Self::Srgb => srgb_to_linear(v),
+        }
+    }
+
+    // Inverse of gamma_to_linear(). The input is clamped to [0, 1].
+    pub(crate) fn linear_to_gamma(&self, l: f32) -> f32 {
+        let l = l.clamp(0.0, 1.0);
+        match self {
+            Self::Bt709
+            | Self::Bt601
+            | Self::Bt1361
+            | Self::Bt2020_10bit
+            | Self::Bt2020_12bit
+            | Self::Iec61966 => bt709_from_linear(l),
+            Self::Bt470m => l.powf(1.0 / 2.2),
+            Self::Bt470bg => l.powf(1.0 / 2.8),
+            Self::Smpte240 => {
+                if l < 0.0228 {
+                    l * 4.0
+                } else {
+                    1.1115 * l.powf(0.45) - 0.1115
+                }
+            }
+            Self::Linear => l,
+            Self::Log100 => {
+                if l < 0.01 {
+                    0.0
+                } else {
+                    1.0 + l.log10() / 2.0
+                }
+            }
+            Self::Log100Sqrt10 => {
+                if l < 0.003_162_277_6 {
+                    0.0
+                } else {
+                    1.0 + l.log10() / 2.5
+                }
+            }
+            Self::Pq => pq_from_linear(l),
+            Self::Smpte428 => (l * 48.0 / 52.37).powf(1.0 / 2.6),
+            Self::Hlg => hlg_from_linear(l),
+            Self::Unknown | Self::Unspecified | Self::Reserved | Self::Srgb => srgb_from_linear(l),
+        }
+        .clamp(0.0, 1.0)
+    }
+
+    // Luminance in cd/m² of a linear value of 1.0 as returned by gamma_to_linear(). SDR transfer
+    // functions are relative to the SDR reference white.
+    pub(crate) fn nominal_peak_nits(&self) -> f32 {
+        match self {
+            Self::Pq => PQ_MAX_NITS,
+            Self::Hlg => HLG_MAX_NITS,
+            _ => SDR_WHITE_NITS,
+        }
+    }
+
+    pub(crate) fn is_hdr(&self) -> bool {
+        matches!(self, Self::Pq | Self::Hlg)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use test_case::test_case;
+
+    #[test_case(TransferCharacteristics::Bt709)]
+    #[test_case(TransferCharacteristics::Bt470m)]
+    #[test_case(TransferCharacteristics::Bt470bg)]
+    #[test_case(TransferCharacteristics::Smpte240)]
+    #[test_case(TransferCharacteristics::Linear)]
+    #[test_case(TransferCharacteristics::Srgb)]
+    #[test_case(TransferCharacteristics::Pq)]
+    #[test_case(TransferCharacteristics::Hlg)]
+    fn round_trip(transfer_characteristics: TransferCharacteristics) {
+        for i in 0..=100 {
+            let v = i as f32 / 100.0;
+            let round_trip = transfer_characteristics
+                .linear_to_gamma(transfer_characteristics.gamma_to_linear(v));
+            assert!((v - round_trip).abs() < 0.001, "{v} {round_trip}");
+        }
+    }
+
+    #[test]
+    fn pq_reference_white() {
+        // 203 cd/m² is encoded as ~58% of the PQ signal range (ITU-R BT.2408).
+        let v = TransferCharacteristics::Pq.linear_to_gamma(SDR_WHITE_NITS / PQ_MAX_NITS);
+        assert!((v - 0.58).abs() < 0.01);
+    }
+}
@@ -0,0 +1,388 @@
+// Copyright 2025 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::decoder::track::ImageTiming;
+use crate::internal_utils::stream::*;
+use crate::internal_utils::*;
+use crate::parser::mp4box::BoxSize;
+use crate::parser::obu::Av1SequenceHeader;
+use crate::*;
+
+const OBU_TEMPORAL_DELIMITER: u8 = 2;
+const OBU_FRAME_HEADER: u8 = 3;
+const OBU_FRAME: u8 = 6;
+const OBU_TILE_LIST: u8 = 8;
+const OBU_PADDING: u8 = 15;
+
+// Temporal delimiter OBU with an obu_size field.
+const TEMPORAL_DELIMITER: [u8; 2] = [0x12, 0x00];
+
+/// Format of a stream of AV1 OBUs.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Av1StreamFormat {
+    /// Low overhead bitstream format (Section 5.2 of the AV1 specification), usually stored in
+    /// .obu files.
+    #[default]
+    LowOverhead,
+    /// Length delimited bitstream format (Annex B of the AV1 specification).
+    AnnexB,
+    /// IVF container of temporal units in the low overhead bitstream format.
+    Ivf,
+}
+
+struct Obu<'a> {
+    obu_type: u8,
+    // The obu_header() bytes, including the optional extension byte.
+    header: &'a [u8],
+    payload: &'a [u8],
+}
+
+// Parses the OBU at the beginning of |data| and returns it along with the number of bytes it
+// spans. If the OBU has no obu_size field, it spans all of |data|.
+fn parse_obu(data: &[u8]) -> AvifResult<(Obu<'_>, usize)> {
+    // Section 5.3.1 of AV1 specification.
+    let mut stream = IStream::create(data);
+    let obu_header = stream.read_u8()?;
+    if (obu_header & 0x80) != 0 {
+        return AvifError::bmff_parse_failed("invalid obu_forbidden_bit");
+    }
+    let obu_type = (obu_header >> 3) & 0xF;
+    let obu_extension_flag = (obu_header & 0x04) != 0;
+    let obu_has_size_field = (obu_header & 0x02) != 0;
+    if obu_extension_flag {
+        stream.skip(1)?;
+    }
+    let header_size = stream.offset;
+    let payload_size = if obu_has_size_field {
+        usize_from_u32(stream.read_uleb128()?)?
+    } else {
+        stream.bytes_left()?
+    };
+    let payload_start = stream.offset;
+    stream.skip(payload_size)?;
+    Ok((
+        Obu {
+            obu_type,
+            header: &data[..header_size],
+            payload: &data[payload_start..stream.offset],
+        },
+        stream.offset,
+    ))
+}
+
+fn write_leb128(output: &mut Vec<u8>, mut value: usize) {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            output.push(byte);
+            return;
+        }
+        output.push(byte | 0x80);
+    }
+}
+
+// Appends |obu| to |sample| with an obu_size field, unless it is not allowed in AV1 samples as
+// per Section 2.4 of the AV1 Codec ISO Media File Format Binding.
+fn append_obu_to_sample(sample: &mut Vec<u8>, obu: &Obu) {
+    if matches!(
+        obu.obu_type,
+        OBU_TEMPORAL_DELIMITER | OBU_TILE_LIST | OBU_PADDING
+    ) {
+        return;
+    }
+    // Set obu_has_size_field.
+    sample.push(obu.header[0] | 0x02);
+    sample.extend_from_slice(&obu.header[1..]);
+    write_leb128(sample, obu.payload.len());
+    sample.extend_from_slice(obu.payload);
+}
+
+fn split_low_overhead_temporal_units(data: &[u8]) -> AvifResult<Vec<Vec<u8>>> {
+    let mut temporal_units = Vec::new();
+    let mut sample = Vec::new();
+    let mut offset = 0;
+    while offset < data.len() {
+        let (obu, obu_size) = parse_obu(&data[offset..])?;
+        offset += obu_size;
+        // Each temporal unit starts with a temporal delimiter OBU.
+        if obu.obu_type == OBU_TEMPORAL_DELIMITER && !sample.is_empty() {
+            temporal_units.push(std::mem::take(&mut sample));
+        }
+        append_obu_to_sample(&mut sample, &obu);
+    }
+    if !sample.is_empty() {
+        temporal_units.push(sample);
+    }
+    Ok(temporal_units)
+}
+
+fn split_annexb_temporal_units(data: &[u8]) -> AvifResult<Vec<Vec<u8>>> {
+    // Annex B.2 of AV1 specification.
+    let mut temporal_units = Vec::new();
+    let mut stream = IStream::create(data);
+    while stream.has_bytes_left()? {
+        let temporal_unit_size = usize_from_u32(stream.read_uleb128()?)?;
+        let mut temporal_unit = stream.sub_stream(&BoxSize::FixedSize(temporal_unit_size))?;
+        let mut sample = Vec::new();
+        while temporal_unit.has_bytes_left()? {
+            let frame_unit_size = usize_from_u32(temporal_unit.read_uleb128()?)?;
+            let mut frame_unit = temporal_unit.sub_stream(&BoxSize::FixedSize(frame_unit_size))?;
+            while frame_unit.has_bytes_left()? {
+                let obu_length = usize_from_u32(frame_unit.read_uleb128()?)?;
+                let obu_data = frame_unit.get_slice(obu_length)?;
+                let (obu, obu_size) = parse_obu(obu_data)?;
+                if obu_size != obu_length {
+                    return AvifError::bmff_parse_failed("invalid obu_length");
+                }
+                append_obu_to_sample(&mut sample, &obu);
+            }
+        }
+        if !sample.is_empty() {
+            temporal_units.push(sample);
+        }
+    }
+    Ok(temporal_units)
+}
+
+fn split_ivf_temporal_units(data: &[u8]) -> AvifResult<Vec<Vec<u8>>> {
+    let mut stream = IStream::create(data);
+    if stream.read_string(4)? != "DKIF" {
+        return AvifError::bmff_parse_failed("invalid IVF signature");
+    }
+    // version
+    stream.skip_u16()?;
+    let header_size = stream.read_u16_le()? as usize;
+    if stream.read_string(4)? != "AV01" {
+        return AvifError::bmff_parse_failed("IVF codec is not AV1");
+    }
+    stream.offset = 0;
+    stream.skip(header_size)?;
+    let mut temporal_units = Vec::new();
+    while stream.has_bytes_left()? {
+        let frame_size = usize_from_u32(stream.read_u32_le()?)?;
+        // timestamp
+        stream.skip_u64()?;
+        let mut sample = Vec::new();
+        let frame = stream.get_slice(frame_size)?;
+        let mut offset = 0;
+        while offset < frame.len() {
+            let (obu, obu_size) = parse_obu(&frame[offset..])?;
+            offset += obu_size;
+            append_obu_to_sample(&mut sample, &obu);
+        }
+        if !sample.is_empty() {
+            temporal_units.push(sample);
+        }
+    }
+    Ok(temporal_units)
+}
+
+/// Splits an AV1 bitstream into temporal units. Each returned temporal unit can be passed as is to
+/// [`crate::encoder::Encoder::add_encoded_image`]: its OBUs are in the low overhead bitstream
+/// format and the temporal delimiter, tile list and padding OBUs are removed, as required for AV1
+/// samples by the AV1 Codec ISO Media File Format Binding.
+pub fn split_temporal_units(data: &[u8], format: Av1StreamFormat) -> AvifResult<Vec<Vec<u8>>> {
+    match format {
+        Av1StreamFormat::LowOverhead => split_low_overhead_temporal_units(data),
+        Av1StreamFormat::AnnexB => split_annexb_temporal_units(data),
+        Av1StreamFormat::Ivf => split_ivf_temporal_units(data),
+    }
+}
+
+// Returns the OBUs of a temporal unit stored in an AVIF file.
+fn parse_sample_obus(sample: &[u8]) -> AvifResult<Vec<Obu<'_>>> {
+    let mut obus = Vec::new();
+    let mut offset = 0;
+    while offset < sample.len() {
+        let (obu, obu_size) = parse_obu(&sample[offset..])?;
+        offset += obu_size;
+        if obu.obu_type != OBU_TEMPORAL_DELIMITER {
+            obus.push(obu);
+        }
+    }
+    Ok(obus)
+}
+
+// Appends a temporal delimiter OBU followed by |obus| to |output|, all with an obu_size field.
+fn append_low_overhead_temporal_unit(output: &mut Vec<u8>, obus: &[Obu]) {
+    output.extend_from_slice(&TEMPORAL_DELIMITER);
+    for obu in obus {
+        append_obu_to_sample(output, obu);
+    }
+}
+
+fn append_annexb_temporal_unit(output: &mut Vec<u8>, obus: &[Obu]) {
+    // Annex B.2 of AV1 specification. Each frame unit contains a single frame.
+    let mut frame_units: Vec<Vec<u8>> = vec![Vec::new()];
+    let mut frame_unit_has_frame = false;
+    let mut obu_data = TEMPORAL_DELIMITER.to_vec();
+    write_leb128(&mut frame_units[0], obu_data.len());
+    frame_units[0].append(&mut obu_data);
+    for obu in obus {
+        if matches!(obu.obu_type, OBU_FRAME_HEADER | OBU_FRAME) {
+            if frame_unit_has_frame {
+                frame_units.push(Vec::new());
+            }
+            frame_unit_has_frame = true;
+        }
+        append_obu_to_sample(&mut obu_data, obu);
+        if obu_data.is_empty() {
+            continue;
+        }
+        let frame_unit = frame_units.last_mut().unwrap();
+        write_leb128(frame_unit, obu_data.len());
+        frame_unit.append(&mut obu_data);
+    }
+    let mut temporal_unit = Vec::new();
+    for frame_unit in frame_units {
+        write_leb128(&mut temporal_unit, frame_unit.len());
+        temporal_unit.extend_from_slice(&frame_unit);
+    }
+    write_leb128(output, temporal_unit.len());
+    output.append(&mut temporal_unit);
+}
+
+fn write_ivf_header(
+    output: &mut Vec<u8>,
+    first_temporal_unit: &[u8],
+    timescale: u32,
+    frame_count: u32,
+) -> AvifResult<()> {
+    let sequence_header = Av1SequenceHeader::parse_from_obus(first_temporal_unit)?;
+    output.extend_from_slice(b"DKIF");
+    // version
+    output.extend_from_slice(&0u16.to_le_bytes());
+    // header size
+    output.extend_from_slice(&32u16.to_le_bytes());
+    output.extend_from_slice(b"AV01");
+    output.extend_from_slice(&u16_from_u32(sequence_header.max_width)?.to_le_bytes());
+    output.extend_from_slice(&u16_from_u32(sequence_header.max_height)?.to_le_bytes());
+    // Frame rate numerator and denominator, in other words the timescale.
+    output.extend_from_slice(&timescale.to_le_bytes());
+    output.extend_from_slice(&1u32.to_le_bytes());
+    output.extend_from_slice(&frame_count.to_le_bytes());
+    // unused
+    output.extend_from_slice(&0u32.to_le_bytes());
+    Ok(())
+}
+
+/// Concatenates AV1 temporal units, as stored in AVIF files, into a bitstream of the given format.
+/// Each temporal unit is prefixed with a temporal delimiter OBU. `timings` is only used for
+/// [`Av1StreamFormat::Ivf`]: if not empty, it contains the timing of each temporal unit, as
+/// returned by [`crate::decoder::Decoder::nth_image_timing`]. Otherwise the temporal units are
+/// numbered with a timescale of 1.
+pub fn join_temporal_units(
+    temporal_units: &[Vec<u8>],
+    timings: &[ImageTiming],
+    format: Av1StreamFormat,
+) -> AvifResult<Vec<u8>> {
+    if temporal_units.is_empty() || (!timings.is_empty() && timings.len() != temporal_units.len()) {
+        return AvifError::invalid_argument();
+    }
+    let mut output = Vec::new();
+    if format == Av1StreamFormat::Ivf {
+        let timescale = if timings.is_empty() { 1 } else { u32_from_u64(timings[0].timescale)? };
+        let frame_count = u32_from_usize(temporal_units.len())?;
+        write_ivf_header(&mut output, &temporal_units[0], timescale, frame_count)?;
+    }
+    for (index, temporal_unit) in temporal_units.iter().enumerate() {
+        let obus = parse_sample_obus(temporal_unit)?;
+        match format {
+            Av1StreamFormat::LowOverhead => append_low_overhead_temporal_unit(&mut output, &obus),
+            Av1StreamFormat::AnnexB => append_annexb_temporal_unit(&mut output, &obus),
+            Av1StreamFormat::Ivf => {
+                let mut frame = Vec::new();
+                append_low_overhead_temporal_unit(&mut frame, &obus);
+                let pts = match timings.get(index) {
+                    Some(timing) => timing.pts_in_timescales,
+                    None => index as u64,
+                };
+                output.extend_from_slice(&u32_from_usize(frame.len())?.to_le_bytes());
+                output.extend_from_slice(&pts.to_le_bytes());
+                output.append(&mut frame);
+            }
+        }
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use test_case::test_case;
+
+    // OBUs of two temporal units, with obu_has_size_field set.
+    const SEQUENCE_HEADER: [u8; 4] = [0x0A, 0x02, 0xAA, 0xBB];
+    const FRAME1: [u8; 5] = [0x32, 0x03, 0x01, 0x02, 0x03];
+    const FRAME2: [u8; 3] = [0x32, 0x01, 0x04];
+    const PADDING: [u8; 3] = [0x7A, 0x01, 0x00];
+
+    fn temporal_units() -> Vec<Vec<&'static [u8]>> {
+        vec![
+            vec![&TEMPORAL_DELIMITER, &SEQUENCE_HEADER, &FRAME1, &PADDING],
+            vec![&TEMPORAL_DELIMITER, &FRAME2],
+        ]
+    }
+
+    fn annexb_obu(obu: &[u8]) -> Vec<u8> {
+        // Clear obu_has_size_field and remove obu_size.
+        let mut annexb_obu = vec![obu[0] & !0x02];
+        annexb_obu.extend_from_slice(&obu[2..]);
+        annexb_obu
+    }
+
+    fn with_size(data: Vec<u8>) -> Vec<u8> {
+        let mut output = Vec::new();
+        write_leb128(&mut output, data.len());
+        output.extend_from_slice(&data);
+        output
+    }
+
+    #[test_case(Av1StreamFormat::LowOverhead)]
+    #[test_case(Av1StreamFormat::AnnexB)]
+    #[test_case(Av1StreamFormat::Ivf)]
+    fn split(format: Av1StreamFormat) -> AvifResult<()> {
+        let mut data = Vec::new();
+        if format == Av1StreamFormat::Ivf {
+            data.extend_from_slice(b"DKIF\0\0\x20\0AV01");
+            data.resize(32, 0);
+        }
+        for temporal_unit in temporal_units() {
+            match format {
+                Av1StreamFormat::LowOverhead => data.extend(temporal_unit.concat()),
+                Av1StreamFormat::AnnexB => {
+                    let frame_unit: Vec<u8> = temporal_unit
+                        .iter()
+                        .flat_map(|obu| with_size(annexb_obu(obu)))
+                        .collect();
+                    data.extend(with_size(with_size(frame_unit)));
+                }
+                Av1StreamFormat::Ivf => {
+                    let frame = temporal_unit.concat();
+                    data.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+                    data.extend_from_slice(&[0; 8]);
+                    data.extend(frame);
+                }
+            }
+        }
+        assert_eq!(
+            split_temporal_units(&data, format)?,
+            vec![[&SEQUENCE_HEADER[..], &FRAME1].concat(), FRAME2.to_vec()]
+        );
+        assert!(split_temporal_units(&data[..data.len() - 1], format).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,488 @@
+// Copyright 2025 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::image;
+use crate::image::Plane;
+use crate::internal_utils::*;
+use crate::reformat::rgb;
+use crate::*;
+
+/// Full-reference image quality metric.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Metric {
+    /// Peak signal-to-noise ratio, in decibels. Identical samples score 99.0 and other scores are
+    /// capped at 98.99, like in libavif.
+    #[default]
+    Psnr,
+    /// Structural similarity index (Gaussian window of 11 samples with a standard deviation of
+    /// 1.5), in [-1, 1] where 1 means identical.
+    Ssim,
+    /// Multi-scale structural similarity index over up to 5 scales, in [0, 1] where 1 means
+    /// identical. Fewer scales are used for small images.
+    MsSsim,
+}
+
+/// Scores of a [`Metric`] for each plane or channel and for the whole image.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Scores {
+    /// Scores of the Y, U, V and A planes of YUV images, or of the R, G, B and A channels of RGB
+    /// images (gray RGB formats use the first entry). None for absent planes or channels.
+    pub planes: [Option<f64>; 4],
+    /// Score of all the compared samples, each plane or channel being weighted by its number of
+    /// samples.
+    pub combined: f64,
+}
+
+// The samples of one plane or channel.
+struct Channel {
+    width: usize,
+    height: usize,
+    samples: Vec<f64>,
+}
+
+impl Channel {
+    fn downscale(&self) -> Channel {
+        let width = self.width / 2;
+        let height = self.height / 2;
+        let mut samples = Vec::with_capacity(width * height);
+        for y in 0..height {
+            let row0 = &self.samples[2 * y * self.width..];
+            let row1 = &self.samples[(2 * y + 1) * self.width..];
+            for x in 0..width {
+                samples.push((row0[2 * x] + row0[2 * x + 1] + row1[2 * x] + row1[2 * x + 1]) / 4.0);
+            }
+        }
+        Channel {
+            width,
+            height,
+            samples,
+        }
+    }
+}
+
+fn yuv_channel(image: &image::Image, plane: Plane) -> AvifResult<Channel> {
+    let width = image.width(plane);
+    let height = image.height(plane);
+    let mut samples: Vec<f64> = create_vec_exact(width * height)?;
+    for y in 0..height as u32 {
+        if image.depth == 8 {
+            samples.extend(image.row(plane, y)?[..width].iter().map(|x| *x as f64));
+        } else {
+            samples.extend(image.row16(plane, y)?[..width].iter().map(|x| *x as f64));
+        }
+    }
+    Ok(Channel {
+        width,
+        height,
+        samples,
+    })
+}
+
+fn rgb_channel(image: &rgb::Image, channel: usize) -> AvifResult<Channel> {
+    let width = usize_from_u32(image.width)?;
+    let height = usize_from_u32(image.height)?;
+    let channel_count = usize_from_u32(image.channel_count())?;
+    let offset = image.format.offsets()[channel];
+    let mut samples: Vec<f64> = create_vec_exact(width * height)?;
+    for y in 0..image.height {
+        if image.depth == 8 {
+            let row = &image.row(y)?[..width * channel_count];
+            samples.extend(row.chunks_exact(channel_count).map(|x| x[offset] as f64));
+        } else {
+            let row = &image.row16(y)?[..width * channel_count];
+            samples.extend(row.chunks_exact(channel_count).map(|x| x[offset] as f64));
+        }
+    }
+    Ok(Channel {
+        width,
+        height,
+        samples,
+    })
+}
+
+fn psnr_from_mse(mse: f64, max_value: f64) -> f64 {
+    if mse == 0.0 {
+        return 99.0;
+    }
+    let normalized_error = mse / (max_value * max_value);
+    if normalized_error <= f64::EPSILON {
+        98.99
+    } else {
+        (-10.0 * normalized_error.log10()).min(98.99)
+    }
+}
+
+fn mse(channel1: &Channel, channel2: &Channel) -> f64 {
+    let sum: f64 = channel1
+        .samples
+        .iter()
+        .zip(&channel2.samples)
+        .map(|(a, b)| {
+            let diff = *a - *b;
+            diff * diff
+        })
+        .sum();
+    sum / channel1.samples.len() as f64
+}
+
+const SSIM_WINDOW_RADIUS: usize = 5;
+
+fn gaussian_window() -> [f64; 2 * SSIM_WINDOW_RADIUS + 1] {
+    const SIGMA: f64 = 1.5;
+    let mut window = [0.0; 2 * SSIM_WINDOW_RADIUS + 1];
+    for (i, weight) in window.iter_mut().enumerate() {
+        let distance = i as f64 - SSIM_WINDOW_RADIUS as f64;
+        *weight = (-distance * distance / (2.0 * SIGMA * SIGMA)).exp();
+    }
+    let sum: f64 = window.iter().sum();
+    window.iter_mut().for_each(|weight| *weight /= sum);
+    window
+}
+
+// Separable Gaussian blur. Samples outside of the plane are clamped to the edges.
+fn blur(samples: &[f64], width: usize, height: usize) -> Vec<f64> {
+    let window = gaussian_window();
+    let tap =
+        |i: usize, k: usize, size: usize| (i + k).saturating_sub(SSIM_WINDOW_RADIUS).min(size - 1);
+    let mut horizontal = vec![0.0f64; samples.len()];
+    for y in 0..height {
+        let row = &samples[y * width..(y + 1) * width];
+        for x in 0..width {
+            horizontal[y * width + x] = window
+                .iter()
+                .enumerate()
+                .map(|(k, weight)| weight * row[tap(x, k, width)])
+                .sum();
+        }
+    }
+    let mut output = vec![0.0f64; samples.len()];
+    for y in 0..height {
+        for x in 0..width {
+            output[y * width + x] = window
+                .iter()
+                .enumerate()
+                .map(|(k, weight)| weight * horizontal[tap(y, k, height) * width + x])
+                .sum();
+        }
+    }
+    output
+}
+
+// Returns the mean SSIM and the mean contrast-structure term of the two channels.
+fn ssim_and_contrast_structure(
+    channel1: &Channel,
+    channel2: &Channel,
+    max_value: f64,
+) -> (f64, f64) {
+    let c1 = (0.01 * max_value).powi(2);
+    let c2 = (0.03 * max_value).powi(2);
+    let (width, height) = (channel1.width, channel1.height);
+    let product = |f: fn(f64, f64) -> f64| -> Vec<f64> {
+        let samples: Vec<f64> = channel1
+            .samples
+            .iter()
+            .zip(&channel2.samples)
+            .map(|(a, b)| f(*a, *b))
+            .collect();
+        blur(&samples, width, height)
+    };
+    let mu1 = blur(&channel1.samples, width, height);
+    let mu2 = blur(&channel2.samples, width, height);
+    let sigma11 = product(|a, _| a * a);
+    let sigma22 = product(|_, b| b * b);
+    let sigma12 = product(|a, b| a * b);
+    let mut ssim_sum = 0.0f64;
+    let mut contrast_structure_sum = 0.0f64;
+    for i in 0..channel1.samples.len() {
+        let (mu1, mu2) = (mu1[i], mu2[i]);
+        let variance1 = sigma11[i] - mu1 * mu1;
+        let variance2 = sigma22[i] - mu2 * mu2;
+        let covariance = sigma12[i] - mu1 * mu2;
+        let luminance = (2.0 * mu1 * mu2 + c1) / (mu1 * mu1 + mu2 * mu2 + c1);
+        let contrast_structure = (2.0 * covariance + c2) / (variance1 + variance2 + c2);
+        ssim_sum += luminance * contrast_structure;
+        contrast_structure_sum += contrast_structure;
+    }
+    let count = channel1.samples.len() as f64;
+    (ssim_sum / count, contrast_structure_sum / count)
+}
+
+fn ms_ssim(channel1: &Channel, channel2: &Channel, max_value: f64) -> f64 {
+    const WEIGHTS: [f64; 5] = [0.0448, 0.2856, 0.3001, 0.2363, 0.1333];
+    let min_dimension = channel1.width.min(channel1.height);
+    let scale_count = WEIGHTS
+        .len()
+        .min(1 + min_dimension.checked_ilog2().unwrap_or(0) as usize);
+    let weight_sum: f64 = WEIGHTS[..scale_count].iter().sum();
+    let mut downscaled: Option<(Channel, Channel)> = None;
+    let mut score = 1.0;
+    for (scale, weight) in WEIGHTS[..scale_count].iter().enumerate() {
+        let (current1, current2) = match &downscaled {
+            Some((current1, current2)) => (current1, current2),
+            None => (channel1, channel2),
+        };
+        let (ssim, contrast_structure) = ssim_and_contrast_structure(current1, current2, max_value);
+        let value = if scale == scale_count - 1 { ssim } else { contrast_structure };
+        // Negative values would make the fractional power undefined.
+        score *= value.max(0.0).powf(weight / weight_sum);
+        if scale != scale_count - 1 {
+            downscaled = Some((current1.downscale(), current2.downscale()));
+        }
+    }
+    score
+}
+
+fn compute(
+    metric: Metric,
+    channels: &[Option<(Channel, Channel)>; 4],
+    max_value: f64,
+) -> AvifResult<Scores> {
+    let mut scores = Scores::default();
+    let mut weighted_sum = 0.0;
+    let mut sample_count = 0usize;
+    for (score, channels) in scores.planes.iter_mut().zip(channels) {
+        let Some((channel1, channel2)) = channels else {
+            continue;
+        };
+        let count = channel1.samples.len();
+        if count == 0 {
+            continue;
+        }
+        let value = match metric {
+            Metric::Psnr => mse(channel1, channel2),
+            Metric::Ssim => ssim_and_contrast_structure(channel1, channel2, max_value).0,
+            Metric::MsSsim => ms_ssim(channel1, channel2, max_value),
+        };
+        weighted_sum += value * count as f64;
+        sample_count = checked_add!(sample_count, count)?;
+        *score = Some(match metric {
+            Metric::Psnr => psnr_from_mse(value, max_value),
+            _ => value,
+        });
+    }
+    if sample_count == 0 {
+        return AvifError::no_content();
+    }
+    let combined = weighted_sum / sample_count as f64;
+    scores.combined = match metric {
+        Metric::Psnr => psnr_from_mse(combined, max_value),
+        _ => combined,
+    };
+    Ok(scores)
+}
+
+impl Metric {
+    /// Compares two YUV images, which must have the same dimensions, depth and pixel format. The
+    /// alpha planes are compared if present, in which case they must be present in both images.
+    pub fn compute(&self, image1: &image::Image, image2: &image::Image) -> AvifResult<Scores> {
+        if image1.width != image2.width
+            || image1.height != image2.height
+            || image1.depth != image2.depth
+            || image1.yuv_format != image2.yuv_format
+            || image1.has_plane(Plane::A) != image2.has_plane(Plane::A)
+        {
+            return AvifError::invalid_argument();
+        }
+        self.compute_planes(image1, image2, &image::ALL_PLANES)
+    }
+
+    /// Same as [`Metric::compute`] but only compares the given `planes`. Planes missing from either
+    /// image are ignored, so the alpha planes do not have to be present in both images.
+    pub fn compute_planes(
+        &self,
+        image1: &image::Image,
+        image2: &image::Image,
+        planes: &[Plane],
+    ) -> AvifResult<Scores> {
+        if image1.width != image2.width
+            || image1.height != image2.height
+            || image1.depth != image2.depth
+            || image1.yuv_format != image2.yuv_format
+        {
+            return AvifError::invalid_argument();
+        }
+        let mut channels: [Option<(Channel, Channel)>; 4] = Default::default();
+        for (channel, plane) in channels.iter_mut().zip(image::ALL_PLANES) {
+            if planes.contains(&plane) && image1.has_plane(plane) && image2.has_plane(plane) {
+                *channel = Some((yuv_channel(image1, plane)?, yuv_channel(image2, plane)?));
+            }
+        }
+        compute(*self, &channels, image1.max_channel() as f64)
+    }
+
+    /// Compares two RGB images, which must have the same dimensions, depth and format. Float and
+    /// packed formats are not supported.
+    pub fn compute_rgb(&self, image1: &rgb::Image, image2: &rgb::Image) -> AvifResult<Scores> {
+        if image1.width != image2.width
+            || image1.height != image2.height
+            || image1.depth != image2.depth
+            || image1.format != image2.format
+            || image1.is_float != image2.is_float
+        {
+            return AvifError::invalid_argument();
+        }
+        if image1.is_float
+            || matches!(
+                image1.format,
+                rgb::Format::Rgb565 | rgb::Format::Rgba1010102
+            )
+        {
+            return AvifError::not_implemented();
+        }
+        let color_channels = if image1.format.is_gray() { 1 } else { 3 };
+        let mut channels: [Option<(Channel, Channel)>; 4] = Default::default();
+        for (index, channel) in channels.iter_mut().enumerate() {
+            if index < color_channels || (index == 3 && image1.has_alpha()) {
+                *channel = Some((rgb_channel(image1, index)?, rgb_channel(image2, index)?));
+            }
+        }
+        compute(*self, &channels, image1.max_channel() as f64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::YuvRange;
+
+    use test_case::test_matrix;
+
+    // Returns an image with a diagonal gradient, with `offset` added to the luma samples.
+    fn gradient(yuv_format: PixelFormat, depth: u8, offset: u16) -> AvifResult<image::Image> {
+        let mut image = image::Image {
+            width: 64,
+            height: 48,
+            depth,
+            yuv_format,
+            yuv_range: YuvRange::Full,
+            ..Default::default()
+        };
+        image.allocate_planes(Category::Color)?;
+        image.allocate_planes(Category::Alpha)?;
+        for plane in image::ALL_PLANES {
+            if !image.has_plane(plane) {
+                continue;
+            }
+            for y in 0..image.height(plane) {
+                let value =
+                    |x: usize| ((x + y) * 2) as u16 + if plane == Plane::Y { offset } else { 0 };
+                if depth == 8 {
+                    for (x, sample) in image.row_mut(plane, y as u32)?.iter_mut().enumerate() {
+                        *sample = value(x) as u8;
+                    }
+                } else {
+                    for (x, sample) in image.row16_mut(plane, y as u32)?.iter_mut().enumerate() {
+                        *sample = value(x);
+                    }
+                }
+            }
+        }
+        Ok(image)
+    }
+
+    #[test_matrix(
+        [Metric::Psnr, Metric::Ssim, Metric::MsSsim],
+        [PixelFormat::Yuv420, PixelFormat::Yuv444, PixelFormat::Yuv400]
+    )]
+    fn identical(metric: Metric, yuv_format: PixelFormat) -> AvifResult<()> {
+        let image = gradient(yuv_format, 10, 0)?;
+        let scores = metric.compute(&image, &image)?;
+        let expected = if metric == Metric::Psnr { 99.0 } else { 1.0 };
+        assert!((scores.combined - expected).abs() < 1e-6);
+        for (plane, score) in image::ALL_PLANES.iter().zip(scores.planes) {
+            assert_eq!(score.is_some(), image.has_plane(*plane));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn psnr() -> AvifResult<()> {
+        let image1 = gradient(PixelFormat::Yuv444, 10, 0)?;
+        let image2 = gradient(PixelFormat::Yuv444, 10, 4)?;
+        let scores = Metric::Psnr.compute(&image1, &image2)?;
+        // The squared error is 16 for all the luma samples and 0 elsewhere.
+        let expected_y = 10.0 * (1023.0f64 * 1023.0 / 16.0).log10();
+        assert!((scores.planes[0].unwrap() - expected_y).abs() < 1e-6);
+        assert_eq!(scores.planes[1], Some(99.0));
+        assert_eq!(scores.planes[3], Some(99.0));
+        let expected_combined = 10.0 * (1023.0f64 * 1023.0 / 4.0).log10();
+        assert!((scores.combined - expected_combined).abs() < 1e-6);
+        let scores = Metric::Psnr.compute_planes(&image1, &image2, &[Plane::Y])?;
+        assert!((scores.combined - expected_y).abs() < 1e-6);
+        assert_eq!(scores.planes[1], None);
+        let scores = Metric::Psnr.compute_planes(&image1, &image2, &[Plane::A])?;
+        assert_eq!(scores.combined, 99.0);
+        Ok(())
+    }
+
+    #[test_matrix([Metric::Ssim, Metric::MsSsim])]
+    fn ssim(metric: Metric) -> AvifResult<()> {
+        let image = gradient(PixelFormat::Yuv420, 8, 0)?;
+        let mut previous = 1.0;
+        for offset in [2, 8, 32] {
+            let scores = metric.compute(&image, &gradient(PixelFormat::Yuv420, 8, offset)?)?;
+            let y = scores.planes[0].unwrap();
+            assert!(y < previous && y > 0.0, "{y}");
+            assert_eq!(scores.planes[1], Some(1.0));
+            previous = y;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn rgb_image() -> AvifResult<()> {
+        let create = || -> AvifResult<rgb::Image> {
+            let mut rgb = rgb::Image {
+                width: 16,
+                height: 16,
+                depth: 8,
+                format: rgb::Format::GrayA,
+                ..Default::default()
+            };
+            rgb.allocate()?;
+            Ok(rgb)
+        };
+        let mut rgb1 = create()?;
+        let mut rgb2 = create()?;
+        for y in 0..16 {
+            for (x, pixel) in rgb1.row_mut(y)?.chunks_exact_mut(2).enumerate() {
+                pixel.copy_from_slice(&[(x * 16) as u8, 255]);
+            }
+            for (x, pixel) in rgb2.row_mut(y)?.chunks_exact_mut(2).enumerate() {
+                pixel.copy_from_slice(&[(x * 16 + 1) as u8, 255]);
+            }
+        }
+        let scores = Metric::Psnr.compute_rgb(&rgb1, &rgb2)?;
+        assert!((scores.planes[0].unwrap() - 20.0 * 255.0f64.log10()).abs() < 1e-6);
+        assert_eq!(scores.planes[1], None);
+        assert_eq!(scores.planes[3], Some(99.0));
+        Ok(())
+    }
+
+    #[test]
+    fn invalid() -> AvifResult<()> {
+        let image = gradient(PixelFormat::Yuv420, 8, 0)?;
+        let mut other = gradient(PixelFormat::Yuv444, 8, 0)?;
+        assert_eq!(
+            Metric::Ssim.compute(&image, &other),
+            Err(AvifError::InvalidArgument)
+        );
+        other = gradient(PixelFormat::Yuv420, 10, 0)?;
+        assert_eq!(
+            Metric::Psnr.compute(&image, &other),
+            Err(AvifError::InvalidArgument)
+        );
+        Ok(())
+    }
+}
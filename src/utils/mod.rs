@@ -15,8 +15,10 @@
 use crate::internal_utils::*;
 use crate::*;
 
+pub mod av1;
 pub mod clap;
 pub mod error;
+pub mod metrics;
 pub mod pixels;
 pub mod reader;
 pub mod writer;
@@ -56,7 +58,11 @@ impl UFraction {
         }
     }
 
-    #[cfg(feature = "jpeg")]
+    pub(crate) fn as_f64(&self) -> AvifResult<f64> {
+        self.is_valid()?;
+        Ok(self.0 as f64 / self.1 as f64)
+    }
+
     fn from_f64(v: f64, max_n: u32) -> Option<Self> {
         if v.is_nan() || v < 0.0 || v > max_n as f64 {
             return None;
@@ -93,21 +99,18 @@ impl UFraction {
     }
 }
 
-#[cfg(feature = "jpeg")]
 impl From<f32> for UFraction {
     fn from(v: f32) -> Self {
         UFraction::from_f64(v as f64, u32::MAX).unwrap_or_default()
     }
 }
 
-#[cfg(feature = "jpeg")]
 impl From<f64> for UFraction {
     fn from(v: f64) -> Self {
         UFraction::from_f64(v, u32::MAX).unwrap_or_default()
     }
 }
 
-#[cfg(feature = "jpeg")]
 impl From<f64> for Fraction {
     fn from(v: f64) -> Self {
         match UFraction::from_f64(v.abs(), i32::MAX as u32) {
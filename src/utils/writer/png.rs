@@ -29,6 +29,7 @@ use libpng_sys::bindings::*;
 pub struct PngWriter {
     pub depth: Option<u8>,
     pub compression_level: Option<i32>,
+    pub tone_mapping: Option<rgb::ToneMapping>,
 }
 
 struct PngWriterNative {
@@ -105,7 +106,26 @@ impl Writer for PngWriter {
             }
             rgb_depth = 8;
         }
-        let copy_y_plane = image.yuv_format == PixelFormat::Yuv400
+        // When tone mapping, the ICC profile and the CICP values of the image no longer describe
+        // the output.
+        let tone_mapping = match self.tone_mapping {
+            Some(tone_mapping) if image.transfer_characteristics.is_hdr() => Some(tone_mapping),
+            _ => None,
+        };
+        let (icc, color_primaries, transfer_characteristics) = match tone_mapping {
+            Some(tone_mapping) => (
+                &[][..],
+                tone_mapping.color_primaries,
+                TransferCharacteristics::Srgb,
+            ),
+            None => (
+                &image.icc[..],
+                image.color_primaries,
+                image.transfer_characteristics,
+            ),
+        };
+        let copy_y_plane = tone_mapping.is_none()
+            && image.yuv_format == PixelFormat::Yuv400
             && !image.alpha_present
             && image.depth == 8
             && rgb_depth == 8
@@ -113,6 +133,7 @@ impl Writer for PngWriter {
             && image.irot_angle.is_none()
             && image.imir_axis.is_none();
         let mut rgb = rgb::Image::create_from_yuv(image);
+        rgb.tone_mapping = tone_mapping;
         let color_type;
         if copy_y_plane {
             color_type = PNG_COLOR_TYPE_GRAY;
@@ -202,16 +223,16 @@ impl Writer for PngWriter {
             );
         }
         let icc_profile_name = CString::new("libavif").unwrap();
-        if image.icc.is_empty() {
-            if image.color_primaries == ColorPrimaries::Srgb
-                && image.transfer_characteristics == TransferCharacteristics::Srgb
+        if icc.is_empty() {
+            if color_primaries == ColorPrimaries::Srgb
+                && transfer_characteristics == TransferCharacteristics::Srgb
             {
                 // # Safety: Calling a C function with valid parameters.
                 unsafe {
                     png_set_sRGB_gAMA_and_cHRM(png.png, png.info, PNG_sRGB_INTENT_PERCEPTUAL as _);
                 }
             } else {
-                if let Some(primaries) = image.color_primaries.values() {
+                if let Some(primaries) = color_primaries.values() {
                     // # Safety: Calling a C function with valid parameters.
                     unsafe {
                         png_set_cHRM(
@@ -228,7 +249,7 @@ impl Writer for PngWriter {
                         );
                     }
                 }
-                if let Some(gamma) = image.transfer_characteristics.gamma() {
+                if let Some(gamma) = transfer_characteristics.gamma() {
                     // # Safety: Calling a C function with valid parameters.
                     unsafe {
                         png_set_gAMA(png.png, png.info, (1.0 / gamma) as _);
@@ -239,7 +260,7 @@ impl Writer for PngWriter {
             // If there is an ICC profile, the CICP values are irrelevant and only the ICC
             // profile is written. If we could extract the primaries/transfer curve from the
             // ICC profile, then they could be written in cHRM/gAMA chunks.
-            let size = u32_from_usize(image.icc.len())?;
+            let size = u32_from_usize(icc.len())?;
             // # Safety: Calling a C function with valid parameters.
             unsafe {
                 png_set_iCCP(
@@ -247,7 +268,7 @@ impl Writer for PngWriter {
                     png.info,
                     icc_profile_name.as_ptr(),
                     0,
-                    image.icc.as_ptr() as _,
+                    icc.as_ptr() as _,
                     size,
                 );
             }
@@ -286,11 +307,11 @@ impl Writer for PngWriter {
         unsafe {
             png_write_info(png.png, png.info);
         }
-        if image.icc.is_empty() {
+        if icc.is_empty() {
             let cicp: [png_byte; 5] = [b'c', b'I', b'C', b'P', 0];
             let cicp_data: [png_byte; 4] = [
-                image.color_primaries as _,
-                image.transfer_characteristics as _,
+                color_primaries as _,
+                transfer_characteristics as _,
                 MatrixCoefficients::Identity as _,
                 1, // full_range
             ];
@@ -16,6 +16,8 @@ use crabby_avif::decoder::CompressionFormat;
 use crabby_avif::decoder::ImageContentType;
 use crabby_avif::image::*;
 use crabby_avif::reformat::rgb;
+use crabby_avif::utils::av1;
+use crabby_avif::utils::clap::CropRect;
 use crabby_avif::*;
 
 mod utils;
@@ -159,13 +161,15 @@ fn animated_image_with_alpha_and_metadata() {
 
 #[test]
 fn animated_image_with_depth_and_metadata() {
-    // Depth map data is not supported and should be ignored.
+    // Depth map data is not decoded by default.
     let mut decoder = get_decoder("colors-animated-8bpc-depth-exif-xmp.avif");
     let res = decoder.parse();
     assert!(res.is_ok());
     assert_eq!(decoder.compression_format(), CompressionFormat::Avif);
     let image = decoder.image().expect("image was none");
     assert!(!image.alpha_present);
+    assert!(decoder.depth_map_present());
+    assert_eq!(decoder.depth_map().image.width, 0);
     assert!(image.image_sequence_track_present);
     assert_eq!(decoder.image_count(), 5);
     assert_eq!(decoder.repetition_count(), RepetitionCount::Infinite);
@@ -181,7 +185,7 @@ fn animated_image_with_depth_and_metadata() {
 
 #[test]
 fn animated_image_with_depth_and_metadata_source_set_to_primary_item() {
-    // Depth map data is not supported and should be ignored.
+    // Depth map data is not decoded by default.
     let mut decoder = get_decoder("colors-animated-8bpc-depth-exif-xmp.avif");
     decoder.settings.source = decoder::Source::PrimaryItem;
     let res = decoder.parse();
@@ -205,6 +209,51 @@ fn animated_image_with_depth_and_metadata_source_set_to_primary_item() {
     assert!(decoder.next_image().is_err());
 }
 
+#[test_matrix(
+    [decoder::Source::Tracks, decoder::Source::PrimaryItem],
+    [ImageContentType::DepthMap, ImageContentType::All]
+)]
+fn depth_map(source: decoder::Source, image_content_to_decode: ImageContentType) -> AvifResult<()> {
+    let mut decoder = get_decoder("colors-animated-8bpc-depth-exif-xmp.avif");
+    decoder.settings.source = source;
+    decoder.settings.image_content_to_decode = image_content_to_decode;
+    decoder.parse()?;
+    assert!(decoder.depth_map_present());
+    let depth_map = decoder.depth_map();
+    if decoder.settings.image_content_to_decode == ImageContentType::All {
+        // The depth map is only decoded when it is explicitly requested.
+        assert_eq!(depth_map.image.width, 0);
+        assert_eq!(depth_map.image.height, 0);
+        if HAS_DECODER {
+            decoder.next_image()?;
+            assert!(!decoder.depth_map().image.has_plane(Plane::Y));
+        }
+        return Ok(());
+    }
+    assert_eq!(depth_map.image.width, 150);
+    assert_eq!(depth_map.image.height, 150);
+    assert_eq!(depth_map.image.depth, 8);
+    assert_eq!(depth_map.image.yuv_format, PixelFormat::Yuv400);
+    // There is no depth_representation_info() in the aux_subtype field of the 'auxC' property.
+    assert!(depth_map.representation_info.is_none());
+    let expected_image_count = if source == decoder::Source::Tracks { 5 } else { 1 };
+    assert_eq!(decoder.image_count(), expected_image_count);
+    // The depth payloads can be extracted without decoding them.
+    assert!(!decoder.encoded_payloads(Category::Depth, 0)?.is_empty());
+    if !HAS_DECODER {
+        return Ok(());
+    }
+    for _ in 0..expected_image_count {
+        decoder.next_image()?;
+        let depth_image = &decoder.depth_map().image;
+        assert_eq!(depth_image.width, 150);
+        assert_eq!(depth_image.height, 150);
+        assert!(depth_image.has_plane(Plane::Y));
+        assert!(!depth_image.has_plane(Plane::U));
+    }
+    Ok(())
+}
+
 #[test]
 fn animated_image_seeking() {
     let mut decoder = get_decoder("anim.avif");
@@ -287,6 +336,13 @@ fn animated_image_with_unsupported_hdlr() {
     }
 }
 
+#[test]
+fn no_thumbnail() {
+    let mut decoder = get_decoder("alpha_premultiplied.avif");
+    decoder.settings.source = decoder::Source::Thumbnail;
+    assert_eq!(decoder.parse(), Err(AvifError::NoContent));
+}
+
 // From avifdecodetest.cc
 #[test]
 fn color_grid_alpha_no_grid() {
@@ -554,6 +610,99 @@ fn color_nogrid_alpha_nogrid_gainmap_grid() {
     assert!(decoder.gainmap().image.row_bytes[0] > 0);
 }
 
+#[test_case("sofa_grid1x5_420.avif", 100, 50, 300, 200)]
+#[test_case("color_grid_alpha_grid_gainmap_nogrid.avif", 100, 150, 200, 100)]
+#[test_case("color_nogrid_alpha_nogrid_gainmap_grid.avif", 10, 20, 30, 40)]
+fn decode_region(filename: &str, x: u32, y: u32, width: u32, height: u32) -> AvifResult<()> {
+    let mut decoder = get_decoder(filename);
+    decoder.parse()?;
+    let image = decoder.image().expect("image was none");
+    let invalid_rect = CropRect {
+        x: image.width - 1,
+        y: 0,
+        width: 2,
+        height: 1,
+    };
+    assert_eq!(
+        decoder.decode_region(&invalid_rect).err(),
+        Some(AvifError::InvalidArgument)
+    );
+    if !HAS_DECODER {
+        return Ok(());
+    }
+    let rect = CropRect {
+        x,
+        y,
+        width,
+        height,
+    };
+    let region = decoder.decode_region(&rect)?;
+    assert_eq!((region.width, region.height), (width, height));
+    assert!(region.clap.is_none());
+    decoder.next_image()?;
+    let image = decoder.image().expect("image was none");
+    assert_eq!(region.has_alpha(), image.has_alpha());
+    for plane in ALL_PLANES.iter().filter(|p| region.has_plane(**p)) {
+        let (plane_x, plane_y) = match plane {
+            Plane::Y | Plane::A => (x as usize, y),
+            _ => (
+                image.yuv_format.apply_chroma_shift_x(x) as usize,
+                image.yuv_format.apply_chroma_shift_y(y),
+            ),
+        };
+        let plane_width = region.width(*plane);
+        for row in 0..region.height(*plane) as u32 {
+            if image.depth == 8 {
+                assert_eq!(
+                    region.row(*plane, row)?,
+                    &image.row(*plane, plane_y + row)?[plane_x..plane_x + plane_width]
+                );
+            } else {
+                assert_eq!(
+                    region.row16(*plane, row)?,
+                    &image.row16(*plane, plane_y + row)?[plane_x..plane_x + plane_width]
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+#[test_matrix(
+    [
+        "sofa_grid1x5_420.avif",
+        "color_grid_alpha_grid_gainmap_nogrid.avif",
+        "color_nogrid_alpha_nogrid_gainmap_grid.avif",
+        "colors-animated-8bpc-alpha-exif-xmp.avif"
+    ],
+    [2, 3, 64]
+)]
+fn parallel_decoding(filename: &str, max_threads: u32) -> AvifResult<()> {
+    if !HAS_DECODER {
+        return Ok(());
+    }
+    let mut decoders = [get_decoder(filename), get_decoder(filename)];
+    decoders[1].settings.max_threads = max_threads;
+    for decoder in &mut decoders {
+        decoder.settings.image_content_to_decode = ImageContentType::All;
+        decoder.parse()?;
+    }
+    for _ in 0..decoders[0].image_count() {
+        for decoder in &mut decoders {
+            decoder.next_image()?;
+        }
+        let images = decoders.each_ref().map(|decoder| decoder.image().unwrap());
+        assert!(are_images_equal(images[0], images[1])?);
+        if decoders[0].gainmap_present() {
+            assert!(are_images_equal(
+                &decoders[0].gainmap().image,
+                &decoders[1].gainmap().image
+            )?);
+        }
+    }
+    Ok(())
+}
+
 // From aviftransformtest.cc
 #[test]
 fn lenient_missing_alpha_transformative_properties() {
@@ -1362,6 +1511,23 @@ fn heic(filename: &str, expected_width: u32, expected_height: u32) {
     }
 }
 
+#[test_case("heic/nokiatech/spring_1440x960.heic", 240, 160)]
+#[test_case("heic/nokiatech/winter_1440x960.heic", 240, 160)]
+fn heic_thumbnail(filename: &str, expected_width: u32, expected_height: u32) {
+    let mut decoder = get_decoder(filename);
+    decoder.settings.strictness = decoder::Strictness::None;
+    decoder.settings.source = decoder::Source::Thumbnail;
+    let res = decoder.parse();
+    if cfg!(feature = "heic") {
+        assert!(res.is_ok());
+        let image = decoder.image().expect("image was none");
+        assert_eq!(image.width, expected_width);
+        assert_eq!(image.height, expected_height);
+    } else {
+        assert!(res.is_err());
+    }
+}
+
 #[test_case("heic/nokiatech/bird_burst.heic", 640, 360, 90, [3, 10, 50, 85])]
 #[test_case("heic/nokiatech/candle_animation.heic", 256, 144, 120, [1, 20, 50, 109])]
 #[test_case("heic/nokiatech/rally_burst.heic", 640, 360, 60, [4, 12, 45, 54])]
@@ -1700,3 +1866,453 @@ fn identity_derivation_invalid(filename: &str) -> AvifResult<()> {
     assert!(decoder.parse().is_err());
     Ok(())
 }
+
+#[test_case("paris_10bpc.avif", Category::Color, 1, 1)]
+#[test_case("sofa_grid1x5_420.avif", Category::Color, 5, 1)]
+#[test_case("alpha.avif", Category::Alpha, 1, 1)]
+#[test_case("colors-animated-8bpc.avif", Category::Color, 1, 5)]
+fn encoded_payloads(
+    filename: &str,
+    category: Category,
+    cell_count: usize,
+    frame_count: u32,
+) -> AvifResult<()> {
+    let mut decoder = get_decoder(filename);
+    assert!(decoder.encoded_payloads(category, 0).is_err());
+    decoder.parse()?;
+    assert_eq!(decoder.image_count(), frame_count);
+    let mut cells: Vec<Vec<Vec<u8>>> = vec![Vec::new(); cell_count];
+    for index in 0..frame_count {
+        let payloads = decoder.encoded_payloads(category, index)?;
+        assert_eq!(payloads.len(), cell_count);
+        for (cell, payload) in cells.iter_mut().zip(payloads) {
+            assert!(!payload.is_empty());
+            cell.push(payload);
+        }
+    }
+    assert!(decoder.encoded_payloads(category, frame_count).is_err());
+
+    let timings: Vec<_> = (0..frame_count)
+        .map(|index| decoder.nth_image_timing(index))
+        .collect::<AvifResult<_>>()?;
+    for temporal_units in &cells {
+        // The obu_size fields may be rewritten with fewer bytes, so compare the normalized
+        // temporal units.
+        let mut expected_temporal_units = None;
+        for format in [
+            av1::Av1StreamFormat::LowOverhead,
+            av1::Av1StreamFormat::AnnexB,
+            av1::Av1StreamFormat::Ivf,
+        ] {
+            let bitstream = av1::join_temporal_units(temporal_units, &timings, format)?;
+            let split_temporal_units = av1::split_temporal_units(&bitstream, format)?;
+            assert_eq!(split_temporal_units.len(), temporal_units.len());
+            match &expected_temporal_units {
+                Some(expected) => assert_eq!(&split_temporal_units, expected),
+                None => expected_temporal_units = Some(split_temporal_units),
+            }
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn items() -> AvifResult<()> {
+    let mut decoder = get_decoder("paris_icc_exif_xmp.avif");
+    assert!(decoder.items().is_empty());
+    decoder.parse()?;
+    assert_eq!(decoder.primary_item_id(), 1);
+    let items = decoder.items();
+    assert_eq!(items.len(), 3);
+
+    let color = &items[0];
+    assert_eq!(color.id, 1);
+    assert_eq!(color.item_type, "av01");
+    assert!(!color.hidden);
+    assert!(color.references.is_empty());
+    let box_types: Vec<_> = color
+        .properties
+        .iter()
+        .map(|x| x.box_type.as_str())
+        .collect();
+    assert_eq!(box_types, ["ispe", "pixi", "av1C", "colr", "colr"]);
+    let ispe = &color.properties[0];
+    assert_eq!(ispe.index, 1);
+    assert!(!ispe.essential);
+    // version and flags, followed by the width and height.
+    assert_eq!(ispe.payload.len(), 12);
+    assert_eq!(
+        ispe.payload[4..8],
+        decoder.image().unwrap().width.to_be_bytes()
+    );
+    assert!(color.properties[2].essential);
+    assert_eq!(color.construction_method, 0);
+    assert_eq!(color.extents.len(), 1);
+    assert_eq!(color.extents[0].size, 15076);
+
+    for (item, item_type, name, content_type) in [
+        (&items[1], "Exif", "Exif", ""),
+        (&items[2], "mime", "XMP", "application/rdf+xml"),
+    ] {
+        assert_eq!(item.item_type, item_type);
+        assert_eq!(item.name, name);
+        assert_eq!(item.content_type, content_type);
+        assert!(item.properties.is_empty());
+        assert_eq!(item.references.len(), 1);
+        assert_eq!(item.references[0].reference_type, "cdsc");
+        assert_eq!(item.references[0].to_item_ids, [1]);
+    }
+    assert!(decoder.entity_groups().is_empty());
+    Ok(())
+}
+
+#[test]
+fn items_grid() -> AvifResult<()> {
+    let mut decoder = get_decoder("sofa_grid1x5_420.avif");
+    decoder.parse()?;
+    let items = decoder.items();
+    assert_eq!(items.len(), 6);
+    assert_eq!(items[0].item_type, "grid");
+    assert_eq!(items[0].references.len(), 1);
+    assert_eq!(items[0].references[0].reference_type, "dimg");
+    assert_eq!(items[0].references[0].to_item_ids, [2, 3, 4, 5, 6]);
+    for cell in &items[1..] {
+        assert_eq!(cell.item_type, "av01");
+        // All cells share the same properties.
+        assert_eq!(cell.properties, items[1].properties);
+    }
+    Ok(())
+}
+
+#[test]
+fn entity_groups() -> AvifResult<()> {
+    let mut decoder = get_decoder("seine_sdr_gainmap_srgb.avif");
+    decoder.parse()?;
+    let entity_groups = decoder.entity_groups();
+    assert_eq!(entity_groups.len(), 1);
+    assert_eq!(entity_groups[0].grouping_type, "altr");
+    assert_eq!(entity_groups[0].group_id, 6);
+    assert_eq!(entity_groups[0].entity_ids, [2, 1]);
+    let items = decoder.items();
+    assert_eq!(items[1].item_type, "tmap");
+    assert!(!items[1].hidden);
+    // The gain map image item is hidden.
+    assert_eq!(items[2].name, "GMap");
+    assert!(items[2].hidden);
+    Ok(())
+}
+
+#[test]
+fn decode_item() -> AvifResult<()> {
+    let mut decoder = get_decoder("sofa_grid1x5_420.avif");
+    assert_eq!(decoder.decode_item(1).err(), Some(AvifError::NoContent));
+    decoder.parse()?;
+    let items = decoder.items().to_vec();
+    assert_eq!(
+        decoder.decode_item(7).err(),
+        Some(AvifError::MissingImageItem)
+    );
+    // The decoder is still usable after a failed decode_item().
+    assert_eq!(decoder.items(), items);
+    assert_eq!(decoder.image().unwrap().width, 1024);
+    if !HAS_DECODER {
+        return Ok(());
+    }
+    let cell = decoder.decode_item(2)?;
+    assert_eq!(cell.width, 1024);
+    assert_eq!(cell.height, 154);
+    let grid = decoder.decode_item(1)?;
+    assert_eq!(grid.width, 1024);
+    assert_eq!(grid.height, 770);
+    // The primary item is decoded after decode_item().
+    decoder.next_image()?;
+    assert_eq!(decoder.image().unwrap().height, 770);
+    Ok(())
+}
+
+#[test]
+fn decode_item_not_image() -> AvifResult<()> {
+    let mut decoder = get_decoder("paris_icc_exif_xmp.avif");
+    decoder.parse()?;
+    assert_eq!(
+        decoder.decode_item(2).err(),
+        Some(AvifError::InvalidArgument)
+    );
+    assert_eq!(
+        decoder.decode_item(3).err(),
+        Some(AvifError::InvalidArgument)
+    );
+    Ok(())
+}
+
+#[test_case("seine_sdr_gainmap_srgb.avif", 1, &[2, 1], 2)]
+// Without the tmap brand, the tmap item is not supported so the next alternative is chosen.
+#[test_case("seine_sdr_gainmap_notmapbrand.avif", 1, &[2, 1], 1)]
+#[test_case("tmap_primary_item.avif", 3, &[3, 1], 3)]
+#[test_case("paris_icc_exif_xmp.avif", 1, &[], 1)]
+fn alternatives(
+    filename: &str,
+    primary_item_id: u32,
+    alternative_item_ids: &[u32],
+    selected_item_id: u32,
+) -> AvifResult<()> {
+    let mut decoder = get_decoder(filename);
+    decoder.parse()?;
+    assert_eq!(decoder.primary_item_id(), primary_item_id);
+    assert_eq!(decoder.alternative_item_ids(), alternative_item_ids);
+    assert_eq!(decoder.selected_item_id(), selected_item_id);
+    assert!(decoder.stereo_pair().is_none());
+    Ok(())
+}
+
+#[test_case(decoder::StereoView::Primary, 1)]
+#[test_case(decoder::StereoView::Left, 2)]
+#[test_case(decoder::StereoView::Right, 1)]
+fn stereo_pair(stereo_view: decoder::StereoView, selected_item_id: u32) -> AvifResult<()> {
+    // Turn the altr group of this file into a ster group, with the tmap item as the left view and
+    // the base image item as the right view.
+    let mut file_bytes = std::fs::read(get_test_file("seine_sdr_gainmap_srgb.avif")).unwrap();
+    let offset = file_bytes.windows(4).position(|x| x == b"altr").unwrap();
+    file_bytes[offset..offset + 4].copy_from_slice(b"ster");
+    let mut decoder = decoder::Decoder::default();
+    decoder.settings.stereo_view = stereo_view;
+    decoder.set_io_vec(file_bytes);
+    decoder.parse()?;
+    assert_eq!(
+        decoder.stereo_pair(),
+        Some(decoder::StereoPair {
+            left_item_id: 2,
+            right_item_id: 1
+        })
+    );
+    assert!(decoder.alternative_item_ids().is_empty());
+    assert_eq!(decoder.selected_item_id(), selected_item_id);
+    // Only the tmap item has a gain map.
+    assert_eq!(decoder.gainmap_present(), selected_item_id == 2);
+    if !HAS_DECODER {
+        return Ok(());
+    }
+    decoder.next_image()?;
+    Ok(())
+}
+
+#[test_case(decoder::StereoView::Left)]
+#[test_case(decoder::StereoView::Right)]
+fn stereo_pair_absent(stereo_view: decoder::StereoView) {
+    let mut decoder = get_decoder("paris_icc_exif_xmp.avif");
+    decoder.settings.stereo_view = stereo_view;
+    assert_eq!(decoder.parse().err(), Some(AvifError::NoContent));
+}
+
+#[test_matrix(
+    [decoder::StereoView::Left, decoder::StereoView::Right],
+    [decoder::Source::Tracks, decoder::Source::Thumbnail]
+)]
+fn stereo_pair_source_conflict(stereo_view: decoder::StereoView, source: decoder::Source) {
+    let mut decoder = get_decoder("seine_sdr_gainmap_srgb.avif");
+    decoder.settings.stereo_view = stereo_view;
+    decoder.settings.source = source;
+    assert_eq!(decoder.parse().err(), Some(AvifError::InvalidArgument));
+}
+
+#[test_case(decoder::StereoView::Primary)]
+#[test_case(decoder::StereoView::Left)]
+#[test_case(decoder::StereoView::Right)]
+fn stereo_pair_malformed(stereo_view: decoder::StereoView) {
+    // Turn the altr group of this file into a ster group with a single entity. The removed 4 bytes
+    // are appended to the hdlr box so that the size of the meta box and the item offsets are kept.
+    let mut file_bytes = std::fs::read(get_test_file("seine_sdr_gainmap_srgb.avif")).unwrap();
+    let read_u32 = |bytes: &[u8], offset: usize| {
+        u32::from_be_bytes(bytes[offset..offset + 4].try_into().unwrap())
+    };
+    let hdlr_offset = file_bytes.windows(4).position(|x| x == b"hdlr").unwrap() - 4;
+    let hdlr_size = read_u32(&file_bytes, hdlr_offset);
+    file_bytes[hdlr_offset..hdlr_offset + 4].copy_from_slice(&(hdlr_size + 4).to_be_bytes());
+    let hdlr_end = hdlr_offset + hdlr_size as usize;
+    file_bytes.splice(hdlr_end..hdlr_end, [0u8; 4]);
+    let grpl_offset = file_bytes.windows(4).position(|x| x == b"grpl").unwrap() - 4;
+    let grpl_size = read_u32(&file_bytes, grpl_offset);
+    file_bytes[grpl_offset..grpl_offset + 4].copy_from_slice(&(grpl_size - 4).to_be_bytes());
+    let altr_offset = file_bytes.windows(4).position(|x| x == b"altr").unwrap() - 4;
+    // size, type, version and flags, group_id, num_entities_in_group, entity_ids.
+    assert_eq!(read_u32(&file_bytes, altr_offset), 28);
+    assert_eq!(read_u32(&file_bytes, altr_offset + 16), 2);
+    file_bytes[altr_offset..altr_offset + 4].copy_from_slice(&24u32.to_be_bytes());
+    file_bytes[altr_offset + 4..altr_offset + 8].copy_from_slice(b"ster");
+    file_bytes[altr_offset + 16..altr_offset + 20].copy_from_slice(&1u32.to_be_bytes());
+    // Keep the primary item (1) only.
+    file_bytes.drain(altr_offset + 20..altr_offset + 24);
+    let mut decoder = decoder::Decoder::default();
+    decoder.settings.stereo_view = stereo_view;
+    decoder.set_io_vec(file_bytes);
+    let res = decoder.parse();
+    if stereo_view == decoder::StereoView::Primary {
+        // The malformed group is ignored.
+        assert!(res.is_ok());
+        assert!(decoder.stereo_pair().is_none());
+    } else {
+        assert!(matches!(res, Err(AvifError::BmffParseFailed(_))));
+    }
+}
+
+#[test_case(decoder::StereoView::Primary, 1002)]
+#[test_case(decoder::StereoView::Left, 1002)]
+#[test_case(decoder::StereoView::Right, 1008)]
+fn stereo_pair_heic(stereo_view: decoder::StereoView, selected_item_id: u32) {
+    let mut decoder = get_decoder("heic/nokiatech/stereo_1200x800.heic");
+    decoder.settings.strictness = decoder::Strictness::None;
+    decoder.settings.stereo_view = stereo_view;
+    let res = decoder.parse();
+    if !cfg!(feature = "heic") {
+        assert!(res.is_err());
+        return;
+    }
+    assert!(res.is_ok());
+    assert_eq!(
+        decoder.stereo_pair(),
+        Some(decoder::StereoPair {
+            left_item_id: 1002,
+            right_item_id: 1008
+        })
+    );
+    assert_eq!(decoder.selected_item_id(), selected_item_id);
+    let image = decoder.image().expect("image was none");
+    assert_eq!(image.width, 1200);
+    assert_eq!(image.height, 800);
+}
+
+#[test]
+fn tracks() -> AvifResult<()> {
+    let mut decoder = get_decoder("colors-animated-8bpc-audio.avif");
+    assert!(decoder.tracks().is_empty());
+    decoder.parse()?;
+    assert_eq!(decoder.color_track_id(), Some(1));
+    assert_eq!(decoder.alpha_track_id(), None);
+    let tracks = decoder.tracks();
+    assert_eq!(tracks.len(), 2);
+    assert_eq!(tracks[0].id, 1);
+    assert_eq!(tracks[0].handler_type, "pict");
+    assert_eq!(tracks[0].format, "av01");
+    assert!(tracks[0].decodable);
+    assert_eq!((tracks[0].width, tracks[0].height), (150, 150));
+    assert_eq!(tracks[0].timescale, 30);
+    assert_eq!(tracks[0].duration_in_timescales, 5);
+    assert_eq!(tracks[0].sample_count, 5);
+    assert_eq!(tracks[0].edit_list.len(), 1);
+    assert_eq!(tracks[0].edit_list[0].segment_duration, 100);
+    assert_eq!(tracks[0].edit_list[0].media_time, 0);
+    assert_eq!(tracks[0].edit_list[0].media_rate_integer, 1);
+    assert!(!tracks[0].edit_list_repeats);
+    assert_eq!(tracks[1].id, 2);
+    assert_eq!(tracks[1].handler_type, "soun");
+    assert_eq!(tracks[1].format, "mp4a");
+    assert!(!tracks[1].decodable);
+    assert!(tracks[1].edit_list.is_empty());
+    Ok(())
+}
+
+#[test]
+fn tracks_alpha() -> AvifResult<()> {
+    let mut decoder = get_decoder("colors-animated-8bpc-alpha-exif-xmp.avif");
+    decoder.parse()?;
+    assert_eq!(decoder.color_track_id(), Some(1));
+    assert_eq!(decoder.alpha_track_id(), Some(2));
+    let tracks = decoder.tracks();
+    assert_eq!(tracks.len(), 2);
+    assert_eq!(tracks[1].handler_type, "auxv");
+    assert_eq!(
+        tracks[1].aux_type,
+        "urn:mpeg:mpegB:cicp:systems:auxiliary:alpha"
+    );
+    assert_eq!(tracks[1].aux_for_id, Some(1));
+    assert!(tracks[1].edit_list_repeats);
+    Ok(())
+}
+
+#[test_case(Some(1), Some(2), Ok(Some(2)))]
+#[test_case(Some(1), None, Ok(Some(2)))]
+#[test_case(None, Some(2), Ok(Some(2)))]
+// The alpha track cannot be the color track.
+#[test_case(Some(1), Some(1), Err(AvifError::InvalidArgument))]
+// The alpha track can be decoded on its own as the color track.
+#[test_case(Some(2), None, Ok(None))]
+#[test_case(Some(3), None, Err(AvifError::NoContent))]
+#[test_case(None, Some(3), Err(AvifError::NoContent))]
+fn select_track(
+    color_track_id: Option<u32>,
+    alpha_track_id: Option<u32>,
+    expected_alpha_track_id: AvifResult<Option<u32>>,
+) {
+    let mut decoder = get_decoder("colors-animated-8bpc-alpha-exif-xmp.avif");
+    decoder.settings.color_track_id = color_track_id;
+    decoder.settings.alpha_track_id = alpha_track_id;
+    match (decoder.parse(), expected_alpha_track_id) {
+        (Ok(()), Ok(expected_alpha_track_id)) => {
+            assert_eq!(decoder.color_track_id(), color_track_id.or(Some(1)));
+            assert_eq!(decoder.alpha_track_id(), expected_alpha_track_id);
+            assert_eq!(
+                decoder.image().unwrap().alpha_present,
+                expected_alpha_track_id.is_some()
+            );
+            assert_eq!(decoder.image_count(), 5);
+        }
+        (Err(err), Err(expected_err)) => assert_eq!(err, expected_err),
+        (res, expected) => panic!("got {res:?}, expected {expected:?}"),
+    }
+}
+
+#[test]
+fn select_track_not_decodable() {
+    let mut decoder = get_decoder("colors-animated-8bpc-audio.avif");
+    decoder.settings.color_track_id = Some(2);
+    assert_eq!(decoder.parse().err(), Some(AvifError::InvalidArgument));
+}
+
+#[test_case(None, 640, 360)]
+#[test_case(Some(1), 640, 360)]
+#[test_case(Some(2), 128, 72)]
+fn select_track_heic(color_track_id: Option<u32>, expected_width: u32, expected_height: u32) {
+    let mut decoder = get_decoder("heic/nokiatech/bird_burst.heic");
+    decoder.settings.strictness = decoder::Strictness::None;
+    decoder.settings.color_track_id = color_track_id;
+    let res = decoder.parse();
+    if !cfg!(feature = "heic") {
+        assert!(res.is_err());
+        return;
+    }
+    assert!(res.is_ok());
+    assert_eq!(decoder.color_track_id(), color_track_id.or(Some(1)));
+    assert_eq!(decoder.tracks().len(), 2);
+    let image = decoder.image().expect("image was none");
+    assert_eq!(image.width, expected_width);
+    assert_eq!(image.height, expected_height);
+    assert_eq!(decoder.image_count(), 90);
+}
+
+#[test]
+fn edit_list_timing() -> AvifResult<()> {
+    // Start the presentation at the third sample and stop it after three samples.
+    let mut file_bytes = std::fs::read(get_test_file("colors-animated-8bpc.avif")).unwrap();
+    let offset = file_bytes.windows(4).position(|x| x == b"elst").unwrap();
+    // version (1), flags (0), entry_count (1), segment_duration (64 bits), media_time (64 bits).
+    assert_eq!(
+        file_bytes[offset + 4..offset + 12],
+        [1, 0, 0, 0, 0, 0, 0, 1]
+    );
+    file_bytes[offset + 12..offset + 20].copy_from_slice(&3u64.to_be_bytes());
+    file_bytes[offset + 20..offset + 28].copy_from_slice(&2u64.to_be_bytes());
+    let mut decoder = decoder::Decoder::default();
+    decoder.set_io_vec(file_bytes);
+    decoder.parse()?;
+    assert_eq!(decoder.image_count(), 5);
+    assert_eq!(decoder.timescale(), 30);
+    assert_eq!(decoder.duration_in_timescales(), 3);
+    assert_eq!(decoder.repetition_count(), RepetitionCount::Finite(0));
+    // The samples before the start of the edit are presented at the start.
+    for (index, expected_pts) in [0, 0, 0, 1, 2].iter().enumerate() {
+        let image_timing = decoder.nth_image_timing(index as u32)?;
+        assert_eq!(image_timing.pts_in_timescales, *expected_pts);
+        assert_eq!(image_timing.duration_in_timescales, 1);
+    }
+    Ok(())
+}
@@ -20,6 +20,9 @@ use crabby_avif::decoder::ProgressiveState;
 use crabby_avif::encoder::*;
 use crabby_avif::gainmap::*;
 use crabby_avif::image::*;
+use crabby_avif::utils::clap::CleanAperture;
+use crabby_avif::utils::clap::CropRect;
+use crabby_avif::utils::metrics::Metric;
 use crabby_avif::utils::*;
 use crabby_avif::*;
 
@@ -29,6 +32,10 @@ use utils::*;
 use test_case::test_case;
 use test_case::test_matrix;
 
+use std::cell::Cell;
+use std::io::Write;
+use std::rc::Rc;
+
 #[test_matrix(
     [100, 121],
     [200, 107],
@@ -337,6 +344,99 @@ fn encode_decode_grid_odd_dimensions(
     encode_decode_grid_impl((cells, expect_success), yuv_format, /*depth=*/ 8)
 }
 
+#[test_case(false ; "single image")]
+#[test_case(true ; "grid")]
+fn decode_region_with_transforms(use_grid: bool) -> AvifResult<()> {
+    if !HAS_ENCODER {
+        return Ok(());
+    }
+    let mut image = generate_gradient_image(128, 96, 8, PixelFormat::Yuv420, YuvRange::Full, true)?;
+    let clap_rect = CropRect {
+        x: 20,
+        y: 10,
+        width: 90,
+        height: 70,
+    };
+    image.clap = Some(CleanAperture::create_from(
+        &clap_rect,
+        256,
+        96,
+        image.yuv_format,
+    )?);
+    image.irot_angle = Some(1);
+    let settings = encoder::Settings {
+        speed: Some(10),
+        mutable: encoder::MutableSettings {
+            quality: 100.0,
+            quality_alpha: 100.0,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let mut encoder = encoder::Encoder::create_with_settings(&settings)?;
+    if use_grid {
+        encoder.add_image_grid(2, 1, &[&image, &image])?;
+    } else {
+        let mut single_image =
+            generate_gradient_image(256, 96, 8, PixelFormat::Yuv420, YuvRange::Full, true)?;
+        single_image.clap = image.clap;
+        single_image.irot_angle = image.irot_angle;
+        encoder.add_image(&single_image)?;
+    }
+    let edata = encoder.finish()?;
+
+    let mut decoder = decoder::Decoder::default();
+    decoder.set_io_vec(edata);
+    decoder.parse()?;
+    // The output image is 70x90 once cropped and rotated by 90 degrees.
+    let rect = CropRect {
+        x: 10,
+        y: 60,
+        width: 40,
+        height: 30,
+    };
+    // Valid in the reconstructed image but not in the output image.
+    let invalid_rect = CropRect {
+        x: 0,
+        y: 0,
+        width: 80,
+        height: 20,
+    };
+    assert_eq!(
+        decoder.decode_region(&invalid_rect).err(),
+        Some(AvifError::InvalidArgument)
+    );
+    if !HAS_DECODER {
+        return Ok(());
+    }
+    let region = decoder.decode_region(&rect)?;
+    assert_eq!((region.width, region.height), (rect.width, rect.height));
+    assert!(region.clap.is_none() && region.irot_angle.is_none());
+    decoder.next_image()?;
+    let transformed = decoder
+        .image()
+        .expect("image was none")
+        .transformed_image()?;
+    assert_eq!((transformed.width, transformed.height), (70, 90));
+    for plane in ALL_PLANES.iter().filter(|p| transformed.has_plane(**p)) {
+        let (x, y) = match plane {
+            Plane::Y | Plane::A => (rect.x as usize, rect.y),
+            _ => (
+                transformed.yuv_format.apply_chroma_shift_x(rect.x) as usize,
+                transformed.yuv_format.apply_chroma_shift_y(rect.y),
+            ),
+        };
+        let width = region.width(*plane);
+        for row in 0..region.height(*plane) as u32 {
+            assert_eq!(
+                region.row(*plane, row)?[..width],
+                transformed.row(*plane, y + row)?[x..x + width]
+            );
+        }
+    }
+    Ok(())
+}
+
 #[test_matrix([true, false])]
 fn encode_decode_grid_matrix_coefficients(same_matrix_coefficients: bool) -> AvifResult<()> {
     if !HAS_ENCODER {
@@ -471,6 +571,55 @@ fn encode_decode_sequence(
     Ok(())
 }
 
+#[test_case(false, 0 ; "forced keyframes")]
+#[test_case(true, 0 ; "forced keyframes with alpha")]
+#[test_case(false, 3 ; "keyframe interval")]
+fn sequence_keyframes(alpha: bool, keyframe_interval: i32) -> AvifResult<()> {
+    if !HAS_ENCODER {
+        return Ok(());
+    }
+    const FRAME_COUNT: u32 = 8;
+    let forced_keyframes = [3, 7];
+    let image = generate_gradient_image(32, 16, 8, PixelFormat::Yuv420, YuvRange::Full, alpha)?;
+    let settings = encoder::Settings {
+        speed: Some(10),
+        keyframe_interval,
+        ..Default::default()
+    };
+    let mut encoder = encoder::Encoder::create_with_settings(&settings)?;
+    for index in 0..FRAME_COUNT {
+        if keyframe_interval == 0 && forced_keyframes.contains(&index) {
+            encoder.add_keyframe_for_sequence(&image, 1)?;
+        } else {
+            encoder.add_image_for_sequence(&image, 1)?;
+        }
+    }
+    let edata = encoder.finish()?;
+
+    let mut decoder = decoder::Decoder::default();
+    decoder.set_io_vec(edata);
+    decoder.parse()?;
+    assert_eq!(decoder.image_count(), FRAME_COUNT);
+    let expected_keyframes: Vec<u32> = if keyframe_interval == 0 {
+        forced_keyframes.to_vec()
+    } else {
+        (0..FRAME_COUNT)
+            .step_by(keyframe_interval as usize)
+            .collect()
+    };
+    for index in 0..FRAME_COUNT {
+        let nearest_keyframe = decoder.nearest_keyframe(index);
+        assert!(nearest_keyframe <= index);
+        if expected_keyframes.contains(&index) {
+            assert!(decoder.is_keyframe(index));
+            assert_eq!(nearest_keyframe, index);
+        } else if let Some(&keyframe) = expected_keyframes.iter().rev().find(|&&k| k < index) {
+            assert!(nearest_keyframe >= keyframe);
+        }
+    }
+    Ok(())
+}
+
 #[test_matrix([true, false])]
 fn sequence_alpha_combinations(first_image_has_alpha: bool) -> AvifResult<()> {
     if !HAS_ENCODER {
@@ -1343,6 +1492,915 @@ fn quality_categories() -> AvifResult<()> {
     Ok(())
 }
 
+#[test_case(false ; "opaque")]
+#[test_case(true ; "alpha")]
+fn thumbnail(alpha: bool) -> AvifResult<()> {
+    if !HAS_ENCODER {
+        return Ok(());
+    }
+    let image = generate_gradient_image(256, 128, 10, PixelFormat::Yuv444, YuvRange::Full, alpha)?;
+    let thumbnail = generate_gradient_image(64, 32, 8, PixelFormat::Yuv420, YuvRange::Full, alpha)?;
+    let settings = encoder::Settings {
+        speed: Some(10),
+        mutable: encoder::MutableSettings {
+            quality: 90.0,
+            quality_alpha: 90.0,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let mut encoder = encoder::Encoder::create_with_settings(&settings)?;
+    encoder.add_image(&image)?;
+    encoder.add_thumbnail(&thumbnail)?;
+    let edata = encoder.finish()?;
+
+    for (source, width, height, depth) in [
+        (decoder::Source::PrimaryItem, 256, 128, 10),
+        (decoder::Source::Thumbnail, 64, 32, 8),
+    ] {
+        let mut decoder = decoder::Decoder::default();
+        decoder.settings.source = source;
+        decoder.set_io_vec(edata.clone());
+        decoder.parse()?;
+        let decoded = decoder.image().expect("image was none");
+        assert_eq!(decoded.width, width);
+        assert_eq!(decoded.height, height);
+        assert_eq!(decoded.depth, depth);
+        assert_eq!(decoded.alpha_present, alpha);
+        if !HAS_DECODER {
+            continue;
+        }
+        decoder.next_image()?;
+        let decoded = decoder.image().expect("image was none");
+        let expected = if source == decoder::Source::Thumbnail { &thumbnail } else { &image };
+        assert!(psnr(decoded, expected)? >= 40.0);
+    }
+    Ok(())
+}
+
+#[test]
+fn thumbnail_invalid() -> AvifResult<()> {
+    if !HAS_ENCODER {
+        return Ok(());
+    }
+    let image = generate_gradient_image(64, 64, 8, PixelFormat::Yuv420, YuvRange::Full, false)?;
+    let settings = encoder::Settings {
+        speed: Some(10),
+        ..Default::default()
+    };
+    // The thumbnail must be added after the image.
+    let mut encoder = encoder::Encoder::create_with_settings(&settings)?;
+    assert_eq!(
+        encoder.add_thumbnail(&image),
+        Err(AvifError::InvalidArgument)
+    );
+    // Only one thumbnail is allowed.
+    encoder.add_image(&image)?;
+    encoder.add_thumbnail(&image)?;
+    assert_eq!(
+        encoder.add_thumbnail(&image),
+        Err(AvifError::InvalidArgument)
+    );
+    // Image sequences cannot have thumbnails.
+    assert_eq!(
+        encoder.add_image_for_sequence(&image, 1),
+        Err(AvifError::InvalidArgument)
+    );
+    Ok(())
+}
+
+fn generate_solid_image(width: u32, height: u32, alpha: bool, yuva: [u8; 4]) -> AvifResult<Image> {
+    let mut image =
+        generate_gradient_image(width, height, 8, PixelFormat::Yuv444, YuvRange::Full, alpha)?;
+    for (plane, value) in ALL_PLANES.iter().zip(yuva) {
+        if !image.has_plane(*plane) {
+            continue;
+        }
+        for y in 0..height {
+            image.row_mut(*plane, y)?.fill(value);
+        }
+    }
+    Ok(image)
+}
+
+#[test_case(false ; "opaque")]
+#[test_case(true ; "alpha")]
+fn overlay(alpha: bool) -> AvifResult<()> {
+    if !HAS_ENCODER {
+        return Ok(());
+    }
+    let cells = [
+        generate_solid_image(64, 48, alpha, [50, 128, 128, 255])?,
+        generate_solid_image(100, 80, alpha, [200, 128, 128, 100])?,
+    ];
+    let overlay = encoder::OverlaySettings {
+        width: 200,
+        height: 150,
+        canvas_fill_value: [0, 0, 0, 65535],
+        offsets: vec![(10, 20), (40, 90)],
+    };
+    let settings = encoder::Settings {
+        speed: Some(10),
+        mutable: encoder::MutableSettings {
+            quality: 100.0,
+            quality_alpha: 100.0,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let mut encoder = encoder::Encoder::create_with_settings(&settings)?;
+    encoder.add_image_overlay(&overlay, &[&cells[0], &cells[1]])?;
+    let edata = encoder.finish()?;
+
+    let mut decoder = decoder::Decoder::default();
+    decoder.set_io_vec(edata);
+    decoder.parse()?;
+    let image = decoder.image().expect("image was none");
+    assert_eq!(image.width, 200);
+    assert_eq!(image.height, 150);
+    assert_eq!(image.alpha_present, alpha);
+    if !HAS_DECODER {
+        return Ok(());
+    }
+    decoder.next_image()?;
+    let image = decoder.image().expect("image was none");
+    for (x, y, expected_y, expected_a) in [
+        (0, 0, 0, 255),       // Canvas.
+        (199, 149, 0, 255),   // Canvas.
+        (15, 25, 50, 255),    // First cell only.
+        (50, 100, 200, 100),  // Second cell on top of the first one.
+        (139, 149, 200, 100), // Second cell, clipped by the canvas.
+    ] {
+        assert!((image.row(Plane::Y, y)?[x] as i32 - expected_y).abs() <= 2);
+        if alpha {
+            assert!((image.row(Plane::A, y)?[x] as i32 - expected_a).abs() <= 2);
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn overlay_invalid() -> AvifResult<()> {
+    if !HAS_ENCODER {
+        return Ok(());
+    }
+    let image = generate_gradient_image(64, 64, 8, PixelFormat::Yuv420, YuvRange::Full, false)?;
+    let image10 = generate_gradient_image(64, 64, 10, PixelFormat::Yuv420, YuvRange::Full, false)?;
+    let overlay = encoder::OverlaySettings {
+        width: 128,
+        height: 64,
+        offsets: vec![(0, 0), (64, 0)],
+        ..Default::default()
+    };
+    let settings = encoder::Settings {
+        speed: Some(10),
+        ..Default::default()
+    };
+    let mut encoder = encoder::Encoder::create_with_settings(&settings)?;
+    // One offset per image is required.
+    assert_eq!(
+        encoder.add_image_overlay(&overlay, &[&image]),
+        Err(AvifError::InvalidArgument)
+    );
+    // All the cells must have the same properties.
+    assert!(matches!(
+        encoder.add_image_overlay(&overlay, &[&image, &image10]),
+        Err(AvifError::InvalidImageGrid(_))
+    ));
+    // Overlays cannot be combined with other images.
+    encoder.add_image(&image)?;
+    assert_eq!(
+        encoder.add_image_overlay(&overlay, &[&image, &image]),
+        Err(AvifError::InvalidArgument)
+    );
+    Ok(())
+}
+
+#[test]
+fn target_size() -> AvifResult<()> {
+    if !HAS_ENCODER {
+        return Ok(());
+    }
+    let image = generate_gradient_image(128, 96, 8, PixelFormat::Yuv420, YuvRange::Full, false)?;
+    let mut settings = encoder::Settings {
+        speed: Some(10),
+        ..Default::default()
+    };
+    settings.mutable.quality = 50.0;
+    let mut encoder = encoder::Encoder::create_with_settings(&settings)?;
+    encoder.add_image(&image)?;
+    let size = encoder.finish()?.len();
+
+    let (edata, quality) =
+        encoder::Encoder::encode_with_target_size(&settings, TargetSize::Bytes(size), |encoder| {
+            encoder.add_image(&image)
+        })?;
+    assert!(edata.len() <= size);
+    assert!(quality >= 50.0);
+    let mut decoder = decoder::Decoder::default();
+    decoder.set_io_vec(edata);
+    decoder.parse()?;
+
+    let bits_per_pixel = (size * 8) as f32 / (128 * 96) as f32;
+    let (edata, quality) = encoder::Encoder::encode_with_target_size(
+        &settings,
+        TargetSize::BitsPerPixel(bits_per_pixel),
+        |encoder| encoder.add_image(&image),
+    )?;
+    assert!(edata.len() <= size);
+    assert!(quality >= 50.0);
+    Ok(())
+}
+
+#[test_case(TargetSize::Bytes(0))]
+#[test_case(TargetSize::Bytes(10))]
+#[test_case(TargetSize::BitsPerPixel(0.0))]
+fn target_size_invalid(target_size: TargetSize) -> AvifResult<()> {
+    if !HAS_ENCODER {
+        return Ok(());
+    }
+    let image = generate_gradient_image(64, 64, 8, PixelFormat::Yuv420, YuvRange::Full, false)?;
+    let settings = encoder::Settings {
+        speed: Some(10),
+        ..Default::default()
+    };
+    assert_eq!(
+        encoder::Encoder::encode_with_target_size(&settings, target_size, |encoder| {
+            encoder.add_image(&image)
+        })
+        .err(),
+        Some(AvifError::InvalidArgument)
+    );
+    Ok(())
+}
+
+#[test_matrix(
+    [Metric::Psnr, Metric::Ssim],
+    [false, true]
+)]
+fn target_quality(metric: Metric, alpha: bool) -> AvifResult<()> {
+    if !HAS_ENCODER || !HAS_DECODER {
+        return Ok(());
+    }
+    let image = generate_gradient_image(64, 48, 8, PixelFormat::Yuv444, YuvRange::Full, alpha)?;
+    let min_score = match metric {
+        Metric::Psnr => 35.0,
+        _ => 0.95,
+    };
+    let settings = encoder::Settings {
+        speed: Some(10),
+        target_quality: Some(TargetQuality { metric, min_score }),
+        ..Default::default()
+    };
+    let mut encoder = encoder::Encoder::create_with_settings(&settings)?;
+    encoder.add_image(&image)?;
+    let mutable = encoder.settings().mutable;
+    let edata = encoder.finish()?;
+    assert!(mutable.quality < 100.0);
+    if alpha {
+        assert!(mutable.quality_alpha < 100.0);
+    }
+
+    let mut decoder = decoder::Decoder::default();
+    decoder.set_io_vec(edata);
+    decoder.parse()?;
+    decoder.next_image()?;
+    let decoded = decoder.image().expect("image was none");
+    let scores = metric.compute_planes(&image, decoded, &YUV_PLANES)?;
+    assert!(scores.combined >= min_score);
+    if alpha {
+        let scores = metric.compute_planes(&image, decoded, &[Plane::A])?;
+        assert!(scores.combined >= min_score);
+    }
+    Ok(())
+}
+
+#[test]
+fn target_quality_not_implemented() -> AvifResult<()> {
+    if !HAS_ENCODER {
+        return Ok(());
+    }
+    let image = generate_gradient_image(64, 64, 8, PixelFormat::Yuv420, YuvRange::Full, false)?;
+    let settings = encoder::Settings {
+        speed: Some(10),
+        target_quality: Some(TargetQuality {
+            metric: Metric::Ssim,
+            min_score: 0.9,
+        }),
+        ..Default::default()
+    };
+    let mut encoder = encoder::Encoder::create_with_settings(&settings)?;
+    assert_eq!(
+        encoder.add_image_grid(2, 1, &[&image, &image]).err(),
+        Some(AvifError::NotImplemented)
+    );
+    let mut encoder = encoder::Encoder::create_with_settings(&settings)?;
+    assert_eq!(
+        encoder.add_image_for_sequence(&image, 1).err(),
+        Some(AvifError::NotImplemented)
+    );
+    if !HAS_DECODER {
+        // The trial encodes cannot be decoded.
+        let mut encoder = encoder::Encoder::create_with_settings(&settings)?;
+        assert_eq!(
+            encoder.add_image(&image).err(),
+            Some(AvifError::NotImplemented)
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn finish_to_writer() -> AvifResult<()> {
+    if !HAS_ENCODER {
+        return Ok(());
+    }
+    let image = generate_gradient_image(64, 48, 8, PixelFormat::Yuv420, YuvRange::Full, true)?;
+    let settings = encoder::Settings {
+        speed: Some(10),
+        ..Default::default()
+    };
+    let mut encoder = encoder::Encoder::create_with_settings(&settings)?;
+    encoder.add_image(&image)?;
+    let edata = encoder.finish()?;
+
+    // The file does not have to start at the beginning of the writer.
+    let prefix = [0xAAu8; 10];
+    let mut writer = std::io::Cursor::new(Vec::new());
+    writer.write_all(&prefix).unwrap();
+    let mut encoder = encoder::Encoder::create_with_settings(&settings)?;
+    encoder.add_image(&image)?;
+    // Still images are not streamed.
+    encoder.flush_to_writer(&mut writer)?;
+    assert_eq!(writer.get_ref().len(), prefix.len());
+    encoder.finish_to_writer(&mut writer)?;
+    assert_eq!(&writer.get_ref()[..prefix.len()], &prefix);
+    assert_eq!(&writer.get_ref()[prefix.len()..], &edata);
+    Ok(())
+}
+
+#[test_matrix([false, true], [false, true])]
+fn flush_to_writer(alpha: bool, flush_every_frame: bool) -> AvifResult<()> {
+    if !HAS_ENCODER {
+        return Ok(());
+    }
+    const FRAME_COUNT: u64 = 5;
+    let image = generate_gradient_image(64, 48, 8, PixelFormat::Yuv420, YuvRange::Full, alpha)?;
+    let settings = encoder::Settings {
+        speed: Some(10),
+        ..Default::default()
+    };
+    let mut encoder = encoder::Encoder::create_with_settings(&settings)?;
+    for index in 0..FRAME_COUNT {
+        encoder.add_image_for_sequence(&image, index + 1)?;
+    }
+    let edata = encoder.finish()?;
+
+    let mut writer = std::io::Cursor::new(Vec::new());
+    let mut encoder = encoder::Encoder::create_with_settings(&settings)?;
+    for index in 0..FRAME_COUNT {
+        encoder.add_image_for_sequence(&image, index + 1)?;
+        if flush_every_frame || index == 1 {
+            encoder.flush_to_writer(&mut writer)?;
+        }
+    }
+    assert!(!writer.get_ref().is_empty());
+    encoder.finish_to_writer(&mut writer)?;
+    // The samples were already written.
+    assert_eq!(encoder.finish().err(), Some(AvifError::InvalidArgument));
+
+    let mut decoder = decoder::Decoder::default();
+    decoder.set_io_vec(writer.into_inner());
+    decoder.parse()?;
+    assert_eq!(decoder.image_count(), FRAME_COUNT as u32);
+    let image = decoder.image().expect("image was none");
+    assert_eq!(image.alpha_present, alpha);
+    assert!(image.image_sequence_track_present);
+    if !HAS_DECODER {
+        return Ok(());
+    }
+    let mut expected_decoder = decoder::Decoder::default();
+    expected_decoder.set_io_vec(edata);
+    expected_decoder.parse()?;
+    for index in 0..FRAME_COUNT {
+        decoder.next_image()?;
+        expected_decoder.next_image()?;
+        assert_eq!(decoder.image_timing().duration_in_timescales, index + 1);
+        let image = decoder.image().expect("image was none");
+        let expected_image = expected_decoder.image().expect("image was none");
+        assert!(are_images_equal(image, expected_image)?);
+    }
+    Ok(())
+}
+
+// IO over data of which only the first available_size bytes can be read.
+struct GrowingIO {
+    data: Vec<u8>,
+    available_size: Rc<Cell<usize>>,
+}
+
+impl decoder::IO for GrowingIO {
+    fn read(&mut self, offset: u64, max_read_size: usize) -> AvifResult<&[u8]> {
+        let available_size = self.available_size.get();
+        let start = usize::try_from(offset).unwrap();
+        if start > available_size {
+            return Err(AvifError::WaitingOnIo);
+        }
+        let end = start + std::cmp::min(max_read_size, available_size - start);
+        Ok(&self.data[start..end])
+    }
+
+    fn size_hint(&self) -> u64 {
+        self.available_size.get() as u64
+    }
+
+    fn persistent(&self) -> bool {
+        false
+    }
+}
+
+#[test_matrix([false, true], [false, true])]
+fn fragmented(alpha: bool, streamed: bool) -> AvifResult<()> {
+    if !HAS_ENCODER {
+        return Ok(());
+    }
+    const FRAME_COUNT: u64 = 5;
+    let image = generate_gradient_image(64, 48, 8, PixelFormat::Yuv420, YuvRange::Full, alpha)?;
+    let mut settings = encoder::Settings {
+        speed: Some(10),
+        ..Default::default()
+    };
+    let mut encoder = encoder::Encoder::create_with_settings(&settings)?;
+    for index in 0..FRAME_COUNT {
+        encoder.add_image_for_sequence(&image, index + 1)?;
+    }
+    let edata = encoder.finish()?;
+
+    settings.fragmented = true;
+    let mut encoder = encoder::Encoder::create_with_settings(&settings)?;
+    let fragmented_edata = if streamed {
+        let mut writer = std::io::Cursor::new(Vec::new());
+        for index in 0..FRAME_COUNT {
+            encoder.add_image_for_sequence(&image, index + 1)?;
+            encoder.flush_to_writer(&mut writer)?;
+        }
+        encoder.finish_to_writer(&mut writer)?;
+        writer.into_inner()
+    } else {
+        for index in 0..FRAME_COUNT {
+            encoder.add_image_for_sequence(&image, index + 1)?;
+        }
+        encoder.finish()?
+    };
+    let moof_count = fragmented_edata
+        .windows(4)
+        .filter(|window| window == b"moof")
+        .count();
+    if streamed {
+        assert!(moof_count >= 1);
+    } else {
+        assert_eq!(moof_count, FRAME_COUNT as usize);
+    }
+
+    let mut decoder = decoder::Decoder::default();
+    decoder.set_io_vec(fragmented_edata.clone());
+    decoder.parse()?;
+    assert_eq!(decoder.image_count(), FRAME_COUNT as u32);
+    assert_eq!(decoder.duration_in_timescales(), 15);
+    let image = decoder.image().expect("image was none");
+    assert_eq!(image.alpha_present, alpha);
+    assert!(image.image_sequence_track_present);
+
+    // Only the frames of the complete fragments are available in a truncated file.
+    if !streamed {
+        let mut truncated_decoder = decoder::Decoder::default();
+        truncated_decoder.set_io_vec(fragmented_edata[..fragmented_edata.len() - 1].to_vec());
+        truncated_decoder.parse()?;
+        assert_eq!(truncated_decoder.image_count(), FRAME_COUNT as u32 - 1);
+
+        // The fragments that become available after parsing are found when requesting frames.
+        let available_size = Rc::new(Cell::new(fragmented_edata.len() - 1));
+        let mut growing_decoder = decoder::Decoder::default();
+        growing_decoder.set_io(Box::new(GrowingIO {
+            data: fragmented_edata.clone(),
+            available_size: available_size.clone(),
+        }));
+        growing_decoder.parse()?;
+        assert_eq!(growing_decoder.image_count(), FRAME_COUNT as u32 - 1);
+        available_size.set(fragmented_edata.len());
+        // Decoding may fail without a decoder but the new fragment is parsed first.
+        let _ = growing_decoder.nth_image(FRAME_COUNT as u32 - 1);
+        assert_eq!(growing_decoder.image_count(), FRAME_COUNT as u32);
+    }
+
+    if !HAS_DECODER {
+        return Ok(());
+    }
+    let mut expected_decoder = decoder::Decoder::default();
+    expected_decoder.set_io_vec(edata);
+    expected_decoder.parse()?;
+    for index in 0..FRAME_COUNT {
+        decoder.next_image()?;
+        expected_decoder.next_image()?;
+        assert_eq!(decoder.image_timing().duration_in_timescales, index + 1);
+        let image = decoder.image().expect("image was none");
+        let expected_image = expected_decoder.image().expect("image was none");
+        assert!(are_images_equal(image, expected_image)?);
+    }
+    Ok(())
+}
+
+#[test]
+fn fragmented_repetition_count() {
+    let mut settings = encoder::Settings {
+        fragmented: true,
+        repetition_count: RepetitionCount::Finite(2),
+        ..Default::default()
+    };
+    // The repetition count cannot be written in fragmented sequences.
+    assert_eq!(
+        encoder::Encoder::create_with_settings(&settings).err(),
+        Some(AvifError::InvalidArgument)
+    );
+    settings.repetition_count = RepetitionCount::Infinite;
+    assert!(encoder::Encoder::create_with_settings(&settings).is_ok());
+}
+
+// Returns the payload of the 'mdat' box of the given test file.
+fn mdat_payload(filename: &str) -> Vec<u8> {
+    let data = std::fs::read(get_test_file(filename)).expect("failed to read test file");
+    let mut offset = 0;
+    loop {
+        let size = u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap()) as usize;
+        if &data[offset + 4..offset + 8] == b"mdat" {
+            return data[offset + 8..offset + size].to_vec();
+        }
+        offset += size;
+    }
+}
+
+// Returns an image without planes with the same properties as the primary image of paris_10bpc.avif
+// and the payload of that image.
+fn paris_10bpc_metadata_and_payload() -> AvifResult<(Image, Vec<u8>)> {
+    // paris_10bpc.avif has a single item whose AV1 payload is the whole 'mdat' box.
+    let payload = mdat_payload("paris_10bpc.avif");
+    let mut decoder = get_decoder("paris_10bpc.avif");
+    decoder.parse()?;
+    let image = decoder.image().expect("image was none");
+    let image_metadata = Image {
+        width: image.width,
+        height: image.height,
+        depth: image.depth,
+        yuv_format: image.yuv_format,
+        yuv_range: image.yuv_range,
+        color_primaries: image.color_primaries,
+        transfer_characteristics: image.transfer_characteristics,
+        matrix_coefficients: image.matrix_coefficients,
+        ..Default::default()
+    };
+    Ok((image_metadata, payload))
+}
+
+#[test_case(1 ; "still")]
+#[test_case(3 ; "sequence")]
+fn encoded_image(frame_count: u32) -> AvifResult<()> {
+    let (image_metadata, payload) = paris_10bpc_metadata_and_payload()?;
+
+    let mut encoder = encoder::Encoder::create_with_settings(&encoder::Settings::default())?;
+    if frame_count == 1 {
+        encoder.add_encoded_image(&image_metadata, &payload, None)?;
+    } else {
+        for _ in 0..frame_count {
+            encoder.add_encoded_image_for_sequence(&image_metadata, &payload, None, 2)?;
+        }
+    }
+    // The alpha payload must be monochrome and be present in all frames.
+    assert!(encoder
+        .add_encoded_image_for_sequence(&image_metadata, &payload, Some(&payload), 2)
+        .is_err());
+    let edata = encoder.finish()?;
+    // The AV1 payload is copied as is.
+    assert!(edata
+        .windows(payload.len())
+        .any(|window| window == payload.as_slice()));
+
+    let mut remuxed_decoder = decoder::Decoder::default();
+    remuxed_decoder.set_io_vec(edata);
+    remuxed_decoder.parse()?;
+    assert_eq!(remuxed_decoder.image_count(), frame_count);
+    let remuxed_image = remuxed_decoder.image().expect("image was none");
+    assert_eq!(remuxed_image.width, image_metadata.width);
+    assert_eq!(remuxed_image.height, image_metadata.height);
+    assert_eq!(remuxed_image.depth, image_metadata.depth);
+    assert_eq!(remuxed_image.yuv_format, image_metadata.yuv_format);
+    assert_eq!(remuxed_image.yuv_range, image_metadata.yuv_range);
+    assert_eq!(
+        remuxed_image.color_primaries,
+        image_metadata.color_primaries
+    );
+    assert_eq!(
+        remuxed_image.transfer_characteristics,
+        image_metadata.transfer_characteristics
+    );
+    assert_eq!(
+        remuxed_image.matrix_coefficients,
+        image_metadata.matrix_coefficients
+    );
+    assert_eq!(remuxed_image.image_sequence_track_present, frame_count > 1);
+    assert!(remuxed_decoder.is_keyframe(0));
+    if !HAS_DECODER {
+        return Ok(());
+    }
+    let mut decoder = get_decoder("paris_10bpc.avif");
+    decoder.parse()?;
+    decoder.next_image()?;
+    for _ in 0..frame_count {
+        remuxed_decoder.next_image()?;
+        assert!(are_images_equal(
+            remuxed_decoder.image().expect("image was none"),
+            decoder.image().expect("image was none")
+        )?);
+    }
+    Ok(())
+}
+
+#[test]
+fn encoded_image_invalid() -> AvifResult<()> {
+    let (image_metadata, payload) = paris_10bpc_metadata_and_payload()?;
+
+    // The metadata must match the sequence header.
+    let mut encoder = encoder::Encoder::create_with_settings(&encoder::Settings::default())?;
+    let (mut wrong_metadata, _) = paris_10bpc_metadata_and_payload()?;
+    wrong_metadata.depth = 8;
+    assert!(encoder
+        .add_encoded_image(&wrong_metadata, &payload, None)
+        .is_err());
+    assert!(encoder
+        .add_encoded_image(&image_metadata, &[], None)
+        .is_err());
+    assert!(encoder
+        .add_encoded_image(&image_metadata, &payload[..payload.len() / 2], None)
+        .is_err());
+
+    // Pre-encoded images cannot be mixed with regular images.
+    encoder.add_encoded_image_for_sequence(&image_metadata, &payload, None, 1)?;
+    let image = generate_gradient_image(
+        image_metadata.width,
+        image_metadata.height,
+        10,
+        PixelFormat::Yuv420,
+        YuvRange::Full,
+        false,
+    )?;
+    assert!(encoder.add_image_for_sequence(&image, 1).is_err());
+    Ok(())
+}
+
+// Returns the encoded payloads of all the frames of the given category.
+fn all_encoded_payloads(decoder: &mut decoder::Decoder, category: Category) -> Vec<Vec<Vec<u8>>> {
+    (0..decoder.image_count())
+        .map(|index| decoder.encoded_payloads(category, index).unwrap())
+        .collect()
+}
+
+// Returns the bytes of the extents of item, if they are file offsets.
+fn item_payload(data: &[u8], item: &decoder::inspect::ItemDescription) -> Vec<u8> {
+    if item.construction_method != 0 {
+        return Vec::new();
+    }
+    item.extents
+        .iter()
+        .flat_map(|extent| &data[extent.offset as usize..extent.offset as usize + extent.size])
+        .copied()
+        .collect()
+}
+
+#[test_case("paris_icc_exif_xmp.avif")]
+#[test_case("alpha_premultiplied.avif")]
+#[test_case("colors-animated-8bpc-alpha-exif-xmp.avif")]
+#[test_case("sofa_grid1x5_420.avif")]
+#[test_case("color_grid_alpha_grid_gainmap_nogrid.avif")]
+#[test_case("seine_sdr_gainmap_srgb.avif")]
+fn rewrite_metadata(filename: &str) -> AvifResult<()> {
+    let data = std::fs::read(get_test_file(filename)).expect("failed to read test file");
+    let mut decoder = get_decoder(filename);
+    decoder.parse()?;
+    let image = decoder.image().expect("image was none");
+    let expected_xmp = image.xmp.clone();
+    let expected_icc = image.icc.clone();
+    let expected_alpha_present = image.alpha_present;
+    let expected_alpha_premultiplied = image.alpha_premultiplied;
+    let clap = CleanAperture {
+        width: UFraction(2, 1),
+        height: UFraction(2, 1),
+        horiz_off: UFraction(0, 1),
+        vert_off: UFraction(0, 1),
+    };
+
+    let rewritten_data = encoder::rewrite::rewrite_metadata(&data, |image| {
+        image.exif.clear();
+        image.irot_angle = Some(1);
+        image.imir_axis = None;
+        image.clap = Some(clap);
+        image.pasp = Some(PixelAspectRatio {
+            h_spacing: 3,
+            v_spacing: 2,
+        });
+        Ok(())
+    })?;
+
+    // The properties are written to the primary item, also for image sequences.
+    let mut rewritten_item_decoder = decoder::Decoder::default();
+    rewritten_item_decoder.settings.source = decoder::Source::PrimaryItem;
+    rewritten_item_decoder.set_io_vec(rewritten_data.clone());
+    rewritten_item_decoder.parse()?;
+    let rewritten_image = rewritten_item_decoder.image().expect("image was none");
+    assert!(rewritten_image.exif.is_empty());
+    assert_eq!(rewritten_image.xmp, expected_xmp);
+    assert_eq!(rewritten_image.icc, expected_icc);
+    assert_eq!(rewritten_image.irot_angle, Some(1));
+    assert_eq!(rewritten_image.imir_axis, None);
+    assert_eq!(rewritten_image.clap, Some(clap));
+    assert_eq!(
+        rewritten_image.pasp,
+        Some(PixelAspectRatio {
+            h_spacing: 3,
+            v_spacing: 2
+        })
+    );
+    assert_eq!(rewritten_image.alpha_present, expected_alpha_present);
+    assert_eq!(
+        rewritten_image.alpha_premultiplied,
+        expected_alpha_premultiplied
+    );
+
+    // All the other items, properties, references and groups are kept.
+    let edited_property_types = ["irot", "imir", "clap", "pasp"];
+    let other_properties =
+        |item: &decoder::inspect::ItemDescription| -> Vec<(String, Vec<u8>, bool)> {
+            item.properties
+                .iter()
+                .filter(|x| !edited_property_types.contains(&x.box_type.as_str()))
+                .map(|x| (x.box_type.clone(), x.payload.clone(), x.essential))
+                .collect()
+        };
+    let mut original_decoder = get_decoder(filename);
+    original_decoder.settings.source = decoder::Source::PrimaryItem;
+    original_decoder.parse()?;
+    for item in original_decoder.items() {
+        let rewritten_item = rewritten_item_decoder
+            .items()
+            .iter()
+            .find(|x| x.id == item.id);
+        if item.item_type == "Exif" {
+            assert!(rewritten_item.is_none());
+            continue;
+        }
+        let rewritten_item = rewritten_item.expect("item was removed");
+        assert_eq!(rewritten_item.item_type, item.item_type);
+        assert_eq!(rewritten_item.name, item.name);
+        assert_eq!(rewritten_item.content_type, item.content_type);
+        assert_eq!(rewritten_item.hidden, item.hidden);
+        assert_eq!(rewritten_item.references, item.references);
+        assert_eq!(other_properties(rewritten_item), other_properties(item));
+        assert_eq!(
+            item_payload(&rewritten_data, rewritten_item),
+            item_payload(&data, item)
+        );
+    }
+    let exif_item_count = original_decoder
+        .items()
+        .iter()
+        .filter(|x| x.item_type == "Exif")
+        .count();
+    assert_eq!(
+        rewritten_item_decoder.items().len(),
+        original_decoder.items().len() - exif_item_count
+    );
+    assert_eq!(
+        rewritten_item_decoder.entity_groups(),
+        original_decoder.entity_groups()
+    );
+    assert_eq!(rewritten_item_decoder.tracks(), original_decoder.tracks());
+    assert_eq!(
+        rewritten_item_decoder.gainmap_present(),
+        original_decoder.gainmap_present()
+    );
+
+    let mut rewritten_decoder = decoder::Decoder::default();
+    rewritten_decoder.set_io_vec(rewritten_data);
+    rewritten_decoder.parse()?;
+    if image.image_sequence_track_present {
+        // The tracks and their metadata are not edited.
+        let rewritten_image = rewritten_decoder.image().expect("image was none");
+        assert_eq!(rewritten_image.exif, image.exif);
+        assert_eq!(rewritten_image.xmp, image.xmp);
+    }
+    assert_eq!(rewritten_decoder.image_count(), decoder.image_count());
+    for index in 0..decoder.image_count() {
+        let timing = decoder.nth_image_timing(index)?;
+        let rewritten_timing = rewritten_decoder.nth_image_timing(index)?;
+        assert_eq!(rewritten_timing.timescale, timing.timescale);
+        assert_eq!(rewritten_timing.pts_in_timescales, timing.pts_in_timescales);
+        assert_eq!(
+            rewritten_timing.duration_in_timescales,
+            timing.duration_in_timescales
+        );
+    }
+    // The payloads are copied as is.
+    assert_eq!(
+        all_encoded_payloads(&mut rewritten_decoder, Category::Color),
+        all_encoded_payloads(&mut decoder, Category::Color)
+    );
+    if expected_alpha_present {
+        assert_eq!(
+            all_encoded_payloads(&mut rewritten_decoder, Category::Alpha),
+            all_encoded_payloads(&mut decoder, Category::Alpha)
+        );
+    }
+    Ok(())
+}
+
+#[test_case("paris_icc_exif_xmp.avif")]
+#[test_case("sofa_grid1x5_420.avif")]
+fn rewrite_metadata_replace(filename: &str) -> AvifResult<()> {
+    let data = std::fs::read(get_test_file(filename)).expect("failed to read test file");
+    let mut decoder = get_decoder("paris_icc_exif_xmp.avif");
+    decoder.parse()?;
+    let exif = decoder.image().expect("image was none").exif.clone();
+    let xmp = b"<x:xmpmeta xmlns:x='adobe:ns:meta/'/>".to_vec();
+    let clli = ContentLightLevelInformation {
+        max_cll: 1000,
+        max_pall: 400,
+    };
+
+    let rewritten_data = encoder::rewrite::rewrite_metadata(&data, |image| {
+        image.exif.clone_from(&exif);
+        image.xmp.clone_from(&xmp);
+        image.icc.clear();
+        image.clli = Some(clli);
+        Ok(())
+    })?;
+
+    let mut rewritten_decoder = decoder::Decoder::default();
+    rewritten_decoder.set_io_vec(rewritten_data.clone());
+    rewritten_decoder.parse()?;
+    let rewritten_image = rewritten_decoder.image().expect("image was none");
+    assert_eq!(rewritten_image.exif, exif);
+    assert_eq!(rewritten_image.xmp, xmp);
+    assert!(rewritten_image.icc.is_empty());
+    assert_eq!(rewritten_image.clli, Some(clli));
+    // There is a single Exif item and a single XMP item.
+    for item_type in ["Exif", "mime"] {
+        assert_eq!(
+            rewritten_decoder
+                .items()
+                .iter()
+                .filter(|x| x.item_type == item_type)
+                .count(),
+            1
+        );
+    }
+    // Nothing changes when rewriting again with the same metadata.
+    assert_eq!(
+        encoder::rewrite::rewrite_metadata(&rewritten_data, |_| Ok(()))?,
+        rewritten_data
+    );
+    Ok(())
+}
+
+#[test]
+fn rewrite_metadata_invalid() -> AvifResult<()> {
+    let data = std::fs::read(get_test_file("paris_icc_exif_xmp.avif")).unwrap();
+    // Only the metadata can be edited.
+    assert_eq!(
+        encoder::rewrite::rewrite_metadata(&data, |image| {
+            image.depth = 10;
+            Ok(())
+        }),
+        Err(AvifError::InvalidArgument)
+    );
+    // The offsets in movie fragments cannot be updated.
+    let (image_metadata, payload) = paris_10bpc_metadata_and_payload()?;
+    let settings = encoder::Settings {
+        fragmented: true,
+        ..Default::default()
+    };
+    let mut encoder = encoder::Encoder::create_with_settings(&settings)?;
+    for _ in 0..2 {
+        encoder.add_encoded_image_for_sequence(&image_metadata, &payload, None, 1)?;
+    }
+    let data = encoder.finish()?;
+    assert_eq!(
+        encoder::rewrite::rewrite_metadata(&data, |image| {
+            image.irot_angle = Some(1);
+            Ok(())
+        }),
+        Err(AvifError::NotImplemented)
+    );
+    Ok(())
+}
+
 #[test]
 fn codec_versions() {
     assert!(crabby_avif::codec_versions().contains("dav1d"));